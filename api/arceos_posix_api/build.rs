@@ -3,15 +3,22 @@ fn main() {
 
     fn gen_pthread_mutex(out_file: &str) -> std::io::Result<()> {
         // TODO: generate size and initial content automatically.
-        let (mutex_size, mutex_init) = if cfg!(feature = "multitask") {
+        let (mut mutex_size, mutex_init) = if cfg!(feature = "multitask") {
             if cfg!(feature = "smp") {
-                (6, "{0, 0, 8, 0, 0, 0}") // core::mem::transmute::<_, [usize; 6]>(axsync::Mutex::new(()))
+                (6, "{0, 0, 8, 0, 0, 0") // core::mem::transmute::<_, [usize; 6]>(axsync::Mutex::new(()))
             } else {
-                (5, "{0, 8, 0, 0, 0}") // core::mem::transmute::<_, [usize; 5]>(axsync::Mutex::new(()))
+                (5, "{0, 8, 0, 0, 0") // core::mem::transmute::<_, [usize; 5]>(axsync::Mutex::new(()))
             }
         } else {
-            (1, "{0}")
+            (1, "{0")
         };
+        // The lock class of the wait queue, unknown for static initializers.
+        let mut mutex_init = String::from(mutex_init);
+        if cfg!(feature = "lockdep") {
+            mutex_size += 1;
+            mutex_init += ", 0";
+        }
+        mutex_init += "}";
 
         let mut output = Vec::new();
         writeln!(
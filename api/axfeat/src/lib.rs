@@ -12,13 +12,23 @@
 //!     - `alloc-tlsf`: Use the TLSF allocator.
 //!     - `alloc-slab`: Use the slab allocator.
 //!     - `alloc-buddy`: Use the buddy system allocator.
+//!     - `alloc-tracking`: Track allocations with their backtraces, for leak
+//!       and out-of-memory reports.
+//!     - `alloc-sanitize`: Place redzones around allocations and poison freed
+//!       memory, to catch heap corruption.
 //!     - `paging`: Enable page table manipulation.
+//!     - `stack-guard`: Map task stacks with an unmapped guard page below
+//!       each, so that stack overflows fault. Drivers that DMA from buffers
+//!       on the stack bounce them if they cross a page.
 //!     - `tls`: Enable thread-local storage.
 //! - Task management
 //!     - `multitask`: Enable multi-threading support.
 //!     - `sched_fifo`: Use the FIFO cooperative scheduler.
 //!     - `sched_rr`: Use the Round-robin preemptive scheduler.
 //!     - `sched_cfs`: Use the Completely Fair Scheduler (CFS) preemptive scheduler.
+//!     - `sched_classes`: Use the deadline, real-time and fair scheduling classes.
+//!     - `lockdep`: Check the order in which locks are taken, and report
+//!       possible deadlocks.
 //! - Upperlayer stacks (fs, net, display)
 //!     - `fs`: Enable file system support.
 //!     - `myfs`: Allow users to define their custom filesystems to override the default.
@@ -29,7 +39,9 @@
 //!     - `bus-pci`: Use PCI bus to probe all PCI devices.
 //!     - `driver-ramdisk`: Use the RAM disk to emulate the block device.
 //!     - `driver-ixgbe`: Enable the Intel 82599 10Gbit NIC driver.
+//!     - `driver-e1000`: Enable the Intel e1000/e1000e Gigabit NIC driver.
 //!     - `driver-bcm2835-sdhci`: Enable the BCM2835 SDHCI driver (Raspberry Pi SD card).
+//!     - `driver-nvme`: Enable the NVMe block device driver on the PCI bus.
 //! - Logging
 //!     - `log-level-off`: Disable all logging.
 //!     - `log-level-error`, `log-level-warn`, `log-level-info`, `log-level-debug`,
@@ -247,6 +247,7 @@ pub fn sys_unlinkat(dirfd: c_int, path: UserConstPtr<c_char>, flags: u32) -> Lin
                 .ok_or(LinuxError::ENOENT)?;
         }
     }
+    axmm::FileCache::forget(path.as_str());
     Ok(0)
 }
 
@@ -343,7 +344,10 @@ pub fn sys_rename(old_path_ptr: UserConstPtr<c_char>, new_path_ptr: UserConstPtr
     let new_path = new_path_ptr.get_as_str()?;
     debug!("sys_rename <= old: {:?}, new: {:?}", old_path, new_path);
 
-    api::rename(old_path, new_path)?;
+    let old_path = handle_file_path(AT_FDCWD, old_path)?;
+    let new_path = handle_file_path(AT_FDCWD, new_path)?;
+    api::rename(old_path.as_str(), new_path.as_str())?;
+    axmm::FileCache::rename(old_path.as_str(), new_path.as_str());
 
     Ok(0) 
 }
\ No newline at end of file
@@ -119,6 +119,12 @@ pub fn sys_fsync(fd: c_int) -> LinuxResult<isize> {
     Ok(0)
 }
 
+pub fn sys_sync() -> LinuxResult<isize> {
+    debug!("sys_sync");
+    axfs::api::sync()?;
+    Ok(0)
+}
+
 // pub fn sys_fdatasync(fd: c_int) -> LinuxResult<isize> {
 //     debug!("sys_fdatasync <= fd: {}", fd);
 //     let file = File::from_fd(fd)?;
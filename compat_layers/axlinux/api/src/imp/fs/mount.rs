@@ -1,7 +1,7 @@
 use core::ffi::{c_char, c_void};
 
 use alloc::vec::Vec;
-use axerrno::{LinuxError, LinuxResult};
+use axerrno::{AxError, LinuxError, LinuxResult};
 use axsync::Mutex;
 use linux_raw_sys::general::AT_FDCWD;
 
@@ -32,11 +32,6 @@ pub fn sys_mount(
         device_path, mount_path, fs_type
     );
 
-    if fs_type != "vfat" {
-        debug!("fs_type can only be vfat.");
-        return Err(LinuxError::EPERM);
-    }
-
     if !mount_path.exists() {
         debug!("mount path not exist");
         return Err(LinuxError::EPERM);
@@ -47,6 +42,26 @@ pub fn sys_mount(
         return Err(LinuxError::EPERM);
     }
 
+    // Block devices and partitions (e.g., `/dev/vda1`) are really mounted.
+    match axfs::api::mount(device_path.as_str(), mount_path.as_str(), fs_type) {
+        Ok(()) => {
+            MOUNTED
+                .lock()
+                .push(MountedFs::new(&device_path, &mount_path));
+            return Ok(0);
+        }
+        Err(AxError::NotFound) => {}
+        Err(e) => {
+            debug!("mount error: {:?}", e);
+            return Err(e.into());
+        }
+    }
+
+    if fs_type != "vfat" {
+        debug!("fs_type can only be vfat.");
+        return Err(LinuxError::EPERM);
+    }
+
     if !mount_fat_fs(&device_path, &mount_path) {
         debug!("mount error");
         return Err(LinuxError::EPERM);
@@ -69,10 +84,17 @@ pub fn sys_umount2(target: UserConstPtr<c_char>, flags: i32) -> LinuxResult<isiz
         return Err(LinuxError::EPERM);
     }
 
+    match axfs::api::umount(mount_path.as_str()) {
+        // `InvalidInput` means it is not a block device mount.
+        Ok(()) | Err(AxError::InvalidInput) => {}
+        Err(e) => return Err(e.into()),
+    }
+
     if !umount_fat_fs(&mount_path) {
         debug!("umount error");
         return Err(LinuxError::EPERM);
     }
+    axmm::FileCache::forget(mount_path.as_str());
     Ok(0)
 }
 
@@ -1,4 +1,5 @@
 use axerrno::LinuxError;
+use axtask::{TaskExtRef, current};
 use core::ffi::c_int;
 use linux_raw_sys::general::{rlimit,RLIMIT_STACK,RLIMIT_DATA,RLIMIT_NOFILE};
 use crate::file::AX_FILE_LIMIT;
@@ -11,9 +12,11 @@ pub fn sys_getrlimit(resource: c_int, rlimits: *mut rlimit) -> Result<isize, Lin
         return Ok(0);
     }
     match resource as u32 {
-        RLIMIT_STACK => unsafe{
-            (*rlimits).rlim_cur = axconfig::TASK_STACK_SIZE as _;
-            (*rlimits).rlim_max = axconfig::TASK_STACK_SIZE as _;
+        RLIMIT_STACK => unsafe {
+            let curr = current();
+            let limit = curr.task_ext().process_data().aspace.lock().stack_limit();
+            (*rlimits).rlim_cur = limit as _;
+            (*rlimits).rlim_max = u64::MAX as _;
         },
         RLIMIT_NOFILE => unsafe {
             (*rlimits).rlim_cur = AX_FILE_LIMIT as _;
@@ -31,10 +34,24 @@ pub fn sys_getrlimit(resource: c_int, rlimits: *mut rlimit) -> Result<isize, Lin
 pub fn sys_setrlimit(resource: c_int, rlimits: *mut rlimit) -> Result<isize, LinuxError> {
     match resource as u32 {
         RLIMIT_DATA => {}
-        RLIMIT_STACK => {}
+        RLIMIT_STACK => {
+            if rlimits.is_null() {
+                return Err(LinuxError::EFAULT);
+            }
+            let limit = unsafe { *rlimits };
+            if limit.rlim_cur > limit.rlim_max {
+                return Err(LinuxError::EINVAL);
+            }
+            let curr = current();
+            curr.task_ext()
+                .process_data()
+                .aspace
+                .lock()
+                .set_stack_limit(limit.rlim_cur.try_into().unwrap_or(usize::MAX));
+        }
         RLIMIT_NOFILE => {}
         _ => return Err(LinuxError::EINVAL),
     }
-    // Currently do not support set resources
+    // Other resources are not enforced yet.
     Ok(0)
 }
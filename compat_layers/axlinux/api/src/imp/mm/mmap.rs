@@ -1,12 +1,14 @@
 use crate::file::{File, FileLike};
-use alloc::{sync::Arc, vec};
-use axerrno::{LinuxError, LinuxResult};
+use alloc::sync::Arc;
+use axerrno::{AxError, LinuxError, LinuxResult};
 use axhal::paging::{MappingFlags, PageSize};
+use axmm::{FileCache, SharedPages};
 use axtask::{TaskExtRef, current};
 use axuio::file::UioDeviceFile;
 use core::any::Any;
 use linux_raw_sys::general::*;
 use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange, align_up_4k};
+use starry_core::shmfs::ShmFile;
 
 fn downcast_file_from_axfs<T: Any>(axfs_file: &axfs::fops::File) -> Option<&T> {
     // 调用我们新加的 node() 方法，然后调用 as_any()
@@ -169,6 +171,27 @@ pub fn sys_mmap(
         start, end, aligned_length
     );
 
+    // Check the mapped file before replacing anything at a fixed address.
+    let file = if fd == -1 || map_flags.contains(MmapFlags::ANONYMOUS) {
+        None
+    } else {
+        if offset < 0 || !(offset as usize).is_aligned_4k() || page_size != PageSize::Size4K {
+            return Err(LinuxError::EINVAL);
+        }
+        let file = File::from_fd(fd)?;
+        let (readable, writable) = {
+            let inner = file.inner();
+            (inner.readable(), inner.writable())
+        };
+        // Shared mappings write back to the file, so they may only be made
+        // writable, now or by a later mprotect, if the file is opened for it.
+        let may_write = !map_flags.contains(MmapFlags::SHARED) || writable;
+        if !readable || (permission_flags.contains(MmapProt::WRITE) && !may_write) {
+            return Err(LinuxError::EACCES);
+        }
+        Some((file, may_write))
+    };
+
     let start_addr = if map_flags.contains(MmapFlags::FIXED) {
         if start == 0 {
             return Err(LinuxError::EINVAL);
@@ -193,32 +216,51 @@ pub fn sys_mmap(
             .ok_or(LinuxError::ENOMEM)?
     };
 
-    let populate = if fd == -1 {
-        false
-    } else {
-        !map_flags.contains(MmapFlags::ANONYMOUS)
-    };
-
-    aspace.map_alloc(
-        start_addr,
-        aligned_length,
-        permission_flags.into(),
-        populate,
-        page_size,
-    )?;
-
-    if populate {
-        let file = File::from_fd(fd)?;
-        let file = file.inner();
-        let file_size = file.get_attr()?.size() as usize;
-        if offset < 0 || offset as usize >= file_size {
-            return Err(LinuxError::EINVAL);
+    if map_flags.contains(MmapFlags::SHARED | MmapFlags::ANONYMOUS) {
+        // Shared with the children after fork.
+        aspace.map_shared(
+            start_addr,
+            aligned_length,
+            permission_flags.into(),
+            SharedPages::new_anonymous(aligned_length, page_size),
+            0,
+            true,
+        )?;
+    } else if let Some((file, may_write)) = file {
+        let shm_pages = downcast_file_from_axfs::<ShmFile>(&file.inner())
+            .filter(|_| map_flags.contains(MmapFlags::SHARED))
+            .map(|shm_file| shm_file.pages().clone());
+        if let Some(pages) = shm_pages {
+            // Objects in /dev/shm map the frames that back the file.
+            aspace.map_shared(
+                start_addr,
+                aligned_length,
+                permission_flags.into(),
+                pages,
+                offset as usize,
+                may_write,
+            )?;
+            return Ok(start_addr.as_usize() as _);
         }
-        let offset = offset as usize;
-        let length = core::cmp::min(length, file_size - offset);
-        let mut buf = vec![0u8; length];
-        file.read_at(offset as u64, &mut buf)?;
-        aspace.write(start_addr, page_size, &buf)?;
+        // File pages are faulted in lazily and shared through the file cache.
+        let node = file.inner().node().clone();
+        aspace.map_file(
+            start_addr,
+            aligned_length,
+            permission_flags.into(),
+            FileCache::get_or_create(node, file.path()),
+            offset as usize,
+            map_flags.contains(MmapFlags::SHARED),
+            may_write,
+        )?;
+    } else {
+        aspace.map_alloc(
+            start_addr,
+            aligned_length,
+            permission_flags.into(),
+            false,
+            page_size,
+        )?;
     }
     Ok(start_addr.as_usize() as _)
 }
@@ -252,3 +294,121 @@ pub fn sys_mprotect(addr: usize, length: usize, prot: u32) -> LinuxResult<isize>
 
     Ok(0)
 }
+
+pub fn sys_mremap(
+    addr: usize,
+    old_size: usize,
+    new_size: usize,
+    flags: u32,
+    new_addr: usize,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_mremap: addr: {:#x}, old_size: {:#x}, new_size: {:#x}, flags: {:#x}, new_addr: {:#x}",
+        addr, old_size, new_size, flags, new_addr
+    );
+    let may_move = flags & MREMAP_MAYMOVE != 0;
+    let fixed = flags & MREMAP_FIXED != 0;
+    // TODO: MREMAP_DONTUNMAP, and duplicating shared mappings with `old_size == 0`
+    if flags & !(MREMAP_MAYMOVE | MREMAP_FIXED) != 0
+        || (fixed && !may_move)
+        || !addr.is_aligned_4k()
+        || old_size == 0
+        || new_size == 0
+    {
+        return Err(LinuxError::EINVAL);
+    }
+    let old_size = align_up_4k(old_size);
+    let new_size = align_up_4k(new_size);
+    let old_start = VirtAddr::from(addr);
+
+    let curr = current();
+    let mut aspace = curr.task_ext().process_data().aspace.lock();
+    if fixed {
+        if !new_addr.is_aligned_4k()
+            || VirtAddrRange::from_start_size(old_start, old_size)
+                .overlaps(VirtAddrRange::from_start_size(new_addr.into(), new_size))
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        let new_start = VirtAddr::from(new_addr);
+        aspace.unmap(new_start, new_size)?;
+        aspace.move_area(old_start, old_size, new_start, new_size)?;
+        return Ok(new_addr as _);
+    }
+
+    match aspace.resize_area(old_start, old_size, new_size) {
+        Err(AxError::NoMemory) if may_move => {
+            let new_start = aspace
+                .find_free_area(
+                    aspace.base(),
+                    new_size,
+                    VirtAddrRange::new(aspace.base(), aspace.end()),
+                    PageSize::Size4K,
+                )
+                .ok_or(LinuxError::ENOMEM)?;
+            aspace.move_area(old_start, old_size, new_start, new_size)?;
+            Ok(new_start.as_usize() as _)
+        }
+        res => res.map(|_| addr as _).map_err(Into::into),
+    }
+}
+
+pub fn sys_madvise(addr: usize, length: usize, advice: u32) -> LinuxResult<isize> {
+    if !addr.is_aligned_4k() {
+        return Err(LinuxError::EINVAL);
+    }
+    let length = align_up_4k(length);
+    if length == 0 {
+        return Ok(0);
+    }
+
+    match advice {
+        // MADV_FREE may free the pages lazily, so freeing them now is fine.
+        MADV_DONTNEED | MADV_FREE => {
+            let curr = current();
+            let mut aspace = curr.task_ext().process_data().aspace.lock();
+            // Only private anonymous pages may be freed, the others hold data.
+            if advice == MADV_FREE && !aspace.is_private_anonymous(VirtAddr::from(addr), length) {
+                return Err(LinuxError::EINVAL);
+            }
+            aspace.discard(VirtAddr::from(addr), length)?;
+        }
+        MADV_PAGEOUT => {
+            let curr = current();
+            let mut aspace = curr.task_ext().process_data().aspace.lock();
+            aspace.pageout(VirtAddr::from(addr), length)?;
+        }
+        MADV_HUGEPAGE | MADV_NOHUGEPAGE => {
+            let curr = current();
+            let mut aspace = curr.task_ext().process_data().aspace.lock();
+            aspace.advise_huge(VirtAddr::from(addr), length, advice == MADV_HUGEPAGE)?;
+        }
+        // Hints only.
+        MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL | MADV_WILLNEED | MADV_DONTFORK
+        | MADV_DOFORK | MADV_MERGEABLE | MADV_UNMERGEABLE | MADV_DONTDUMP | MADV_DODUMP
+        | MADV_COLD => {}
+        _ => {
+            warn!("sys_madvise: unsupported advice {}", advice);
+            return Err(LinuxError::EINVAL);
+        }
+    }
+    Ok(0)
+}
+
+pub fn sys_msync(addr: usize, length: usize, flags: u32) -> LinuxResult<isize> {
+    if !addr.is_aligned_4k()
+        || flags & !(MS_ASYNC | MS_SYNC | MS_INVALIDATE) != 0
+        || flags & (MS_ASYNC | MS_SYNC) == MS_ASYNC | MS_SYNC
+    {
+        return Err(LinuxError::EINVAL);
+    }
+    // Like Linux, MS_ASYNC is a no-op: dirty pages stay tracked by the page
+    // cache and are written back by a later MS_SYNC or when unmapped.
+    if flags & MS_SYNC == 0 {
+        return Ok(0);
+    }
+    let curr = current();
+    let mut aspace = curr.task_ext().process_data().aspace.lock();
+    aspace.msync(VirtAddr::from(addr), align_up_4k(length))?;
+    Ok(0)
+}
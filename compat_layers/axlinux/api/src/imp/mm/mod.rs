@@ -1,5 +1,9 @@
 mod brk;
 mod mmap;
+mod shm;
+mod swap;
 
 pub use self::brk::*;
 pub use self::mmap::*;
+pub use self::shm::*;
+pub use self::swap::*;
@@ -0,0 +1,206 @@
+use axerrno::{LinuxError, LinuxResult};
+use axhal::paging::MappingFlags;
+use axtask::{TaskExtRef, current};
+use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange};
+use starry_core::shm::ShmTable;
+
+use crate::ptr::{UserConstPtr, UserPtr};
+
+/// Attach the segment read-only.
+const SHM_RDONLY: u32 = 0o10000;
+/// Round the attach address down to [`SHMLBA`].
+const SHM_RND: u32 = 0o20000;
+/// Replace existing mappings at the attach address.
+const SHM_REMAP: u32 = 0o40000;
+/// Allow executing the segment.
+const SHM_EXEC: u32 = 0o100000;
+/// Attach addresses must be multiples of this.
+const SHMLBA: usize = 0x1000;
+
+const IPC_RMID: u32 = 0;
+const IPC_SET: u32 = 1;
+const IPC_STAT: u32 = 2;
+const SHM_LOCK: u32 = 11;
+const SHM_UNLOCK: u32 = 12;
+/// Set in the `mode` reported by `IPC_STAT` once the segment is removed.
+const SHM_DEST: u32 = 0o1000;
+/// Set by some libcs in `cmd` to ask for the 64-bit structures, which are
+/// the only ones supported.
+const IPC_64: u32 = 0x100;
+
+/// `struct ipc64_perm`
+#[repr(C)]
+#[derive(Default)]
+pub struct IpcPerm {
+    key: i32,
+    uid: u32,
+    gid: u32,
+    cuid: u32,
+    cgid: u32,
+    mode: u32,
+    seq: u16,
+    __pad2: u16,
+    __unused1: usize,
+    __unused2: usize,
+}
+
+/// `struct shmid64_ds`
+#[repr(C)]
+#[derive(Default)]
+pub struct ShmidDs {
+    shm_perm: IpcPerm,
+    shm_segsz: usize,
+    shm_atime: i64,
+    shm_dtime: i64,
+    shm_ctime: i64,
+    shm_cpid: i32,
+    shm_lpid: i32,
+    shm_nattch: usize,
+    __unused4: usize,
+    __unused5: usize,
+}
+
+pub fn sys_shmget(key: i32, size: usize, shmflg: u32) -> LinuxResult<isize> {
+    let curr = current();
+    let pid = curr.task_ext().thread.process().pid();
+    let cred = curr.task_ext().process_data().cred.lock().clone();
+    let id = ShmTable::with(|table| table.get_or_create(key, size, shmflg, pid, &cred))?;
+    debug!("sys_shmget: key {:#x}, size {:#x} -> {}", key, size, id);
+    Ok(id as _)
+}
+
+pub fn sys_shmat(shmid: i32, shmaddr: usize, shmflg: u32) -> LinuxResult<isize> {
+    let curr = current();
+    let process_data = curr.task_ext().process_data();
+    let pid = curr.task_ext().thread.process().pid();
+    let cred = process_data.cred.lock().clone();
+
+    let mut flags = MappingFlags::USER | MappingFlags::READ;
+    if shmflg & SHM_RDONLY == 0 {
+        flags |= MappingFlags::WRITE;
+    }
+    if shmflg & SHM_EXEC != 0 {
+        flags |= MappingFlags::EXECUTE;
+    }
+
+    let pages = ShmTable::with(|table| {
+        let seg = table.get(shmid)?;
+        seg.perm.check(&cred, flags.contains(MappingFlags::WRITE))?;
+        Ok::<_, LinuxError>(seg.pages.clone())
+    })?;
+    let size = pages.size();
+
+    let mut aspace = process_data.aspace.lock();
+    let start = if shmaddr == 0 {
+        aspace
+            .find_free_area(
+                aspace.base(),
+                size,
+                VirtAddrRange::new(aspace.base(), aspace.end()),
+                pages.align(),
+            )
+            .ok_or(LinuxError::ENOMEM)?
+    } else {
+        let addr = if shmflg & SHM_RND != 0 {
+            shmaddr.align_down(SHMLBA)
+        } else if shmaddr.is_aligned(SHMLBA) {
+            shmaddr
+        } else {
+            return Err(LinuxError::EINVAL);
+        };
+        let addr = VirtAddr::from(addr);
+        if shmflg & SHM_REMAP != 0 {
+            aspace.unmap(addr, size)?;
+        } else if !aspace.is_free(addr, size) {
+            return Err(LinuxError::EINVAL);
+        }
+        addr
+    };
+    let may_write = shmflg & SHM_RDONLY == 0;
+    aspace.map_shared(start, size, flags, pages, 0, may_write)?;
+
+    // The segment may have been destroyed while it was being mapped.
+    if let Err(e) = ShmTable::with(|table| table.attach(shmid, pid).map(|_| ())) {
+        aspace.unmap(start, size)?;
+        return Err(e);
+    }
+    process_data.shm.insert(start.as_usize(), shmid, size);
+    debug!("sys_shmat: segment {} attached at {:#x}", shmid, start);
+    Ok(start.as_usize() as _)
+}
+
+pub fn sys_shmdt(shmaddr: usize) -> LinuxResult<isize> {
+    let curr = current();
+    let process_data = curr.task_ext().process_data();
+    let pid = curr.task_ext().thread.process().pid();
+    let (shmid, size) = process_data.shm.get(shmaddr).ok_or(LinuxError::EINVAL)?;
+    let pages = ShmTable::with(|table| table.get(shmid).map(|seg| seg.pages.clone()))?;
+    // The segment may have been unmapped, and something else mapped there.
+    let unmapped =
+        process_data
+            .aspace
+            .lock()
+            .unmap_shared(VirtAddr::from(shmaddr), size, &pages)?;
+    process_data.shm.remove(shmaddr, pid);
+    if !unmapped {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(0)
+}
+
+pub fn sys_shmctl(shmid: i32, cmd: u32, buf: usize) -> LinuxResult<isize> {
+    let curr = current();
+    let cred = curr.task_ext().process_data().cred.lock().clone();
+
+    match cmd & !IPC_64 {
+        IPC_RMID => ShmTable::with(|table| {
+            table.get(shmid)?.perm.check_owner(&cred)?;
+            table.remove(shmid)
+        })?,
+        IPC_SET => {
+            let ds = UserConstPtr::<ShmidDs>::from(buf).get_as_ref()?;
+            ShmTable::with(|table| {
+                let seg = table.get_mut(shmid)?;
+                seg.perm.check_owner(&cred)?;
+                seg.perm.uid = ds.shm_perm.uid;
+                seg.perm.gid = ds.shm_perm.gid;
+                seg.perm.mode = ds.shm_perm.mode & 0o777;
+                seg.ctime = axhal::time::wall_time().as_secs();
+                Ok::<_, LinuxError>(())
+            })?;
+        }
+        IPC_STAT => {
+            let ds = UserPtr::<ShmidDs>::from(buf).get_as_mut()?;
+            *ds = ShmTable::with(|table| {
+                let seg = table.get(shmid)?;
+                seg.perm.check(&cred, false)?;
+                Ok::<_, LinuxError>(ShmidDs {
+                    shm_perm: IpcPerm {
+                        key: seg.key,
+                        uid: seg.perm.uid,
+                        gid: seg.perm.gid,
+                        cuid: seg.perm.cuid,
+                        cgid: seg.perm.cgid,
+                        mode: seg.perm.mode | if seg.removed { SHM_DEST } else { 0 },
+                        ..Default::default()
+                    },
+                    shm_segsz: seg.size,
+                    shm_atime: seg.atime as _,
+                    shm_dtime: seg.dtime as _,
+                    shm_ctime: seg.ctime as _,
+                    shm_cpid: seg.cpid as _,
+                    shm_lpid: seg.lpid as _,
+                    shm_nattch: seg.nattch,
+                    ..Default::default()
+                })
+            })?;
+        }
+        // Segments are never swapped out.
+        SHM_LOCK | SHM_UNLOCK => ShmTable::with(|table| table.get(shmid)?.perm.check_owner(&cred))?,
+        _ => {
+            warn!("sys_shmctl: unsupported cmd {}", cmd);
+            return Err(LinuxError::EINVAL);
+        }
+    }
+    Ok(0)
+}
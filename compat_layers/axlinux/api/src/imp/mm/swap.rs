@@ -0,0 +1,28 @@
+use core::ffi::c_char;
+
+use axerrno::LinuxResult;
+use axfs::fops::{File, OpenOptions};
+use linux_raw_sys::general::AT_FDCWD;
+use starry_core::mm::swap_in_all;
+
+use crate::{imp::check_privileged, path::handle_file_path, ptr::UserConstPtr};
+
+pub fn sys_swapon(path: UserConstPtr<c_char>, flags: i32) -> LinuxResult<isize> {
+    check_privileged()?;
+    let path = handle_file_path(AT_FDCWD, path.get_as_str()?)?;
+    debug!("sys_swapon <= path: {:?}, flags: {:#x}", path, flags);
+
+    // Priorities and discard policies are ignored.
+    let opts = OpenOptions::new().set_read(true).set_write(true);
+    let file = File::open(path.as_str(), &opts)?;
+    axmm::swapon(path.as_str(), file.node().clone())?;
+    Ok(0)
+}
+
+pub fn sys_swapoff(path: UserConstPtr<c_char>) -> LinuxResult<isize> {
+    check_privileged()?;
+    let path = handle_file_path(AT_FDCWD, path.get_as_str()?)?;
+    debug!("sys_swapoff <= path: {:?}", path);
+    axmm::swapoff(path.as_str(), swap_in_all)?;
+    Ok(0)
+}
@@ -1,12 +1,22 @@
 use core::ffi::c_char;
 
-use axerrno::LinuxResult;
+use axerrno::{LinuxError, LinuxResult};
 use linux_raw_sys::system::new_utsname;
 
 use crate::ptr::UserPtr;
 use axtask::current;
 use axtask::TaskExtRef;
 
+/// Fails with `EPERM` unless the current process is the superuser, e.g., for
+/// the swap areas and the real-time scheduling policies.
+pub(crate) fn check_privileged() -> LinuxResult {
+    if current().task_ext().process_data().cred.lock().euid == 0 {
+        Ok(())
+    } else {
+        Err(LinuxError::EPERM)
+    }
+}
+
 pub fn sys_getuid() -> LinuxResult<isize> {
      // 1. 获取当前任务
     let task = current();
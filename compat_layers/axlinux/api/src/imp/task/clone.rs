@@ -234,7 +234,8 @@ pub fn sys_clone(
     let thread = process.new_thread(tid).data(thread_data).build();
     add_thread_to_table(&thread);
     new_task.init_task_ext(TaskExt::new(thread));
-    axtask::spawn_task(new_task);
+    let new_task = axtask::spawn_task(new_task);
+    new_task.task_ext().thread_data().set_task(&new_task);
     // --- 新增 vfork 父进程等待逻辑 ---
     // 6. 如果是 vfork，父进程在此等待
     if let Some(wq) = vfork_wait_queue {
@@ -50,6 +50,7 @@ pub fn sys_execve(
 
     let mut aspace = curr_ext.process_data().aspace.lock();
     aspace.unmap_user_areas()?;
+    curr_ext.process_data().shm.clear();
     map_trampoline(&mut aspace)?;
     axhal::arch::flush_tlb(None);
 
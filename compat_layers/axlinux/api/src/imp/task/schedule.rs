@@ -2,11 +2,14 @@ use axerrno::{LinuxError, LinuxResult};
 use linux_raw_sys::general::{timespec,CLOCK_MONOTONIC,CLOCK_REALTIME,TIMER_ABSTIME,__kernel_clockid_t};
 
 use crate::{
+    imp::check_privileged,
     ptr::{UserConstPtr, UserPtr, nullable},
     time::TimeValueLike,
 };
 
-use axtask::current;
+use axprocess::Pid;
+use axtask::{AxTaskRef, TaskExtRef, current};
+use starry_core::task::{ThreadData, get_thread};
 // use axsignal::Signo;
 
 pub fn sys_sched_yield() -> LinuxResult<isize> {
@@ -118,3 +121,236 @@ pub fn sys_pause() -> LinuxResult<isize> {
 
     Err(LinuxError::EINTR)
 }
+
+const SCHED_NORMAL: u32 = 0;
+const SCHED_FIFO: u32 = 1;
+const SCHED_RR: u32 = 2;
+const SCHED_BATCH: u32 = 3;
+const SCHED_IDLE: u32 = 5;
+const SCHED_DEADLINE: u32 = 6;
+
+/// The size of the first version of [`sched_attr`], the one supported.
+const SCHED_ATTR_SIZE_VER0: u32 = 48;
+
+/// The parameters of `sched_setscheduler` and `sched_setparam`.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub struct sched_param {
+    pub sched_priority: i32,
+}
+
+/// The attributes of `sched_setattr`, with the times in nanoseconds.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct sched_attr {
+    pub size: u32,
+    pub sched_policy: u32,
+    pub sched_flags: u64,
+    pub sched_nice: i32,
+    pub sched_priority: u32,
+    pub sched_runtime: u64,
+    pub sched_deadline: u64,
+    pub sched_period: u64,
+}
+
+/// Finds the task of the thread `tid`, or of the current thread if it is 0.
+fn find_task(tid: Pid) -> LinuxResult<AxTaskRef> {
+    if tid == 0 {
+        return Ok(current().as_task_ref().clone());
+    }
+    get_thread(tid)?
+        .data::<ThreadData>()
+        .and_then(ThreadData::task)
+        .ok_or(LinuxError::ESRCH)
+}
+
+#[cfg(feature = "sched_classes")]
+fn get_sched_attr(task: &AxTaskRef) -> sched_attr {
+    use axtask::SchedPolicy;
+
+    let attr = task.sched_attr();
+    sched_attr {
+        size: SCHED_ATTR_SIZE_VER0,
+        sched_policy: match attr.policy {
+            SchedPolicy::Normal => SCHED_NORMAL,
+            SchedPolicy::Fifo => SCHED_FIFO,
+            SchedPolicy::RoundRobin => SCHED_RR,
+            SchedPolicy::Deadline => SCHED_DEADLINE,
+        },
+        sched_flags: 0,
+        sched_nice: task.base_priority() as _,
+        sched_priority: attr.rt_priority,
+        sched_runtime: attr.runtime,
+        sched_deadline: attr.deadline,
+        sched_period: attr.period,
+    }
+}
+
+#[cfg(not(feature = "sched_classes"))]
+fn get_sched_attr(task: &AxTaskRef) -> sched_attr {
+    sched_attr {
+        size: SCHED_ATTR_SIZE_VER0,
+        sched_policy: SCHED_NORMAL,
+        sched_nice: task.base_priority() as _,
+        ..Default::default()
+    }
+}
+
+/// Sets the scheduling policy and its parameters. Only the superuser may
+/// set the real-time and deadline policies, which can starve the others.
+#[cfg(feature = "sched_classes")]
+fn set_sched_attr(task: &AxTaskRef, attr: &sched_attr) -> LinuxResult<()> {
+    use axtask::{SchedAttr, SchedAttrError, SchedPolicy};
+
+    let policy = match attr.sched_policy {
+        SCHED_NORMAL | SCHED_BATCH | SCHED_IDLE => SchedPolicy::Normal,
+        SCHED_FIFO => SchedPolicy::Fifo,
+        SCHED_RR => SchedPolicy::RoundRobin,
+        SCHED_DEADLINE => SchedPolicy::Deadline,
+        _ => return Err(LinuxError::EINVAL),
+    };
+    if policy != SchedPolicy::Normal {
+        check_privileged()?;
+    }
+    let period = if attr.sched_period == 0 {
+        attr.sched_deadline
+    } else {
+        attr.sched_period
+    };
+    let new = SchedAttr {
+        policy,
+        rt_priority: attr.sched_priority,
+        runtime: attr.sched_runtime,
+        deadline: attr.sched_deadline,
+        period,
+    };
+    axtask::set_sched_attr(task, new).map_err(|err| match err {
+        SchedAttrError::Invalid => LinuxError::EINVAL,
+        SchedAttrError::Busy => LinuxError::EBUSY,
+    })
+}
+
+#[cfg(not(feature = "sched_classes"))]
+fn set_sched_attr(_task: &AxTaskRef, attr: &sched_attr) -> LinuxResult<()> {
+    match attr.sched_policy {
+        SCHED_NORMAL | SCHED_BATCH | SCHED_IDLE if attr.sched_priority == 0 => Ok(()),
+        _ => Err(LinuxError::EINVAL),
+    }
+}
+
+pub fn sys_sched_setscheduler(
+    pid: Pid,
+    policy: u32,
+    param: UserConstPtr<sched_param>,
+) -> LinuxResult<isize> {
+    let param = param.get_as_ref()?;
+    if policy == SCHED_DEADLINE || param.sched_priority < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let task = find_task(pid)?;
+    debug!(
+        "sys_sched_setscheduler <= task: {}, policy: {}, priority: {}",
+        task.id_name(),
+        policy,
+        param.sched_priority
+    );
+    set_sched_attr(
+        &task,
+        &sched_attr {
+            sched_policy: policy,
+            sched_priority: param.sched_priority as _,
+            ..Default::default()
+        },
+    )?;
+    Ok(0)
+}
+
+pub fn sys_sched_getscheduler(pid: Pid) -> LinuxResult<isize> {
+    Ok(get_sched_attr(&find_task(pid)?).sched_policy as _)
+}
+
+pub fn sys_sched_setparam(pid: Pid, param: UserConstPtr<sched_param>) -> LinuxResult<isize> {
+    let param = param.get_as_ref()?;
+    if param.sched_priority < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let task = find_task(pid)?;
+    let mut attr = get_sched_attr(&task);
+    attr.sched_priority = param.sched_priority as _;
+    set_sched_attr(&task, &attr)?;
+    Ok(0)
+}
+
+pub fn sys_sched_getparam(pid: Pid, param: UserPtr<sched_param>) -> LinuxResult<isize> {
+    let attr = get_sched_attr(&find_task(pid)?);
+    *param.get_as_mut()? = sched_param {
+        sched_priority: attr.sched_priority as _,
+    };
+    Ok(0)
+}
+
+pub fn sys_sched_setattr(
+    pid: Pid,
+    attr: UserConstPtr<sched_attr>,
+    flags: u32,
+) -> LinuxResult<isize> {
+    if flags != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let attr = attr.get_as_ref()?;
+    if attr.size != 0 && attr.size < SCHED_ATTR_SIZE_VER0 {
+        return Err(LinuxError::E2BIG);
+    }
+    let task = find_task(pid)?;
+    debug!(
+        "sys_sched_setattr <= task: {}, attr: {:?}",
+        task.id_name(),
+        attr
+    );
+    let normal = matches!(attr.sched_policy, SCHED_NORMAL | SCHED_BATCH | SCHED_IDLE);
+    if normal {
+        if !(-20..=19).contains(&attr.sched_nice) {
+            return Err(LinuxError::EINVAL);
+        }
+        // Only the superuser may lower the nice value.
+        if (attr.sched_nice as isize) < task.base_priority() {
+            check_privileged()?;
+        }
+    }
+    set_sched_attr(&task, attr)?;
+    if normal {
+        axtask::set_task_priority(&task, attr.sched_nice as _);
+    }
+    Ok(0)
+}
+
+pub fn sys_sched_getattr(
+    pid: Pid,
+    attr: UserPtr<sched_attr>,
+    size: u32,
+    flags: u32,
+) -> LinuxResult<isize> {
+    if flags != 0 || size < SCHED_ATTR_SIZE_VER0 {
+        return Err(LinuxError::EINVAL);
+    }
+    *attr.get_as_mut()? = get_sched_attr(&find_task(pid)?);
+    Ok(0)
+}
+
+pub fn sys_sched_get_priority_max(policy: u32) -> LinuxResult<isize> {
+    match policy {
+        SCHED_FIFO | SCHED_RR => Ok(99),
+        SCHED_NORMAL | SCHED_BATCH | SCHED_IDLE | SCHED_DEADLINE => Ok(0),
+        _ => Err(LinuxError::EINVAL),
+    }
+}
+
+pub fn sys_sched_get_priority_min(policy: u32) -> LinuxResult<isize> {
+    match policy {
+        SCHED_FIFO | SCHED_RR => Ok(1),
+        SCHED_NORMAL | SCHED_BATCH | SCHED_IDLE | SCHED_DEADLINE => Ok(0),
+        _ => Err(LinuxError::EINVAL),
+    }
+}
@@ -15,6 +15,8 @@ fn check_region(start: VirtAddr, layout: Layout, access_flags: MappingFlags) ->
     let task = current();
     let mut aspace = task.task_ext().process_data().aspace.lock();
 
+    // The region may be on the stack, below its current bottom.
+    aspace.grow_stack(start);
     if !aspace.check_region_access(
         VirtAddrRange::from_start_size(start, layout.size()),
         access_flags,
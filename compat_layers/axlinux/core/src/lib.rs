@@ -10,6 +10,9 @@ extern crate alloc;
 
 pub mod futex;
 pub mod mm;
+pub mod procfs;
+pub mod shm;
+pub mod shmfs;
 pub mod task;
 pub mod cred;
 mod time;
@@ -1,6 +1,6 @@
 //! User address space management.
 
-use core::ffi::CStr;
+use core::{ffi::CStr, time::Duration};
 
 use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
 use axerrno::{AxError, AxResult};
@@ -8,11 +8,14 @@ use axhal::{
     mem::virt_to_phys,
     paging::{MappingFlags, PageSize},
 };
-use axmm::{AddrSpace, kernel_aspace};
+use axmm::{AddrSpace, SwapAreaId, kernel_aspace};
+use axprocess::Process;
 use kernel_elf_parser::{AuxvEntry, ELFParser, app_stack_region};
-use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr};
 use xmas_elf::{ElfFile, program::SegmentData};
 
+use crate::task::{ProcessData, processes};
+
 /// Creates a new empty user address space.
 pub fn new_user_aspace_empty() -> AxResult<AddrSpace> {
     AddrSpace::new_empty(
@@ -181,12 +184,11 @@ pub fn load_user_app(
     );
 
     let stack_data = app_stack_region(args, envs, &mut auxv, ustack_start, ustack_size);
-    uspace.map_alloc(
-        ustack_start,
+    // The stack grows down on faults below it, up to `RLIMIT_STACK`.
+    uspace.map_stack(
+        ustack_end,
         ustack_size,
         MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER,
-        true,
-        PageSize::Size4K,
     )?;
 
     let heap_start = VirtAddr::from_usize(axconfig::plat::USER_HEAP_BASE);
@@ -224,3 +226,92 @@ pub fn access_user_memory<R>(f: impl FnOnce() -> R) -> R {
 pub fn is_accessing_user_memory() -> bool {
     ACCESSING_USER_MEM.read_current()
 }
+
+/// Runs `f` on the address space of every process.
+fn for_each_aspace(mut f: impl FnMut(&Process, &mut AddrSpace)) {
+    for proc in processes() {
+        if let Some(data) = proc.data::<ProcessData>() {
+            f(&proc, &mut data.aspace.lock());
+        }
+    }
+}
+
+/// Adds hotplugged physical memory `[paddr, paddr + size)` for allocation,
+/// and copies its kernel mapping into the address space of every process.
+pub fn add_memory(paddr: PhysAddr, size: usize) -> AxResult {
+    axmm::add_memory(paddr, size)?;
+    let mut result = Ok(());
+    for_each_aspace(|_, aspace| {
+        if let Err(err) = copy_from_kernel(aspace) {
+            result = Err(err);
+        }
+    });
+    result
+}
+
+/// Spawns a kernel task that periodically collapses the populated anonymous
+/// memory of all processes into transparent huge pages, like Linux's
+/// `khugepaged`.
+///
+/// Not available with `smp`: the old pages are freed after a flush of the
+/// local TLB only, while threads on other CPUs may still write to them.
+/// TODO: flush the TLBs of other CPUs, once IPIs are supported.
+#[cfg(not(feature = "smp"))]
+pub fn spawn_khugepaged() {
+    const SCAN_INTERVAL: Duration = Duration::from_secs(10);
+    axtask::spawn_raw(
+        || loop {
+            axtask::sleep(SCAN_INTERVAL);
+            for_each_aspace(|proc, aspace| {
+                let collapsed = aspace.collapse_huge_pages();
+                if collapsed > 0 {
+                    debug!(
+                        "khugepaged: {} huge pages in process {}",
+                        collapsed,
+                        proc.pid()
+                    );
+                }
+            });
+        },
+        "khugepaged".into(),
+        axconfig::TASK_STACK_SIZE,
+    );
+}
+
+/// Spawns a kernel task that swaps out pages of all processes while free
+/// memory is low, like Linux's `kswapd`.
+///
+/// Not available with `smp`, for the same reason as [`spawn_khugepaged`]:
+/// pages are only reclaimed by the processes faulting for lack of memory.
+#[cfg(not(feature = "smp"))]
+pub fn spawn_kswapd() {
+    const SCAN_INTERVAL: Duration = Duration::from_millis(100);
+    const RECLAIM_BATCH: usize = 32;
+    axtask::spawn_raw(
+        || loop {
+            axtask::sleep(SCAN_INTERVAL);
+            if !axmm::should_reclaim() {
+                continue;
+            }
+            for_each_aspace(|_, aspace| {
+                if axmm::should_reclaim() {
+                    aspace.reclaim_pages(RECLAIM_BATCH);
+                }
+            });
+        },
+        "kswapd".into(),
+        axconfig::TASK_STACK_SIZE,
+    );
+}
+
+/// Reads back the pages of the swap area `id` into all processes, for
+/// `swapoff`.
+pub fn swap_in_all(id: SwapAreaId) -> AxResult {
+    let mut res = Ok(());
+    for_each_aspace(|_, aspace| {
+        if res.is_ok() {
+            res = aspace.swap_in_area(id);
+        }
+    });
+    res
+}
@@ -0,0 +1,312 @@
+//! The per-process files of procfs.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Write;
+
+use axhal::{paging::MappingFlags, time::NANOS_PER_SEC};
+use axmm::{AddrSpace, MappingInfo, MappingUsage};
+use axprocess::{Process, Thread};
+use axtask::{AxTaskRef, TaskExtRef, TaskStats, current};
+use memory_addr::PAGE_SIZE_4K;
+
+use crate::task::{ProcessData, ThreadData, get_process, processes};
+
+/// The column where `/proc/[pid]/maps` starts the path of a mapping.
+const MAPS_PATH_COLUMN: usize = 73;
+/// The clock ticks per second of the times in `/proc/[pid]/stat`, i.e.
+/// `USER_HZ`.
+const USER_HZ: u64 = 100;
+
+struct ProcessInfoImpl;
+
+impl axfs::ProcessInfo for ProcessInfoImpl {
+    fn pids(&self) -> Vec<u32> {
+        processes().iter().map(|proc| proc.pid()).collect()
+    }
+
+    fn current_pid(&self) -> u32 {
+        current().task_ext().thread.process().pid()
+    }
+
+    fn files(&self) -> &'static [&'static str] {
+        &["maps", "schedstat", "smaps", "stat", "status"]
+    }
+
+    fn read(&self, pid: u32, name: &str) -> Option<String> {
+        let proc = get_process(pid).ok()?;
+        let data = proc.data::<ProcessData>()?;
+        let aspace = data.aspace.lock();
+        Some(match name {
+            "maps" => render_maps(&aspace, false),
+            "schedstat" => render_schedstat(&process_stats(&proc)),
+            "smaps" => render_maps(&aspace, true),
+            "stat" => render_stat(proc.pid(), &proc, data, &aspace, &process_stats(&proc)),
+            "status" => render_status(&proc, data, &aspace),
+            _ => return None,
+        })
+    }
+
+    fn tids(&self, pid: u32) -> Option<Vec<u32>> {
+        let proc = get_process(pid).ok()?;
+        Some(proc.threads().iter().map(|thr| thr.tid()).collect())
+    }
+
+    fn thread_files(&self) -> &'static [&'static str] {
+        &["schedstat", "stat"]
+    }
+
+    fn read_thread(&self, pid: u32, tid: u32, name: &str) -> Option<String> {
+        let proc = get_process(pid).ok()?;
+        let thread = proc.threads().into_iter().find(|thr| thr.tid() == tid)?;
+        let stats = thread_stats(&thread_task(&thread)?);
+        Some(match name {
+            "schedstat" => render_schedstat(&stats),
+            "stat" => {
+                let data = proc.data::<ProcessData>()?;
+                render_stat(tid, &proc, data, &data.aspace.lock(), &stats)
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// Registers the per-process directories of `/proc`.
+pub fn init_procfs() {
+    axfs::set_process_info(&ProcessInfoImpl);
+}
+
+/// Returns the name of the process, i.e. the file name of its executable.
+fn comm(data: &ProcessData) -> String {
+    let exe_path = data.exe_path.read();
+    exe_path.rsplit('/').next().unwrap_or_default().to_string()
+}
+
+/// The CPU time and scheduling statistics of a thread, or of a process.
+#[derive(Default)]
+struct CpuStats {
+    sched: TaskStats,
+    /// The time run in user mode, in nanoseconds.
+    utime: u64,
+    /// The time run in kernel mode, in nanoseconds.
+    stime: u64,
+}
+
+fn thread_task(thread: &Thread) -> Option<AxTaskRef> {
+    thread.data::<ThreadData>()?.task()
+}
+
+fn thread_stats(task: &AxTaskRef) -> CpuStats {
+    let (utime, stime) = task.task_ext().time_stat_output();
+    CpuStats {
+        sched: task.stats(),
+        utime: utime as u64,
+        stime: stime as u64,
+    }
+}
+
+/// Returns the statistics of the process, summed up over its threads;
+/// `last_cpu` is that of the first thread.
+fn process_stats(proc: &Process) -> CpuStats {
+    let threads = proc.threads();
+    let mut stats = threads
+        .iter()
+        .filter_map(|thr| thread_task(thr))
+        .map(|task| thread_stats(&task));
+    let Some(mut total) = stats.next() else {
+        return CpuStats::default();
+    };
+    for stats in stats {
+        total.sched.run_time += stats.sched.run_time;
+        total.sched.wait_time += stats.sched.wait_time;
+        total.sched.nr_switches += stats.sched.nr_switches;
+        total.sched.nr_voluntary_switches += stats.sched.nr_voluntary_switches;
+        total.sched.nr_involuntary_switches += stats.sched.nr_involuntary_switches;
+        total.sched.nr_migrations += stats.sched.nr_migrations;
+        total.utime += stats.utime;
+        total.stime += stats.stime;
+    }
+    total
+}
+
+/// Returns the name shown for a mapping without a file.
+fn special_name(mapping: &MappingInfo) -> Option<&'static str> {
+    if mapping.start.as_usize() == axconfig::plat::USER_HEAP_BASE {
+        Some("[heap]")
+    } else if mapping.grows_down {
+        Some("[stack]")
+    } else {
+        None
+    }
+}
+
+fn render_maps(aspace: &AddrSpace, usage: bool) -> String {
+    let mut out = String::new();
+    for mapping in aspace.mappings() {
+        let flags = mapping.flags;
+        let perm = |flag, c| if flags.contains(flag) { c } else { '-' };
+        let line = format!(
+            "{:08x}-{:08x} {}{}{}{} {:08x} 00:00 0",
+            mapping.start.as_usize(),
+            mapping.end.as_usize(),
+            perm(MappingFlags::READ, 'r'),
+            perm(MappingFlags::WRITE, 'w'),
+            perm(MappingFlags::EXECUTE, 'x'),
+            if mapping.shared { 's' } else { 'p' },
+            mapping.offset,
+        );
+        match mapping.path.as_deref().or(special_name(&mapping)) {
+            Some(name) => writeln!(out, "{:<width$}{}", line, name, width = MAPS_PATH_COLUMN),
+            None => writeln!(out, "{}", line),
+        }
+        .unwrap();
+        if usage && let Some(usage) = aspace.mapping_usage(mapping.start) {
+            render_usage(&mut out, &mapping, &usage);
+        }
+    }
+    out
+}
+
+fn render_usage(out: &mut String, mapping: &MappingInfo, usage: &MappingUsage) {
+    // Dirtiness is not tracked: file pages are reported clean, and anonymous
+    // and shared memory pages dirty.
+    let (shared_clean, shared_dirty, private_clean, private_dirty) = match mapping.path {
+        Some(_) => (usage.shared, 0, usage.private, 0),
+        None => (0, usage.shared, 0, usage.private),
+    };
+    let fields = [
+        ("Size", mapping.end - mapping.start),
+        ("KernelPageSize", PAGE_SIZE_4K),
+        ("MMUPageSize", usage.page_size),
+        ("Rss", usage.rss),
+        ("Pss", usage.pss),
+        ("Shared_Clean", shared_clean),
+        ("Shared_Dirty", shared_dirty),
+        ("Private_Clean", private_clean),
+        ("Private_Dirty", private_dirty),
+        ("Referenced", usage.rss),
+        ("Anonymous", usage.anonymous),
+        ("AnonHugePages", usage.anon_huge),
+        ("Swap", usage.swap),
+        ("Locked", 0),
+    ];
+    for (name, bytes) in fields {
+        writeln!(out, "{:<16}{:>8} kB", format!("{}:", name), bytes / 1024).unwrap();
+    }
+    let flags = mapping.flags;
+    let mut vm_flags = String::new();
+    for (flag, name) in [
+        (MappingFlags::READ, "rd"),
+        (MappingFlags::WRITE, "wr"),
+        (MappingFlags::EXECUTE, "ex"),
+    ] {
+        if flags.contains(flag) {
+            vm_flags += name;
+            vm_flags.push(' ');
+        }
+    }
+    if mapping.shared {
+        vm_flags += "sh ";
+    }
+    if mapping.grows_down {
+        vm_flags += "gd ";
+    }
+    writeln!(out, "VmFlags: {}", vm_flags).unwrap();
+}
+
+/// Renders `/proc/[pid]/stat`, or `/proc/[pid]/task/[tid]/stat` with the
+/// `id` and `stats` of the thread.
+fn render_stat(
+    id: u32,
+    proc: &Process,
+    data: &ProcessData,
+    aspace: &AddrSpace,
+    stats: &CpuStats,
+) -> String {
+    let ppid = proc.parent().map_or(0, |parent| parent.pid());
+    let group = proc.group();
+    let state = if proc.is_zombie() { 'Z' } else { 'R' };
+    let utime = stats.utime * USER_HZ / NANOS_PER_SEC;
+    let stime = stats.stime * USER_HZ / NANOS_PER_SEC;
+    // Fields 1 to 24, up to `rss`, and 39, `processor`, as described in
+    // proc_pid_stat(5); the rest are not tracked.
+    format!(
+        "{} ({}) {} {} {} {} 0 -1 0 0 0 0 0 {} {} 0 0 20 0 {} 0 0 {} {}{} {}{}\n",
+        id,
+        comm(data),
+        state,
+        ppid,
+        group.pgid(),
+        group.session().sid(),
+        utime,
+        stime,
+        proc.threads().len(),
+        aspace.virtual_size(),
+        aspace.rss().total(),
+        " 0".repeat(38 - 24),
+        stats.sched.last_cpu,
+        " 0".repeat(52 - 39),
+    )
+}
+
+fn render_schedstat(stats: &CpuStats) -> String {
+    let stats = &stats.sched;
+    format!(
+        "{} {} {}\n",
+        stats.run_time, stats.wait_time, stats.nr_switches
+    )
+}
+
+fn render_status(proc: &Process, data: &ProcessData, aspace: &AddrSpace) -> String {
+    let cred = data.cred.lock().clone();
+    let rss = aspace.rss();
+    let pid = proc.pid();
+    let ppid = proc.parent().map_or(0, |parent| parent.pid());
+    let mut out = String::new();
+    writeln!(out, "Name:\t{}", comm(data)).unwrap();
+    writeln!(
+        out,
+        "State:\t{}",
+        if proc.is_zombie() {
+            "Z (zombie)"
+        } else {
+            "R (running)"
+        }
+    )
+    .unwrap();
+    writeln!(out, "Tgid:\t{}", pid).unwrap();
+    writeln!(out, "Pid:\t{}", pid).unwrap();
+    writeln!(out, "PPid:\t{}", ppid).unwrap();
+    writeln!(out, "Uid:\t{0}\t{1}\t{1}\t{1}", cred.uid, cred.euid).unwrap();
+    writeln!(out, "Gid:\t{0}\t{1}\t{1}\t{1}", cred.gid, cred.egid).unwrap();
+    let pages = [
+        ("VmSize", aspace.virtual_size() / PAGE_SIZE_4K),
+        ("VmHWM", aspace.rss_peak()),
+        ("VmRSS", rss.total()),
+        ("RssAnon", rss.anon),
+        ("RssFile", rss.file),
+        ("RssShmem", rss.shmem),
+        ("VmSwap", aspace.swapped_pages()),
+    ];
+    for (name, pages) in pages {
+        writeln!(out, "{}:\t{:>8} kB", name, pages * PAGE_SIZE_4K / 1024).unwrap();
+    }
+    writeln!(out, "Threads:\t{}", proc.threads().len()).unwrap();
+    let stats = process_stats(proc).sched;
+    writeln!(
+        out,
+        "voluntary_ctxt_switches:\t{}",
+        stats.nr_voluntary_switches
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "nonvoluntary_ctxt_switches:\t{}",
+        stats.nr_involuntary_switches
+    )
+    .unwrap();
+    out
+}
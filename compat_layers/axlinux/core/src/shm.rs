@@ -0,0 +1,309 @@
+//! System V shared memory segments.
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use axerrno::{LinuxError, LinuxResult};
+use axhal::paging::PageSize;
+use axmm::SharedPages;
+use axprocess::Pid;
+use axsync::Mutex;
+use spin::Mutex as SpinMutex;
+
+use crate::cred::Credentials;
+
+/// The key that always creates a new segment.
+pub const IPC_PRIVATE: i32 = 0;
+/// Create the segment if the key does not exist.
+pub const IPC_CREAT: u32 = 0o1000;
+/// Fail if the key exists.
+pub const IPC_EXCL: u32 = 0o2000;
+
+/// Minimum size of a segment in bytes.
+pub const SHMMIN: usize = 1;
+/// Maximum size of a segment in bytes.
+pub const SHMMAX: usize = 1 << 30;
+/// Maximum number of segments.
+pub const SHMMNI: usize = 4096;
+
+/// Ownership and permissions of a segment, the kernel side of `ipc_perm`.
+#[derive(Debug, Clone, Copy)]
+pub struct ShmPerm {
+    pub uid: u32,
+    pub gid: u32,
+    pub cuid: u32,
+    pub cgid: u32,
+    /// The lower 9 bits of the `shmflg` at creation.
+    pub mode: u32,
+}
+
+impl ShmPerm {
+    /// Checks whether `cred` may read (and `write`) the segment.
+    pub fn check(&self, cred: &Credentials, write: bool) -> LinuxResult {
+        if cred.euid == 0 {
+            return Ok(());
+        }
+        let bits = if cred.euid == self.uid || cred.euid == self.cuid {
+            self.mode >> 6
+        } else if cred.egid == self.gid || cred.egid == self.cgid {
+            self.mode >> 3
+        } else {
+            self.mode
+        };
+        let need = if write { 0o6 } else { 0o4 };
+        if bits & need == need {
+            Ok(())
+        } else {
+            Err(LinuxError::EACCES)
+        }
+    }
+
+    /// Checks whether `cred` owns the segment, i.e., may change or remove it.
+    pub fn check_owner(&self, cred: &Credentials) -> LinuxResult {
+        if cred.euid == 0 || cred.euid == self.uid || cred.euid == self.cuid {
+            Ok(())
+        } else {
+            Err(LinuxError::EPERM)
+        }
+    }
+}
+
+/// A System V shared memory segment.
+pub struct ShmSegment {
+    /// The key the segment was created with, [`IPC_PRIVATE`] once removed.
+    pub key: i32,
+    /// The size requested at creation.
+    pub size: usize,
+    /// The frames of the segment, shared by all its attachments.
+    pub pages: Arc<SharedPages>,
+    pub perm: ShmPerm,
+    /// Creator PID.
+    pub cpid: Pid,
+    /// PID of the last `shmat`/`shmdt`.
+    pub lpid: Pid,
+    /// Number of current attachments.
+    pub nattch: usize,
+    /// Time of the last `shmat`, in seconds.
+    pub atime: u64,
+    /// Time of the last `shmdt`, in seconds.
+    pub dtime: u64,
+    /// Time of the creation or the last `IPC_SET`, in seconds.
+    pub ctime: u64,
+    /// Marked for destruction by `IPC_RMID`.
+    pub removed: bool,
+}
+
+/// All System V shared memory segments of the system.
+///
+/// A segment stays in the table until it is removed with `IPC_RMID` and
+/// the last attachment goes away. Its frames live until the last mapping
+/// of them is unmapped.
+pub struct ShmTable {
+    /// Maps keys to segment IDs, private and removed segments are not here.
+    keys: BTreeMap<i32, i32>,
+    segments: BTreeMap<i32, ShmSegment>,
+    /// The next sequence number, makes IDs of reused slots differ.
+    next_id: i32,
+}
+
+static SHM_TABLE: SpinMutex<ShmTable> = SpinMutex::new(ShmTable {
+    keys: BTreeMap::new(),
+    segments: BTreeMap::new(),
+    next_id: 0,
+});
+
+fn now_secs() -> u64 {
+    axhal::time::wall_time().as_secs()
+}
+
+impl ShmTable {
+    /// Runs `f` with the global segment table locked.
+    pub fn with<R>(f: impl FnOnce(&mut ShmTable) -> R) -> R {
+        f(&mut SHM_TABLE.lock())
+    }
+
+    /// Returns the ID of the segment of `key`, creating it as `shmget` does.
+    pub fn get_or_create(
+        &mut self,
+        key: i32,
+        size: usize,
+        shmflg: u32,
+        pid: Pid,
+        cred: &Credentials,
+    ) -> LinuxResult<i32> {
+        if key != IPC_PRIVATE {
+            if let Some(&id) = self.keys.get(&key) {
+                if shmflg & (IPC_CREAT | IPC_EXCL) == IPC_CREAT | IPC_EXCL {
+                    return Err(LinuxError::EEXIST);
+                }
+                let seg = &self.segments[&id];
+                if size > seg.size {
+                    return Err(LinuxError::EINVAL);
+                }
+                seg.perm.check(cred, false)?;
+                return Ok(id);
+            }
+            if shmflg & IPC_CREAT == 0 {
+                return Err(LinuxError::ENOENT);
+            }
+        }
+
+        if !(SHMMIN..=SHMMAX).contains(&size) {
+            return Err(LinuxError::EINVAL);
+        }
+        if self.segments.len() >= SHMMNI {
+            return Err(LinuxError::ENOSPC);
+        }
+        let id = self.alloc_id();
+        self.segments.insert(
+            id,
+            ShmSegment {
+                key,
+                size,
+                pages: SharedPages::new(size, PageSize::Size4K),
+                perm: ShmPerm {
+                    uid: cred.euid,
+                    gid: cred.egid,
+                    cuid: cred.euid,
+                    cgid: cred.egid,
+                    mode: shmflg & 0o777,
+                },
+                cpid: pid,
+                lpid: 0,
+                nattch: 0,
+                atime: 0,
+                dtime: 0,
+                ctime: now_secs(),
+                removed: false,
+            },
+        );
+        if key != IPC_PRIVATE {
+            self.keys.insert(key, id);
+        }
+        Ok(id)
+    }
+
+    fn alloc_id(&mut self) -> i32 {
+        loop {
+            let id = self.next_id;
+            self.next_id = self.next_id.checked_add(1).unwrap_or(0);
+            if !self.segments.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// Returns the segment `id`.
+    pub fn get(&self, id: i32) -> LinuxResult<&ShmSegment> {
+        self.segments.get(&id).ok_or(LinuxError::EINVAL)
+    }
+
+    /// Returns the segment `id` for modification.
+    pub fn get_mut(&mut self, id: i32) -> LinuxResult<&mut ShmSegment> {
+        self.segments.get_mut(&id).ok_or(LinuxError::EINVAL)
+    }
+
+    /// Records a new attachment of the segment `id` by `pid`.
+    pub fn attach(&mut self, id: i32, pid: Pid) -> LinuxResult<&ShmSegment> {
+        let seg = self.get_mut(id)?;
+        seg.nattch += 1;
+        seg.lpid = pid;
+        seg.atime = now_secs();
+        Ok(seg)
+    }
+
+    /// Records that the segment `id` was detached, by `pid` if it is known,
+    /// destroying it if it was removed and this was the last attachment.
+    pub fn detach(&mut self, id: i32, pid: Option<Pid>) {
+        let Ok(seg) = self.get_mut(id) else {
+            return;
+        };
+        seg.nattch = seg.nattch.saturating_sub(1);
+        if let Some(pid) = pid {
+            seg.lpid = pid;
+        }
+        seg.dtime = now_secs();
+        if seg.removed && seg.nattch == 0 {
+            self.segments.remove(&id);
+        }
+    }
+
+    /// Marks the segment `id` for destruction, as `IPC_RMID` does.
+    ///
+    /// The key is released at once, so that `shmget` creates a new segment
+    /// for it, while attached processes keep using the old one.
+    pub fn remove(&mut self, id: i32) -> LinuxResult {
+        let seg = self.get_mut(id)?;
+        seg.removed = true;
+        let key = core::mem::replace(&mut seg.key, IPC_PRIVATE);
+        let nattch = seg.nattch;
+        if key != IPC_PRIVATE {
+            self.keys.remove(&key);
+        }
+        if nattch == 0 {
+            self.segments.remove(&id);
+        }
+        Ok(())
+    }
+}
+
+/// The segments attached by a process, keyed by the attach address.
+///
+/// Attachments are inherited by children on fork, and detached when the
+/// process goes away.
+#[derive(Default)]
+pub struct ShmAttachments(Mutex<BTreeMap<usize, (i32, usize)>>);
+
+impl ShmAttachments {
+    /// Creates an empty attachment list.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copies the attachments of a parent process into its child.
+    pub fn fork_from(parent: &Self) -> Self {
+        let map = parent.0.lock().clone();
+        ShmTable::with(|table| {
+            for &(id, _) in map.values() {
+                if let Ok(seg) = table.get_mut(id) {
+                    seg.nattch += 1;
+                }
+            }
+        });
+        Self(Mutex::new(map))
+    }
+
+    /// Records that the segment `id` is attached at `addr` with `size` bytes.
+    pub fn insert(&self, addr: usize, id: i32, size: usize) {
+        self.0.lock().insert(addr, (id, size));
+    }
+
+    /// Returns the segment attached at `addr` and the size of the
+    /// attachment.
+    pub fn get(&self, addr: usize) -> Option<(i32, usize)> {
+        self.0.lock().get(&addr).copied()
+    }
+
+    /// Detaches the segment attached at `addr` by `pid`, returning the size
+    /// of the attachment.
+    pub fn remove(&self, addr: usize, pid: Pid) -> Option<usize> {
+        let (id, size) = self.0.lock().remove(&addr)?;
+        ShmTable::with(|table| table.detach(id, Some(pid)));
+        Some(size)
+    }
+
+    /// Detaches all segments, e.g., on `execve` when the address space is
+    /// replaced.
+    pub fn clear(&self) {
+        let map = core::mem::take(&mut *self.0.lock());
+        ShmTable::with(|table| {
+            for (id, _) in map.into_values() {
+                table.detach(id, None);
+            }
+        });
+    }
+}
+
+impl Drop for ShmAttachments {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
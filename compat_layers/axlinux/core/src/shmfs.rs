@@ -0,0 +1,229 @@
+//! The filesystem of `/dev/shm`, where POSIX shared memory objects
+//! (`shm_open`) live.
+//!
+//! The contents of each file are kept in a [`SharedPages`] object, so that
+//! shared mappings of the file map the very frames that `read` and `write`
+//! access.
+
+use alloc::{
+    collections::btree_map::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+};
+use core::any::Any;
+
+use axfs_vfs::{
+    VfsDirEntry, VfsError, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef, VfsNodeType, VfsOps,
+    VfsResult,
+};
+use axhal::paging::PageSize;
+use axmm::SharedPages;
+use spin::Mutex as SpinMutex;
+
+/// The filesystem mounted on `/dev/shm`.
+pub struct ShmFileSystem {
+    root: Arc<ShmDir>,
+}
+
+impl ShmFileSystem {
+    /// Creates an empty filesystem.
+    pub fn new() -> Self {
+        Self {
+            root: Arc::new(ShmDir {
+                parent: SpinMutex::new(None),
+                files: SpinMutex::new(BTreeMap::new()),
+            }),
+        }
+    }
+}
+
+impl Default for ShmFileSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VfsOps for ShmFileSystem {
+    fn mount(&self, _path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        *self.root.parent.lock() = mount_point.parent().as_ref().map(Arc::downgrade);
+        Ok(())
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.root.clone()
+    }
+}
+
+/// The root directory, the only one, as shared memory objects have flat
+/// names.
+struct ShmDir {
+    parent: SpinMutex<Option<Weak<dyn VfsNodeOps>>>,
+    files: SpinMutex<BTreeMap<String, Arc<ShmFile>>>,
+}
+
+impl ShmDir {
+    /// Returns the name of the file at `path`, relative to the directory.
+    fn file_name(path: &str) -> VfsResult<&str> {
+        let path = path.trim_matches('/');
+        let name = path.strip_prefix("./").unwrap_or(path);
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(VfsError::InvalidInput);
+        }
+        if name.contains('/') {
+            return Err(VfsError::NotFound);
+        }
+        Ok(name)
+    }
+}
+
+impl VfsNodeOps for ShmDir {
+    axfs_vfs::impl_vfs_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(0o777),
+            VfsNodeType::Dir,
+            0,
+            0,
+        ))
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        self.parent.lock().as_ref().and_then(Weak::upgrade)
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let path = path.trim_matches('/');
+        if path.is_empty() || path == "." {
+            return Ok(self);
+        }
+        if let Some(rest) = path.strip_prefix("./") {
+            return self.lookup(rest);
+        }
+        let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+        if name == ".." {
+            return self.parent().ok_or(VfsError::NotFound)?.lookup(rest);
+        }
+        let file = self
+            .files
+            .lock()
+            .get(name)
+            .cloned()
+            .ok_or(VfsError::NotFound)?;
+        if !rest.is_empty() {
+            return Err(VfsError::NotADirectory);
+        }
+        Ok(file)
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        if ty != VfsNodeType::File {
+            return Err(VfsError::Unsupported);
+        }
+        let name = Self::file_name(path)?;
+        let mut files = self.files.lock();
+        if files.contains_key(name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        files.insert(name.into(), Arc::new(ShmFile::new()));
+        Ok(())
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        let name = Self::file_name(path)?;
+        self.files
+            .lock()
+            .remove(name)
+            .map(|_| ())
+            .ok_or(VfsError::NotFound)
+    }
+
+    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
+        let (src, dst) = (Self::file_name(src_path)?, Self::file_name(dst_path)?);
+        let mut files = self.files.lock();
+        let file = files.remove(src).ok_or(VfsError::NotFound)?;
+        files.insert(dst.into(), file);
+        Ok(())
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let files = self.files.lock();
+        let entries = [(".", VfsNodeType::Dir), ("..", VfsNodeType::Dir)]
+            .into_iter()
+            .chain(files.keys().map(|name| (name.as_str(), VfsNodeType::File)))
+            .skip(start_idx);
+        let mut n = 0;
+        for ((name, ty), dirent) in entries.zip(dirents.iter_mut()) {
+            *dirent = VfsDirEntry::new(name, ty);
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+/// A shared memory object.
+pub struct ShmFile {
+    pages: Arc<SharedPages>,
+    /// The size in bytes. That of `pages` is rounded up to whole pages.
+    size: SpinMutex<usize>,
+}
+
+impl ShmFile {
+    fn new() -> Self {
+        Self {
+            pages: SharedPages::new(0, PageSize::Size4K),
+            size: SpinMutex::new(0),
+        }
+    }
+
+    /// The frames of the file, to be mapped by shared mappings of it.
+    pub fn pages(&self) -> &Arc<SharedPages> {
+        &self.pages
+    }
+}
+
+impl VfsNodeOps for ShmFile {
+    axfs_vfs::impl_vfs_non_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let size = *self.size.lock();
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(0o666),
+            VfsNodeType::File,
+            size as u64,
+            size.div_ceil(512) as u64,
+        ))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let size = *self.size.lock();
+        let offset = (offset as usize).min(size);
+        let len = buf.len().min(size - offset);
+        Ok(self.pages.read_at(offset, &mut buf[..len]))
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let mut size = self.size.lock();
+        let written = self.pages.write_at(offset as usize, buf)?;
+        *size = (*size).max(offset as usize + written);
+        Ok(written)
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult {
+        let mut curr = self.size.lock();
+        self.pages.set_size(size as usize);
+        *curr = size as usize;
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Mounts the filesystem of shared memory objects on `/dev/shm`.
+pub fn init_shmfs() {
+    if let Err(e) = axfs::api::mount_fs("/dev/shm", Arc::new(ShmFileSystem::new())) {
+        warn!("failed to mount /dev/shm: {:?}", e);
+    }
+}
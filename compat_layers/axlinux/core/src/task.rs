@@ -2,7 +2,6 @@
 
 use core::{
     alloc::Layout,
-    cell::RefCell,
     sync::atomic::{AtomicUsize, Ordering},
     time::Duration,
 };
@@ -15,7 +14,7 @@ use alloc::{
 use axerrno::{LinuxError, LinuxResult};
 use axhal::{
     arch::UspaceContext,
-    time::{NANOS_PER_MICROS, NANOS_PER_SEC, monotonic_time_nanos},
+    time::{NANOS_PER_MICROS, NANOS_PER_SEC},
 };
 use axmm::{AddrSpace, kernel_aspace};
 use axns::{AxNamespace, AxNamespaceIf};
@@ -25,14 +24,14 @@ use axsignal::{
     api::{ProcessSignalManager, SignalActions, ThreadSignalManager},
 };
 use axsync::{Mutex, RawMutex};
-use axtask::{TaskExtRef, TaskInner, WaitQueue, current};
+use axtask::{AxTaskRef, TaskExtRef, TaskInner, WaitQueue, WeakAxTaskRef, current};
 use memory_addr::VirtAddrRange;
 use spin::{Once, RwLock};
 use weak_map::WeakMap;
 
 use axlog::info;
 
-use crate::{futex::FutexTable, time::TimeStat};
+use crate::{futex::FutexTable, shm::ShmAttachments, time::TimeStat};
 use crate::cred::Credentials;
 
 /// Create a new user task.
@@ -48,6 +47,7 @@ pub fn new_user_task(
                 *tid = curr.id().as_u64() as Pid;
             }
 
+            time_stat_from_kernel_to_user();
             let kstack_top = curr.kernel_stack_top().unwrap();
             info!(
                 "Enter user space: entry={:#x}, ustack={:#x}, kstack={:#x}",
@@ -64,8 +64,9 @@ pub fn new_user_task(
 
 /// Task extended data for the monolithic kernel.
 pub struct TaskExt {
-    /// The time statistics
-    pub time: RefCell<TimeStat>,
+    /// The time statistics, in the CPU time of the task, so that the time
+    /// it waits in either mode is not counted
+    pub time: spin::Mutex<TimeStat>,
     /// The thread
     pub thread: Arc<Thread>,
 }
@@ -74,21 +75,23 @@ impl TaskExt {
     /// Create a new [`TaskExt`].
     pub fn new(thread: Arc<Thread>) -> Self {
         Self {
-            time: RefCell::new(TimeStat::new()),
+            time: spin::Mutex::new(TimeStat::new()),
             thread,
         }
     }
 
-    pub(crate) fn time_stat_from_kernel_to_user(&self, current_tick: usize) {
-        self.time.borrow_mut().switch_into_user_mode(current_tick);
+    pub(crate) fn time_stat_from_kernel_to_user(&self, cpu_time: usize) {
+        self.time.lock().switch_into_user_mode(cpu_time);
     }
 
-    pub(crate) fn time_stat_from_user_to_kernel(&self, current_tick: usize) {
-        self.time.borrow_mut().switch_into_kernel_mode(current_tick);
+    pub(crate) fn time_stat_from_user_to_kernel(&self, cpu_time: usize) {
+        self.time.lock().switch_into_kernel_mode(cpu_time);
     }
 
+    /// Returns the time run in user mode and in kernel mode, in nanoseconds,
+    /// up to the last switch between them.
     pub(crate) fn time_stat_output(&self) -> (usize, usize) {
-        self.time.borrow().output()
+        self.time.lock().output()
     }
 
     /// Get the [`ThreadData`] associated with this task.
@@ -109,7 +112,7 @@ pub fn time_stat_from_kernel_to_user() {
     let curr_task = current();
     curr_task
         .task_ext()
-        .time_stat_from_kernel_to_user(monotonic_time_nanos() as usize);
+        .time_stat_from_kernel_to_user(curr_task.stats().run_time as usize);
 }
 
 /// Update the time statistics to reflect a switch from user mode to kernel mode.
@@ -117,7 +120,7 @@ pub fn time_stat_from_user_to_kernel() {
     let curr_task = current();
     curr_task
         .task_ext()
-        .time_stat_from_user_to_kernel(monotonic_time_nanos() as usize);
+        .time_stat_from_user_to_kernel(curr_task.stats().run_time as usize);
 }
 
 /// Get the time statistics for the current task.
@@ -165,6 +168,9 @@ pub struct ThreadData {
 
     /// The thread-level signal manager
     pub signal: ThreadSignalManager<RawMutex, WaitQueueWrapper>,
+
+    /// The task running the thread
+    task: Once<WeakAxTaskRef>,
 }
 
 impl ThreadData {
@@ -175,9 +181,21 @@ impl ThreadData {
             clear_child_tid: AtomicUsize::new(0),
 
             signal: ThreadSignalManager::new(proc.signal.clone()),
+
+            task: Once::new(),
         }
     }
 
+    /// Set the task running the thread, once it is spawned.
+    pub fn set_task(&self, task: &AxTaskRef) {
+        self.task.call_once(|| Arc::downgrade(task));
+    }
+
+    /// Get the task running the thread, if it has not been dropped.
+    pub fn task(&self) -> Option<AxTaskRef> {
+        self.task.get()?.upgrade()
+    }
+
     /// Get the clear child tid field.
     pub fn clear_child_tid(&self) -> usize {
         self.clear_child_tid.load(Ordering::Relaxed)
@@ -214,6 +232,9 @@ pub struct ProcessData {
     /// The futex table.
     pub futex_table: FutexTable,
 
+    /// The System V shared memory segments attached by the process.
+    pub shm: ShmAttachments,
+
     /// Process user and group credentials.
 	pub cred: Mutex<Credentials>,
 
@@ -247,6 +268,8 @@ impl ProcessData {
 
             futex_table: FutexTable::new(),
 
+            shm: ShmAttachments::new(),
+
             // 添加 cred 字段的初始化
 		    cred: Mutex::new(Credentials::default()),
 
@@ -286,6 +309,9 @@ impl ProcessData {
 
             // 子进程有自己独立的 Futex 表
             futex_table: FutexTable::new(), // 假设 FutexTable 实现了 Default    
+
+            // 子进程继承父进程挂接的共享内存段
+            shm: ShmAttachments::fork_from(&parent.shm),
             
             // 【核心修改】克隆父进程的用户凭证
             cred: Mutex::new(parent.cred.lock().clone()),
@@ -65,7 +65,7 @@ impl TimeStat {
 
     pub fn switch_into_kernel_mode(&mut self, current_timestamp: usize) {
         let now_time_ns = current_timestamp;
-        let delta = now_time_ns - self.kernel_timestamp;
+        let delta = now_time_ns - self.user_timestamp;
         self.utime_ns += delta;
         self.kernel_timestamp = now_time_ns;
         if self.timer_type != TimerType::NONE {
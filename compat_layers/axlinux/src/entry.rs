@@ -4,6 +4,7 @@ use axhal::arch::UspaceContext;
 use axprocess::{Pid, init_proc};
 use axsignal::Signo;
 use axsync::Mutex;
+use axtask::TaskExtRef;
 use starry_api::file::FD_TABLE;
 use starry_core::{
     mm::{copy_from_kernel, load_user_app, map_trampoline, new_user_aspace_empty},
@@ -60,6 +61,7 @@ pub fn run_user_app(args: &[String], envs: &[String]) -> Option<i32> {
     task.init_task_ext(TaskExt::new(thread));
 
     let task = axtask::spawn_task(task);
+    task.task_ext().thread_data().set_task(&task);
 
     // TODO: we need a way to wait on the process but not only the main task
     task.join()
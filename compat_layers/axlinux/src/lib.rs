@@ -39,6 +39,12 @@ mod syscall;
 pub fn init() -> ! {
     axprocess::Process::new_init(axtask::current().id().as_u64() as _).build();
     info!("[axlinux] init process structure created.");
+    #[cfg(not(feature = "smp"))]
+    starry_core::mm::spawn_khugepaged();
+    #[cfg(not(feature = "smp"))]
+    starry_core::mm::spawn_kswapd();
+    starry_core::procfs::init_procfs();
+    starry_core::shmfs::init_shmfs();
 
     #[cfg(not(feature = "normal_mode"))]
     run_tests();
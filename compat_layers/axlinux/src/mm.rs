@@ -3,9 +3,10 @@ use axhal::{
     paging::MappingFlags,
     trap::{PAGE_FAULT, register_trap_handler},
 };
+use axsignal::{SignalAction, SignalDisposition, SignalInfo, Signo};
 use axtask::{TaskExtRef, current};
-use linux_raw_sys::general::SIGSEGV;
-use starry_api::do_exit;
+use linux_raw_sys::general::{SEGV_MAPERR, SIGSEGV};
+use starry_api::{do_exit, signal::send_signal_thread};
 use starry_core::mm::is_accessing_user_memory;
 
 #[register_trap_handler(PAGE_FAULT)]
@@ -27,12 +28,44 @@ fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags, is_user: bool)
         .handle_page_fault(vaddr, access_flags)
     {
         warn!(
-            "{} ({:?}): segmentation fault at {:#x}, exit!",
+            "{} ({:?}): segmentation fault at {:#x}",
             curr.id_name(),
             curr.task_ext().thread,
             vaddr
         );
-        do_exit(SIGSEGV as _, true);
+        if !is_user {
+            // The kernel cannot go on with the access, e.g., to set up a
+            // signal frame on an overflowed stack.
+            do_exit(SIGSEGV as _, true);
+        }
+        force_sigsegv();
     }
     true
 }
+
+/// Sends `SIGSEGV` to the current thread, to be handled on the way back to
+/// user space, e.g., on an alternate signal stack after a stack overflow.
+///
+/// Like Linux, a blocked or ignored `SIGSEGV` is reset to the default action
+/// so that it kills the process rather than faulting again.
+fn force_sigsegv() {
+    let curr = current();
+    let blocked = curr
+        .task_ext()
+        .thread_data()
+        .signal
+        .with_blocked_mut(|blocked| blocked.remove(Signo::SIGSEGV));
+    {
+        let mut actions = curr.task_ext().process_data().signal.actions.lock();
+        if blocked
+            || matches!(
+                actions[Signo::SIGSEGV].disposition,
+                SignalDisposition::Ignore
+            )
+        {
+            actions[Signo::SIGSEGV] = SignalAction::default();
+        }
+    }
+    let sig = SignalInfo::new(Signo::SIGSEGV, SEGV_MAPERR as _);
+    let _ = send_signal_thread(&curr.task_ext().thread, sig);
+}
@@ -112,6 +112,21 @@ fn handle_syscall(tf: &mut TrapFrame, syscall_num: usize) -> isize {
         ),
         Sysno::munmap => sys_munmap(tf.arg0(), tf.arg1() as _),
         Sysno::mprotect => sys_mprotect(tf.arg0(), tf.arg1() as _, tf.arg2() as _),
+        Sysno::mremap => sys_mremap(
+            tf.arg0(),
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4(),
+        ),
+        Sysno::madvise => sys_madvise(tf.arg0(), tf.arg1() as _, tf.arg2() as _),
+        Sysno::msync => sys_msync(tf.arg0(), tf.arg1() as _, tf.arg2() as _),
+        Sysno::shmget => sys_shmget(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::shmat => sys_shmat(tf.arg0() as _, tf.arg1(), tf.arg2() as _),
+        Sysno::shmdt => sys_shmdt(tf.arg0()),
+        Sysno::shmctl => sys_shmctl(tf.arg0() as _, tf.arg1() as _, tf.arg2()),
+        Sysno::swapon => sys_swapon(tf.arg0().into(), tf.arg1() as _),
+        Sysno::swapoff => sys_swapoff(tf.arg0().into()),
 
         // task info
         Sysno::getpid => sys_getpid(),
@@ -120,6 +135,21 @@ fn handle_syscall(tf: &mut TrapFrame, syscall_num: usize) -> isize {
 
         // task sched
         Sysno::sched_yield => sys_sched_yield(),
+        Sysno::sched_setscheduler => {
+            sys_sched_setscheduler(tf.arg0() as _, tf.arg1() as _, tf.arg2().into())
+        }
+        Sysno::sched_getscheduler => sys_sched_getscheduler(tf.arg0() as _),
+        Sysno::sched_setparam => sys_sched_setparam(tf.arg0() as _, tf.arg1().into()),
+        Sysno::sched_getparam => sys_sched_getparam(tf.arg0() as _, tf.arg1().into()),
+        Sysno::sched_setattr => sys_sched_setattr(tf.arg0() as _, tf.arg1().into(), tf.arg2() as _),
+        Sysno::sched_getattr => sys_sched_getattr(
+            tf.arg0() as _,
+            tf.arg1().into(),
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ),
+        Sysno::sched_get_priority_max => sys_sched_get_priority_max(tf.arg0() as _),
+        Sysno::sched_get_priority_min => sys_sched_get_priority_min(tf.arg0() as _),
         Sysno::nanosleep => sys_nanosleep(tf.arg0().into(), tf.arg1().into()),
         Sysno::clock_nanosleep => sys_clock_nanosleep(
             tf.arg0() as _,
@@ -230,6 +260,7 @@ fn handle_syscall(tf: &mut TrapFrame, syscall_num: usize) -> isize {
         Sysno::chmod => sys_chmod(tf.arg0().into(), tf.arg1() as _),
         Sysno::chown => sys_chown(tf.arg0().into(), tf.arg1() as _, tf.arg2() as _),
         Sysno::fsync => sys_fsync(tf.arg0() as _),
+        Sysno::sync => sys_sync(),
         // Sysno::fdatasync => sys_fdatasync(tf.arg0() as _),
         Sysno::access => sys_faccessat(
             AT_FDCWD,
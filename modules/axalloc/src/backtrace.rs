@@ -0,0 +1,62 @@
+//! Backtraces by walking the frame pointers.
+//!
+//! They are only meaningful if the kernel is built with
+//! `-C force-frame-pointers=yes`.
+
+use core::arch::asm;
+
+/// The largest stack frame followed, to stop at a corrupted frame pointer.
+const MAX_FRAME_SIZE: usize = 0x10_0000;
+
+/// Fills `frames` with the return addresses of the callers, innermost first,
+/// and returns how many were found.
+#[inline(never)]
+pub fn capture(frames: &mut [usize]) -> usize {
+    let mut fp = frame_pointer();
+    let mut depth = 0;
+    while depth < frames.len() && fp != 0 && fp % size_of::<usize>() == 0 {
+        let (ra, next) = unsafe { read_frame(fp) };
+        if ra == 0 {
+            break;
+        }
+        frames[depth] = ra;
+        depth += 1;
+        if next <= fp || next - fp > MAX_FRAME_SIZE {
+            break;
+        }
+        fp = next;
+    }
+    depth
+}
+
+#[inline(always)]
+fn frame_pointer() -> usize {
+    let fp: usize;
+    unsafe {
+        #[cfg(target_arch = "x86_64")]
+        asm!("mov {}, rbp", out(reg) fp);
+        #[cfg(target_arch = "aarch64")]
+        asm!("mov {}, x29", out(reg) fp);
+        #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+        asm!("mv {}, s0", out(reg) fp);
+        #[cfg(target_arch = "loongarch64")]
+        asm!("move {}, $fp", out(reg) fp);
+    }
+    fp
+}
+
+/// Reads the return address and the caller's frame pointer of the frame at
+/// `fp`.
+unsafe fn read_frame(fp: usize) -> (usize, usize) {
+    let fp = fp as *const usize;
+    unsafe {
+        if cfg!(any(target_arch = "x86_64", target_arch = "aarch64")) {
+            // The caller's frame pointer, then the return address.
+            (fp.add(1).read(), fp.read())
+        } else {
+            // Below the frame pointer: the return address, then the caller's
+            // frame pointer.
+            (fp.sub(1).read(), fp.sub(2).read())
+        }
+    }
+}
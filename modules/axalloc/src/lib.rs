@@ -11,17 +11,35 @@
 extern crate log;
 extern crate alloc;
 
+#[cfg(feature = "backtrace")]
+pub mod backtrace;
 mod page;
+#[cfg(feature = "smp")]
+mod pcp;
+mod region;
+mod report;
+#[cfg(feature = "sanitize")]
+mod sanitize;
+#[cfg(feature = "tracking")]
+mod tracking;
 
-use allocator::{AllocResult, BaseAllocator, BitmapPageAllocator, ByteAllocator, PageAllocator};
+use alloc::vec::Vec;
+use allocator::{AllocResult, BaseAllocator, ByteAllocator};
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::NonNull;
 use kspin::SpinNoIrq;
 
+use self::region::RegionPageAllocator;
+
 const PAGE_SIZE: usize = 0x1000;
 const MIN_HEAP_SIZE: usize = 0x8000; // 32 K
 
+pub use allocator::AllocError;
 pub use page::GlobalPage;
+#[cfg(feature = "sanitize")]
+pub use sanitize::{check_heap, on_timer_tick};
+#[cfg(feature = "tracking")]
+pub use tracking::{ClassStat, class_stats, dump_live_allocations};
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "slab")] {
@@ -44,12 +62,14 @@ cfg_if::cfg_if! {
 /// the byte allocator.
 ///
 /// Currently, [`TlsfByteAllocator`] is used as the byte allocator, while
-/// [`BitmapPageAllocator`] is used as the page allocator.
+/// the page allocator keeps a bitmap for each physical memory region, so
+/// that regions with holes between them, and regions added after boot, can
+/// be managed without a limit on the memory size fixed at build time.
 ///
 /// [`TlsfByteAllocator`]: allocator::TlsfByteAllocator
 pub struct GlobalAllocator {
     balloc: SpinNoIrq<DefaultByteAllocator>,
-    palloc: SpinNoIrq<BitmapPageAllocator<PAGE_SIZE>>,
+    palloc: SpinNoIrq<RegionPageAllocator>,
 }
 
 impl GlobalAllocator {
@@ -57,7 +77,7 @@ impl GlobalAllocator {
     pub const fn new() -> Self {
         Self {
             balloc: SpinNoIrq::new(DefaultByteAllocator::new()),
-            palloc: SpinNoIrq::new(BitmapPageAllocator::new()),
+            palloc: SpinNoIrq::new(RegionPageAllocator::new()),
         }
     }
 
@@ -82,8 +102,11 @@ impl GlobalAllocator {
     pub fn init(&self, start_vaddr: usize, size: usize) {
         assert!(size > MIN_HEAP_SIZE);
         let init_heap_size = MIN_HEAP_SIZE;
-        self.palloc.lock().init(start_vaddr, size);
+        unsafe { self.palloc.lock().add_region(start_vaddr, size) }
+            .expect("failed to add the initial memory region");
         let heap_ptr = self
+            .palloc
+            .lock()
             .alloc_pages(init_heap_size / PAGE_SIZE, PAGE_SIZE)
             .unwrap();
         self.balloc.lock().init(heap_ptr, init_heap_size);
@@ -91,9 +114,12 @@ impl GlobalAllocator {
 
     /// Add the given region to the allocator.
     ///
-    /// It will add the whole region to the byte allocator.
+    /// It will add the whole region to the page allocator as a new region,
+    /// whose start holds the bitmap of its pages, so the region must already
+    /// be accessible. Regions can be added at any time, e.g., on memory
+    /// hotplug.
     pub fn add_memory(&self, start_vaddr: usize, size: usize) -> AllocResult {
-        self.balloc.lock().add_memory(start_vaddr, size)
+        unsafe { self.palloc.lock().add_region(start_vaddr, size) }
     }
 
     /// Allocate arbitrary number of bytes. Returns the left bound of the
@@ -102,9 +128,37 @@ impl GlobalAllocator {
     /// It firstly tries to allocate from the byte allocator. If there is no
     /// memory, it asks the page allocator for more memory and adds it to the
     /// byte allocator.
+    ///
+    /// With the `smp` feature, small objects come from a per-CPU cache first.
+    /// With the `tracking` feature, the allocation is recorded with the
+    /// backtrace of its caller. With the `sanitize` feature, it is placed
+    /// between redzones, which are checked when it is freed.
     pub fn alloc(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        #[cfg(feature = "sanitize")]
+        let res = self.alloc_sanitized(layout);
+        #[cfg(not(feature = "sanitize"))]
+        let res = self.alloc_raw(layout);
+        #[cfg(feature = "tracking")]
+        if let Ok(ptr) = res {
+            tracking::record_alloc(ptr.as_ptr() as usize, layout.size());
+        }
+        res
+    }
+
+    fn alloc_raw(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        #[cfg(feature = "smp")]
+        if let Some(class) = pcp::size_class(layout) {
+            return self.alloc_cached(class);
+        }
+        self.alloc_locked(&mut self.balloc.lock(), layout)
+    }
+
+    fn alloc_locked(
+        &self,
+        balloc: &mut DefaultByteAllocator,
+        layout: Layout,
+    ) -> AllocResult<NonNull<u8>> {
         // simple two-level allocator: if no heap memory, allocate from the page allocator.
-        let mut balloc = self.balloc.lock();
         loop {
             if let Ok(ptr) = balloc.alloc(layout) {
                 return Ok(ptr);
@@ -114,7 +168,12 @@ impl GlobalAllocator {
                     .max(layout.size())
                     .next_power_of_two()
                     .max(PAGE_SIZE);
-                let heap_ptr = self.alloc_pages(expand_size / PAGE_SIZE, PAGE_SIZE)?;
+                // Bypass the per-CPU page cache, which may be locked by the
+                // caller.
+                let heap_ptr = self
+                    .palloc
+                    .lock()
+                    .alloc_pages(expand_size / PAGE_SIZE, PAGE_SIZE)?;
                 debug!(
                     "expand heap memory: [{:#x}, {:#x})",
                     heap_ptr,
@@ -133,6 +192,19 @@ impl GlobalAllocator {
     ///
     /// [`alloc`]: GlobalAllocator::alloc
     pub fn dealloc(&self, pos: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "tracking")]
+        tracking::record_dealloc(pos.as_ptr() as usize, layout.size());
+        #[cfg(feature = "sanitize")]
+        self.dealloc_sanitized(pos, layout);
+        #[cfg(not(feature = "sanitize"))]
+        self.dealloc_raw(pos, layout);
+    }
+
+    fn dealloc_raw(&self, pos: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "smp")]
+        if let Some(class) = pcp::size_class(layout) {
+            return self.dealloc_cached(pos, class);
+        }
         self.balloc.lock().dealloc(pos, layout)
     }
 
@@ -142,7 +214,31 @@ impl GlobalAllocator {
     ///
     /// `align_pow2` must be a power of 2, and the returned region bound will be
     /// aligned to it.
+    ///
+    /// With the `smp` feature, single pages come from a per-CPU cache first,
+    /// and the caches of all CPUs are drained when the page allocator runs
+    /// out of memory.
     pub fn alloc_pages(&self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        let res = self.alloc_pages_inner(num_pages, align_pow2);
+        #[cfg(feature = "tracking")]
+        if let Ok(pos) = res {
+            tracking::record_alloc(pos, num_pages * PAGE_SIZE);
+        }
+        res
+    }
+
+    fn alloc_pages_inner(&self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        #[cfg(feature = "smp")]
+        {
+            if num_pages == 1 && align_pow2 == PAGE_SIZE {
+                if let Ok(page) = self.alloc_page_cached() {
+                    return Ok(page);
+                }
+            } else if let Ok(pos) = self.palloc.lock().alloc_pages(num_pages, align_pow2) {
+                return Ok(pos);
+            }
+            self.drain_page_caches();
+        }
         self.palloc.lock().alloc_pages(num_pages, align_pow2)
     }
 
@@ -154,10 +250,18 @@ impl GlobalAllocator {
     ///
     /// [`alloc_pages`]: GlobalAllocator::alloc_pages
     pub fn dealloc_pages(&self, pos: usize, num_pages: usize) {
+        #[cfg(feature = "tracking")]
+        tracking::record_dealloc(pos, num_pages * PAGE_SIZE);
+        #[cfg(feature = "smp")]
+        if num_pages == 1 {
+            return self.dealloc_page_cached(pos);
+        }
         self.palloc.lock().dealloc_pages(pos, num_pages)
     }
 
     /// Returns the number of allocated bytes in the byte allocator.
+    ///
+    /// Objects cached by CPUs count as allocated.
     pub fn used_bytes(&self) -> usize {
         self.balloc.lock().used_bytes()
     }
@@ -169,12 +273,30 @@ impl GlobalAllocator {
 
     /// Returns the number of allocated pages in the page allocator.
     pub fn used_pages(&self) -> usize {
-        self.palloc.lock().used_pages()
+        let used = self.palloc.lock().used_pages();
+        #[cfg(feature = "smp")]
+        let used = used.saturating_sub(self.cached_pages());
+        used
     }
 
-    /// Returns the number of available pages in the page allocator.
+    /// Returns the number of available pages in the page allocator,
+    /// including those cached by CPUs.
     pub fn available_pages(&self) -> usize {
-        self.palloc.lock().available_pages()
+        let available = self.palloc.lock().available_pages();
+        #[cfg(feature = "smp")]
+        let available = available + self.cached_pages();
+        available
+    }
+
+    /// Returns the number of pages managed by the page allocator.
+    pub fn total_pages(&self) -> usize {
+        self.palloc.lock().total_pages()
+    }
+
+    /// Returns the memory regions of the page allocator, as the start virtual
+    /// address and size of the allocatable pages of each.
+    pub fn memory_regions(&self) -> Vec<(usize, usize)> {
+        self.palloc.lock().regions()
     }
 }
 
@@ -183,6 +305,7 @@ unsafe impl GlobalAlloc for GlobalAllocator {
         if let Ok(ptr) = GlobalAllocator::alloc(self, layout) {
             ptr.as_ptr()
         } else {
+            self.report_oom(layout);
             alloc::alloc::handle_alloc_error(layout)
         }
     }
@@ -222,7 +345,8 @@ pub fn global_init(start_vaddr: usize, size: usize) {
 /// Users should ensure that the region is valid and not being used by others,
 /// so that the allocated memory is also valid.
 ///
-/// It's similar to [`global_init`], but can be called multiple times.
+/// It's similar to [`global_init`], but can be called multiple times, also
+/// after boot to add hotplugged memory.
 pub fn global_add_memory(start_vaddr: usize, size: usize) -> AllocResult {
     debug!(
         "add a memory region to global allocator: [{:#x}, {:#x})",
@@ -0,0 +1,181 @@
+//! Per-CPU caches in front of the global pools.
+//!
+//! Each CPU keeps some free single pages, and a magazine of free objects for
+//! each small size class, so that most allocations and frees only take a
+//! lock private to the current CPU. Caches are refilled from, and drained to,
+//! the global pools in batches.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use allocator::AllocResult;
+use kspin::SpinNoIrq;
+
+use crate::{GlobalAllocator, PAGE_SIZE};
+
+/// The number of free pages a CPU keeps at most.
+const PAGE_CACHE_HIGH: usize = 64;
+/// The number of pages moved between a CPU and the global pool at a time.
+const PAGE_CACHE_BATCH: usize = 16;
+
+/// Small objects are rounded up to a size class, a power of two from
+/// `1 << MIN_CLASS_SHIFT` to `1 << MAX_CLASS_SHIFT` bytes.
+const MIN_CLASS_SHIFT: usize = 3;
+const MAX_CLASS_SHIFT: usize = 9;
+const NUM_CLASSES: usize = MAX_CLASS_SHIFT - MIN_CLASS_SHIFT + 1;
+/// The number of free objects a CPU keeps at most for each size class.
+const MAGAZINE_SIZE: usize = 32;
+
+/// A bounded stack of addresses.
+struct Stack<const N: usize> {
+    items: [usize; N],
+    len: usize,
+}
+
+impl<const N: usize> Stack<N> {
+    const fn new() -> Self {
+        Self {
+            items: [0; N],
+            len: 0,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    fn push(&mut self, item: usize) {
+        self.items[self.len] = item;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        self.len = self.len.checked_sub(1)?;
+        Some(self.items[self.len])
+    }
+}
+
+struct CpuCache {
+    pages: Stack<PAGE_CACHE_HIGH>,
+    magazines: [Stack<MAGAZINE_SIZE>; NUM_CLASSES],
+}
+
+impl CpuCache {
+    const fn new() -> Self {
+        Self {
+            pages: Stack::new(),
+            magazines: [const { Stack::new() }; NUM_CLASSES],
+        }
+    }
+}
+
+#[percpu::def_percpu]
+static CPU_CACHE: SpinNoIrq<CpuCache> = SpinNoIrq::new(CpuCache::new());
+
+/// Returns the cache of the current CPU.
+fn this_cpu_cache() -> &'static SpinNoIrq<CpuCache> {
+    // SAFETY: if the task migrates before locking, it just uses the cache of
+    // another CPU, which is locked all the same.
+    unsafe { CPU_CACHE.current_ref_raw() }
+}
+
+/// Returns the size class of `layout`, or `None` if it is not small.
+///
+/// All allocations of a class, cached or not, are made with the layout
+/// returned by [`class_layout`], so that objects can be freed to either.
+pub(crate) fn size_class(layout: Layout) -> Option<usize> {
+    let size = layout
+        .size()
+        .max(layout.align())
+        .max(1 << MIN_CLASS_SHIFT)
+        .next_power_of_two();
+    (size <= 1 << MAX_CLASS_SHIFT).then(|| size.trailing_zeros() as usize - MIN_CLASS_SHIFT)
+}
+
+fn class_layout(class: usize) -> Layout {
+    let size = 1 << (class + MIN_CLASS_SHIFT);
+    Layout::from_size_align(size, size).unwrap()
+}
+
+impl GlobalAllocator {
+    /// Allocates an object of the size class `class` from the current CPU.
+    pub(crate) fn alloc_cached(&self, class: usize) -> AllocResult<NonNull<u8>> {
+        let mut cache = this_cpu_cache().lock();
+        let magazine = &mut cache.magazines[class];
+        if magazine.len == 0 {
+            let layout = class_layout(class);
+            let mut balloc = self.balloc.lock();
+            while magazine.len < MAGAZINE_SIZE / 2 {
+                match self.alloc_locked(&mut balloc, layout) {
+                    Ok(ptr) => magazine.push(ptr.as_ptr() as usize),
+                    Err(err) if magazine.len == 0 => return Err(err),
+                    Err(_) => break,
+                }
+            }
+        }
+        Ok(NonNull::new(magazine.pop().unwrap() as *mut u8).unwrap())
+    }
+
+    /// Frees an object of the size class `class` to the current CPU.
+    pub(crate) fn dealloc_cached(&self, pos: NonNull<u8>, class: usize) {
+        let mut cache = this_cpu_cache().lock();
+        let magazine = &mut cache.magazines[class];
+        if magazine.is_full() {
+            let layout = class_layout(class);
+            let mut balloc = self.balloc.lock();
+            while magazine.len > MAGAZINE_SIZE / 2 {
+                let ptr = magazine.pop().unwrap() as *mut u8;
+                balloc.dealloc(NonNull::new(ptr).unwrap(), layout);
+            }
+        }
+        magazine.push(pos.as_ptr() as usize);
+    }
+
+    /// Allocates a single page from the current CPU.
+    pub(crate) fn alloc_page_cached(&self) -> AllocResult<usize> {
+        let mut cache = this_cpu_cache().lock();
+        if cache.pages.len == 0 {
+            let mut palloc = self.palloc.lock();
+            while cache.pages.len < PAGE_CACHE_BATCH {
+                match palloc.alloc_pages(1, PAGE_SIZE) {
+                    Ok(page) => cache.pages.push(page),
+                    Err(err) if cache.pages.len == 0 => return Err(err),
+                    Err(_) => break,
+                }
+            }
+        }
+        Ok(cache.pages.pop().unwrap())
+    }
+
+    /// Frees a single page to the current CPU.
+    pub(crate) fn dealloc_page_cached(&self, pos: usize) {
+        let mut cache = this_cpu_cache().lock();
+        if cache.pages.is_full() {
+            let mut palloc = self.palloc.lock();
+            for _ in 0..PAGE_CACHE_BATCH {
+                palloc.dealloc_pages(cache.pages.pop().unwrap(), 1);
+            }
+        }
+        cache.pages.push(pos);
+    }
+
+    /// Gives the cached pages of all CPUs back to the global pool, e.g., when
+    /// it runs out of memory.
+    pub(crate) fn drain_page_caches(&self) {
+        for cpu in 0..percpu::percpu_area_num() {
+            // SAFETY: the cache is only accessed with its lock held.
+            let mut cache = unsafe { CPU_CACHE.remote_ref_raw(cpu) }.lock();
+            let mut palloc = self.palloc.lock();
+            while let Some(page) = cache.pages.pop() {
+                palloc.dealloc_pages(page, 1);
+            }
+        }
+    }
+
+    /// Returns the number of pages cached by all CPUs.
+    pub(crate) fn cached_pages(&self) -> usize {
+        (0..percpu::percpu_area_num())
+            .map(|cpu| unsafe { CPU_CACHE.remote_ref_raw(cpu) }.lock().pages.len)
+            .sum()
+    }
+}
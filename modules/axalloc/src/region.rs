@@ -0,0 +1,267 @@
+//! A page allocator over several physical memory regions.
+//!
+//! Each region keeps a bitmap of its pages in its own first pages, so the
+//! allocator needs no memory of its own, and the amount of memory it manages
+//! is only known at runtime, from the memory map given by the firmware or
+//! added by memory hotplug.
+
+use alloc::vec::Vec;
+
+use allocator::{AllocError, AllocResult};
+use memory_addr::{align_down, align_up};
+
+use crate::PAGE_SIZE;
+
+/// The maximum number of memory regions.
+const MAX_REGIONS: usize = 32;
+
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// A region of contiguous pages, with a bitmap of the allocated ones.
+struct PageRegion {
+    /// The address of the first allocatable page.
+    base: usize,
+    num_pages: usize,
+    /// One bit for each page, set if the page is allocated.
+    bitmap: &'static mut [u64],
+    free_pages: usize,
+    /// All pages below it are allocated.
+    hint: usize,
+}
+
+impl PageRegion {
+    /// Creates a region over `[start, start + size)`, placing the bitmap at
+    /// its start.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be valid and unused.
+    unsafe fn new(start: usize, size: usize) -> Option<Self> {
+        let total_pages = size / PAGE_SIZE;
+        let words = total_pages.div_ceil(BITS_PER_WORD);
+        let meta_pages = (words * size_of::<u64>()).div_ceil(PAGE_SIZE);
+        if total_pages <= meta_pages {
+            return None;
+        }
+        let bitmap = unsafe { core::slice::from_raw_parts_mut(start as *mut u64, words) };
+        bitmap.fill(0);
+        let num_pages = total_pages - meta_pages;
+        Some(Self {
+            base: start + meta_pages * PAGE_SIZE,
+            num_pages,
+            bitmap,
+            free_pages: num_pages,
+            hint: 0,
+        })
+    }
+
+    fn end(&self) -> usize {
+        self.base + self.num_pages * PAGE_SIZE
+    }
+
+    /// Checks whether `[start, end)` overlaps the region, including its
+    /// bitmap.
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        let region_start = self.bitmap.as_ptr() as usize;
+        start < self.end() && region_start < end
+    }
+
+    fn is_used(&self, idx: usize) -> bool {
+        self.bitmap[idx / BITS_PER_WORD] & (1 << (idx % BITS_PER_WORD)) != 0
+    }
+
+    /// Returns the first free page at or after `idx`.
+    fn first_free(&self, mut idx: usize) -> Option<usize> {
+        while idx < self.num_pages {
+            let word = !self.bitmap[idx / BITS_PER_WORD] >> (idx % BITS_PER_WORD);
+            if word != 0 {
+                let free = idx + word.trailing_zeros() as usize;
+                return (free < self.num_pages).then_some(free);
+            }
+            idx = align_down(idx, BITS_PER_WORD) + BITS_PER_WORD;
+        }
+        None
+    }
+
+    /// Returns the first allocated page in `[idx, end)`.
+    fn first_used(&self, mut idx: usize, end: usize) -> Option<usize> {
+        while idx < end {
+            let word = self.bitmap[idx / BITS_PER_WORD] >> (idx % BITS_PER_WORD);
+            if word != 0 {
+                let used = idx + word.trailing_zeros() as usize;
+                return (used < end).then_some(used);
+            }
+            idx = align_down(idx, BITS_PER_WORD) + BITS_PER_WORD;
+        }
+        None
+    }
+
+    fn set_range(&mut self, idx: usize, num_pages: usize, used: bool) {
+        for i in idx..idx + num_pages {
+            let (word, bit) = (i / BITS_PER_WORD, i % BITS_PER_WORD);
+            if used {
+                self.bitmap[word] |= 1 << bit;
+            } else {
+                self.bitmap[word] &= !(1 << bit);
+            }
+        }
+    }
+
+    /// Returns the first page index not below `idx` whose address is aligned
+    /// to `align_pow2`.
+    fn align_index(&self, idx: usize, align_pow2: usize) -> usize {
+        (align_up(self.base + idx * PAGE_SIZE, align_pow2) - self.base) / PAGE_SIZE
+    }
+
+    fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> Option<usize> {
+        if num_pages > self.free_pages {
+            return None;
+        }
+        let first = self.first_free(self.hint)?;
+        self.hint = first;
+        let mut idx = first;
+        loop {
+            idx = self.align_index(self.first_free(idx)?, align_pow2);
+            if idx + num_pages > self.num_pages {
+                return None;
+            }
+            match self.first_used(idx, idx + num_pages) {
+                Some(used) => idx = used + 1,
+                None => break,
+            }
+        }
+        self.set_range(idx, num_pages, true);
+        self.free_pages -= num_pages;
+        if idx == first {
+            self.hint = idx + num_pages;
+        }
+        Some(self.base + idx * PAGE_SIZE)
+    }
+
+    /// Returns the length of the longest run of free pages.
+    fn largest_free_run(&self) -> usize {
+        let mut largest = 0;
+        let mut idx = self.hint;
+        while let Some(free) = self.first_free(idx) {
+            let end = self
+                .first_used(free, self.num_pages)
+                .unwrap_or(self.num_pages);
+            largest = largest.max(end - free);
+            idx = end;
+        }
+        largest
+    }
+
+    fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
+        let idx = (pos - self.base) / PAGE_SIZE;
+        debug_assert!((idx..idx + num_pages).all(|i| self.is_used(i)));
+        self.set_range(idx, num_pages, false);
+        self.free_pages += num_pages;
+        self.hint = self.hint.min(idx);
+    }
+}
+
+/// The state of a memory region, for reports.
+pub(crate) struct RegionStat {
+    pub start: usize,
+    pub num_pages: usize,
+    pub free_pages: usize,
+    /// The length of the longest run of free pages.
+    pub largest_free_run: usize,
+}
+
+/// The page allocator of all memory regions.
+pub(crate) struct RegionPageAllocator {
+    regions: [Option<PageRegion>; MAX_REGIONS],
+}
+
+impl RegionPageAllocator {
+    pub const fn new() -> Self {
+        Self {
+            regions: [const { None }; MAX_REGIONS],
+        }
+    }
+
+    /// Adds the memory `[start, start + size)` as a new region.
+    ///
+    /// # Safety
+    ///
+    /// The memory must be valid, mapped and unused, as the bitmap of the
+    /// region is written to its start.
+    pub unsafe fn add_region(&mut self, start: usize, size: usize) -> AllocResult {
+        let end = align_down(start + size, PAGE_SIZE);
+        let start = align_up(start, PAGE_SIZE);
+        if start >= end {
+            return Err(AllocError::InvalidParam);
+        }
+        if self.iter().any(|region| region.overlaps(start, end)) {
+            return Err(AllocError::MemoryOverlap);
+        }
+        let slot = self
+            .regions
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(AllocError::NoMemory)?;
+        *slot =
+            Some(unsafe { PageRegion::new(start, end - start) }.ok_or(AllocError::InvalidParam)?);
+        Ok(())
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &PageRegion> {
+        self.regions.iter().flatten()
+    }
+
+    pub fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        if num_pages == 0 || align_pow2 % PAGE_SIZE != 0 || !align_pow2.is_power_of_two() {
+            return Err(AllocError::InvalidParam);
+        }
+        self.regions
+            .iter_mut()
+            .flatten()
+            .find_map(|region| region.alloc_pages(num_pages, align_pow2))
+            .ok_or(AllocError::NoMemory)
+    }
+
+    pub fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
+        match self
+            .regions
+            .iter_mut()
+            .flatten()
+            .find(|region| region.base <= pos && pos < region.end())
+        {
+            Some(region) => region.dealloc_pages(pos, num_pages),
+            None => panic!("dealloc_pages: {:#x} is not in any memory region", pos),
+        }
+    }
+
+    pub fn total_pages(&self) -> usize {
+        self.iter().map(|region| region.num_pages).sum()
+    }
+
+    pub fn used_pages(&self) -> usize {
+        self.iter()
+            .map(|region| region.num_pages - region.free_pages)
+            .sum()
+    }
+
+    pub fn available_pages(&self) -> usize {
+        self.iter().map(|region| region.free_pages).sum()
+    }
+
+    /// Returns the state of each region, without allocating.
+    pub fn region_stats(&self) -> impl Iterator<Item = RegionStat> + '_ {
+        self.iter().map(|region| RegionStat {
+            start: region.base,
+            num_pages: region.num_pages,
+            free_pages: region.free_pages,
+            largest_free_run: region.largest_free_run(),
+        })
+    }
+
+    /// Returns the allocatable range of each region, as start and size.
+    pub fn regions(&self) -> Vec<(usize, usize)> {
+        self.iter()
+            .map(|region| (region.base, region.num_pages * PAGE_SIZE))
+            .collect()
+    }
+}
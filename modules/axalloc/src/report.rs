@@ -0,0 +1,62 @@
+//! Reports of the allocator state, e.g., when it runs out of memory.
+
+use core::alloc::Layout;
+
+use allocator::ByteAllocator;
+
+use crate::{GlobalAllocator, PAGE_SIZE};
+
+/// The number of top consumers shown in reports.
+#[cfg(feature = "tracking")]
+const TOP_CONSUMERS: usize = 8;
+
+impl GlobalAllocator {
+    /// Prints the state of the byte and page allocators, and with the
+    /// `tracking` feature, the statistics of each size class and the call
+    /// sites holding the most memory.
+    ///
+    /// It does not allocate, so it can be called when out of memory.
+    pub fn report(&self) {
+        {
+            let balloc = self.balloc.lock();
+            error!(
+                "byte allocator ({}): {} bytes in total, {} used, {} available",
+                self.name(),
+                balloc.total_bytes(),
+                balloc.used_bytes(),
+                balloc.available_bytes()
+            );
+        }
+        error!(
+            "page allocator: {} pages in total, {} used, {} available",
+            self.total_pages(),
+            self.used_pages(),
+            self.available_pages()
+        );
+        #[cfg(feature = "smp")]
+        error!("  {} pages cached by CPUs", self.cached_pages());
+        for stat in self.palloc.lock().region_stats() {
+            error!(
+                "  region [{:#x}, {:#x}): {} of {} pages free, largest free run {} pages",
+                stat.start,
+                stat.start + stat.num_pages * PAGE_SIZE,
+                stat.free_pages,
+                stat.num_pages,
+                stat.largest_free_run
+            );
+        }
+        #[cfg(feature = "tracking")]
+        crate::tracking::report(TOP_CONSUMERS);
+    }
+
+    /// Prints why the allocation of `layout` failed, then the state of the
+    /// allocator.
+    pub(crate) fn report_oom(&self, layout: Layout) {
+        error!(
+            "out of memory: failed to allocate {} bytes aligned to {}",
+            layout.size(),
+            layout.align()
+        );
+        self.report();
+    }
+}
@@ -0,0 +1,332 @@
+//! Redzones, poisoning and a quarantine to catch heap corruption.
+//!
+//! Each byte allocation is wrapped in a larger block: a header and a left
+//! redzone before it, and a right redzone after it, both filled with a known
+//! pattern. Freed memory is poisoned and held in a quarantine for a while
+//! before it is really freed, so that writes to it can be noticed.
+//!
+//! The redzones are checked on free, and the whole heap, i.e., all live
+//! blocks and the quarantine, on a periodic sweep. Any corruption is reported
+//! with the backtraces of where the block was allocated and freed.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use allocator::{AllocError, AllocResult};
+use kspin::SpinNoIrq;
+use memory_addr::align_up;
+
+use crate::{GlobalAllocator, backtrace};
+
+/// The minimum size of each redzone.
+const REDZONE: usize = 16;
+/// The minimum alignment of the blocks.
+const MIN_ALIGN: usize = 16;
+
+/// The pattern of the redzones.
+const REDZONE_BYTE: u8 = 0xfc;
+/// The pattern of freed memory.
+const FREED_BYTE: u8 = 0xfd;
+
+const LIVE_MAGIC: usize = 0xa110_c8ed;
+const FREED_MAGIC: usize = 0xf4ee_d0ff;
+
+/// The number of freed blocks held at most.
+const QUARANTINE_LEN: usize = 256;
+/// The number of freed bytes held at most.
+const QUARANTINE_BYTES: usize = 0x10_0000;
+/// The number of timer ticks between sweeps of the heap.
+const SWEEP_INTERVAL_TICKS: usize = 100;
+
+/// The header at the start of each block.
+#[repr(C)]
+struct Header {
+    magic: usize,
+    /// The size of the allocation.
+    size: usize,
+    /// The alignment of the block.
+    align: usize,
+    /// The offset of the allocation from the start of the block.
+    offset: usize,
+    /// The size of the block.
+    total: usize,
+    /// The neighbours in the list of live blocks.
+    prev: usize,
+    next: usize,
+    alloc_frames: [usize; 5],
+    free_frames: [usize; 4],
+}
+
+const HEADER_SIZE: usize = size_of::<Header>();
+
+impl Header {
+    fn base(&self) -> usize {
+        self as *const _ as usize
+    }
+
+    fn user(&self) -> usize {
+        self.base() + self.offset
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::from_size_align(self.total, self.align).unwrap()
+    }
+}
+
+/// Returns the layout of the block wrapping an allocation of `layout`, and
+/// the offset of the allocation in it.
+fn block_layout(layout: Layout) -> Option<(Layout, usize)> {
+    let align = layout.align().max(MIN_ALIGN);
+    let offset = align_up(HEADER_SIZE + REDZONE, align);
+    let size = offset.checked_add(layout.size().checked_add(REDZONE + MIN_ALIGN - 1)?)?;
+    let size = size & !(MIN_ALIGN - 1);
+    Some((Layout::from_size_align(size, align).ok()?, offset))
+}
+
+/// Returns the offset of the first byte in `[start, end)` that is not
+/// `byte`.
+fn find_mismatch(start: usize, end: usize, byte: u8) -> Option<usize> {
+    let bytes = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
+    bytes.iter().position(|&b| b != byte).map(|pos| start + pos)
+}
+
+/// A kind of heap corruption, with the address where it was found.
+enum Fault {
+    InvalidFree,
+    DoubleFree,
+    SizeMismatch(usize),
+    Underflow(usize),
+    Overflow(usize),
+    UseAfterFree(usize),
+}
+
+/// Checks the redzones of a block, and if it is freed, its poison.
+fn check_block(header: &Header) -> Result<(), Fault> {
+    let user = header.user();
+    let end = user + header.size;
+    if let Some(addr) = find_mismatch(header.base() + HEADER_SIZE, user, REDZONE_BYTE) {
+        return Err(Fault::Underflow(addr));
+    }
+    if let Some(addr) = find_mismatch(end, header.base() + header.total, REDZONE_BYTE) {
+        return Err(Fault::Overflow(addr));
+    }
+    if header.magic == FREED_MAGIC
+        && let Some(addr) = find_mismatch(user, end, FREED_BYTE)
+    {
+        return Err(Fault::UseAfterFree(addr));
+    }
+    Ok(())
+}
+
+fn print_frames(frames: &[usize]) {
+    for &ra in frames.iter().take_while(|&&ra| ra != 0) {
+        error!("    at {:#x}", ra);
+    }
+}
+
+/// Reports the corruption of the block of `header` and panics.
+fn report(fault: Fault, addr: usize, header: Option<&Header>) -> ! {
+    match fault {
+        Fault::InvalidFree => error!("heap: invalid free of {:#x}", addr),
+        Fault::DoubleFree => error!("heap: double free of {:#x}", addr),
+        Fault::SizeMismatch(size) => {
+            error!("heap: free of {:#x} with a wrong size {}", addr, size)
+        }
+        Fault::Underflow(at) => error!(
+            "heap: buffer underflow at {:#x}, {} bytes before {:#x}",
+            at,
+            addr - at,
+            addr
+        ),
+        Fault::Overflow(at) => error!(
+            "heap: buffer overflow at {:#x}, {} bytes after the end of {:#x}",
+            at,
+            at - addr - header.map_or(0, |header| header.size),
+            addr
+        ),
+        Fault::UseAfterFree(at) => error!(
+            "heap: use after free at {:#x}, offset {} of {:#x}",
+            at,
+            at - addr,
+            addr
+        ),
+    }
+    if let Some(header) = header {
+        error!("  a block of {} bytes, allocated", header.size);
+        print_frames(&header.alloc_frames);
+        if header.magic == FREED_MAGIC {
+            error!("  freed");
+            print_frames(&header.free_frames);
+        }
+    }
+    panic!("heap corruption detected");
+}
+
+/// The live blocks and the quarantine.
+struct Sanitizer {
+    /// The first live block, or zero.
+    live: usize,
+    /// A ring of the freed blocks, oldest first.
+    quarantine: [usize; QUARANTINE_LEN],
+    head: usize,
+    len: usize,
+    quarantine_bytes: usize,
+}
+
+static SANITIZER: SpinNoIrq<Sanitizer> = SpinNoIrq::new(Sanitizer {
+    live: 0,
+    quarantine: [0; QUARANTINE_LEN],
+    head: 0,
+    len: 0,
+    quarantine_bytes: 0,
+});
+
+unsafe fn header<'a>(base: usize) -> &'a mut Header {
+    unsafe { &mut *(base as *mut Header) }
+}
+
+impl Sanitizer {
+    fn link(&mut self, header: &mut Header) {
+        header.prev = 0;
+        header.next = self.live;
+        if self.live != 0 {
+            unsafe { self::header(self.live) }.prev = header.base();
+        }
+        self.live = header.base();
+    }
+
+    fn unlink(&mut self, header: &Header) {
+        if header.prev != 0 {
+            unsafe { self::header(header.prev) }.next = header.next;
+        } else {
+            self.live = header.next;
+        }
+        if header.next != 0 {
+            unsafe { self::header(header.next) }.prev = header.prev;
+        }
+    }
+
+    /// Takes the oldest block out of the quarantine, if it is full or would
+    /// be with `bytes` more.
+    fn evict(&mut self, bytes: usize) -> Option<&'static Header> {
+        if self.len == 0
+            || (self.len < QUARANTINE_LEN && self.quarantine_bytes + bytes <= QUARANTINE_BYTES)
+        {
+            return None;
+        }
+        let header = unsafe { self::header(self.quarantine[self.head]) };
+        self.head = (self.head + 1) % QUARANTINE_LEN;
+        self.len -= 1;
+        self.quarantine_bytes -= header.total;
+        Some(header)
+    }
+
+    fn quarantine(&mut self, header: &Header) {
+        self.quarantine[(self.head + self.len) % QUARANTINE_LEN] = header.base();
+        self.len += 1;
+        self.quarantine_bytes += header.total;
+    }
+
+    fn check_all(&self) {
+        let mut base = self.live;
+        while base != 0 {
+            let header = unsafe { self::header(base) };
+            if let Err(fault) = check_block(header) {
+                report(fault, header.user(), Some(header));
+            }
+            base = header.next;
+        }
+        for i in 0..self.len {
+            let header = unsafe { self::header(self.quarantine[(self.head + i) % QUARANTINE_LEN]) };
+            if let Err(fault) = check_block(header) {
+                report(fault, header.user(), Some(header));
+            }
+        }
+    }
+}
+
+impl GlobalAllocator {
+    /// Allocates `layout` in a block with redzones.
+    pub(crate) fn alloc_sanitized(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        let (block_layout, offset) = block_layout(layout).ok_or(AllocError::InvalidParam)?;
+        let base = self.alloc_raw(block_layout)?.as_ptr() as usize;
+        let header = unsafe { header(base) };
+        *header = Header {
+            magic: LIVE_MAGIC,
+            size: layout.size(),
+            align: block_layout.align(),
+            offset,
+            total: block_layout.size(),
+            prev: 0,
+            next: 0,
+            alloc_frames: [0; 5],
+            free_frames: [0; 4],
+        };
+        backtrace::capture(&mut header.alloc_frames);
+        let user = base + offset;
+        unsafe {
+            core::ptr::write_bytes(
+                (base + HEADER_SIZE) as *mut u8,
+                REDZONE_BYTE,
+                offset - HEADER_SIZE,
+            );
+            let end = user + layout.size();
+            core::ptr::write_bytes(end as *mut u8, REDZONE_BYTE, base + header.total - end);
+        }
+        SANITIZER.lock().link(header);
+        Ok(NonNull::new(user as *mut u8).unwrap())
+    }
+
+    /// Checks and poisons the allocation at `pos`, and puts its block in the
+    /// quarantine.
+    pub(crate) fn dealloc_sanitized(&self, pos: NonNull<u8>, layout: Layout) {
+        let user = pos.as_ptr() as usize;
+        let Some((_, offset)) = block_layout(layout) else {
+            report(Fault::InvalidFree, user, None);
+        };
+        let header = unsafe { header(user - offset) };
+        let mut sanitizer = SANITIZER.lock();
+        match header.magic {
+            LIVE_MAGIC if header.offset == offset => {}
+            FREED_MAGIC if header.offset == offset => report(Fault::DoubleFree, user, Some(header)),
+            _ => report(Fault::InvalidFree, user, None),
+        }
+        if header.size != layout.size() {
+            report(Fault::SizeMismatch(layout.size()), user, Some(header));
+        }
+        if let Err(fault) = check_block(header) {
+            report(fault, user, Some(header));
+        }
+        sanitizer.unlink(header);
+        header.magic = FREED_MAGIC;
+        backtrace::capture(&mut header.free_frames);
+        unsafe { core::ptr::write_bytes(user as *mut u8, FREED_BYTE, header.size) };
+        while let Some(old) = sanitizer.evict(header.total) {
+            if let Err(fault) = check_block(old) {
+                report(fault, old.user(), Some(old));
+            }
+            self.dealloc_raw(NonNull::new(old.base() as *mut u8).unwrap(), old.layout());
+        }
+        sanitizer.quarantine(header);
+    }
+}
+
+/// Checks the redzones of all live allocations, and that no freed memory in
+/// the quarantine was written to.
+///
+/// It panics with a report if any corruption is found.
+pub fn check_heap() {
+    SANITIZER.lock().check_all();
+}
+
+/// Checks the heap every few timer ticks.
+///
+/// It should be called on each timer tick, and as it walks the whole heap,
+/// makes interrupts much slower with many allocations.
+pub fn on_timer_tick() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static TICKS: AtomicUsize = AtomicUsize::new(0);
+    if TICKS.fetch_add(1, Ordering::Relaxed) % SWEEP_INTERVAL_TICKS == 0 {
+        check_heap();
+    }
+}
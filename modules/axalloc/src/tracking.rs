@@ -0,0 +1,255 @@
+//! Statistics by size class, and tracking of live allocations with their
+//! backtraces.
+//!
+//! Nothing here allocates, so it can be used from within the allocator and
+//! when it is out of memory.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+
+use crate::backtrace;
+
+/// The number of frames kept in the backtrace of each allocation.
+const MAX_FRAMES: usize = 8;
+/// The number of live allocations tracked at most.
+const MAX_TRACKED: usize = 4096;
+/// The number of distinct backtraces told apart in reports.
+const MAX_CONSUMERS: usize = 64;
+/// Allocations of up to `1 << i` bytes fall into the size class `i`.
+const NUM_CLASSES: usize = usize::BITS as usize;
+
+/// The statistics of a size class.
+#[derive(Debug, Clone, Copy)]
+pub struct ClassStat {
+    /// The largest size of the class, in bytes.
+    pub size: usize,
+    /// The number of allocations so far.
+    pub allocs: usize,
+    /// The number of frees so far.
+    pub frees: usize,
+    /// The number of bytes allocated and not freed.
+    pub live_bytes: usize,
+}
+
+struct ClassCounters {
+    allocs: AtomicUsize,
+    frees: AtomicUsize,
+    live_bytes: AtomicUsize,
+}
+
+static CLASSES: [ClassCounters; NUM_CLASSES] = [const {
+    ClassCounters {
+        allocs: AtomicUsize::new(0),
+        frees: AtomicUsize::new(0),
+        live_bytes: AtomicUsize::new(0),
+    }
+}; NUM_CLASSES];
+
+fn class_of(size: usize) -> usize {
+    size.max(1).next_power_of_two().trailing_zeros() as usize
+}
+
+/// Returns the statistics of each size class that has seen allocations.
+pub fn class_stats() -> impl Iterator<Item = ClassStat> {
+    CLASSES
+        .iter()
+        .enumerate()
+        .map(|(class, counters)| ClassStat {
+            size: 1 << class,
+            allocs: counters.allocs.load(Ordering::Relaxed),
+            frees: counters.frees.load(Ordering::Relaxed),
+            live_bytes: counters.live_bytes.load(Ordering::Relaxed),
+        })
+        .filter(|stat| stat.allocs > 0)
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    /// Zero if the slot is empty.
+    addr: usize,
+    size: usize,
+    frames: [usize; MAX_FRAMES],
+}
+
+impl Entry {
+    const EMPTY: Self = Self {
+        addr: 0,
+        size: 0,
+        frames: [0; MAX_FRAMES],
+    };
+}
+
+/// The live allocations sharing a backtrace.
+#[derive(Clone, Copy)]
+struct Consumer {
+    frames: [usize; MAX_FRAMES],
+    bytes: usize,
+    count: usize,
+}
+
+/// A hash table of live allocations by address, with linear probing.
+struct Tracker {
+    entries: [Entry; MAX_TRACKED],
+    len: usize,
+    /// The number of allocations not tracked as the table was full.
+    dropped: usize,
+    /// Scratch space to group allocations by backtrace in reports.
+    consumers: [Consumer; MAX_CONSUMERS],
+}
+
+static TRACKER: SpinNoIrq<Tracker> = SpinNoIrq::new(Tracker {
+    entries: [Entry::EMPTY; MAX_TRACKED],
+    len: 0,
+    dropped: 0,
+    consumers: [Consumer {
+        frames: [0; MAX_FRAMES],
+        bytes: 0,
+        count: 0,
+    }; MAX_CONSUMERS],
+});
+
+fn home(addr: usize) -> usize {
+    (addr >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15) % MAX_TRACKED
+}
+
+impl Tracker {
+    fn insert(&mut self, entry: Entry) {
+        // Keep one slot empty, so that probing always ends.
+        if self.len + 1 >= MAX_TRACKED {
+            self.dropped += 1;
+            return;
+        }
+        let mut idx = home(entry.addr);
+        while self.entries[idx].addr != 0 {
+            idx = (idx + 1) % MAX_TRACKED;
+        }
+        self.entries[idx] = entry;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, addr: usize) -> Option<Entry> {
+        let mut idx = home(addr);
+        while self.entries[idx].addr != addr {
+            if self.entries[idx].addr == 0 {
+                return None;
+            }
+            idx = (idx + 1) % MAX_TRACKED;
+        }
+        let removed = self.entries[idx];
+        self.len -= 1;
+        // Shift back the following entries that would no longer be found.
+        let mut hole = idx;
+        let mut next = idx;
+        loop {
+            next = (next + 1) % MAX_TRACKED;
+            let addr = self.entries[next].addr;
+            if addr == 0 {
+                break;
+            }
+            let home = home(addr);
+            let stays = if hole <= next {
+                hole < home && home <= next
+            } else {
+                hole < home || home <= next
+            };
+            if !stays {
+                self.entries[hole] = self.entries[next];
+                hole = next;
+            }
+        }
+        self.entries[hole] = Entry::EMPTY;
+        Some(removed)
+    }
+
+    fn live(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().filter(|entry| entry.addr != 0)
+    }
+
+    /// Groups the live allocations by backtrace, and returns the groups
+    /// sorted by bytes, largest first.
+    fn group_consumers(&mut self) -> &[Consumer] {
+        let mut len = 0;
+        for entry in self.entries.iter().filter(|entry| entry.addr != 0) {
+            let consumers = &mut self.consumers[..len];
+            if let Some(consumer) = consumers.iter_mut().find(|c| c.frames == entry.frames) {
+                consumer.bytes += entry.size;
+                consumer.count += 1;
+            } else if len < MAX_CONSUMERS {
+                self.consumers[len] = Consumer {
+                    frames: entry.frames,
+                    bytes: entry.size,
+                    count: 1,
+                };
+                len += 1;
+            }
+        }
+        let consumers = &mut self.consumers[..len];
+        consumers.sort_unstable_by(|a, b| b.bytes.cmp(&a.bytes));
+        consumers
+    }
+}
+
+/// Records an allocation of `size` bytes at `addr`.
+pub(crate) fn record_alloc(addr: usize, size: usize) {
+    let class = &CLASSES[class_of(size)];
+    class.allocs.fetch_add(1, Ordering::Relaxed);
+    class.live_bytes.fetch_add(size, Ordering::Relaxed);
+    let mut entry = Entry {
+        addr,
+        size,
+        frames: [0; MAX_FRAMES],
+    };
+    backtrace::capture(&mut entry.frames);
+    TRACKER.lock().insert(entry);
+}
+
+/// Records the free of `size` bytes at `addr`.
+pub(crate) fn record_dealloc(addr: usize, size: usize) {
+    let class = &CLASSES[class_of(size)];
+    class.frees.fetch_add(1, Ordering::Relaxed);
+    class.live_bytes.fetch_sub(size, Ordering::Relaxed);
+    TRACKER.lock().remove(addr);
+}
+
+fn print_frames(frames: &[usize]) {
+    for &ra in frames.iter().take_while(|&&ra| ra != 0) {
+        error!("    at {:#x}", ra);
+    }
+}
+
+/// Prints every live allocation with its backtrace, e.g., to find leaks.
+pub fn dump_live_allocations() {
+    let tracker = TRACKER.lock();
+    error!(
+        "{} live allocations ({} untracked):",
+        tracker.len, tracker.dropped
+    );
+    for entry in tracker.live() {
+        error!("  {:#x}: {} bytes", entry.addr, entry.size);
+        print_frames(&entry.frames);
+    }
+}
+
+/// Prints the size classes, and the `top` backtraces holding the most live
+/// memory.
+pub(crate) fn report(top: usize) {
+    error!("size classes:");
+    for stat in class_stats() {
+        error!(
+            "  <= {:>10} bytes: {:>8} allocs, {:>8} frees, {:>10} bytes live",
+            stat.size, stat.allocs, stat.frees, stat.live_bytes
+        );
+    }
+    let mut tracker = TRACKER.lock();
+    let dropped = tracker.dropped;
+    let consumers = tracker.group_consumers();
+    error!("top consumers ({} allocations untracked):", dropped);
+    for consumer in consumers.iter().take(top) {
+        error!(
+            "  {} bytes in {} allocations",
+            consumer.bytes, consumer.count
+        );
+        print_frames(&consumer.frames);
+    }
+}
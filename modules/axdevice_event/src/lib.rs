@@ -18,7 +18,7 @@ use lazy_static::lazy_static;
 pub struct DiscoveredDeviceInfo {
     pub device_type: DeviceType,
     pub name: String,
-    pub pci_bdf: String, // For logging and unique naming, e.g., "00:03.0"
+    pub pci_bdf: String, // For logging and unique naming, e.g., "00:03.0" or "mmio@10001000"
     pub mmio_region: Option<(PhysAddr, usize)>, // Base address and size (physical)
     pub irq_num: Option<usize>,
     // Add other relevant info as needed, like capabilities, transport specific data etc.
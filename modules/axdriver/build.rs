@@ -1,5 +1,5 @@
-const NET_DEV_FEATURES: &[&str] = &["fxmac", "ixgbe", "virtio-net"];
-const BLOCK_DEV_FEATURES: &[&str] = &["ramdisk", "bcm2835-sdhci", "virtio-blk"];
+const NET_DEV_FEATURES: &[&str] = &["fxmac", "ixgbe", "e1000", "virtio-net"];
+const BLOCK_DEV_FEATURES: &[&str] = &["ramdisk", "bcm2835-sdhci", "nvme", "virtio-blk"];
 const DISPLAY_DEV_FEATURES: &[&str] = &["virtio-gpu"];
 
 fn make_cfg_values(str_list: &[&str]) -> String {
@@ -3,7 +3,7 @@ use crate::{AllDevices, prelude::*};
 
 impl AllDevices {
     pub(crate) fn probe_bus_devices(&mut self) {
-        // TODO: parse device tree
+        // TODO: parse device tree instead of using the fixed slot table
         #[cfg(feature = "virtio")]
         for reg in axconfig::devices::VIRTIO_MMIO_REGIONS {
             for_each_drivers!(type Driver, {
@@ -81,6 +81,48 @@ cfg_if::cfg_if! {
     }
 }
 
+cfg_if::cfg_if! {
+    if #[cfg(block_dev = "nvme")] {
+        use crate::nvme::{NvmeDev, PCI_CLASS_STORAGE, PCI_PROG_IF_NVME, PCI_SUBCLASS_NVM};
+        pub struct NvmeDriver;
+        register_block_driver!(NvmeDriver, NvmeDev);
+
+        impl DriverProbe for NvmeDriver {
+            #[cfg(bus = "pci")]
+            fn probe_pci(
+                root: &mut PciRoot,
+                bdf: DeviceFunction,
+                dev_info: &DeviceFunctionInfo,
+            ) -> Option<AxDeviceEnum> {
+                if dev_info.class != PCI_CLASS_STORAGE
+                    || dev_info.subclass != PCI_SUBCLASS_NVM
+                    || dev_info.prog_if != PCI_PROG_IF_NVME
+                {
+                    return None;
+                }
+                info!("NVMe PCI device found at {:?}", bdf);
+
+                match root.bar_info(bdf, 0) {
+                    Ok(axdriver_pci::BarInfo::Memory { address, .. }) => {
+                        let mmio_base = axhal::mem::phys_to_virt((address as usize).into());
+                        match NvmeDev::init(bdf, mmio_base.as_usize()) {
+                            Ok(dev) => Some(AxDeviceEnum::from_block(dev)),
+                            Err(e) => {
+                                warn!("failed to initialize NVMe device at {}: {:?}", bdf, e);
+                                None
+                            }
+                        }
+                    }
+                    _ => {
+                        error!("nvme: BAR0 is not a memory BAR");
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
 cfg_if::cfg_if! {
     if #[cfg(net_dev = "ixgbe")] {
         use crate::ixgbe::IxgbeHalImpl;
@@ -129,6 +171,43 @@ cfg_if::cfg_if! {
     }
 }
 
+cfg_if::cfg_if! {
+    if #[cfg(net_dev = "e1000")] {
+        use crate::e1000::E1000Nic;
+        pub struct E1000Driver;
+        register_net_driver!(E1000Driver, E1000Nic);
+
+        impl DriverProbe for E1000Driver {
+            #[cfg(bus = "pci")]
+            fn probe_pci(
+                root: &mut PciRoot,
+                bdf: DeviceFunction,
+                dev_info: &DeviceFunctionInfo,
+            ) -> Option<AxDeviceEnum> {
+                let is_e1000e = crate::e1000::match_device(dev_info.vendor_id, dev_info.device_id)?;
+                info!("e1000 PCI device found at {:?}", bdf);
+
+                match root.bar_info(bdf, 0) {
+                    Ok(axdriver_pci::BarInfo::Memory { address, .. }) => {
+                        let mmio_base = axhal::mem::phys_to_virt((address as usize).into());
+                        match E1000Nic::init(mmio_base.as_usize(), is_e1000e) {
+                            Ok(nic) => Some(AxDeviceEnum::from_net(nic)),
+                            Err(e) => {
+                                warn!("failed to initialize e1000 device at {}: {:?}", bdf, e);
+                                None
+                            }
+                        }
+                    }
+                    _ => {
+                        error!("e1000: BAR0 is not a memory BAR");
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
 cfg_if::cfg_if! {
     if #[cfg(net_dev = "fxmac")]{
         use axalloc::global_allocator;
@@ -0,0 +1,479 @@
+//! Intel 8254x (e1000) and 82574 (e1000e) network driver.
+//!
+//! Only the legacy descriptor format is used, which all the supported models
+//! understand. Descriptor rings are allocated from [`axdma`], and packet
+//! buffers come from a [`NetBufPool`], like the VirtIO network driver.
+//! Interrupts are masked and both rings are polled.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::alloc::Layout;
+use core::ptr::{read_volatile, write_volatile};
+
+use axdma::{DMAInfo, alloc_coherent, dealloc_coherent};
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_net::{EthernetAddress, NetBuf, NetBufBox, NetBufPool, NetBufPtr, NetDriverOps};
+use axhal::mem::kernel_virt_to_phys;
+use axhal::time::{Duration, busy_wait};
+
+/// PCI vendor ID of Intel.
+pub const INTEL_VENDOR_ID: u16 = 0x8086;
+
+/// PCI device IDs of the supported 8254x models (e1000).
+const E1000_DEVICE_IDS: &[u16] = &[
+    0x100e, // 82540EM, QEMU `-device e1000`
+    0x100f, // 82545EM copper
+    0x1004, // 82543GC copper
+    0x1015, // 82540EM LOM
+    0x1026, // 82545GM copper
+    0x1076, // 82541GI
+    0x107c, // 82541PI
+];
+
+/// PCI device IDs of the supported 82574-like models (e1000e).
+const E1000E_DEVICE_IDS: &[u16] = &[
+    0x10d3, // 82574L, QEMU `-device e1000e`
+    0x10f6, // 82574LA
+    0x150c, // 82583V
+];
+
+/// Returns whether the PCI device is supported, and whether it is an e1000e.
+pub fn match_device(vendor_id: u16, device_id: u16) -> Option<bool> {
+    if vendor_id != INTEL_VENDOR_ID {
+        return None;
+    }
+    if E1000_DEVICE_IDS.contains(&device_id) {
+        Some(false)
+    } else if E1000E_DEVICE_IDS.contains(&device_id) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// Number of descriptors in each ring.
+const QUEUE_SIZE: usize = 256;
+
+/// Size of each packet buffer, matching `RCTL.BSIZE`.
+const BUF_LEN: usize = 2048;
+
+mod regs {
+    pub const CTRL: usize = 0x0000;
+    pub const STATUS: usize = 0x0008;
+    pub const EERD: usize = 0x0014;
+    pub const IMC: usize = 0x00d8;
+    pub const RCTL: usize = 0x0100;
+    pub const TCTL: usize = 0x0400;
+    pub const TIPG: usize = 0x0410;
+    pub const RDBAL: usize = 0x2800;
+    pub const RDBAH: usize = 0x2804;
+    pub const RDLEN: usize = 0x2808;
+    pub const RDH: usize = 0x2810;
+    pub const RDT: usize = 0x2818;
+    pub const TDBAL: usize = 0x3800;
+    pub const TDBAH: usize = 0x3804;
+    pub const TDLEN: usize = 0x3808;
+    pub const TDH: usize = 0x3810;
+    pub const TDT: usize = 0x3818;
+    pub const MTA: usize = 0x5200;
+    pub const RAL0: usize = 0x5400;
+    pub const RAH0: usize = 0x5404;
+
+    pub const CTRL_ASDE: u32 = 1 << 5;
+    pub const CTRL_SLU: u32 = 1 << 6;
+    pub const CTRL_RST: u32 = 1 << 26;
+
+    pub const STATUS_LU: u32 = 1 << 1;
+
+    pub const RCTL_EN: u32 = 1 << 1;
+    pub const RCTL_BAM: u32 = 1 << 15;
+    pub const RCTL_SECRC: u32 = 1 << 26;
+
+    pub const TCTL_EN: u32 = 1 << 1;
+    pub const TCTL_PSP: u32 = 1 << 3;
+    pub const TCTL_CT: u32 = 0x10 << 4;
+    pub const TCTL_COLD: u32 = 0x40 << 12;
+
+    pub const RAH_AV: u32 = 1 << 31;
+}
+
+/// Legacy receive descriptor.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct RxDesc {
+    addr: u64,
+    length: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+/// Legacy transmit descriptor.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct TxDesc {
+    addr: u64,
+    length: u16,
+    cso: u8,
+    cmd: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+const DESC_STATUS_DD: u8 = 1 << 0;
+const RX_STATUS_EOP: u8 = 1 << 1;
+const TX_CMD_EOP: u8 = 1 << 0;
+const TX_CMD_IFCS: u8 = 1 << 1;
+const TX_CMD_RS: u8 = 1 << 3;
+
+/// A descriptor ring allocated from [`axdma`].
+struct Ring<D> {
+    info: DMAInfo,
+    layout: Layout,
+    _marker: core::marker::PhantomData<D>,
+}
+
+impl<D: Copy + Default> Ring<D> {
+    fn new() -> DevResult<Self> {
+        // Descriptor rings must be 128-byte aligned.
+        let layout = Layout::from_size_align(QUEUE_SIZE * size_of::<D>(), 128).unwrap();
+        let info = unsafe { alloc_coherent(layout) }.map_err(|_| DevError::NoMemory)?;
+        let ring = Self {
+            info,
+            layout,
+            _marker: core::marker::PhantomData,
+        };
+        for i in 0..QUEUE_SIZE {
+            ring.write(i, D::default());
+        }
+        Ok(ring)
+    }
+
+    fn bus_addr(&self) -> u64 {
+        self.info.bus_addr.as_u64()
+    }
+
+    fn read(&self, idx: usize) -> D {
+        unsafe { read_volatile((self.info.cpu_addr.as_ptr() as *const D).add(idx)) }
+    }
+
+    fn write(&self, idx: usize, desc: D) {
+        unsafe { write_volatile((self.info.cpu_addr.as_ptr() as *mut D).add(idx), desc) }
+    }
+}
+
+impl<D> Drop for Ring<D> {
+    fn drop(&mut self) {
+        unsafe { dealloc_coherent(self.info, self.layout) };
+    }
+}
+
+/// The e1000/e1000e network device.
+pub struct E1000Nic {
+    mmio_base: usize,
+    is_e1000e: bool,
+    mac: [u8; 6],
+    rx_ring: Ring<RxDesc>,
+    tx_ring: Ring<TxDesc>,
+    /// Buffers owned by the receive descriptors, `None` for the slots whose
+    /// buffers have been handed to the upper layer.
+    rx_buffers: Vec<Option<NetBufBox>>,
+    tx_buffers: Vec<Option<NetBufBox>>,
+    /// Next receive descriptor to be checked for a packet.
+    rx_next: usize,
+    /// Receive tail, i.e., the next slot to be refilled by `recycle_rx_buffer`.
+    rx_tail: usize,
+    /// Next transmit descriptor to be checked for completion.
+    tx_clean: usize,
+    /// Transmit tail, i.e., the next slot to be used by `transmit`.
+    tx_tail: usize,
+    buf_pool: Arc<NetBufPool>,
+}
+
+unsafe impl Send for E1000Nic {}
+unsafe impl Sync for E1000Nic {}
+
+impl E1000Nic {
+    /// Initializes the NIC whose registers are mapped at `mmio_base`
+    /// (virtual address of BAR0).
+    pub fn init(mmio_base: usize, is_e1000e: bool) -> DevResult<Self> {
+        let buf_pool = NetBufPool::new(2 * QUEUE_SIZE, BUF_LEN)?;
+        let mut nic = Self {
+            mmio_base,
+            is_e1000e,
+            mac: [0; 6],
+            rx_ring: Ring::new()?,
+            tx_ring: Ring::new()?,
+            rx_buffers: (0..QUEUE_SIZE).map(|_| None).collect(),
+            tx_buffers: (0..QUEUE_SIZE).map(|_| None).collect(),
+            rx_next: 0,
+            rx_tail: 0,
+            tx_clean: 0,
+            tx_tail: 0,
+            buf_pool,
+        };
+        nic.reset();
+        nic.mac = nic.read_mac_address();
+        nic.init_rx()?;
+        nic.init_tx();
+
+        info!(
+            "{}: MAC address {:02x?}, link {}",
+            nic.device_name(),
+            nic.mac,
+            if nic.read_reg(regs::STATUS) & regs::STATUS_LU != 0 {
+                "up"
+            } else {
+                "down"
+            }
+        );
+        Ok(nic)
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.mmio_base + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.mmio_base + offset) as *mut u32, value) }
+    }
+
+    fn reset(&self) {
+        self.write_reg(regs::IMC, u32::MAX);
+        self.write_reg(regs::CTRL, self.read_reg(regs::CTRL) | regs::CTRL_RST);
+        busy_wait(Duration::from_millis(1));
+        while self.read_reg(regs::CTRL) & regs::CTRL_RST != 0 {
+            core::hint::spin_loop();
+        }
+        // Reset unmasks interrupts again.
+        self.write_reg(regs::IMC, u32::MAX);
+        self.write_reg(
+            regs::CTRL,
+            self.read_reg(regs::CTRL) | regs::CTRL_SLU | regs::CTRL_ASDE,
+        );
+    }
+
+    /// Reads the MAC address from the receive address registers, which are
+    /// loaded from the EEPROM on reset, or from the EEPROM directly.
+    fn read_mac_address(&self) -> [u8; 6] {
+        let (ral, rah) = (self.read_reg(regs::RAL0), self.read_reg(regs::RAH0));
+        if rah & regs::RAH_AV != 0 {
+            let mut mac = [0; 6];
+            mac[..4].copy_from_slice(&ral.to_le_bytes());
+            mac[4..].copy_from_slice(&(rah as u16).to_le_bytes());
+            return mac;
+        }
+
+        let mut mac = [0; 6];
+        for i in 0..3 {
+            mac[i * 2..i * 2 + 2].copy_from_slice(&self.read_eeprom(i as u8).to_le_bytes());
+        }
+        self.write_reg(regs::RAL0, u32::from_le_bytes(mac[..4].try_into().unwrap()));
+        self.write_reg(
+            regs::RAH0,
+            u16::from_le_bytes(mac[4..].try_into().unwrap()) as u32 | regs::RAH_AV,
+        );
+        mac
+    }
+
+    fn read_eeprom(&self, addr: u8) -> u16 {
+        // The address field and the done bit moved in the 82574.
+        let (addr_shift, done) = if self.is_e1000e {
+            (2, 1 << 1)
+        } else {
+            (8, 1 << 4)
+        };
+        self.write_reg(regs::EERD, ((addr as u32) << addr_shift) | 1);
+        loop {
+            let val = self.read_reg(regs::EERD);
+            if val & done != 0 {
+                return (val >> 16) as u16;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn init_rx(&mut self) -> DevResult {
+        for i in 0..128 {
+            self.write_reg(regs::MTA + i * 4, 0);
+        }
+
+        // Keep the slot at the tail empty, so that a full ring can be told
+        // apart from an empty one.
+        for i in 0..QUEUE_SIZE - 1 {
+            let buf = self.buf_pool.alloc_boxed().ok_or(DevError::NoMemory)?;
+            self.rx_ring.write(i, rx_desc(&buf));
+            self.rx_buffers[i] = Some(buf);
+        }
+        self.rx_tail = QUEUE_SIZE - 1;
+
+        let addr = self.rx_ring.bus_addr();
+        self.write_reg(regs::RDBAL, addr as u32);
+        self.write_reg(regs::RDBAH, (addr >> 32) as u32);
+        self.write_reg(regs::RDLEN, (QUEUE_SIZE * size_of::<RxDesc>()) as u32);
+        self.write_reg(regs::RDH, 0);
+        self.write_reg(regs::RDT, self.rx_tail as u32);
+        // 2048-byte buffers, accept broadcast, strip CRC.
+        self.write_reg(
+            regs::RCTL,
+            regs::RCTL_EN | regs::RCTL_BAM | regs::RCTL_SECRC,
+        );
+        Ok(())
+    }
+
+    fn init_tx(&mut self) {
+        let addr = self.tx_ring.bus_addr();
+        self.write_reg(regs::TDBAL, addr as u32);
+        self.write_reg(regs::TDBAH, (addr >> 32) as u32);
+        self.write_reg(regs::TDLEN, (QUEUE_SIZE * size_of::<TxDesc>()) as u32);
+        self.write_reg(regs::TDH, 0);
+        self.write_reg(regs::TDT, 0);
+        self.write_reg(
+            regs::TCTL,
+            regs::TCTL_EN | regs::TCTL_PSP | regs::TCTL_CT | regs::TCTL_COLD,
+        );
+        // Recommended IPG for copper: IPGT 10, IPGR1 8, IPGR2 6.
+        self.write_reg(regs::TIPG, 10 | (8 << 10) | (6 << 20));
+    }
+}
+
+/// Returns the physical address of `buf`, which comes from a pool allocated
+/// in one physically contiguous block.
+fn buf_phys_addr(buf: &[u8]) -> u64 {
+    kernel_virt_to_phys((buf.as_ptr() as usize).into())
+        .expect("e1000: unmapped DMA buffer")
+        .as_usize() as u64
+}
+
+fn rx_desc(buf: &NetBuf) -> RxDesc {
+    RxDesc {
+        addr: buf_phys_addr(buf.raw_buf()),
+        ..Default::default()
+    }
+}
+
+impl BaseDriverOps for E1000Nic {
+    fn device_name(&self) -> &str {
+        if self.is_e1000e { "e1000e" } else { "e1000" }
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Net
+    }
+}
+
+impl NetDriverOps for E1000Nic {
+    fn mac_address(&self) -> EthernetAddress {
+        EthernetAddress(self.mac)
+    }
+
+    fn can_transmit(&self) -> bool {
+        (self.tx_tail + 1) % QUEUE_SIZE != self.tx_clean
+    }
+
+    fn can_receive(&self) -> bool {
+        self.rx_ring.read(self.rx_next).status & DESC_STATUS_DD != 0
+    }
+
+    fn rx_queue_size(&self) -> usize {
+        QUEUE_SIZE
+    }
+
+    fn tx_queue_size(&self) -> usize {
+        QUEUE_SIZE
+    }
+
+    fn recycle_rx_buffer(&mut self, rx_buf: NetBufPtr) -> DevResult {
+        let rx_buf = unsafe { NetBuf::from_buf_ptr(rx_buf) };
+        if self.rx_buffers[self.rx_tail].is_some() {
+            return Err(DevError::BadState);
+        }
+        self.rx_ring.write(self.rx_tail, rx_desc(&rx_buf));
+        self.rx_buffers[self.rx_tail] = Some(rx_buf);
+        self.rx_tail = (self.rx_tail + 1) % QUEUE_SIZE;
+        self.write_reg(regs::RDT, self.rx_tail as u32);
+        Ok(())
+    }
+
+    fn recycle_tx_buffers(&mut self) -> DevResult {
+        while self.tx_clean != self.tx_tail
+            && self.tx_ring.read(self.tx_clean).status & DESC_STATUS_DD != 0
+        {
+            self.tx_buffers[self.tx_clean]
+                .take()
+                .ok_or(DevError::BadState)?;
+            self.tx_clean = (self.tx_clean + 1) % QUEUE_SIZE;
+        }
+        Ok(())
+    }
+
+    fn transmit(&mut self, tx_buf: NetBufPtr) -> DevResult {
+        if !self.can_transmit() {
+            return Err(DevError::Again);
+        }
+        let tx_buf = unsafe { NetBuf::from_buf_ptr(tx_buf) };
+        let packet = tx_buf.packet_with_header();
+        self.tx_ring.write(
+            self.tx_tail,
+            TxDesc {
+                addr: buf_phys_addr(packet),
+                length: packet.len() as u16,
+                cmd: TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS,
+                ..Default::default()
+            },
+        );
+        self.tx_buffers[self.tx_tail] = Some(tx_buf);
+        self.tx_tail = (self.tx_tail + 1) % QUEUE_SIZE;
+        self.write_reg(regs::TDT, self.tx_tail as u32);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> DevResult<NetBufPtr> {
+        let desc = self.rx_ring.read(self.rx_next);
+        if desc.status & DESC_STATUS_DD == 0 {
+            return Err(DevError::Again);
+        }
+        let mut rx_buf = self.rx_buffers[self.rx_next]
+            .take()
+            .ok_or(DevError::BadState)?;
+        self.rx_ring.write(self.rx_next, RxDesc::default());
+        self.rx_next = (self.rx_next + 1) % QUEUE_SIZE;
+
+        // Frames spanning several buffers are not expected with a 1500 MTU.
+        if desc.status & RX_STATUS_EOP == 0 || desc.errors != 0 {
+            warn!(
+                "{}: dropped frame (status={:#x}, errors={:#x})",
+                self.device_name(),
+                desc.status,
+                desc.errors
+            );
+            self.recycle_rx_buffer(rx_buf.into_buf_ptr())?;
+            return Err(DevError::Again);
+        }
+
+        rx_buf.set_header_len(0);
+        rx_buf.set_packet_len(desc.length as usize);
+        Ok(rx_buf.into_buf_ptr())
+    }
+
+    fn alloc_tx_buffer(&mut self, size: usize) -> DevResult<NetBufPtr> {
+        if size > BUF_LEN {
+            return Err(DevError::InvalidParam);
+        }
+        let mut tx_buf = self.buf_pool.alloc_boxed().ok_or(DevError::NoMemory)?;
+        tx_buf.set_header_len(0);
+        tx_buf.set_packet_len(size);
+        Ok(tx_buf.into_buf_ptr())
+    }
+}
+
+impl Drop for E1000Nic {
+    fn drop(&mut self) {
+        // Stop DMA before the rings and buffers are freed.
+        self.write_reg(regs::RCTL, 0);
+        self.write_reg(regs::TCTL, 0);
+    }
+}
@@ -32,13 +32,17 @@
 //! |-|-|-|
 //! | Block | `ramdisk` | A RAM disk that stores data in a vector |
 //! | Block | `virtio-blk` | VirtIO block device |
+//! | Block | `nvme` | NVM Express controller on the PCI bus |
 //! | Network | `virtio-net` | VirtIO network device |
+//! | Network | `e1000` | Intel 8254x (e1000) and 82574 (e1000e) NICs |
 //! | Display | `virtio-gpu` | VirtIO graphics device |
 //!
 //! # Other Cargo Features
 //!
 //! - `dyn`: use the dynamic device model (see above).
-//! - `bus-mmio`: use device tree to probe all MMIO devices.
+//! - `bus-mmio`: probe VirtIO MMIO devices from the `virtio-mmio-regions` slot
+//!   table of the platform config. Interrupt lines are taken from the
+//!   `virtio-mmio-irqs` table.
 //! - `bus-pci`: use PCI bus to probe all PCI devices. This feature is
 //!   enabled by default.
 //! - `virtio`: use VirtIO devices. This is enabled if any of `virtio-blk`,
@@ -81,6 +85,12 @@ mod virtio;
 #[cfg(feature = "ixgbe")]
 mod ixgbe;
 
+#[cfg(feature = "nvme")]
+mod nvme;
+
+#[cfg(feature = "e1000")]
+mod e1000;
+
 pub mod prelude;
 
 #[allow(unused_imports)]
@@ -59,11 +59,21 @@ macro_rules! for_each_drivers {
             type $drv_type = crate::drivers::BcmSdhciDriver;
             $code
         }
+        #[cfg(block_dev = "nvme")]
+        {
+            type $drv_type = crate::drivers::NvmeDriver;
+            $code
+        }
         #[cfg(net_dev = "ixgbe")]
         {
             type $drv_type = crate::drivers::IxgbeDriver;
             $code
         }
+        #[cfg(net_dev = "e1000")]
+        {
+            type $drv_type = crate::drivers::E1000Driver;
+            $code
+        }
         #[cfg(net_dev = "fxmac")]
         {
             type $drv_type = crate::drivers::FXmacDriver;
@@ -0,0 +1,677 @@
+//! NVMe block device driver over PCI.
+//!
+//! The driver uses one admin queue pair and one I/O queue pair, both allocated
+//! from [`axdma`]. Since [`BlockDriverOps`] is synchronous, the CPU waits for
+//! each completion. If the controller supports MSI-X and the platform
+//! message-signaled interrupts, I/O completions raise an interrupt, and the CPU
+//! sleeps until it instead of spinning. Otherwise MSI-X is enabled with all
+//! vectors masked, so that the device never falls back to pin-based
+//! interrupts, and completions are polled.
+//!
+//! Whatever the LBA size of the namespace, the device is exposed with blocks
+//! of 512 bytes, going through the bounce buffer.
+
+use alloc::string::String;
+use core::alloc::Layout;
+use core::ptr::{read_volatile, write_volatile};
+
+use axdma::{DMAInfo, alloc_coherent, dealloc_coherent};
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use axdriver_block::BlockDriverOps;
+use axdriver_pci::DeviceFunction;
+use axhal::mem::phys_to_virt;
+use axhal::time::{Duration, monotonic_time};
+
+/// PCI class code of mass storage controllers.
+pub const PCI_CLASS_STORAGE: u8 = 0x01;
+/// PCI subclass code of non-volatile memory controllers.
+pub const PCI_SUBCLASS_NVM: u8 = 0x08;
+/// PCI programming interface of NVM Express controllers.
+pub const PCI_PROG_IF_NVME: u8 = 0x02;
+
+const PAGE_SIZE: usize = 0x1000;
+
+/// Size of the blocks exposed by the device.
+const BLOCK_SIZE: usize = 512;
+
+/// Number of entries of each submission/completion queue.
+const QUEUE_DEPTH: u16 = 32;
+
+/// ID of the only I/O queue pair.
+const IO_QUEUE_ID: u16 = 1;
+
+/// ID of the namespace exposed as the block device.
+const NAMESPACE_ID: u32 = 1;
+
+/// PCI capability ID of MSI-X.
+const PCI_CAP_ID_MSIX: u8 = 0x11;
+
+mod regs {
+    /// Controller capabilities.
+    pub const CAP: usize = 0x00;
+    /// Controller configuration.
+    pub const CC: usize = 0x14;
+    /// Controller status.
+    pub const CSTS: usize = 0x1c;
+    /// Admin queue attributes.
+    pub const AQA: usize = 0x24;
+    /// Admin submission queue base address.
+    pub const ASQ: usize = 0x28;
+    /// Admin completion queue base address.
+    pub const ACQ: usize = 0x30;
+    /// First doorbell register.
+    pub const DOORBELL_BASE: usize = 0x1000;
+
+    pub const CC_ENABLE: u32 = 1 << 0;
+    /// I/O submission queue entry size: 64 bytes (2^6).
+    pub const CC_IOSQES: u32 = 6 << 16;
+    /// I/O completion queue entry size: 16 bytes (2^4).
+    pub const CC_IOCQES: u32 = 4 << 20;
+
+    pub const CSTS_READY: u32 = 1 << 0;
+    pub const CSTS_FATAL: u32 = 1 << 1;
+}
+
+mod opcode {
+    pub const ADMIN_CREATE_IO_SQ: u8 = 0x01;
+    pub const ADMIN_CREATE_IO_CQ: u8 = 0x05;
+    pub const ADMIN_IDENTIFY: u8 = 0x06;
+
+    pub const IO_FLUSH: u8 = 0x00;
+    pub const IO_WRITE: u8 = 0x01;
+    pub const IO_READ: u8 = 0x02;
+}
+
+/// A submission queue entry.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct NvmeCommand {
+    opcode: u8,
+    flags: u8,
+    cid: u16,
+    nsid: u32,
+    cdw2: u32,
+    cdw3: u32,
+    mptr: u64,
+    prp1: u64,
+    prp2: u64,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+}
+
+/// A completion queue entry.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct NvmeCompletion {
+    result: u32,
+    _rsvd: u32,
+    sq_head: u16,
+    sq_id: u16,
+    cid: u16,
+    status: u16,
+}
+
+const _: () = assert!(size_of::<NvmeCommand>() == 64);
+const _: () = assert!(size_of::<NvmeCompletion>() == 16);
+
+/// A DMA buffer of whole pages.
+struct DmaPages {
+    info: DMAInfo,
+    layout: Layout,
+}
+
+impl DmaPages {
+    fn new(num_pages: usize) -> DevResult<Self> {
+        let layout = Layout::from_size_align(num_pages * PAGE_SIZE, PAGE_SIZE).unwrap();
+        let info = unsafe { alloc_coherent(layout) }.map_err(|_| DevError::NoMemory)?;
+        unsafe { core::ptr::write_bytes(info.cpu_addr.as_ptr(), 0, layout.size()) };
+        Ok(Self { info, layout })
+    }
+
+    fn bus_addr(&self) -> u64 {
+        self.info.bus_addr.as_u64()
+    }
+
+    fn as_ptr<T>(&self) -> *mut T {
+        self.info.cpu_addr.as_ptr() as *mut T
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.info.cpu_addr.as_ptr(), self.layout.size()) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.info.cpu_addr.as_ptr(), self.layout.size()) }
+    }
+}
+
+impl Drop for DmaPages {
+    fn drop(&mut self) {
+        unsafe { dealloc_coherent(self.info, self.layout) };
+    }
+}
+
+/// A submission/completion queue pair.
+struct QueuePair {
+    id: u16,
+    sq: DmaPages,
+    cq: DmaPages,
+    sq_tail: u16,
+    cq_head: u16,
+    phase: bool,
+    /// ID of the next command, not reused before 65536 other commands, so
+    /// that a late completion is never taken for the one of a later command.
+    next_cid: u16,
+    /// The CPU the completion interrupts are sent to, if they are enabled.
+    irq_cpu: Option<usize>,
+}
+
+impl QueuePair {
+    fn new(id: u16) -> DevResult<Self> {
+        Ok(Self {
+            id,
+            sq: DmaPages::new(
+                (QUEUE_DEPTH as usize * size_of::<NvmeCommand>()).div_ceil(PAGE_SIZE),
+            )?,
+            cq: DmaPages::new(
+                (QUEUE_DEPTH as usize * size_of::<NvmeCompletion>()).div_ceil(PAGE_SIZE),
+            )?,
+            sq_tail: 0,
+            cq_head: 0,
+            phase: true,
+            next_cid: 0,
+            irq_cpu: None,
+        })
+    }
+
+    /// Empties the queues, after the controller has been reset.
+    fn clear(&mut self) {
+        self.sq.as_mut_slice().fill(0);
+        self.cq.as_mut_slice().fill(0);
+        self.sq_tail = 0;
+        self.cq_head = 0;
+        self.phase = true;
+    }
+
+    /// Returns the completion entry at the head, if the controller has posted
+    /// it.
+    fn peek(&self) -> Option<NvmeCompletion> {
+        let entry = self.cq.as_ptr::<NvmeCompletion>();
+        let completion = unsafe { read_volatile(entry.add(self.cq_head as usize)) };
+        ((completion.status & 1 != 0) == self.phase).then_some(completion)
+    }
+
+    /// Waits a while for a new completion: until the next interrupt if the
+    /// completions of the queue raise one on this CPU, or one spin otherwise.
+    fn relax(&self) {
+        #[cfg(all(target_arch = "x86_64", target_os = "none"))]
+        if self.irq_cpu == Some(axhal::cpu::this_cpu_id()) && axhal::arch::irqs_enabled() {
+            axhal::arch::disable_irqs();
+            if self.peek().is_some() {
+                axhal::arch::enable_irqs();
+            } else {
+                // `sti` takes effect after the next instruction, so that the
+                // interrupt can't come in between and be missed by `hlt`.
+                unsafe { core::arch::asm!("sti; hlt") };
+            }
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// The NVMe block device.
+pub struct NvmeDev {
+    mmio_base: usize,
+    doorbell_stride: usize,
+    timeout: Duration,
+    admin: QueuePair,
+    io: QueuePair,
+    /// Bounce buffer for block transfers, one page long.
+    buffer: DmaPages,
+    model: String,
+    /// Number of blocks of [`BLOCK_SIZE`] bytes.
+    num_blocks: u64,
+    /// Size of the LBAs of the namespace.
+    lba_size: usize,
+}
+
+unsafe impl Send for NvmeDev {}
+unsafe impl Sync for NvmeDev {}
+
+impl NvmeDev {
+    /// Initializes the controller of the PCI function at `bdf`, whose
+    /// registers are mapped at `mmio_base` (virtual address of BAR0).
+    pub fn init(bdf: DeviceFunction, mmio_base: usize) -> DevResult<Self> {
+        let cap = unsafe { read_volatile((mmio_base + regs::CAP) as *const u64) };
+        let max_entries = (cap & 0xffff) as u16 + 1;
+        if max_entries < QUEUE_DEPTH {
+            warn!("nvme: queues of {} entries are not supported", QUEUE_DEPTH);
+            return Err(DevError::Unsupported);
+        }
+        let mut dev = Self {
+            mmio_base,
+            doorbell_stride: 4 << ((cap >> 32) & 0xf),
+            // CAP.TO is in units of 500 milliseconds.
+            timeout: Duration::from_millis(((cap >> 24) & 0xff).max(1) * 500),
+            admin: QueuePair::new(0)?,
+            io: QueuePair::new(IO_QUEUE_ID)?,
+            buffer: DmaPages::new(1)?,
+            model: String::new(),
+            num_blocks: 0,
+            lba_size: 0,
+        };
+
+        dev.io.irq_cpu = enable_msix(bdf, mmio_base);
+        dev.reset()?;
+        dev.identify()?;
+        dev.create_io_queues()?;
+        Ok(dev)
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.mmio_base + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.mmio_base + offset) as *mut u32, value) }
+    }
+
+    fn write_reg64(&self, offset: usize, value: u64) {
+        self.write_reg(offset, value as u32);
+        self.write_reg(offset + 4, (value >> 32) as u32);
+    }
+
+    fn wait_ready(&self, ready: bool) -> DevResult {
+        let deadline = monotonic_time() + self.timeout;
+        loop {
+            let csts = self.read_reg(regs::CSTS);
+            if csts & regs::CSTS_FATAL != 0 {
+                error!("nvme: controller fatal status");
+                return Err(DevError::Io);
+            }
+            if (csts & regs::CSTS_READY != 0) == ready {
+                return Ok(());
+            }
+            if monotonic_time() > deadline {
+                warn!("nvme: timed out waiting for CSTS.RDY={}", ready as u8);
+                return Err(DevError::Io);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Resets the controller and sets up the admin queue pair.
+    fn reset(&mut self) -> DevResult {
+        self.write_reg(regs::CC, self.read_reg(regs::CC) & !regs::CC_ENABLE);
+        self.wait_ready(false)?;
+
+        let depth = (QUEUE_DEPTH - 1) as u32;
+        self.write_reg(regs::AQA, (depth << 16) | depth);
+        self.write_reg64(regs::ASQ, self.admin.sq.bus_addr());
+        self.write_reg64(regs::ACQ, self.admin.cq.bus_addr());
+
+        // NVM command set, 4K memory pages, round robin arbitration.
+        self.write_reg(
+            regs::CC,
+            regs::CC_ENABLE | regs::CC_IOSQES | regs::CC_IOCQES,
+        );
+        self.wait_ready(true)
+    }
+
+    /// Reads the model number and the geometry of namespace 1.
+    fn identify(&mut self) -> DevResult {
+        // CNS 1: identify controller.
+        self.submit_admin(NvmeCommand {
+            opcode: opcode::ADMIN_IDENTIFY,
+            prp1: self.buffer.bus_addr(),
+            cdw10: 1,
+            ..Default::default()
+        })?;
+        self.model = String::from_utf8_lossy(&self.buffer.as_slice()[24..64])
+            .trim()
+            .into();
+
+        // CNS 0: identify namespace.
+        self.submit_admin(NvmeCommand {
+            opcode: opcode::ADMIN_IDENTIFY,
+            nsid: NAMESPACE_ID,
+            prp1: self.buffer.bus_addr(),
+            cdw10: 0,
+            ..Default::default()
+        })?;
+        let data = self.buffer.as_slice();
+        let nsze = u64::from_le_bytes(data[0..8].try_into().unwrap());
+        let flbas = (data[26] & 0xf) as usize;
+        let lbaf = u32::from_le_bytes(data[128 + flbas * 4..132 + flbas * 4].try_into().unwrap());
+        let lbads = (lbaf >> 16) & 0xff;
+        if nsze == 0 || !(9..=12).contains(&lbads) {
+            warn!(
+                "nvme: unsupported namespace (nsze={}, lbads={})",
+                nsze, lbads
+            );
+            return Err(DevError::Unsupported);
+        }
+        self.num_blocks = nsze << (lbads - 9);
+        self.lba_size = 1 << lbads;
+        info!(
+            "nvme: {:?}, {} LBAs of {} bytes",
+            self.model, nsze, self.lba_size
+        );
+        Ok(())
+    }
+
+    fn create_io_queues(&mut self) -> DevResult {
+        let qid = self.io.id as u32;
+        let size = (QUEUE_DEPTH - 1) as u32;
+        // Physically contiguous, interrupts on MSI-X vector 0 if enabled.
+        let interrupts = if self.io.irq_cpu.is_some() { 1 << 1 } else { 0 };
+        self.submit_admin(NvmeCommand {
+            opcode: opcode::ADMIN_CREATE_IO_CQ,
+            prp1: self.io.cq.bus_addr(),
+            cdw10: (size << 16) | qid,
+            cdw11: interrupts | 1,
+            ..Default::default()
+        })?;
+        // Physically contiguous, bound to the completion queue above.
+        self.submit_admin(NvmeCommand {
+            opcode: opcode::ADMIN_CREATE_IO_SQ,
+            prp1: self.io.sq.bus_addr(),
+            cdw10: (size << 16) | qid,
+            cdw11: (qid << 16) | 1,
+            ..Default::default()
+        })?;
+        Ok(())
+    }
+
+    fn submit_admin(&mut self, cmd: NvmeCommand) -> DevResult<u32> {
+        let (mmio_base, stride, timeout) = (self.mmio_base, self.doorbell_stride, self.timeout);
+        let completion =
+            submit(&mut self.admin, mmio_base, stride, timeout, cmd).ok_or(DevError::Io)?;
+        check_completion(&self.admin, &cmd, &completion)
+    }
+
+    fn submit_io(&mut self, cmd: NvmeCommand) -> DevResult<u32> {
+        let (mmio_base, stride, timeout) = (self.mmio_base, self.doorbell_stride, self.timeout);
+        let Some(completion) = submit(&mut self.io, mmio_base, stride, timeout, cmd) else {
+            if let Err(e) = self.recover() {
+                error!("nvme: failed to reset the controller: {:?}", e);
+            }
+            return Err(DevError::Io);
+        };
+        check_completion(&self.io, &cmd, &completion)
+    }
+
+    /// Resets the controller and recreates the I/O queues, after a command
+    /// timed out.
+    ///
+    /// The reset aborts the commands in flight, so that none of them
+    /// completes later into the queues or the bounce buffer.
+    fn recover(&mut self) -> DevResult {
+        warn!("nvme: resetting the controller");
+        self.admin.clear();
+        self.io.clear();
+        self.reset()?;
+        self.create_io_queues()
+    }
+
+    /// Transfers `count` LBAs starting from `lba` between the device and the
+    /// bounce buffer.
+    fn transfer(&mut self, opcode: u8, lba: u64, count: usize) -> DevResult {
+        self.submit_io(NvmeCommand {
+            opcode,
+            nsid: NAMESPACE_ID,
+            prp1: self.buffer.bus_addr(),
+            cdw10: lba as u32,
+            cdw11: (lba >> 32) as u32,
+            cdw12: (count - 1) as u32,
+            ..Default::default()
+        })?;
+        Ok(())
+    }
+
+    /// Checks that the request of `len` bytes from the block `block_id` is
+    /// made of whole blocks within the device.
+    fn check_request(&self, block_id: u64, len: usize) -> DevResult {
+        if len % BLOCK_SIZE != 0 {
+            return Err(DevError::InvalidParam);
+        }
+        if block_id + (len / BLOCK_SIZE) as u64 > self.num_blocks {
+            return Err(DevError::InvalidParam);
+        }
+        Ok(())
+    }
+
+    /// Returns the LBAs holding the first bytes of the `len` ones from the
+    /// offset `pos`, as many as fit in the bounce buffer: the first LBA, the
+    /// number of LBAs, the offset of `pos` in the first LBA, and the number of
+    /// bytes covered.
+    fn span(&self, pos: u64, len: usize) -> (u64, usize, usize, usize) {
+        let lba = pos / self.lba_size as u64;
+        let skip = (pos % self.lba_size as u64) as usize;
+        let len = len.min(PAGE_SIZE - skip);
+        (lba, (skip + len).div_ceil(self.lba_size), skip, len)
+    }
+}
+
+/// Returns the address of a 32-bit register in the PCI configuration space
+/// of `bdf`, through the ECAM window.
+fn config_reg(bdf: DeviceFunction, offset: u8) -> *mut u32 {
+    let ecam_offset = ((bdf.bus as usize) << 20)
+        | ((bdf.device as usize) << 15)
+        | ((bdf.function as usize) << 12)
+        | (offset & !0b11) as usize;
+    phys_to_virt((axconfig::devices::PCI_ECAM_BASE + ecam_offset).into()).as_mut_ptr() as _
+}
+
+/// Handles the completion interrupts, which only wake up the CPU waiting in
+/// [`QueuePair::relax`].
+fn handle_irq() {}
+
+/// Enables MSI-X if the function supports it, with the completions of vector 0
+/// raising an interrupt on this CPU, and the other vectors masked.
+///
+/// If the platform can't take the interrupt, all vectors are masked. Returns
+/// the CPU the interrupts are sent to, if they are enabled.
+fn enable_msix(bdf: DeviceFunction, mmio_base: usize) -> Option<usize> {
+    const STATUS_CAP_LIST: u32 = 1 << 20;
+    const CAP_POINTER: u8 = 0x34;
+
+    let status_command = unsafe { read_volatile(config_reg(bdf, 0x04)) };
+    if status_command & STATUS_CAP_LIST == 0 {
+        debug!("nvme: no capability list, using polled completions");
+        return None;
+    }
+    let mut offset = unsafe { read_volatile(config_reg(bdf, CAP_POINTER)) } as u8 & !0b11;
+    while offset != 0 {
+        let reg = config_reg(bdf, offset);
+        let header = unsafe { read_volatile(reg) };
+        if header as u8 != PCI_CAP_ID_MSIX {
+            offset = (header >> 8) as u8 & !0b11;
+            continue;
+        }
+        // The table of vectors, in BAR0 for the controllers we support.
+        let table = unsafe { read_volatile(config_reg(bdf, offset + 4)) };
+        let msg = if table & 0b111 == 0 {
+            axhal::irq::alloc_msi(handle_irq)
+        } else {
+            None
+        };
+        // Message control: bit 31 enables MSI-X, bit 30 masks all vectors.
+        let Some(msg) = msg else {
+            unsafe { write_volatile(reg, header | (1 << 31) | (1 << 30)) };
+            debug!("nvme: MSI-X enabled with all vectors masked, using polled completions");
+            return None;
+        };
+        // Address, data, and vector control (unmasked) of vector 0.
+        let entry = (mmio_base + (table & !0b111) as usize) as *mut u32;
+        unsafe {
+            write_volatile(entry, msg.addr as u32);
+            write_volatile(entry.add(1), (msg.addr >> 32) as u32);
+            write_volatile(entry.add(2), msg.data);
+            write_volatile(entry.add(3), 0);
+            write_volatile(reg, (header | (1 << 31)) & !(1 << 30));
+        }
+        debug!(
+            "nvme: MSI-X enabled with {} vectors, completions on IRQ {}",
+            ((header >> 16) & 0x7ff) + 1,
+            msg.vector
+        );
+        return Some(axhal::cpu::this_cpu_id());
+    }
+    debug!("nvme: MSI-X not available, using polled completions");
+    None
+}
+
+/// Submits one command to the queue pair and waits for its completion.
+///
+/// Completions of earlier commands that are not waited for any more are
+/// skipped. Returns `None` if the command timed out, leaving it in flight.
+fn submit(
+    queue: &mut QueuePair,
+    mmio_base: usize,
+    doorbell_stride: usize,
+    timeout: Duration,
+    mut cmd: NvmeCommand,
+) -> Option<NvmeCompletion> {
+    let sq_doorbell = mmio_base + regs::DOORBELL_BASE + (2 * queue.id as usize) * doorbell_stride;
+    let cq_doorbell = sq_doorbell + doorbell_stride;
+
+    cmd.cid = queue.next_cid;
+    queue.next_cid = queue.next_cid.wrapping_add(1);
+    unsafe {
+        write_volatile(
+            queue.sq.as_ptr::<NvmeCommand>().add(queue.sq_tail as usize),
+            cmd,
+        );
+    }
+    queue.sq_tail = (queue.sq_tail + 1) % QUEUE_DEPTH;
+    unsafe { write_volatile(sq_doorbell as *mut u32, queue.sq_tail as u32) };
+
+    let deadline = monotonic_time() + timeout;
+    loop {
+        let Some(completion) = queue.peek() else {
+            if monotonic_time() > deadline {
+                warn!(
+                    "nvme: command {:#x} on queue {} timed out",
+                    cmd.opcode, queue.id
+                );
+                return None;
+            }
+            queue.relax();
+            continue;
+        };
+
+        queue.cq_head += 1;
+        if queue.cq_head == QUEUE_DEPTH {
+            queue.cq_head = 0;
+            queue.phase = !queue.phase;
+        }
+        unsafe { write_volatile(cq_doorbell as *mut u32, queue.cq_head as u32) };
+
+        if completion.cid == cmd.cid {
+            return Some(completion);
+        }
+        warn!(
+            "nvme: skipped stale completion of command {} on queue {}",
+            completion.cid, queue.id
+        );
+    }
+}
+
+/// Checks the status of the `completion` of `cmd` on `queue`.
+///
+/// Returns the command-specific result (DW0 of the completion entry).
+fn check_completion(
+    queue: &QueuePair,
+    cmd: &NvmeCommand,
+    completion: &NvmeCompletion,
+) -> DevResult<u32> {
+    let status = completion.status >> 1;
+    if status != 0 {
+        warn!(
+            "nvme: command {:#x} on queue {} failed: sct={:#x}, sc={:#x}",
+            cmd.opcode,
+            queue.id,
+            (status >> 8) & 0x7,
+            status & 0xff
+        );
+        return Err(DevError::Io);
+    }
+    Ok(completion.result)
+}
+
+impl BaseDriverOps for NvmeDev {
+    fn device_name(&self) -> &str {
+        "nvme"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+}
+
+impl BlockDriverOps for NvmeDev {
+    #[inline]
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    #[inline]
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        self.check_request(block_id, buf.len())?;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = block_id * BLOCK_SIZE as u64 + done as u64;
+            let (lba, count, skip, len) = self.span(pos, buf.len() - done);
+            self.transfer(opcode::IO_READ, lba, count)?;
+            buf[done..done + len].copy_from_slice(&self.buffer.as_slice()[skip..skip + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> DevResult {
+        self.check_request(block_id, buf.len())?;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = block_id * BLOCK_SIZE as u64 + done as u64;
+            let (lba, count, skip, len) = self.span(pos, buf.len() - done);
+            if skip != 0 || (skip + len) % self.lba_size != 0 {
+                // Keep the bytes of the partially written LBAs.
+                self.transfer(opcode::IO_READ, lba, count)?;
+            }
+            self.buffer.as_mut_slice()[skip..skip + len].copy_from_slice(&buf[done..done + len]);
+            self.transfer(opcode::IO_WRITE, lba, count)?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> DevResult {
+        self.submit_io(NvmeCommand {
+            opcode: opcode::IO_FLUSH,
+            nsid: NAMESPACE_ID,
+            ..Default::default()
+        })?;
+        Ok(())
+    }
+}
+
+impl Drop for NvmeDev {
+    fn drop(&mut self) {
+        // Disable the controller before freeing the queues it may access.
+        self.write_reg(regs::CC, self.read_reg(regs::CC) & !regs::CC_ENABLE);
+        let _ = self.wait_ready(false);
+    }
+}
@@ -1,11 +1,13 @@
-use crate::alloc::string::ToString;
+use alloc::string::String;
 use axalloc::global_allocator;
-use axdevice_event;
+use axdevice_event::{self, DiscoveredDeviceInfo};
 use axdriver_base::{BaseDriverOps, DevResult, DeviceType};
-use axdriver_pci::BarInfo;
 use axdriver_virtio::{BufferDirection, PhysAddr, VirtIoHal};
 use axhal::mem::PhysAddr as PhysAddrTrait;
-use axhal::mem::{phys_to_virt, virt_to_phys};
+use axhal::mem::{
+    MemoryAddr, PAGE_SIZE_4K, VirtAddr, is_vmalloc_addr, kernel_virt_to_phys, phys_to_virt,
+    virt_to_phys,
+};
 use cfg_if::cfg_if;
 use core::{marker::PhantomData, ptr::NonNull};
 
@@ -13,7 +15,8 @@ use crate::{AxDeviceEnum, drivers::DriverProbe};
 
 cfg_if! {
     if #[cfg(bus = "pci")] {
-        use axdriver_pci::{PciRoot, DeviceFunction, DeviceFunctionInfo};
+        use crate::alloc::string::ToString;
+        use axdriver_pci::{BarInfo, PciRoot, DeviceFunction, DeviceFunctionInfo};
         type VirtIoTransport = axdriver_virtio::PciTransport;
     } else if #[cfg(bus =  "mmio")] {
         type VirtIoTransport = axdriver_virtio::MmioTransport;
@@ -78,6 +81,50 @@ cfg_if! {
 /// A common driver for all VirtIO devices that implements [`DriverProbe`].
 pub struct VirtIoDriver<D: VirtIoDevMeta + ?Sized>(PhantomData<D>);
 
+impl<D: VirtIoDevMeta + ?Sized> VirtIoDriver<D> {
+    /// Returns the device name, e.g., `virtio-blk-00:02.0` or `virtio-net-mmio@10008000`.
+    fn device_name(location: &str) -> String {
+        format!(
+            "virtio-{}-{}",
+            match D::DEVICE_TYPE {
+                DeviceType::Net => "net",
+                DeviceType::Block => "blk",
+                DeviceType::Display => "gpu",
+                _ => "unknown",
+            },
+            location
+        )
+    }
+
+    /// Publishes the device to [`axdevice_event`], so that other modules
+    /// (e.g., axuio) can pick up its registers and interrupt line.
+    fn publish(
+        location: String,
+        mmio_region: Option<(PhysAddrTrait, usize)>,
+        irq_num: Option<usize>,
+    ) {
+        let info = DiscoveredDeviceInfo {
+            device_type: D::DEVICE_TYPE,
+            name: Self::device_name(&location),
+            pci_bdf: location,
+            mmio_region,
+            irq_num,
+        };
+        info!("Published device info for {}.", info.pci_bdf);
+        axdevice_event::publish_device_info(info);
+    }
+}
+
+/// Returns the interrupt line wired to the VirtIO MMIO slot at `mmio_base`,
+/// according to the `virtio-mmio-irqs` table of the platform config.
+#[cfg(bus = "mmio")]
+fn virtio_mmio_irq(mmio_base: usize) -> Option<usize> {
+    axconfig::devices::VIRTIO_MMIO_REGIONS
+        .iter()
+        .position(|reg| reg.0 == mmio_base)
+        .and_then(|slot| axconfig::devices::VIRTIO_MMIO_IRQS.get(slot).copied())
+}
+
 impl<D: VirtIoDevMeta> DriverProbe for VirtIoDriver<D> {
     #[cfg(bus = "mmio")]
     fn probe_mmio(mmio_base: usize, mmio_size: usize) -> Option<AxDeviceEnum> {
@@ -86,6 +133,20 @@ impl<D: VirtIoDevMeta> DriverProbe for VirtIoDriver<D> {
             axdriver_virtio::probe_mmio_device(base_vaddr.as_mut_ptr(), mmio_size)
             && ty == D::DEVICE_TYPE
         {
+            let irq_num = virtio_mmio_irq(mmio_base);
+            if irq_num.is_none() {
+                warn!(
+                    "VirtIO MMIO device at [PA:{:#x}, PA:{:#x}): no IRQ in the slot table.",
+                    mmio_base,
+                    mmio_base + mmio_size
+                );
+            }
+            Self::publish(
+                format!("mmio@{:x}", mmio_base),
+                Some((PhysAddrTrait::from(mmio_base), mmio_size)),
+                irq_num,
+            );
+
             match D::try_new(transport) {
                 Ok(dev) => return Some(dev),
                 Err(e) => {
@@ -177,17 +238,6 @@ impl<D: VirtIoDevMeta> DriverProbe for VirtIoDriver<D> {
             bdf, irq_num
         );
 
-        let device_name_str = format!(
-            "virtio-{}-{}",
-            match D::DEVICE_TYPE {
-                DeviceType::Net => "net",
-                DeviceType::Block => "blk",
-                DeviceType::Display => "gpu",
-                _ => "Unknown",
-            },
-            bdf.to_string()
-        );
-
         // 如果是 Memory BAR，就发布其地址信息；否则，mmio_region 为 None。
         let mmio_region_info: Option<(PhysAddrTrait, usize)> = if is_memory_bar {
             Some((PhysAddrTrait::from(pci_bar_paddr_raw), pci_bar_size))
@@ -200,15 +250,7 @@ impl<D: VirtIoDevMeta> DriverProbe for VirtIoDriver<D> {
             bdf, mmio_region_info
         );
 
-        axdevice_event::publish_device_info(axdevice_event::DiscoveredDeviceInfo {
-            device_type: D::DEVICE_TYPE,
-            name: device_name_str,
-            pci_bdf: bdf.to_string(),
-            mmio_region: mmio_region_info,
-            irq_num: Some(irq_num),
-        });
-        info!("Published device info for {}.", bdf.to_string());
-        // 【【【 发布结束 】】】
+        Self::publish(bdf.to_string(), mmio_region_info, Some(irq_num));
 
         if let Some((ty, transport)) =
             axdriver_virtio::probe_pci_device::<VirtIoHalImpl>(root, bdf, dev_info)
@@ -254,12 +296,60 @@ unsafe impl VirtIoHal for VirtIoHalImpl {
         NonNull::new(phys_to_virt(paddr.into()).as_mut_ptr()).unwrap()
     }
 
-    #[inline]
-    unsafe fn share(buffer: NonNull<[u8]>, _direction: BufferDirection) -> PhysAddr {
-        let vaddr = buffer.as_ptr() as *mut u8 as usize;
-        virt_to_phys(vaddr.into()).into()
+    unsafe fn share(buffer: NonNull<[u8]>, direction: BufferDirection) -> PhysAddr {
+        let vaddr = VirtAddr::from(buffer.as_ptr() as *mut u8 as usize);
+        if let Some(paddr) = contiguous_paddr(vaddr, buffer.len()) {
+            return paddr.as_usize();
+        }
+        // Outside the linear mapping (e.g., on a kernel task stack) and
+        // crossing a page boundary, so bounce it through contiguous pages.
+        let (paddr, bounce) = Self::dma_alloc(bounce_pages(buffer.len()), direction);
+        assert_ne!(paddr, 0, "failed to allocate a DMA bounce buffer");
+        if !matches!(direction, BufferDirection::DeviceToDriver) {
+            unsafe {
+                bounce.copy_from_nonoverlapping(buffer.cast::<u8>(), buffer.len());
+            }
+        }
+        paddr
     }
 
-    #[inline]
-    unsafe fn unshare(_paddr: PhysAddr, _buffer: NonNull<[u8]>, _direction: BufferDirection) {}
+    unsafe fn unshare(paddr: PhysAddr, buffer: NonNull<[u8]>, direction: BufferDirection) {
+        let vaddr = VirtAddr::from(buffer.as_ptr() as *mut u8 as usize);
+        if contiguous_paddr(vaddr, buffer.len()).is_some_and(|p| p.as_usize() == paddr) {
+            return;
+        }
+        let bounce = NonNull::new(phys_to_virt(paddr.into()).as_mut_ptr()).unwrap();
+        if !matches!(direction, BufferDirection::DriverToDevice) {
+            unsafe {
+                buffer
+                    .cast::<u8>()
+                    .copy_from_nonoverlapping(bounce, buffer.len());
+            }
+        }
+        unsafe { Self::dma_dealloc(paddr, bounce, bounce_pages(buffer.len())) };
+    }
+}
+
+/// Returns the physical address of the buffer of `len` bytes at `vaddr`, if
+/// it is physically contiguous.
+///
+/// Buffers outside the linear mapping are backed by separate frames per
+/// page, which may or may not happen to be adjacent.
+fn contiguous_paddr(vaddr: VirtAddr, len: usize) -> Option<axhal::mem::PhysAddr> {
+    let start = kernel_virt_to_phys(vaddr)?;
+    if !is_vmalloc_addr(vaddr) {
+        return Some(start);
+    }
+    let mut page = vaddr.align_down_4k() + PAGE_SIZE_4K;
+    while page.as_usize() < vaddr.as_usize() + len {
+        if kernel_virt_to_phys(page)? != start + (page.as_usize() - vaddr.as_usize()) {
+            return None;
+        }
+        page += PAGE_SIZE_4K;
+    }
+    Some(start)
+}
+
+fn bounce_pages(len: usize) -> usize {
+    len.div_ceil(PAGE_SIZE_4K).max(1)
 }
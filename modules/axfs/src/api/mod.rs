@@ -6,7 +6,8 @@ mod file;
 pub use self::dir::{DirBuilder, DirEntry, ReadDir};
 pub use self::file::{File, FileType, Metadata, OpenOptions, Permissions};
 
-use alloc::{string::String, vec::Vec};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use axfs_vfs::VfsOps;
 use axio::{self as io, prelude::*};
 use axio::Result as IoResult;
 
@@ -89,6 +90,27 @@ pub fn remove_file(path: &str) -> io::Result<()> {
     crate::root::remove_file(None, path)
 }
 
+/// Mounts the filesystem of type `fs_type` on the block device or partition
+/// `source` (e.g., `/dev/vda1`) at the directory `target`.
+pub fn mount(source: &str, target: &str, fs_type: &str) -> io::Result<()> {
+    crate::root::mount(source, target, fs_type)
+}
+
+/// Mounts `fs`, which lies on no block device, at the directory `target`.
+pub fn mount_fs(target: &str, fs: Arc<dyn VfsOps>) -> io::Result<()> {
+    crate::root::mount_fs(target, fs)
+}
+
+/// Unmounts the filesystem mounted at `target`.
+pub fn umount(target: &str) -> io::Result<()> {
+    crate::root::umount(target)
+}
+
+/// Writes back the cached blocks of all block devices.
+pub fn sync() -> io::Result<()> {
+    crate::dev::sync_all().map_err(|_| AxError::Io)
+}
+
 /// Rename a file or directory to a new name.
 /// Delete the original file if `old` already exists.
 ///
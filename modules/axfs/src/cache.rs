@@ -0,0 +1,173 @@
+//! A write-back block cache shared by all filesystems on a block device.
+
+use alloc::{boxed::Box, collections::BTreeMap};
+use axdriver::prelude::*;
+use axsync::Mutex;
+
+use crate::dev::BLOCK_SIZE;
+
+/// Default number of blocks cached per device (1 MiB of 512-byte blocks).
+pub const DEFAULT_CACHE_BLOCKS: usize = 2048;
+
+/// Statistics of a [`BlockCache`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BlockCacheStats {
+    /// Number of block reads served from the cache.
+    pub hits: u64,
+    /// Number of block reads that went to the device.
+    pub misses: u64,
+    /// Number of dirty blocks written back to the device.
+    pub writebacks: u64,
+}
+
+struct CacheEntry {
+    data: Box<[u8; BLOCK_SIZE]>,
+    dirty: bool,
+    /// Position in the LRU list, larger is more recently used.
+    stamp: u64,
+}
+
+struct CacheInner {
+    dev: AxBlockDevice,
+    capacity: usize,
+    entries: BTreeMap<u64, CacheEntry>,
+    /// Maps LRU stamps to block IDs, the first one is the least recently used.
+    lru: BTreeMap<u64, u64>,
+    next_stamp: u64,
+    stats: BlockCacheStats,
+}
+
+/// A block device with an LRU cache of its blocks.
+///
+/// Writes are kept in the cache until the block is evicted or [`sync`] is
+/// called.
+///
+/// [`sync`]: BlockCache::sync
+pub struct BlockCache {
+    inner: Mutex<CacheInner>,
+}
+
+impl BlockCache {
+    /// Creates a cache of at most `capacity` blocks over `dev`.
+    pub fn new(dev: AxBlockDevice, capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self {
+            inner: Mutex::new(CacheInner {
+                dev,
+                capacity,
+                entries: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                next_stamp: 0,
+                stats: BlockCacheStats::default(),
+            }),
+        }
+    }
+
+    /// The name of the underlying device.
+    pub fn device_name(&self) -> &'static str {
+        self.inner.lock().dev.device_name()
+    }
+
+    /// The number of blocks of the underlying device.
+    pub fn num_blocks(&self) -> u64 {
+        self.inner.lock().dev.num_blocks()
+    }
+
+    /// Returns the cache statistics.
+    pub fn stats(&self) -> BlockCacheStats {
+        self.inner.lock().stats
+    }
+
+    /// Reads the block `block_id` into `buf`.
+    pub fn read_block(&self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        let mut inner = self.inner.lock();
+        if inner.entries.contains_key(&block_id) {
+            inner.stats.hits += 1;
+        } else {
+            inner.stats.misses += 1;
+            let mut data = Box::new([0u8; BLOCK_SIZE]);
+            inner.dev.read_block(block_id, &mut data[..])?;
+            inner.insert(block_id, data, false)?;
+        }
+        let entry = inner.touch(block_id);
+        buf[..BLOCK_SIZE].copy_from_slice(&entry.data[..]);
+        Ok(())
+    }
+
+    /// Writes `buf` to the block `block_id`.
+    ///
+    /// The block is only marked dirty, it reaches the device on eviction or
+    /// on [`sync`](BlockCache::sync).
+    pub fn write_block(&self, block_id: u64, buf: &[u8]) -> DevResult {
+        let mut inner = self.inner.lock();
+        if block_id >= inner.dev.num_blocks() {
+            return Err(DevError::InvalidParam);
+        }
+        if inner.entries.contains_key(&block_id) {
+            let entry = inner.touch(block_id);
+            entry.data.copy_from_slice(&buf[..BLOCK_SIZE]);
+            entry.dirty = true;
+        } else {
+            let mut data = Box::new([0u8; BLOCK_SIZE]);
+            data.copy_from_slice(&buf[..BLOCK_SIZE]);
+            inner.insert(block_id, data, true)?;
+        }
+        Ok(())
+    }
+
+    /// Writes all dirty blocks back to the device and flushes it.
+    pub fn sync(&self) -> DevResult {
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        let mut written = 0;
+        // In block order, so that the device sees sequential writes.
+        for (&block_id, entry) in inner.entries.iter_mut().filter(|(_, e)| e.dirty) {
+            inner.dev.write_block(block_id, &entry.data[..])?;
+            entry.dirty = false;
+            written += 1;
+        }
+        inner.stats.writebacks += written;
+        inner.dev.flush()
+    }
+}
+
+impl CacheInner {
+    /// Marks `block_id` as the most recently used, and returns its entry.
+    fn touch(&mut self, block_id: u64) -> &mut CacheEntry {
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+        let entry = self.entries.get_mut(&block_id).unwrap();
+        self.lru.remove(&entry.stamp);
+        self.lru.insert(stamp, block_id);
+        entry.stamp = stamp;
+        entry
+    }
+
+    fn insert(&mut self, block_id: u64, data: Box<[u8; BLOCK_SIZE]>, dirty: bool) -> DevResult {
+        while self.entries.len() >= self.capacity {
+            self.evict()?;
+        }
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+        self.lru.insert(stamp, block_id);
+        self.entries
+            .insert(block_id, CacheEntry { data, dirty, stamp });
+        Ok(())
+    }
+
+    /// Evicts the least recently used block, writing it back if dirty.
+    fn evict(&mut self) -> DevResult {
+        let Some((&stamp, &block_id)) = self.lru.first_key_value() else {
+            return Ok(());
+        };
+        let entry = &self.entries[&block_id];
+        if entry.dirty {
+            // Keep the entry if the write fails, so that no data is lost.
+            self.dev.write_block(block_id, &entry.data[..])?;
+            self.stats.writebacks += 1;
+        }
+        self.lru.remove(&stamp);
+        self.entries.remove(&block_id);
+        Ok(())
+    }
+}
@@ -1,28 +1,87 @@
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
 use axdriver::prelude::*;
+use axsync::Mutex;
 
-const BLOCK_SIZE: usize = 512;
+use crate::cache::{BlockCache, DEFAULT_CACHE_BLOCKS};
+use crate::partition;
+
+pub(crate) const BLOCK_SIZE: usize = 512;
+
+/// A cached block device shared by the whole disk and all its partitions.
+pub type SharedBlockDevice = Arc<BlockCache>;
 
 /// A disk device with a cursor.
+///
+/// It covers either the whole block device, or one partition of it.
 pub struct Disk {
     block_id: u64,
     offset: usize,
-    dev: AxBlockDevice,
+    start_block: u64,
+    num_blocks: u64,
+    dev: SharedBlockDevice,
 }
 
 impl Disk {
     /// Create a new disk.
     pub fn new(dev: AxBlockDevice) -> Self {
         assert_eq!(BLOCK_SIZE, dev.block_size());
+        let num_blocks = dev.num_blocks();
+        let dev = BlockCache::new(dev, DEFAULT_CACHE_BLOCKS);
+        Self::partition(Arc::new(dev), 0, num_blocks)
+    }
+
+    /// Create a disk covering `num_blocks` blocks of `dev`, starting from
+    /// `start_block`.
+    pub fn partition(dev: SharedBlockDevice, start_block: u64, num_blocks: u64) -> Self {
         Self {
             block_id: 0,
             offset: 0,
+            start_block,
+            num_blocks,
             dev,
         }
     }
 
+    /// Get the underlying block device.
+    pub fn device(&self) -> &SharedBlockDevice {
+        &self.dev
+    }
+
+    /// Create another disk covering the same blocks, with its cursor at the
+    /// start.
+    pub(crate) fn reopen(&self) -> Self {
+        Self::partition(self.dev.clone(), self.start_block, self.num_blocks)
+    }
+
+    /// Whether this disk shares any block with `other`.
+    pub(crate) fn overlaps(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.dev, &other.dev)
+            && self.start_block < other.start_block + other.num_blocks
+            && other.start_block < self.start_block + self.num_blocks
+    }
+
     /// Get the size of the disk.
     pub fn size(&self) -> u64 {
-        self.dev.num_blocks() * BLOCK_SIZE as u64
+        self.num_blocks * BLOCK_SIZE as u64
+    }
+
+    fn read_block(&self, block_id: u64, buf: &mut [u8]) -> DevResult {
+        if block_id >= self.num_blocks {
+            return Err(DevError::InvalidParam);
+        }
+        self.dev.read_block(self.start_block + block_id, buf)
+    }
+
+    fn write_block(&self, block_id: u64, buf: &[u8]) -> DevResult {
+        if block_id >= self.num_blocks {
+            return Err(DevError::InvalidParam);
+        }
+        self.dev.write_block(self.start_block + block_id, buf)
+    }
+
+    /// Write back the cached blocks of the underlying device.
+    pub fn sync(&self) -> DevResult {
+        self.dev.sync()
     }
 
     /// Get the position of the cursor.
@@ -41,10 +100,8 @@ impl Disk {
         let read_size = if self.offset == 0 && buf.len() >= BLOCK_SIZE {
             // whole block
             let mut data = [0u8; BLOCK_SIZE];
-            self.dev.read_block(self.block_id, &mut data)?;
+            self.read_block(self.block_id, &mut data)?;
             buf[0..BLOCK_SIZE].copy_from_slice(&data);
-            // self.dev
-            //     .read_block(self.block_id, &mut buf[0..BLOCK_SIZE])?;
             self.block_id += 1;
             BLOCK_SIZE
         } else {
@@ -53,7 +110,7 @@ impl Disk {
             let start = self.offset;
             let count = buf.len().min(BLOCK_SIZE - self.offset);
 
-            self.dev.read_block(self.block_id, &mut data)?;
+            self.read_block(self.block_id, &mut data)?;
             buf[..count].copy_from_slice(&data[start..start + count]);
 
             self.offset += count;
@@ -69,12 +126,8 @@ impl Disk {
     /// Write within one block, returns the number of bytes written.
     pub fn write_one(&mut self, buf: &[u8]) -> DevResult<usize> {
         let write_size = if self.offset == 0 && buf.len() >= BLOCK_SIZE {
-            // whole block
-            // copy data to kernel address space
-            // Because underlying driver assumes a linear mapping between virtual address and
-            // physical address when converting them, which is only present in kernel address space.
-            let data = buf[0..BLOCK_SIZE].to_vec();
-            self.dev.write_block(self.block_id, &data)?;
+            // whole block, copied into the cache (in kernel address space)
+            self.write_block(self.block_id, &buf[0..BLOCK_SIZE])?;
             self.block_id += 1;
             BLOCK_SIZE
         } else {
@@ -83,9 +136,9 @@ impl Disk {
             let start = self.offset;
             let count = buf.len().min(BLOCK_SIZE - self.offset);
 
-            self.dev.read_block(self.block_id, &mut data)?;
+            self.read_block(self.block_id, &mut data)?;
             data[start..start + count].copy_from_slice(&buf[..count]);
-            self.dev.write_block(self.block_id, &data)?;
+            self.write_block(self.block_id, &data)?;
 
             self.offset += count;
             if self.offset >= BLOCK_SIZE {
@@ -102,9 +155,7 @@ impl Disk {
     pub fn read_offset(&mut self, offset: usize) -> [u8; BLOCK_SIZE] {
         let block_id = offset / BLOCK_SIZE;
         let mut block_data = [0u8; BLOCK_SIZE];
-        self.dev
-            .read_block(block_id as u64, &mut block_data)
-            .unwrap();
+        self.read_block(block_id as u64, &mut block_data).unwrap();
         block_data
     }
 
@@ -117,7 +168,241 @@ impl Disk {
         );
         assert!(offset % BLOCK_SIZE == 0);
         let block_id = offset / BLOCK_SIZE;
-        self.dev.write_block(block_id as u64, buf).unwrap();
+        self.write_block(block_id as u64, buf).unwrap();
         Ok(buf.len())
     }
 }
+
+/// A registered block device or partition, e.g., `vda` or `vda1`.
+pub struct BlockDevInfo {
+    /// Name of the device node under `/dev`.
+    pub name: &'static str,
+    /// Partition number, or `None` for the whole device.
+    pub partition: Option<usize>,
+    /// First block on the underlying device.
+    pub start_block: u64,
+    /// Number of blocks.
+    pub num_blocks: u64,
+    dev: SharedBlockDevice,
+}
+
+impl BlockDevInfo {
+    /// Create a new [`Disk`] covering this device or partition.
+    pub fn disk(&self) -> Disk {
+        Disk::partition(self.dev.clone(), self.start_block, self.num_blocks)
+    }
+}
+
+static BLOCK_DEVICES: Mutex<Vec<Arc<BlockDevInfo>>> = Mutex::new(Vec::new());
+
+/// Returns all registered block devices and partitions.
+pub fn block_devices() -> Vec<Arc<BlockDevInfo>> {
+    BLOCK_DEVICES.lock().clone()
+}
+
+/// Finds a registered block device or partition by its name (`vda1`) or
+/// its device path (`/dev/vda1`).
+pub fn find_block_device(name: &str) -> Option<Arc<BlockDevInfo>> {
+    let name = name.strip_prefix("/dev/").unwrap_or(name);
+    BLOCK_DEVICES
+        .lock()
+        .iter()
+        .find(|info| info.name == name)
+        .cloned()
+}
+
+/// Returns the name of the `index`-th block device: `vda` to `vdz`, then
+/// `vdaa` to `vdzz`, `vdaaa`, ..., as Linux does.
+fn disk_name(mut index: usize) -> String {
+    let mut suffix = Vec::new();
+    loop {
+        suffix.push(b'a' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    suffix.reverse();
+    format!("vd{}", String::from_utf8(suffix).unwrap())
+}
+
+/// Registers block devices as `vda`, `vdb`, ..., and their partitions as
+/// `vda1`, `vda2`, ...
+///
+/// Devices whose blocks are not of [`BLOCK_SIZE`] bytes are skipped.
+///
+/// Returns the disk holding the root filesystem: the first partition of the
+/// first device that contains the main filesystem, or the whole first device
+/// if there is no such partition.
+pub(crate) fn register_block_devices(devs: Vec<AxBlockDevice>) -> Disk {
+    let mut registered = BLOCK_DEVICES.lock();
+    let mut root_disk = None;
+    let mut index = 0;
+    for dev in devs {
+        if dev.block_size() != BLOCK_SIZE {
+            warn!(
+                "  block device {:?}: unsupported block size {}",
+                dev.device_name(),
+                dev.block_size()
+            );
+            continue;
+        }
+        let disk_name = disk_name(index);
+        let num_blocks = dev.num_blocks();
+        info!(
+            "  block device {}: {:?}, {} blocks",
+            disk_name,
+            dev.device_name(),
+            num_blocks
+        );
+        let dev = Arc::new(BlockCache::new(dev, DEFAULT_CACHE_BLOCKS));
+        let whole = Disk::partition(dev.clone(), 0, num_blocks);
+        let parts = partition::parse_partitions(num_blocks, |block_id, buf| {
+            whole.read_block(block_id, buf)
+        })
+        .unwrap_or_else(|e| {
+            warn!("failed to read partition table of {}: {:?}", disk_name, e);
+            Vec::new()
+        });
+
+        registered.push(Arc::new(BlockDevInfo {
+            name: Box::leak(disk_name.clone().into_boxed_str()),
+            partition: None,
+            start_block: 0,
+            num_blocks,
+            dev: dev.clone(),
+        }));
+        for part in parts {
+            let info = Arc::new(BlockDevInfo {
+                name: Box::leak(format!("{}{}", disk_name, part.number).into_boxed_str()),
+                partition: Some(part.number),
+                start_block: part.start_block,
+                num_blocks: part.num_blocks,
+                dev: dev.clone(),
+            });
+            info!(
+                "    partition {}: start {}, {} blocks",
+                info.name, info.start_block, info.num_blocks
+            );
+            if index == 0 && root_disk.is_none() {
+                let disk = info.disk();
+                let fs_type =
+                    partition::probe_fs_type(|block_id, buf| disk.read_block(block_id, buf));
+                if fs_type.is_some() && fs_type == main_fs_type() {
+                    info!("  use {} as the root filesystem", info.name);
+                    root_disk = Some(disk);
+                }
+            }
+            registered.push(info);
+        }
+        if index == 0 && root_disk.is_none() {
+            root_disk = Some(whole);
+        }
+        index += 1;
+    }
+    root_disk.expect("No block device found!")
+}
+
+/// Writes back the cached blocks of all block devices.
+pub fn sync_all() -> DevResult {
+    let mut result = Ok(());
+    let devices = BLOCK_DEVICES.lock();
+    // Partitions share the cache of their disk, which is registered first.
+    for info in devices.iter().filter(|info| info.partition.is_none()) {
+        if let Err(e) = info.dev.sync() {
+            warn!("failed to sync {}: {:?}", info.name, e);
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// A `/dev` node of a block device or partition.
+#[cfg(feature = "devfs")]
+pub(crate) struct BlockDevNode(Arc<BlockDevInfo>);
+
+#[cfg(feature = "devfs")]
+impl BlockDevNode {
+    pub(crate) fn new(info: Arc<BlockDevInfo>) -> Self {
+        Self(info)
+    }
+}
+
+#[cfg(feature = "devfs")]
+impl axfs_vfs::VfsNodeOps for BlockDevNode {
+    axfs_vfs::impl_vfs_non_dir_default! {}
+
+    fn get_attr(&self) -> axfs_vfs::VfsResult<axfs_vfs::VfsNodeAttr> {
+        Ok(axfs_vfs::VfsNodeAttr::new(
+            axfs_vfs::VfsNodePerm::from_bits_truncate(0o660),
+            axfs_vfs::VfsNodeType::BlockDevice,
+            self.0.num_blocks * BLOCK_SIZE as u64,
+            self.0.num_blocks,
+        ))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> axfs_vfs::VfsResult<usize> {
+        let mut disk = self.0.disk();
+        let len = buf.len().min(disk.size().saturating_sub(offset) as usize);
+        disk.set_position(offset);
+        let mut read_len = 0;
+        while read_len < len {
+            read_len += disk
+                .read_one(&mut buf[read_len..len])
+                .map_err(|_| axfs_vfs::VfsError::Io)?;
+        }
+        Ok(read_len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> axfs_vfs::VfsResult<usize> {
+        let mut disk = self.0.disk();
+        let len = buf.len().min(disk.size().saturating_sub(offset) as usize);
+        disk.set_position(offset);
+        let mut write_len = 0;
+        while write_len < len {
+            write_len += disk
+                .write_one(&buf[write_len..len])
+                .map_err(|_| axfs_vfs::VfsError::Io)?;
+        }
+        Ok(write_len)
+    }
+
+    fn fsync(&self) -> axfs_vfs::VfsResult {
+        self.0.dev.sync().map_err(|_| axfs_vfs::VfsError::Io)
+    }
+
+    fn truncate(&self, _size: u64) -> axfs_vfs::VfsResult {
+        Ok(())
+    }
+}
+
+/// Filesystem type of the root filesystem, as returned by
+/// [`partition::probe_fs_type`].
+fn main_fs_type() -> Option<&'static str> {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "myfs")] {
+            None
+        } else if #[cfg(feature = "lwext4_rs")] {
+            Some("ext4")
+        } else if #[cfg(feature = "fatfs")] {
+            Some("vfat")
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disk_name;
+
+    #[test]
+    fn test_disk_name() {
+        assert_eq!(disk_name(0), "vda");
+        assert_eq!(disk_name(25), "vdz");
+        assert_eq!(disk_name(26), "vdaa");
+        assert_eq!(disk_name(27), "vdab");
+        assert_eq!(disk_name(701), "vdzz");
+        assert_eq!(disk_name(702), "vdaaa");
+    }
+}
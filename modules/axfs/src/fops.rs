@@ -280,6 +280,16 @@ impl File {
         self.access_node(Cap::empty())?.get_attr()
     }
 
+    /// Whether the file is opened for reading.
+    pub fn readable(&self) -> bool {
+        self.access_node(Cap::READ).is_ok()
+    }
+
+    /// Whether the file is opened for writing.
+    pub fn writable(&self) -> bool {
+        self.access_node(Cap::WRITE).is_ok()
+    }
+
     // 【【【 新增这个公共的 getter 方法 】】】
     /// Returns a reference to the underlying VFS node.
     ///
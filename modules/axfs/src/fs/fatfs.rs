@@ -1,4 +1,4 @@
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use core::cell::UnsafeCell;
 
 use axfs_vfs::{VfsDirEntry, VfsError, VfsNodePerm, VfsResult};
@@ -12,11 +12,19 @@ const BLOCK_SIZE: usize = 512;
 
 pub struct FatFileSystem {
     inner: fatfs::FileSystem<Disk, NullTimeProvider, LossyOemCpConverter>,
-    root_dir: UnsafeCell<Option<VfsNodeRef>>,
+    this: Weak<FatFileSystem>,
 }
 
-pub struct FileWrapper<'a, IO: IoTrait>(Mutex<File<'a, IO, NullTimeProvider, LossyOemCpConverter>>);
-pub struct DirWrapper<'a, IO: IoTrait>(Dir<'a, IO, NullTimeProvider, LossyOemCpConverter>);
+/// The filesystem a node borrows from, kept alive as long as the node. `None`
+/// if the filesystem lives forever.
+type FsRef = Option<Arc<FatFileSystem>>;
+
+// The file or directory is dropped before the filesystem it borrows from.
+pub struct FileWrapper<'a, IO: IoTrait>(
+    Mutex<File<'a, IO, NullTimeProvider, LossyOemCpConverter>>,
+    FsRef,
+);
+pub struct DirWrapper<'a, IO: IoTrait>(Dir<'a, IO, NullTimeProvider, LossyOemCpConverter>, FsRef);
 
 pub trait IoTrait: Read + Write + Seek {}
 
@@ -29,42 +37,41 @@ unsafe impl<'a, IO: IoTrait> Sync for DirWrapper<'a, IO> {}
 
 impl FatFileSystem {
     #[cfg(feature = "use-ramdisk")]
-    pub fn new(mut disk: Disk) -> Self {
+    pub fn new(mut disk: Disk) -> Arc<Self> {
         let opts = fatfs::FormatVolumeOptions::new();
         fatfs::format_volume(&mut disk, opts).expect("failed to format volume");
-        let inner = fatfs::FileSystem::new(disk, fatfs::FsOptions::new())
-            .expect("failed to initialize FAT filesystem");
-        Self {
-            inner,
-            root_dir: UnsafeCell::new(None),
-        }
+        Self::try_new(disk).expect("failed to initialize FAT filesystem")
     }
 
     #[cfg(not(feature = "use-ramdisk"))]
-    pub fn new(disk: Disk) -> Self {
-        let inner = fatfs::FileSystem::new(disk, fatfs::FsOptions::new())
-            .expect("failed to initialize FAT filesystem");
-        Self {
-            inner,
-            root_dir: UnsafeCell::new(None),
-        }
+    pub fn new(disk: Disk) -> Arc<Self> {
+        Self::try_new(disk).expect("failed to initialize FAT filesystem")
     }
 
-    pub fn init(&'static self) {
-        // must be called before later operations
-        unsafe { *self.root_dir.get() = Some(Self::new_dir(self.inner.root_dir())) }
+    /// Opens the FAT filesystem on `disk`, without formatting it.
+    ///
+    /// The filesystem is freed when it is unmounted and its last node is
+    /// dropped.
+    pub fn try_new(disk: Disk) -> VfsResult<Arc<Self>> {
+        let inner = fatfs::FileSystem::new(disk, fatfs::FsOptions::new()).map_err(as_vfs_err)?;
+        Ok(Arc::new_cyclic(|this| Self {
+            inner,
+            this: this.clone(),
+        }))
     }
 
     fn new_file<IO: IoTrait>(
-        file: File<'_, IO, NullTimeProvider, LossyOemCpConverter>,
-    ) -> Arc<FileWrapper<IO>> {
-        Arc::new(FileWrapper(Mutex::new(file)))
+        file: File<'static, IO, NullTimeProvider, LossyOemCpConverter>,
+        fs: FsRef,
+    ) -> Arc<FileWrapper<'static, IO>> {
+        Arc::new(FileWrapper(Mutex::new(file), fs))
     }
 
     fn new_dir<IO: IoTrait>(
-        dir: Dir<'_, IO, NullTimeProvider, LossyOemCpConverter>,
-    ) -> Arc<DirWrapper<IO>> {
-        Arc::new(DirWrapper(dir))
+        dir: Dir<'static, IO, NullTimeProvider, LossyOemCpConverter>,
+        fs: FsRef,
+    ) -> Arc<DirWrapper<'static, IO>> {
+        Arc::new(DirWrapper(dir, fs))
     }
 }
 
@@ -91,6 +98,10 @@ impl<IO: IoTrait> VfsNodeOps for FileWrapper<'static, IO> {
         file.write(buf).map_err(as_vfs_err)
     }
 
+    fn fsync(&self) -> VfsResult {
+        self.0.lock().flush().map_err(as_vfs_err)
+    }
+
     fn truncate(&self, size: u64) -> VfsResult {
         let mut file = self.0.lock();
         file.seek(SeekFrom::Start(size)).map_err(as_vfs_err)?; // TODO: more efficient
@@ -112,9 +123,9 @@ impl<IO: IoTrait> VfsNodeOps for DirWrapper<'static, IO> {
     }
 
     fn parent(&self) -> Option<VfsNodeRef> {
-        self.0
-            .open_dir("..")
-            .map_or(None, |dir| Some(FatFileSystem::new_dir(dir)))
+        self.0.open_dir("..").map_or(None, |dir| {
+            Some(FatFileSystem::new_dir(dir, self.1.clone()))
+        })
     }
 
     fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
@@ -129,9 +140,9 @@ impl<IO: IoTrait> VfsNodeOps for DirWrapper<'static, IO> {
 
         // TODO: use `fatfs::Dir::find_entry`, but it's not public.
         if let Ok(file) = self.0.open_file(path) {
-            Ok(FatFileSystem::new_file(file))
+            Ok(FatFileSystem::new_file(file, self.1.clone()))
         } else if let Ok(dir) = self.0.open_dir(path) {
-            Ok(FatFileSystem::new_dir(dir))
+            Ok(FatFileSystem::new_dir(dir, self.1.clone()))
         } else {
             Err(VfsError::NotFound)
         }
@@ -206,8 +217,10 @@ impl<IO: IoTrait> VfsNodeOps for DirWrapper<'static, IO> {
 
 impl VfsOps for FatFileSystem {
     fn root_dir(&self) -> VfsNodeRef {
-        let root_dir = unsafe { (*self.root_dir.get()).as_ref().unwrap() };
-        root_dir.clone()
+        let fs = self.this.upgrade().unwrap();
+        // The node holds `fs`, so that the filesystem outlives the borrow.
+        let inner = unsafe { &*(&fs.inner as *const fatfs::FileSystem<_, _, _>) };
+        Self::new_dir(inner.root_dir(), Some(fs))
     }
 }
 
@@ -251,7 +264,7 @@ impl Write for Disk {
         Ok(write_len)
     }
     fn flush(&mut self) -> Result<(), Self::Error> {
-        Ok(())
+        self.sync().map_err(|_| ())
     }
 }
 
@@ -294,7 +307,7 @@ impl Clone for FileWrapper<'static, Disk> {
     fn clone(&self) -> Self {
         let file = self.0.lock();
         let cloned_file = file.clone();
-        Self(Mutex::new(cloned_file))
+        Self(Mutex::new(cloned_file), self.1.clone())
     }
 }
 
@@ -319,7 +332,7 @@ impl FatFileSystemFromFile {
 
     pub fn init(&'static self) {
         // must be called before later operations
-        unsafe { *self.root_dir.get() = Some(FatFileSystem::new_dir(self.inner.root_dir())) }
+        unsafe { *self.root_dir.get() = Some(FatFileSystem::new_dir(self.inner.root_dir(), None)) }
     }
 }
 
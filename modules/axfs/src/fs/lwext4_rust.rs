@@ -401,7 +401,8 @@ impl KernelDevOp for Disk {
         trace!("WRITE rt len={}", write_len);
         Ok(write_len)
     }
-    fn flush(_dev: &mut Self::DevType) -> Result<usize, i32> {
+    fn flush(dev: &mut Self::DevType) -> Result<usize, i32> {
+        dev.sync().map_err(|_| -1)?;
         Ok(0)
     }
     fn seek(dev: &mut Disk, off: i64, whence: i32) -> Result<i64, i32> {
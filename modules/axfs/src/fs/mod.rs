@@ -1,16 +1,19 @@
-cfg_if::cfg_if! {
-    if #[cfg(feature = "myfs")] {
-        pub mod myfs;
-    } else if #[cfg(feature = "lwext4_rs")] {
-        pub mod lwext4_rust;
-    } else if #[cfg(feature = "fatfs")] {
-        pub mod fatfs;
-    }
+#[cfg(feature = "myfs")]
+pub mod myfs;
 
-}
+// Built independently of the main filesystem selection, so that partitions
+// with other filesystems can still be mounted.
+#[cfg(feature = "lwext4_rs")]
+pub mod lwext4_rust;
+
+#[cfg(feature = "fatfs")]
+pub mod fatfs;
 
 #[cfg(feature = "devfs")]
 pub use axfs_devfs as devfs;
 
 #[cfg(feature = "ramfs")]
 pub use axfs_ramfs as ramfs;
+
+#[cfg(feature = "procfs")]
+pub mod procfs;
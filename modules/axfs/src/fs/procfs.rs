@@ -0,0 +1,387 @@
+//! The proc filesystem.
+//!
+//! Static entries such as `/proc/sys/...` live in a RAM filesystem, while the
+//! per-process directories `/proc/[pid]` and `/proc/self`, and the per-thread
+//! ones `/proc/[pid]/task/[tid]`, are generated on lookup from the
+//! [`ProcessInfo`] registered by the kernel.
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+use axfs_ramfs::RamFileSystem;
+use axfs_vfs::{VfsDirEntry, VfsError, VfsNodePerm, VfsResult};
+use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps};
+use lazyinit::LazyInit;
+
+/// Provides the contents of the per-process directories of procfs.
+pub trait ProcessInfo: Send + Sync {
+    /// Lists the IDs of all processes.
+    fn pids(&self) -> Vec<u32>;
+
+    /// Returns the ID of the calling process, which `/proc/self` refers to.
+    fn current_pid(&self) -> u32;
+
+    /// Names the files in each process directory.
+    fn files(&self) -> &'static [&'static str];
+
+    /// Renders the file `name` of process `pid`, or returns `None` if the
+    /// process no longer exists.
+    fn read(&self, pid: u32, name: &str) -> Option<String>;
+
+    /// Lists the IDs of the threads of process `pid`, or returns `None` if
+    /// the process no longer exists.
+    fn tids(&self, pid: u32) -> Option<Vec<u32>>;
+
+    /// Names the files in each thread directory.
+    fn thread_files(&self) -> &'static [&'static str];
+
+    /// Renders the file `name` of thread `tid` of process `pid`, or returns
+    /// `None` if the thread no longer exists.
+    fn read_thread(&self, pid: u32, tid: u32, name: &str) -> Option<String>;
+}
+
+static PROCESS_INFO: LazyInit<&'static dyn ProcessInfo> = LazyInit::new();
+
+/// Registers the provider of the `/proc/[pid]` directories.
+///
+/// Until it is called, `/proc` only holds its static entries.
+pub fn set_process_info(info: &'static dyn ProcessInfo) {
+    PROCESS_INFO.init_once(info);
+}
+
+fn process_info() -> Option<&'static dyn ProcessInfo> {
+    PROCESS_INFO.is_inited().then(|| *PROCESS_INFO)
+}
+
+pub struct ProcFileSystem {
+    inner: RamFileSystem,
+    root: Arc<ProcRoot>,
+}
+
+impl ProcFileSystem {
+    /// Creates a procfs on top of `inner`, which holds the static entries.
+    pub fn new(inner: RamFileSystem) -> Self {
+        let root = Arc::new(ProcRoot(inner.root_dir()));
+        Self { inner, root }
+    }
+}
+
+impl VfsOps for ProcFileSystem {
+    fn mount(&self, path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        self.inner.mount(path, mount_point)
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.root.clone()
+    }
+}
+
+/// The root directory, which adds the process directories to the static
+/// entries.
+struct ProcRoot(VfsNodeRef);
+
+impl ProcRoot {
+    fn pid_dir(self: &Arc<Self>, name: &str) -> Option<VfsNodeRef> {
+        let info = process_info()?;
+        let pid = match name {
+            "self" => info.current_pid(),
+            _ => name.parse().ok()?,
+        };
+        info.pids().contains(&pid).then(|| {
+            Arc::new(PidDir {
+                pid,
+                parent: self.clone(),
+            }) as VfsNodeRef
+        })
+    }
+}
+
+impl VfsNodeOps for ProcRoot {
+    axfs_vfs::impl_vfs_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        self.0.get_attr()
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        self.0.parent()
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let path = path.trim_matches('/');
+        if path.is_empty() || path == "." {
+            return Ok(self);
+        }
+        if let Some(rest) = path.strip_prefix("./") {
+            return self.lookup(rest);
+        }
+        let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+        match self.pid_dir(name) {
+            Some(dir) => dir.lookup(rest),
+            None => self.0.clone().lookup(path),
+        }
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        self.0.create(path, ty)
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        self.0.remove(path)
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        // The static entries come first, then a directory for each process.
+        let mut entries = Vec::new();
+        let mut buf = [const { VfsDirEntry::default() }; 16];
+        loop {
+            let n = self.0.read_dir(entries.len(), &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            entries.extend(buf[..n].iter().map(|entry| {
+                let name = core::str::from_utf8(entry.name_as_bytes()).unwrap_or_default();
+                VfsDirEntry::new(name, entry.entry_type())
+            }));
+        }
+        if let Some(info) = process_info() {
+            entries.extend(
+                info.pids()
+                    .into_iter()
+                    .map(|pid| VfsDirEntry::new(&pid.to_string(), VfsNodeType::Dir)),
+            );
+        }
+        Ok(fill_dirents(entries.into_iter().skip(start_idx), dirents))
+    }
+}
+
+/// The attributes of the generated directories.
+fn dir_attr() -> VfsResult<VfsNodeAttr> {
+    Ok(VfsNodeAttr::new(
+        VfsNodePerm::from_bits_truncate(0o555),
+        VfsNodeType::Dir,
+        0,
+        0,
+    ))
+}
+
+/// Looks up `path` in the generated directory `dir`: `.` and `..` are
+/// resolved here, and any other name by `child`, given the rest of the path.
+fn lookup_generated(
+    dir: VfsNodeRef,
+    parent: VfsNodeRef,
+    path: &str,
+    child: impl FnOnce(&str, &str) -> VfsResult<VfsNodeRef>,
+) -> VfsResult<VfsNodeRef> {
+    let path = path.trim_matches('/');
+    if path.is_empty() || path == "." {
+        return Ok(dir);
+    }
+    if let Some(rest) = path.strip_prefix("./") {
+        return lookup_generated(dir, parent, rest, child);
+    }
+    let (name, rest) = path.split_once('/').unwrap_or((path, ""));
+    if name == ".." {
+        return parent.lookup(rest);
+    }
+    child(name, rest)
+}
+
+/// Finds the file `name` among `files`, where `rest` must be empty.
+fn find_file(files: &[&'static str], name: &str, rest: &str) -> VfsResult<&'static str> {
+    let name = *files
+        .iter()
+        .find(|file| **file == name)
+        .ok_or(VfsError::NotFound)?;
+    if !rest.is_empty() {
+        return Err(VfsError::NotADirectory);
+    }
+    Ok(name)
+}
+
+/// Lists `.`, `..`, then the `dirs` and the `files` of a generated
+/// directory.
+fn list_generated(
+    dirs: impl Iterator<Item = String>,
+    files: &[&str],
+    start_idx: usize,
+    dirents: &mut [VfsDirEntry],
+) -> usize {
+    let entries = [".", ".."]
+        .into_iter()
+        .map(|name| VfsDirEntry::new(name, VfsNodeType::Dir))
+        .chain(dirs.map(|name| VfsDirEntry::new(&name, VfsNodeType::Dir)))
+        .chain(
+            files
+                .iter()
+                .map(|name| VfsDirEntry::new(name, VfsNodeType::File)),
+        );
+    fill_dirents(entries.skip(start_idx), dirents)
+}
+
+/// The directory `/proc/[pid]`.
+struct PidDir {
+    pid: u32,
+    parent: Arc<ProcRoot>,
+}
+
+impl VfsNodeOps for PidDir {
+    axfs_vfs::impl_vfs_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        dir_attr()
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        Some(self.parent.clone())
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let parent = self.parent.clone();
+        lookup_generated(self.clone(), parent, path, |name, rest| {
+            if name == "task" {
+                return Arc::new(TaskDir { parent: self }).lookup(rest);
+            }
+            let info = process_info().ok_or(VfsError::NotFound)?;
+            Ok(Arc::new(ProcFile {
+                pid: self.pid,
+                tid: None,
+                name: find_file(info.files(), name, rest)?,
+            }))
+        })
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let files = process_info().map_or(&[][..], |info| info.files());
+        let dirs = core::iter::once("task".to_string());
+        Ok(list_generated(dirs, files, start_idx, dirents))
+    }
+}
+
+/// The directory `/proc/[pid]/task`, which holds a directory for each thread.
+struct TaskDir {
+    parent: Arc<PidDir>,
+}
+
+impl VfsNodeOps for TaskDir {
+    axfs_vfs::impl_vfs_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        dir_attr()
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        Some(self.parent.clone())
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let parent = self.parent.clone();
+        lookup_generated(self.clone(), parent, path, |name, rest| {
+            let info = process_info().ok_or(VfsError::NotFound)?;
+            let tid = name.parse().map_err(|_| VfsError::NotFound)?;
+            if !info
+                .tids(self.parent.pid)
+                .unwrap_or_default()
+                .contains(&tid)
+            {
+                return Err(VfsError::NotFound);
+            }
+            Arc::new(TidDir { tid, parent: self }).lookup(rest)
+        })
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let tids = process_info()
+            .and_then(|info| info.tids(self.parent.pid))
+            .unwrap_or_default();
+        let dirs = tids.into_iter().map(|tid| tid.to_string());
+        Ok(list_generated(dirs, &[], start_idx, dirents))
+    }
+}
+
+/// The directory `/proc/[pid]/task/[tid]`.
+struct TidDir {
+    tid: u32,
+    parent: Arc<TaskDir>,
+}
+
+impl VfsNodeOps for TidDir {
+    axfs_vfs::impl_vfs_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        dir_attr()
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        Some(self.parent.clone())
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let parent = self.parent.clone();
+        lookup_generated(self.clone(), parent, path, |name, rest| {
+            let info = process_info().ok_or(VfsError::NotFound)?;
+            Ok(Arc::new(ProcFile {
+                pid: self.parent.parent.pid,
+                tid: Some(self.tid),
+                name: find_file(info.thread_files(), name, rest)?,
+            }))
+        })
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let files = process_info().map_or(&[][..], |info| info.thread_files());
+        Ok(list_generated(
+            core::iter::empty(),
+            files,
+            start_idx,
+            dirents,
+        ))
+    }
+}
+
+/// A file in `/proc/[pid]` or `/proc/[pid]/task/[tid]`, rendered anew on
+/// every read.
+struct ProcFile {
+    pid: u32,
+    tid: Option<u32>,
+    name: &'static str,
+}
+
+impl VfsNodeOps for ProcFile {
+    axfs_vfs::impl_vfs_non_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        // Like Linux, the size is unknown until the file is read.
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(0o444),
+            VfsNodeType::File,
+            0,
+            0,
+        ))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let content = process_info()
+            .and_then(|info| match self.tid {
+                Some(tid) => info.read_thread(self.pid, tid, self.name),
+                None => info.read(self.pid, self.name),
+            })
+            .ok_or(VfsError::NotFound)?;
+        let content = content.as_bytes();
+        let start = content.len().min(offset as usize);
+        let len = buf.len().min(content.len() - start);
+        buf[..len].copy_from_slice(&content[start..start + len]);
+        Ok(len)
+    }
+}
+
+fn fill_dirents(entries: impl Iterator<Item = VfsDirEntry>, dirents: &mut [VfsDirEntry]) -> usize {
+    dirents
+        .iter_mut()
+        .zip(entries)
+        .map(|(out_entry, entry)| *out_entry = entry)
+        .count()
+}
@@ -10,6 +10,9 @@
 //!   **enabled** by default.
 //! - `ramfs`: Mount [`axfs_ramfs::RamFileSystem`] on `/tmp`. This feature is
 //!   **enabled** by default.
+//! - `procfs`: Mount a proc filesystem on `/proc`. Its per-process directories
+//!   are provided by the [`ProcessInfo`] registered with [`set_process_info`].
+//!   This feature is **enabled** by default.
 //! - `myfs`: Allow users to define their custom filesystems to override the
 //!   default. In this case, [`MyFileSystemIf`] is required to be implemented
 //!   to create and initialize other filesystems. This feature is **disabled** by
@@ -28,16 +31,22 @@ extern crate alloc;
 #[macro_use]
 extern crate log;
 
+mod cache;
 mod dev;
 mod fs;
 mod mounts;
+mod partition;
 mod root;
 
 pub mod api;
 pub mod fops;
+pub use cache::{BlockCache, BlockCacheStats};
+pub use dev::{BlockDevInfo, block_devices, find_block_device};
+#[cfg(feature = "procfs")]
+pub use fs::procfs::{ProcessInfo, set_process_info};
 pub use root::{CURRENT_DIR, CURRENT_DIR_PATH};
 
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 use axdriver::{AxDeviceContainer, prelude::*};
 use axfs_devfs::DeviceFileSystem;
 use spin::Mutex; // 使用 spinlock
@@ -54,12 +63,20 @@ pub mod DEVFS {
 }
 
 /// Initializes filesystems by block devices.
+///
+/// All block devices and their MBR/GPT partitions are registered as
+/// `/dev/vdX` and `/dev/vdXN`. The root filesystem is mounted from the first
+/// partition of the first device that holds the main filesystem, or from the
+/// whole first device if it is not partitioned.
 pub fn init_filesystems(mut blk_devs: AxDeviceContainer<AxBlockDevice>) {
     info!("Initialize filesystems...");
 
-    let dev = blk_devs.take_one().expect("No block device found!");
-    info!("  use block device 0: {:?}", dev.device_name());
-    self::root::init_rootfs(self::dev::Disk::new(dev));
+    let mut devs = Vec::new();
+    while let Some(dev) = blk_devs.take_one() {
+        devs.push(dev);
+    }
+    let disk = self::dev::register_block_devices(devs);
+    self::root::init_rootfs(disk);
 }
 
 pub fn set_devfs_instance(instance: Arc<DeviceFileSystem>) {
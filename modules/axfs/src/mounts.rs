@@ -13,6 +13,9 @@ pub(crate) fn devfs() -> Arc<fs::devfs::DeviceFileSystem> {
     devfs.add("null", Arc::new(null));
     devfs.add("zero", Arc::new(zero));
     foo_dir.add("bar", Arc::new(bar));
+    for info in crate::dev::block_devices() {
+        devfs.add(info.name, Arc::new(crate::dev::BlockDevNode::new(info)));
+    }
     let devfs_arc = Arc::new(devfs);
     crate::set_devfs_instance(devfs_arc.clone());
     devfs_arc
@@ -24,7 +27,7 @@ pub(crate) fn ramfs() -> Arc<fs::ramfs::RamFileSystem> {
 }
 
 #[cfg(feature = "procfs")]
-pub(crate) fn procfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
+pub(crate) fn procfs() -> VfsResult<Arc<fs::procfs::ProcFileSystem>> {
     let procfs = fs::ramfs::RamFileSystem::new();
     let proc_root = procfs.root_dir();
 
@@ -42,11 +45,12 @@ pub(crate) fn procfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
     let file_over = proc_root.clone().lookup("./sys/vm/overcommit_memory")?;
     file_over.write_at(0, b"0\n")?;
 
-    // Create /proc/self/stat
+    // Create /proc/self/stat, shadowed by the process directories once the
+    // kernel registers its process info.
     proc_root.create("self", VfsNodeType::Dir)?;
     proc_root.create("self/stat", VfsNodeType::File)?;
 
-    Ok(Arc::new(procfs))
+    Ok(Arc::new(fs::procfs::ProcFileSystem::new(procfs)))
 }
 
 #[cfg(feature = "sysfs")]
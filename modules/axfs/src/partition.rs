@@ -0,0 +1,343 @@
+//! MBR and GPT partition table parsing.
+
+use alloc::vec::Vec;
+use axdriver::prelude::DevResult;
+
+use crate::dev::BLOCK_SIZE;
+
+/// Partition types of MBR extended partitions.
+const MBR_EXTENDED_TYPES: [u8; 3] = [0x05, 0x0f, 0x85];
+/// Partition type of the protective MBR of a GPT disk.
+const MBR_GPT_PROTECTIVE: u8 = 0xee;
+/// Maximum number of logical partitions followed in an extended partition.
+const MAX_LOGICAL_PARTITIONS: usize = 64;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// The size of the GPT entry array read at most, the usual 128 entries of
+/// 128 bytes.
+const GPT_MAX_ENTRIES_SIZE: usize = 16 * 1024;
+/// The ext2/3/4 superblock magic, at byte 1080 of the filesystem.
+const EXT4_MAGIC: u16 = 0xef53;
+
+/// A partition found in the partition table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    /// Partition number, starting from 1 (logical MBR partitions from 5).
+    pub number: usize,
+    /// First block of the partition.
+    pub start_block: u64,
+    /// Number of blocks of the partition.
+    pub num_blocks: u64,
+}
+
+/// Parses the partition table of a disk with `num_blocks` blocks.
+///
+/// `read_block` reads one 512-byte block. Returns an empty list if the disk
+/// has no valid partition table, e.g., it holds an unpartitioned filesystem.
+pub fn parse_partitions<F>(num_blocks: u64, mut read_block: F) -> DevResult<Vec<Partition>>
+where
+    F: FnMut(u64, &mut [u8]) -> DevResult,
+{
+    let mut mbr = [0u8; BLOCK_SIZE];
+    read_block(0, &mut mbr)?;
+    let Some(entries) = mbr_entries(&mbr, num_blocks) else {
+        return Ok(Vec::new());
+    };
+
+    if entries.iter().any(|e| e.ty == MBR_GPT_PROTECTIVE) {
+        return parse_gpt(num_blocks, read_block);
+    }
+
+    let mut parts = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.ty == 0 {
+            continue;
+        }
+        if MBR_EXTENDED_TYPES.contains(&entry.ty) {
+            parse_logical(entry.start, num_blocks, &mut read_block, &mut parts)?;
+        } else {
+            parts.push(Partition {
+                number: i + 1,
+                start_block: entry.start,
+                num_blocks: entry.len,
+            });
+        }
+    }
+    parts.sort_by_key(|p| p.number);
+    Ok(parts)
+}
+
+/// Detects the filesystem on a disk or partition by its signature.
+///
+/// Returns `"vfat"` or `"ext4"`, or `None` if unknown.
+pub fn probe_fs_type<F>(mut read_block: F) -> Option<&'static str>
+where
+    F: FnMut(u64, &mut [u8]) -> DevResult,
+{
+    let mut block = [0u8; BLOCK_SIZE];
+    read_block(0, &mut block).ok()?;
+    if is_fat_boot_sector(&block) {
+        return Some("vfat");
+    }
+    read_block(1080 / BLOCK_SIZE as u64, &mut block).ok()?;
+    let offset = 1080 % BLOCK_SIZE;
+    if u16::from_le_bytes([block[offset], block[offset + 1]]) == EXT4_MAGIC {
+        return Some("ext4");
+    }
+    None
+}
+
+fn is_fat_boot_sector(block: &[u8; BLOCK_SIZE]) -> bool {
+    block[510] == 0x55
+        && block[511] == 0xaa
+        && (block[0] == 0xeb || block[0] == 0xe9)
+        && (&block[54..57] == b"FAT" || &block[82..87] == b"FAT32")
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MbrEntry {
+    ty: u8,
+    start: u64,
+    len: u64,
+}
+
+/// Returns the 4 primary entries of an MBR (or EBR), or `None` if the block
+/// is not a valid partition table.
+fn mbr_entries(block: &[u8; BLOCK_SIZE], num_blocks: u64) -> Option<[MbrEntry; 4]> {
+    // A FAT boot sector also ends with 0x55aa, but starts with a jump.
+    if block[510] != 0x55 || block[511] != 0xaa || is_fat_boot_sector(block) {
+        return None;
+    }
+
+    let mut entries = [MbrEntry {
+        ty: 0,
+        start: 0,
+        len: 0,
+    }; 4];
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let raw = &block[446 + i * 16..446 + (i + 1) * 16];
+        let status = raw[0];
+        if status != 0 && status != 0x80 {
+            return None;
+        }
+        entry.ty = raw[4];
+        entry.start = u32::from_le_bytes(raw[8..12].try_into().unwrap()) as u64;
+        entry.len = u32::from_le_bytes(raw[12..16].try_into().unwrap()) as u64;
+        // The protective MBR of GPT may cover less or more than the disk.
+        if entry.ty != 0 && entry.ty != MBR_GPT_PROTECTIVE && entry.start + entry.len > num_blocks {
+            return None;
+        }
+    }
+    Some(entries)
+}
+
+/// Follows the EBR chain of an extended partition starting at `ext_start`.
+fn parse_logical<F>(
+    ext_start: u64,
+    num_blocks: u64,
+    read_block: &mut F,
+    parts: &mut Vec<Partition>,
+) -> DevResult
+where
+    F: FnMut(u64, &mut [u8]) -> DevResult,
+{
+    let mut ebr_block = ext_start;
+    let mut ebr = [0u8; BLOCK_SIZE];
+    for number in 5..5 + MAX_LOGICAL_PARTITIONS {
+        read_block(ebr_block, &mut ebr)?;
+        let Some(entries) = mbr_entries(&ebr, num_blocks) else {
+            break;
+        };
+        // Relative to the EBR itself.
+        if entries[0].ty != 0 {
+            parts.push(Partition {
+                number,
+                start_block: ebr_block + entries[0].start,
+                num_blocks: entries[0].len,
+            });
+        }
+        // Relative to the start of the extended partition.
+        if !MBR_EXTENDED_TYPES.contains(&entries[1].ty) || entries[1].start == 0 {
+            break;
+        }
+        ebr_block = ext_start + entries[1].start;
+    }
+    Ok(())
+}
+
+fn parse_gpt<F>(num_blocks: u64, mut read_block: F) -> DevResult<Vec<Partition>>
+where
+    F: FnMut(u64, &mut [u8]) -> DevResult,
+{
+    let mut header = [0u8; BLOCK_SIZE];
+    read_block(1, &mut header)?;
+    if &header[0..8] != GPT_SIGNATURE {
+        warn!("protective MBR found, but the GPT header is invalid");
+        return Ok(Vec::new());
+    }
+    let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+    let num_entries = u32::from_le_bytes(header[80..84].try_into().unwrap()) as usize;
+    let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as usize;
+    if entry_size < 128 || BLOCK_SIZE % entry_size != 0 {
+        warn!("unsupported GPT entry size {}", entry_size);
+        return Ok(Vec::new());
+    }
+    let max_entries = GPT_MAX_ENTRIES_SIZE / entry_size;
+    if num_entries > max_entries {
+        warn!(
+            "too many GPT entries {}, only {} read",
+            num_entries, max_entries
+        );
+    }
+    let num_entries = num_entries.min(max_entries);
+    let entries_blocks = (num_entries * entry_size).div_ceil(BLOCK_SIZE) as u64;
+    if entries_lba < 2
+        || entries_lba
+            .checked_add(entries_blocks)
+            .is_none_or(|end| end > num_blocks)
+    {
+        warn!("GPT entries at block {} out of the disk", entries_lba);
+        return Ok(Vec::new());
+    }
+
+    let mut parts = Vec::new();
+    let mut block = [0u8; BLOCK_SIZE];
+    let per_block = BLOCK_SIZE / entry_size;
+    for i in 0..num_entries {
+        if i % per_block == 0 {
+            read_block(entries_lba + (i / per_block) as u64, &mut block)?;
+        }
+        let raw = &block[(i % per_block) * entry_size..][..entry_size];
+        if raw[0..16].iter().all(|&b| b == 0) {
+            continue; // unused entry
+        }
+        let first = u64::from_le_bytes(raw[32..40].try_into().unwrap());
+        let last = u64::from_le_bytes(raw[40..48].try_into().unwrap());
+        if first > last || last >= num_blocks {
+            warn!("GPT entry {} out of range: [{}, {}]", i + 1, first, last);
+            continue;
+        }
+        parts.push(Partition {
+            number: i + 1,
+            start_block: first,
+            num_blocks: last - first + 1,
+        });
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axdriver::prelude::DevError;
+
+    fn read_from(image: &[u8]) -> impl FnMut(u64, &mut [u8]) -> DevResult + '_ {
+        |block_id, buf| {
+            let start = block_id as usize * BLOCK_SIZE;
+            let data = image.get(start..start + BLOCK_SIZE).ok_or(DevError::Io)?;
+            buf.copy_from_slice(data);
+            Ok(())
+        }
+    }
+
+    fn part(number: usize, start_block: u64, num_blocks: u64) -> Partition {
+        Partition {
+            number,
+            start_block,
+            num_blocks,
+        }
+    }
+
+    fn set_mbr_entry(block: &mut [u8], i: usize, ty: u8, start: u32, len: u32) {
+        let raw = &mut block[446 + i * 16..446 + (i + 1) * 16];
+        raw[4] = ty;
+        raw[8..12].copy_from_slice(&start.to_le_bytes());
+        raw[12..16].copy_from_slice(&len.to_le_bytes());
+        block[510] = 0x55;
+        block[511] = 0xaa;
+    }
+
+    #[test]
+    fn test_mbr() {
+        let mut image = vec![0u8; 64 * BLOCK_SIZE];
+        set_mbr_entry(&mut image, 0, 0x0c, 2, 20);
+        set_mbr_entry(&mut image, 1, 0x83, 22, 40);
+        let parts = parse_partitions(64, read_from(&image)).unwrap();
+        assert_eq!(parts, [part(1, 2, 20), part(2, 22, 40),]);
+    }
+
+    #[test]
+    fn test_mbr_logical() {
+        let mut image = vec![0u8; 64 * BLOCK_SIZE];
+        set_mbr_entry(&mut image, 0, 0x05, 10, 50);
+        let ebr1 = &mut image[10 * BLOCK_SIZE..11 * BLOCK_SIZE];
+        set_mbr_entry(ebr1, 0, 0x83, 1, 9);
+        set_mbr_entry(ebr1, 1, 0x05, 20, 20);
+        let ebr2 = &mut image[30 * BLOCK_SIZE..31 * BLOCK_SIZE];
+        set_mbr_entry(ebr2, 0, 0x83, 1, 19);
+        let parts = parse_partitions(64, read_from(&image)).unwrap();
+        assert_eq!(parts, [part(5, 11, 9), part(6, 31, 19),]);
+    }
+
+    #[test]
+    fn test_gpt() {
+        let mut image = vec![0u8; 64 * BLOCK_SIZE];
+        set_mbr_entry(&mut image, 0, MBR_GPT_PROTECTIVE, 1, u32::MAX);
+        let header = &mut image[BLOCK_SIZE..2 * BLOCK_SIZE];
+        header[0..8].copy_from_slice(GPT_SIGNATURE);
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        for (i, (first, last)) in [(34u64, 39u64), (40, 63)].into_iter().enumerate() {
+            let entry = &mut image[2 * BLOCK_SIZE + i * 128..][..128];
+            entry[0] = 0xaa; // non-zero type GUID
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+        }
+        let parts = parse_partitions(64, read_from(&image)).unwrap();
+        assert_eq!(parts, [part(1, 34, 6), part(2, 40, 24),]);
+    }
+
+    #[test]
+    fn test_gpt_bounds() {
+        let mut image = vec![0u8; 64 * BLOCK_SIZE];
+        set_mbr_entry(&mut image, 0, MBR_GPT_PROTECTIVE, 1, u32::MAX);
+        let header = &mut image[BLOCK_SIZE..2 * BLOCK_SIZE];
+        header[0..8].copy_from_slice(GPT_SIGNATURE);
+        header[72..80].copy_from_slice(&2u64.to_le_bytes());
+        header[80..84].copy_from_slice(&u32::MAX.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        let mut reads = 0;
+        let mut read = read_from(&image);
+        let parts = parse_partitions(64, |block_id, buf| {
+            reads += 1;
+            read(block_id, buf)
+        })
+        .unwrap();
+        assert!(parts.is_empty());
+        assert_eq!(reads, 2 + GPT_MAX_ENTRIES_SIZE / BLOCK_SIZE);
+
+        // The entries beyond the end of the disk.
+        image[BLOCK_SIZE + 72..BLOCK_SIZE + 80].copy_from_slice(&60u64.to_le_bytes());
+        assert!(parse_partitions(64, read_from(&image)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unpartitioned_fat() {
+        let mut image = vec![0u8; 64 * BLOCK_SIZE];
+        image[0] = 0xeb;
+        image[54..57].copy_from_slice(b"FAT");
+        image[510] = 0x55;
+        image[511] = 0xaa;
+        assert!(parse_partitions(64, read_from(&image)).unwrap().is_empty());
+        assert_eq!(probe_fs_type(read_from(&image)), Some("vfat"));
+    }
+
+    #[test]
+    fn test_probe_ext4() {
+        let mut image = vec![0u8; 64 * BLOCK_SIZE];
+        image[1080..1082].copy_from_slice(&EXT4_MAGIC.to_le_bytes());
+        assert_eq!(probe_fs_type(read_from(&image)), Some("ext4"));
+        assert_eq!(probe_fs_type(read_from(&image[..BLOCK_SIZE])), None);
+    }
+}
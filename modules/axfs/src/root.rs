@@ -13,6 +13,7 @@ use spin::RwLock;
 
 use crate::{
     api::FileType,
+    dev::Disk,
     fs::{self},
     mounts,
 };
@@ -41,20 +42,27 @@ impl CURRENT_DIR {
 }
 
 struct MountPoint {
-    path: &'static str,
+    path: String,
     fs: Arc<dyn VfsOps>,
+    /// The disk the filesystem lies on, if any.
+    disk: Option<Disk>,
 }
 
 struct RootDirectory {
     main_fs: Arc<dyn VfsOps>,
+    main_disk: Option<Disk>,
     mounts: RwLock<Vec<MountPoint>>,
 }
 
 static ROOT_DIR: LazyInit<Arc<RootDirectory>> = LazyInit::new();
 
 impl MountPoint {
-    pub fn new(path: &'static str, fs: Arc<dyn VfsOps>) -> Self {
-        Self { path, fs }
+    pub fn new(path: &str, fs: Arc<dyn VfsOps>, disk: Option<Disk>) -> Self {
+        Self {
+            path: path.into(),
+            fs,
+            disk,
+        }
     }
 }
 
@@ -65,14 +73,36 @@ impl Drop for MountPoint {
 }
 
 impl RootDirectory {
-    pub const fn new(main_fs: Arc<dyn VfsOps>) -> Self {
+    pub const fn new(main_fs: Arc<dyn VfsOps>, main_disk: Option<Disk>) -> Self {
         Self {
             main_fs,
+            main_disk,
             mounts: RwLock::new(Vec::new()),
         }
     }
 
-    pub fn mount(&self, path: &'static str, fs: Arc<dyn VfsOps>) -> AxResult {
+    pub fn mount(&self, path: &str, fs: Arc<dyn VfsOps>) -> AxResult {
+        self.mount_on(path, fs, None)
+    }
+
+    /// Mounts `fs`, which lies on `disk`, at `path`.
+    pub fn mount_disk(&self, path: &str, fs: Arc<dyn VfsOps>, disk: Disk) -> AxResult {
+        self.mount_on(path, fs, Some(disk))
+    }
+
+    /// Whether a mounted filesystem, including the main one, lies on any
+    /// block of `disk`.
+    pub fn is_disk_mounted(&self, disk: &Disk) -> bool {
+        self.main_disk.as_ref().is_some_and(|d| d.overlaps(disk))
+            || self
+                .mounts
+                .read()
+                .iter()
+                .filter_map(|mp| mp.disk.as_ref())
+                .any(|d| d.overlaps(disk))
+    }
+
+    fn mount_on(&self, path: &str, fs: Arc<dyn VfsOps>, disk: Option<Disk>) -> AxResult {
         if path == "/" {
             return ax_err!(InvalidInput, "cannot mount root filesystem");
         }
@@ -85,12 +115,36 @@ impl RootDirectory {
         // create the mount point in the main filesystem if it does not exist
         self.main_fs.root_dir().create(path, FileType::Dir)?;
         fs.mount(path, self.main_fs.root_dir().lookup(path)?)?;
-        self.mounts.write().push(MountPoint::new(path, fs));
+        self.mounts.write().push(MountPoint::new(path, fs, disk));
         Ok(())
     }
 
-    pub fn _umount(&self, path: &str) {
-        self.mounts.write().retain(|mp| mp.path != path);
+    /// Unmounts the filesystem at `path`, after writing back its data and
+    /// the cached blocks of its disk.
+    pub fn umount(&self, path: &str) -> AxResult {
+        let fs = self
+            .mounts
+            .read()
+            .iter()
+            .find(|mp| mp.path == path)
+            .map(|mp| mp.fs.clone())
+            .ok_or(AxError::InvalidInput)?;
+        fs.root_dir().fsync()?;
+
+        let mut mp = {
+            let mut mounts = self.mounts.write();
+            let idx = mounts
+                .iter()
+                .position(|mp| mp.path == path)
+                .ok_or(AxError::InvalidInput)?;
+            mounts.remove(idx)
+        };
+        let disk = mp.disk.take();
+        drop(mp); // unmounts the filesystem
+        if let Some(disk) = disk {
+            disk.sync().map_err(|_| AxError::Io)?;
+        }
+        Ok(())
     }
 
     pub fn contains(&self, path: &str) -> bool {
@@ -170,7 +224,52 @@ impl VfsNodeOps for RootDirectory {
     }
 }
 
-pub(crate) fn init_rootfs(disk: crate::dev::Disk) {
+/// Opens the filesystem of type `fs_type` on `disk`.
+fn new_block_fs(disk: Disk, fs_type: &str) -> AxResult<Arc<dyn VfsOps>> {
+    match fs_type {
+        #[cfg(feature = "fatfs")]
+        "vfat" | "fat" | "msdos" => Ok(fs::fatfs::FatFileSystem::try_new(disk)?),
+        #[cfg(feature = "lwext4_rs")]
+        "ext4" => Ok(Arc::new(fs::lwext4_rust::Ext4FileSystem::new(disk))),
+        _ => ax_err!(Unsupported, "unsupported filesystem type"),
+    }
+}
+
+/// Mounts the filesystem on the block device `source` (e.g., `/dev/vda1`)
+/// at `target`.
+///
+/// Fails with [`AxError::ResourceBusy`] if a mounted filesystem already lies
+/// on the device, or on a partition overlapping it.
+pub(crate) fn mount(source: &str, target: &str, fs_type: &str) -> AxResult {
+    // Serializes the check for busy devices with the mount.
+    static MOUNT_LOCK: Mutex<()> = Mutex::new(());
+
+    let info = crate::dev::find_block_device(source).ok_or(AxError::NotFound)?;
+    let target = absolute_path(target)?;
+    let target = target.trim_end_matches('/');
+    let disk = info.disk();
+    let _guard = MOUNT_LOCK.lock();
+    if ROOT_DIR.is_disk_mounted(&disk) {
+        return ax_err!(ResourceBusy, "block device already mounted");
+    }
+    let fs = new_block_fs(disk.reopen(), fs_type)?;
+    ROOT_DIR.mount_disk(target, fs, disk)?;
+    info!("mounted {} ({}) at {}", info.name, fs_type, target);
+    Ok(())
+}
+
+/// Mounts `fs`, which lies on no block device, at `target`.
+pub(crate) fn mount_fs(target: &str, fs: Arc<dyn VfsOps>) -> AxResult {
+    ROOT_DIR.mount(absolute_path(target)?.trim_end_matches('/'), fs)
+}
+
+/// Unmounts the filesystem mounted at `target`.
+pub(crate) fn umount(target: &str) -> AxResult {
+    ROOT_DIR.umount(absolute_path(target)?.trim_end_matches('/'))
+}
+
+pub(crate) fn init_rootfs(disk: Disk) {
+    let main_disk = disk.reopen();
     cfg_if::cfg_if! {
         if #[cfg(feature = "myfs")] { // override the default filesystem
             let main_fs = fs::myfs::new_myfs(disk);
@@ -180,13 +279,12 @@ pub(crate) fn init_rootfs(disk: crate::dev::Disk) {
             let main_fs = EXT4_FS.clone();
         } else if #[cfg(feature = "fatfs")] {
             static FAT_FS: LazyInit<Arc<fs::fatfs::FatFileSystem>> = LazyInit::new();
-            FAT_FS.init_once(Arc::new(fs::fatfs::FatFileSystem::new(disk)));
-            FAT_FS.init();
+            FAT_FS.init_once(fs::fatfs::FatFileSystem::new(disk));
             let main_fs = FAT_FS.clone();
         }
     }
 
-    let root_dir = RootDirectory::new(main_fs);
+    let root_dir = RootDirectory::new(main_fs, Some(main_disk));
 
     #[cfg(feature = "devfs")]
     root_dir
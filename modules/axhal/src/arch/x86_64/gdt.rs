@@ -14,6 +14,19 @@ static TSS: TaskStateSegment = TaskStateSegment::new();
 #[percpu::def_percpu]
 static GDT: LazyInit<GdtStruct> = LazyInit::new();
 
+/// The size of the stack that double faults are handled on.
+const DOUBLE_FAULT_STACK_SIZE: usize = 0x4000;
+
+/// The stack that double faults are handled on, so that a kernel stack
+/// overflow, which faults again when pushing the exception frame onto the
+/// guard page, is reported rather than escalating to a triple fault.
+#[percpu::def_percpu]
+static DOUBLE_FAULT_STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+
+/// The index in the interrupt stack table of the TSS of the stack that
+/// double faults are handled on.
+pub(super) const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
 /// A wrapper of the Global Descriptor Table (GDT) with maximum 16 entries.
 #[repr(align(16))]
 pub struct GdtStruct {
@@ -103,6 +116,9 @@ impl fmt::Debug for GdtStruct {
 /// current CPU.
 pub fn init_gdt() {
     unsafe {
+        let stack_top = DOUBLE_FAULT_STACK.current_ref_raw().as_ptr_range().end;
+        TSS.current_ref_mut_raw().interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            VirtAddr::from_ptr(stack_top);
         let gdt = GDT.current_ref_raw();
         gdt.init_once(GdtStruct::new(TSS.current_ref_raw()));
         gdt.load();
@@ -41,6 +41,11 @@ impl IdtStruct {
                 // enable user space breakpoints and legacy int 0x80 syscall
                 opt.set_privilege_level(x86_64::PrivilegeLevel::Ring3);
             }
+            if i == x86::irq::DOUBLE_FAULT_VECTOR as usize {
+                // Switch to a known good stack, as the current one may have
+                // overflowed.
+                unsafe { opt.set_stack_index(super::gdt::DOUBLE_FAULT_IST_INDEX) };
+            }
         }
         idt
     }
@@ -39,6 +39,12 @@ fn x86_trap_handler(tf: &mut TrapFrame) {
     match tf.vector as u8 {
         PAGE_FAULT_VECTOR => handle_page_fault(tf),
         BREAKPOINT_VECTOR => debug!("#BP @ {:#x} ", tf.rip),
+        DOUBLE_FAULT_VECTOR => {
+            panic!(
+                "#DF @ {:#x}, rsp={:#x}, possibly a kernel stack overflow:\n{:#x?}",
+                tf.rip, tf.rsp, tf
+            );
+        }
         GENERAL_PROTECTION_FAULT_VECTOR => {
             panic!(
                 "#GP @ {:#x}, error_code={:#x}:\n{:#x?}",
@@ -5,13 +5,39 @@ use handler_table::HandlerTable;
 use crate::platform::irq::{MAX_IRQ_COUNT, dispatch_irq};
 use crate::trap::{IRQ, register_trap_handler};
 
-pub use crate::platform::irq::{register_handler, set_enable};
+pub use crate::platform::irq::{IPI_IRQ_NUM, register_handler, send_ipi, set_enable};
 
 /// The type if an IRQ handler.
 pub type IrqHandler = handler_table::Handler;
 
+/// A message-signaled interrupt, raised by a device writing `data` to `addr`.
+#[derive(Debug, Clone, Copy)]
+pub struct MsiMessage {
+    /// The IRQ number the interrupt is dispatched as.
+    pub vector: usize,
+    /// The address the device writes the message to.
+    pub addr: u64,
+    /// The data of the message.
+    pub data: u32,
+}
+
 static IRQ_HANDLER_TABLE: HandlerTable<MAX_IRQ_COUNT> = HandlerTable::new();
 
+/// Allocates a message-signaled interrupt handled by `handler`.
+///
+/// Returns `None` if the platform does not support them, or has no more
+/// vectors for them.
+pub fn alloc_msi(handler: IrqHandler) -> Option<MsiMessage> {
+    cfg_if::cfg_if! {
+        if #[cfg(all(target_arch = "x86_64", platform_family = "x86-pc"))] {
+            crate::platform::irq::alloc_msi(handler)
+        } else {
+            let _ = handler;
+            None
+        }
+    }
+}
+
 /// Platform-independent IRQ dispatching.
 #[allow(dead_code)]
 pub(crate) fn dispatch_irq_common(irq_num: usize) {
@@ -36,6 +36,7 @@ extern crate log;
 #[macro_use]
 extern crate memory_addr;
 
+mod memmap;
 mod platform;
 
 #[macro_use]
@@ -2,7 +2,9 @@
 
 use core::fmt;
 
-use axconfig::plat::{PHYS_MEMORY_BASE, PHYS_MEMORY_SIZE, PHYS_VIRT_OFFSET};
+use axconfig::plat::{
+    KERNEL_ASPACE_BASE, KERNEL_ASPACE_SIZE, PHYS_MEMORY_BASE, PHYS_MEMORY_SIZE, PHYS_VIRT_OFFSET,
+};
 
 #[doc(no_inline)]
 pub use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr};
@@ -46,21 +48,66 @@ pub struct MemRegion {
     pub name: &'static str,
 }
 
+/// The size of the kernel virtual range for mappings outside the linear
+/// mapping (e.g., kernel task stacks), at the top of the kernel address space.
+pub const VMALLOC_SIZE: usize = 0x4000_0000;
+
+/// The start of the kernel virtual range for mappings outside the linear
+/// mapping, see [`VMALLOC_SIZE`].
+pub const VMALLOC_START: usize =
+    memory_addr::align_down(KERNEL_ASPACE_BASE + KERNEL_ASPACE_SIZE, VMALLOC_SIZE) - VMALLOC_SIZE;
+
+/// Whether `vaddr` is in the kernel virtual range for mappings outside the
+/// linear mapping, see [`VMALLOC_START`].
+#[inline]
+pub const fn is_vmalloc_addr(vaddr: VirtAddr) -> bool {
+    vaddr.as_usize() >= VMALLOC_START && vaddr.as_usize() - VMALLOC_START < VMALLOC_SIZE
+}
+
 /// Converts a virtual address to a physical address.
 ///
 /// It assumes that there is a linear mapping with the offset
 /// [`PHYS_VIRT_OFFSET`], that maps all the physical memory to the virtual
 /// space at the address plus the offset. So we have
 /// `paddr = vaddr - PHYS_VIRT_OFFSET`.
+///
+/// Addresses outside the linear mapping, e.g., on kernel task stacks, must
+/// be converted by [`kernel_virt_to_phys`] instead.
 #[inline]
 pub const fn virt_to_phys(vaddr: VirtAddr) -> PhysAddr {
     assert!(
         vaddr.as_usize() >= PHYS_VIRT_OFFSET,
         "Converted address is invalid, check if the virtual address is in kernel space"
     );
+    assert!(
+        !is_vmalloc_addr(vaddr),
+        "Converted address is not linearly mapped, use `kernel_virt_to_phys` instead"
+    );
     pa!(vaddr.as_usize() - PHYS_VIRT_OFFSET)
 }
 
+/// Converts a kernel virtual address to a physical address, whether it is in
+/// the linear mapping or not. Addresses outside of it are translated by
+/// walking the kernel page table.
+///
+/// Only the page containing `vaddr` is translated: a buffer outside the
+/// linear mapping may be physically discontiguous across pages.
+///
+/// Returns `None` if `vaddr` is not mapped.
+pub fn kernel_virt_to_phys(vaddr: VirtAddr) -> Option<PhysAddr> {
+    if !is_vmalloc_addr(vaddr) {
+        return Some(virt_to_phys(vaddr));
+    }
+    #[cfg(feature = "paging")]
+    {
+        crate::paging::kernel_translate(vaddr)
+    }
+    #[cfg(not(feature = "paging"))]
+    {
+        None
+    }
+}
+
 /// Converts a physical address to a virtual address.
 ///
 /// It assumes that there is a linear mapping with the offset
@@ -128,14 +175,22 @@ pub(crate) fn default_mmio_regions() -> impl Iterator<Item = MemRegion> {
     })
 }
 
-/// Returns the default free memory regions (kernel image end to physical memory end).
+/// Returns the default free memory regions.
+///
+/// They come from the memory map passed at boot if the platform found one,
+/// otherwise they span from the kernel image end to the physical memory end.
 #[allow(dead_code)]
 pub(crate) fn default_free_regions() -> impl Iterator<Item = MemRegion> {
     let start = virt_to_phys((_ekernel as usize).into()).align_up_4k();
     let end = pa!(PHYS_MEMORY_BASE + PHYS_MEMORY_SIZE).align_down_4k();
-    core::iter::once(MemRegion {
-        paddr: start,
-        size: end.as_usize() - start.as_usize(),
+    let ranges = crate::memmap::free_ranges().unwrap_or_else(|| {
+        let mut ranges = crate::memmap::RangeList::new();
+        ranges.push(start.as_usize(), end.as_usize());
+        ranges
+    });
+    ranges.into_ranges().map(|(start, end)| MemRegion {
+        paddr: pa!(start),
+        size: end - start,
         flags: MemRegionFlags::FREE | MemRegionFlags::READ | MemRegionFlags::WRITE,
         name: "free memory",
     })
@@ -0,0 +1,291 @@
+//! The physical memory map passed by the firmware at boot.
+//!
+//! If the platform finds one, in the device tree or the multiboot
+//! information, the free memory regions are taken from it, so that all RAM,
+//! including regions with holes between them, is used regardless of the
+//! size configured at build time.
+
+#![allow(dead_code)]
+
+use lazyinit::LazyInit;
+use memory_addr::{align_down_4k, align_up, align_up_4k};
+
+use crate::mem::{phys_to_virt, virt_to_phys};
+
+/// The maximum number of ranges in each list.
+const MAX_RANGES: usize = 32;
+
+/// A list of disjoint physical address ranges, `[start, end)`.
+#[derive(Clone, Copy)]
+pub(crate) struct RangeList {
+    ranges: [(usize, usize); MAX_RANGES],
+    len: usize,
+}
+
+impl RangeList {
+    pub const fn new() -> Self {
+        Self {
+            ranges: [(0, 0); MAX_RANGES],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+        if self.len == MAX_RANGES {
+            warn!(
+                "too many memory ranges, ignoring [{:#x}, {:#x})",
+                start, end
+            );
+            return;
+        }
+        self.ranges[self.len] = (start, end);
+        self.len += 1;
+    }
+
+    /// Removes `[start, end)` from all ranges.
+    fn subtract(&mut self, start: usize, end: usize) {
+        let old = *self;
+        self.len = 0;
+        for &(s, e) in old.iter() {
+            if e <= start || end <= s {
+                self.push(s, e);
+            } else {
+                self.push(s, start);
+                self.push(end, e);
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(usize, usize)> {
+        self.ranges[..self.len].iter()
+    }
+
+    pub fn into_ranges(self) -> impl Iterator<Item = (usize, usize)> {
+        (0..self.len).map(move |i| self.ranges[i])
+    }
+}
+
+/// The free memory ranges from the boot memory map.
+static FREE_RANGES: LazyInit<RangeList> = LazyInit::new();
+
+/// Returns the free memory ranges from the boot memory map, or `None` if the
+/// platform did not find one.
+pub(crate) fn free_ranges() -> Option<RangeList> {
+    FREE_RANGES.is_inited().then(|| *FREE_RANGES)
+}
+
+/// Records the free memory: all of `ram` except `reserved` and the kernel
+/// image.
+fn init_free_ranges(ram: RangeList, reserved: RangeList) {
+    let kernel_start = virt_to_phys((_skernel as usize).into()).as_usize();
+    let kernel_end = align_up_4k(virt_to_phys((_ekernel as usize).into()).as_usize());
+    let mut free = RangeList::new();
+    for &(start, end) in ram.iter() {
+        // In the range holding the kernel, only the memory after it is free,
+        // as firmware often keeps its own data below the kernel.
+        if start <= kernel_start && kernel_start < end {
+            free.push(kernel_end, align_down_4k(end));
+        } else {
+            free.push(align_up_4k(start), align_down_4k(end));
+        }
+    }
+    for &(start, end) in reserved.iter() {
+        free.subtract(align_down_4k(start), align_up_4k(end));
+    }
+    for &(start, end) in free.iter() {
+        debug!("boot memory map: free [{:#x}, {:#x})", start, end);
+    }
+    FREE_RANGES.init_once(free);
+}
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// Reads the memory map from the device tree blob at `fdt_paddr`.
+///
+/// The `reg` of the `/memory` nodes gives the RAM, while the reservation
+/// block, the `reg` of the enabled children of `/reserved-memory` (whether
+/// `no-map` or not, as the memory is owned by the firmware or devices either
+/// way) and the blob itself are reserved.
+///
+/// # Safety
+///
+/// `fdt_paddr` must be zero or point to a valid blob, which must be mapped.
+pub(crate) unsafe fn init_from_fdt(fdt_paddr: usize) {
+    if fdt_paddr == 0 {
+        return;
+    }
+    let base = phys_to_virt(fdt_paddr.into()).as_usize();
+    let be32 = |off: usize| u32::from_be(unsafe { ((base + off) as *const u32).read_unaligned() });
+    let be64 = |off: usize| u64::from_be(unsafe { ((base + off) as *const u64).read_unaligned() });
+    if be32(0) != FDT_MAGIC {
+        warn!("invalid device tree at {:#x}", fdt_paddr);
+        return;
+    }
+    let total_size = be32(4) as usize;
+    let struct_off = be32(8) as usize;
+    let strings_off = be32(12) as usize;
+    let rsvmap_off = be32(16) as usize;
+    let c_str = |off: usize| {
+        let ptr = (base + off) as *const u8;
+        let len = (0..).take_while(|&i| unsafe { *ptr.add(i) } != 0).count();
+        unsafe { core::slice::from_raw_parts(ptr, len) }
+    };
+
+    let mut ram = RangeList::new();
+    let mut reserved = RangeList::new();
+    reserved.push(fdt_paddr, fdt_paddr + total_size);
+    let mut off = rsvmap_off;
+    loop {
+        let (start, size) = (be64(off) as usize, be64(off + 8) as usize);
+        if size == 0 {
+            break;
+        }
+        reserved.push(start, start + size);
+        off += 16;
+    }
+
+    // The cells of the `reg` of the nodes under the root.
+    let (mut addr_cells, mut size_cells) = (2, 1);
+    let read_cells = |off: usize, cells: usize| {
+        (0..cells).fold(0usize, |acc, i| (acc << 32) | be32(off + i * 4) as usize)
+    };
+    // Those of the children of `/reserved-memory`.
+    let (mut rsv_addr_cells, mut rsv_size_cells) = (2, 1);
+    let mut depth = 0;
+    let mut in_memory = false;
+    let mut in_reserved = false;
+    // The `reg` of the current child of `/reserved-memory`, reserved when
+    // the node ends unless its `status` disables it.
+    let mut child_regs = RangeList::new();
+    let mut child_disabled = false;
+    let mut off = struct_off;
+    loop {
+        let token = be32(off);
+        off += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = c_str(off);
+                off += align_up(name.len() + 1, 4);
+                depth += 1;
+                in_memory = depth == 2 && (name == b"memory" || name.starts_with(b"memory@"));
+                if depth == 2 && name == b"reserved-memory" {
+                    in_reserved = true;
+                    (rsv_addr_cells, rsv_size_cells) = (addr_cells, size_cells);
+                }
+                if depth == 3 {
+                    child_regs = RangeList::new();
+                    child_disabled = false;
+                }
+            }
+            FDT_END_NODE => {
+                if in_reserved && depth == 3 && !child_disabled {
+                    for &(start, end) in child_regs.iter() {
+                        reserved.push(start, end);
+                    }
+                }
+                if depth == 2 {
+                    in_reserved = false;
+                }
+                depth -= 1;
+                in_memory = false;
+            }
+            FDT_PROP => {
+                let len = be32(off) as usize;
+                let name = c_str(strings_off + be32(off + 4) as usize);
+                let value = off + 8;
+                off = value + align_up(len, 4);
+                match (depth, name) {
+                    (1, b"#address-cells") => addr_cells = be32(value) as usize,
+                    (1, b"#size-cells") => size_cells = be32(value) as usize,
+                    (2, b"reg") if in_memory => {
+                        let entry = (addr_cells + size_cells) * 4;
+                        for reg in (value..value + len / entry * entry).step_by(entry) {
+                            let start = read_cells(reg, addr_cells);
+                            let size = read_cells(reg + addr_cells * 4, size_cells);
+                            ram.push(start, start + size);
+                        }
+                    }
+                    (2, b"#address-cells") if in_reserved => rsv_addr_cells = be32(value) as usize,
+                    (2, b"#size-cells") if in_reserved => rsv_size_cells = be32(value) as usize,
+                    (3, b"reg") if in_reserved => {
+                        let entry = (rsv_addr_cells + rsv_size_cells) * 4;
+                        for reg in (value..value + len / entry * entry).step_by(entry) {
+                            let start = read_cells(reg, rsv_addr_cells);
+                            let size = read_cells(reg + rsv_addr_cells * 4, rsv_size_cells);
+                            child_regs.push(start, start + size);
+                        }
+                    }
+                    (3, b"status") if in_reserved => {
+                        let status = c_str(value);
+                        child_disabled = status != b"okay" && status != b"ok";
+                    }
+                    _ => {}
+                }
+            }
+            FDT_NOP => {}
+            FDT_END => break,
+            _ => {
+                warn!("invalid device tree token {:#x}", token);
+                return;
+            }
+        }
+    }
+    if ram.len > 0 {
+        init_free_ranges(ram, reserved);
+    }
+}
+
+/// The multiboot memory map is present.
+const MULTIBOOT_INFO_MEM_MAP: u32 = 1 << 6;
+/// The type of RAM in the multiboot memory map.
+const MULTIBOOT_MEMORY_AVAILABLE: u32 = 1;
+/// The memory below it holds the BIOS data, the multiboot information, etc.
+const LOW_MEMORY_END: usize = 0x10_0000;
+
+/// Reads the memory map from the multiboot information at `mbi_paddr`.
+///
+/// # Safety
+///
+/// `mbi_paddr` must point to valid multiboot information, which must be
+/// mapped.
+pub(crate) unsafe fn init_from_multiboot(mbi_paddr: usize) {
+    let base = phys_to_virt(mbi_paddr.into()).as_usize();
+    let read32 = |addr: usize| unsafe { (addr as *const u32).read_unaligned() };
+    let read64 = |addr: usize| unsafe { (addr as *const u64).read_unaligned() };
+    if read32(base) & MULTIBOOT_INFO_MEM_MAP == 0 {
+        return;
+    }
+    let mmap_len = read32(base + 44) as usize;
+    let mmap = phys_to_virt((read32(base + 48) as usize).into()).as_usize();
+
+    let mut ram = RangeList::new();
+    let mut reserved = RangeList::new();
+    reserved.push(0, LOW_MEMORY_END);
+    let mut entry = mmap;
+    while entry < mmap + mmap_len {
+        // The `size` field does not count itself.
+        let size = read32(entry) as usize;
+        let start = read64(entry + 4) as usize;
+        let len = read64(entry + 12) as usize;
+        if read32(entry + 20) == MULTIBOOT_MEMORY_AVAILABLE {
+            ram.push(start, start + len);
+        }
+        entry += size + 4;
+    }
+    if ram.len > 0 {
+        init_free_ranges(ram, reserved);
+    }
+}
+
+unsafe extern "C" {
+    fn _skernel();
+    fn _ekernel();
+}
@@ -57,18 +57,164 @@ cfg_if::cfg_if! {
     if #[cfg(target_arch = "x86_64")] {
         /// The architecture-specific page table.
         pub type PageTable = page_table_multiarch::x86_64::X64PageTable<PagingHandlerImpl>;
+        type PageTableEntry = page_table_entry::x86_64::X64PTE;
+        const PAGE_TABLE_LEVELS: usize = 4;
     } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
         /// The architecture-specific page table.
         pub type PageTable = page_table_multiarch::riscv::Sv39PageTable<PagingHandlerImpl>;
+        type PageTableEntry = page_table_entry::riscv::Rv64PTE;
+        const PAGE_TABLE_LEVELS: usize = 3;
     } else if #[cfg(target_arch = "aarch64")]{
         /// The architecture-specific page table.
         pub type PageTable = page_table_multiarch::aarch64::A64PageTable<PagingHandlerImpl>;
+        type PageTableEntry = page_table_entry::aarch64::A64PTE;
+        const PAGE_TABLE_LEVELS: usize = 4;
     } else if #[cfg(target_arch = "loongarch64")] {
         /// The architecture-specific page table.
         pub type PageTable = page_table_multiarch::loongarch64::LA64PageTable<PagingHandlerImpl>;
+        type PageTableEntry = page_table_entry::loongarch64::LA64PTE;
+        const PAGE_TABLE_LEVELS: usize = 4;
     }
 }
 
+/// Frees the last-level table of `pt` that maps the 2M region containing
+/// `vaddr`, if none of its entries is in use.
+///
+/// [`PageTable`] never frees intermediate tables, and a 2M huge page cannot
+/// be mapped where such a table exists. Returns `true` if the table was
+/// freed, or if there was none.
+pub fn free_empty_last_level_table(pt: &mut PageTable, vaddr: VirtAddr) -> bool {
+    use page_table_entry::GenericPTE;
+
+    const ENTRY_COUNT: usize = 512;
+    let index = |level: usize| {
+        (vaddr.as_usize() >> (12 + 9 * (PAGE_TABLE_LEVELS - 1 - level))) % ENTRY_COUNT
+    };
+
+    let mut table = pt.root_paddr();
+    for level in 0..PAGE_TABLE_LEVELS - 1 {
+        // SAFETY: `table` is a page table frame of `pt`, which is borrowed
+        // mutably, so no one else walks or modifies it meanwhile.
+        let entry = unsafe {
+            &mut *phys_to_virt(table)
+                .as_mut_ptr_of::<PageTableEntry>()
+                .add(index(level))
+        };
+        if entry.is_unused() {
+            return true;
+        }
+        if !entry.is_present() || entry.is_huge() {
+            return false;
+        }
+        if level == PAGE_TABLE_LEVELS - 2 {
+            let last = entry.paddr();
+            let entries = unsafe {
+                core::slice::from_raw_parts(
+                    phys_to_virt(last).as_ptr_of::<PageTableEntry>(),
+                    ENTRY_COUNT,
+                )
+            };
+            if !entries.iter().all(|e| e.is_unused()) {
+                return false;
+            }
+            entry.clear();
+            // Drop cached walks through the freed table.
+            crate::arch::flush_tlb(None);
+            PagingHandlerImpl::dealloc_frame(last);
+            return true;
+        }
+        table = entry.paddr();
+    }
+    false
+}
+
+/// Tests and clears the accessed bit of the entry mapping the 4K page at
+/// `vaddr` in `pt`, which the hardware sets whenever it walks to the entry.
+///
+/// Returns `None` if no 4K page is mapped there, or if the architecture does
+/// not set the bit in hardware. Only x86_64 does among the supported ones,
+/// elsewhere an access through an entry without the bit faults.
+pub fn test_and_clear_accessed(pt: &mut PageTable, vaddr: VirtAddr) -> Option<bool> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use core::sync::atomic::{AtomicU64, Ordering};
+        use page_table_entry::GenericPTE;
+
+        const ENTRY_COUNT: usize = 512;
+        const PTE_ACCESSED: u64 = 1 << 5;
+        let index = |level: usize| {
+            (vaddr.as_usize() >> (12 + 9 * (PAGE_TABLE_LEVELS - 1 - level))) % ENTRY_COUNT
+        };
+
+        let mut table = pt.root_paddr();
+        for level in 0..PAGE_TABLE_LEVELS {
+            // SAFETY: `table` is a page table frame of `pt`, which is borrowed
+            // mutably, so no one but the hardware modifies it meanwhile.
+            let entry = unsafe {
+                phys_to_virt(table)
+                    .as_mut_ptr_of::<PageTableEntry>()
+                    .add(index(level))
+            };
+            let pte = unsafe { *entry };
+            if !pte.is_present() || (pte.is_huge() && level != PAGE_TABLE_LEVELS - 1) {
+                return None;
+            }
+            if level == PAGE_TABLE_LEVELS - 1 {
+                // The hardware may set the bit concurrently.
+                let bits = unsafe { AtomicU64::from_ptr(entry.cast()) };
+                let accessed = bits.fetch_and(!PTE_ACCESSED, Ordering::AcqRel) & PTE_ACCESSED != 0;
+                if accessed {
+                    // Otherwise the cached entry keeps the page accessed
+                    // without setting the bit again.
+                    crate::arch::flush_tlb(Some(vaddr));
+                }
+                return Some(accessed);
+            }
+            table = pte.paddr();
+        }
+        None
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        let _ = (pt, vaddr);
+        None
+    }
+}
+
+/// Translates `vaddr` by walking the kernel page table, for addresses
+/// outside the linear mapping.
+///
+/// Returns `None` if `vaddr` is not mapped, or before the kernel page table
+/// is set up.
+pub(crate) fn kernel_translate(vaddr: VirtAddr) -> Option<PhysAddr> {
+    use page_table_entry::GenericPTE;
+
+    const ENTRY_COUNT: usize = 512;
+    let shift = |level: usize| 12 + 9 * (PAGE_TABLE_LEVELS - 1 - level);
+
+    let mut table = *KERNEL_PAGE_TABLE_ROOT.get()?;
+    for level in 0..PAGE_TABLE_LEVELS {
+        // SAFETY: `table` is a frame of the kernel page table, whose tables
+        // are never freed. The entry may be changed concurrently, but the
+        // caller owns the memory at `vaddr`, so it stays mapped.
+        let pte = unsafe {
+            phys_to_virt(table)
+                .as_ptr_of::<PageTableEntry>()
+                .add((vaddr.as_usize() >> shift(level)) % ENTRY_COUNT)
+                .read_volatile()
+        };
+        if !pte.is_present() {
+            return None;
+        }
+        if level == PAGE_TABLE_LEVELS - 1 || pte.is_huge() {
+            let offset = vaddr.as_usize() & ((1 << shift(level)) - 1);
+            return Some(pte.paddr() + offset);
+        }
+        table = pte.paddr();
+    }
+    None
+}
+
 static KERNEL_PAGE_TABLE_ROOT: LazyInit<PhysAddr> = LazyInit::new();
 
 /// Saves the root physical address of the kernel page table, which may be used
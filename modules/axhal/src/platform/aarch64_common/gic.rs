@@ -10,12 +10,18 @@ pub const MAX_IRQ_COUNT: usize = 1024;
 /// The timer IRQ number.
 pub const TIMER_IRQ_NUM: usize = translate_irq(14, InterruptType::PPI).unwrap();
 
+/// The IRQ number of the inter-processor interrupts sent by [`send_ipi`].
+pub const IPI_IRQ_NUM: usize = translate_irq(1, InterruptType::SGI).unwrap();
+
 /// The UART IRQ number.
 pub const UART_IRQ_NUM: usize = translate_irq(UART_IRQ, InterruptType::SPI).unwrap();
 
 const GICD_BASE: PhysAddr = pa!(GICD_PADDR);
 const GICC_BASE: PhysAddr = pa!(GICC_PADDR);
 
+/// The offset of the software generated interrupt register of GICD.
+const GICD_SGIR: usize = 0xf00;
+
 static GICD: SpinNoIrq<GicDistributor> =
     SpinNoIrq::new(GicDistributor::new(phys_to_virt(GICD_BASE).as_mut_ptr()));
 
@@ -37,6 +43,16 @@ pub fn register_handler(irq_num: usize, handler: IrqHandler) -> bool {
     crate::irq::register_handler_common(irq_num, handler)
 }
 
+/// Sends an inter-processor interrupt of [`IPI_IRQ_NUM`] to the CPU `cpu_id`.
+///
+/// The CPU interfaces of GIC are numbered as the CPUs.
+pub fn send_ipi(cpu_id: usize) {
+    let sgir = phys_to_virt(GICD_BASE + GICD_SGIR).as_mut_ptr() as *mut u32;
+    // Make the memory writes before visible to the target CPU.
+    core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+    unsafe { sgir.write_volatile((1 << (16 + cpu_id)) | IPI_IRQ_NUM as u32) };
+}
+
 /// Dispatches the IRQ.
 ///
 /// This function is called by the common interrupt handler. It looks
@@ -57,4 +73,6 @@ pub(crate) fn init_primary() {
 #[cfg(feature = "smp")]
 pub(crate) fn init_secondary() {
     GICC.init();
+    // The enable bits of SGIs are banked per CPU.
+    set_enable(IPI_IRQ_NUM, true);
 }
@@ -4,21 +4,44 @@ use loongArch64::register::{
 };
 
 /// The maximum number of IRQs.
-pub const MAX_IRQ_COUNT: usize = 12;
+pub const MAX_IRQ_COUNT: usize = 13;
 
 /// The timer IRQ number.
 pub const TIMER_IRQ_NUM: usize = estat::Interrupt::Timer as usize;
 
-/// Enables or disables the given IRQ.
+/// The IRQ number of the inter-processor interrupts sent by [`send_ipi`].
+pub const IPI_IRQ_NUM: usize = estat::Interrupt::IPI as usize;
+
+/// The action bit of the inter-processor interrupts sent by [`send_ipi`].
+const ACTION_WAKEUP: u32 = 2;
+
+const IOCSR_IPI_STATUS: usize = 0x1000;
+const IOCSR_IPI_ENABLE: usize = 0x1004;
+const IOCSR_IPI_CLEAR: usize = 0x100c;
+
+fn iocsr_read_w(reg: usize) -> u32 {
+    let value: u32;
+    unsafe { core::arch::asm!("iocsrrd.w {}, {}", out(reg) value, in(reg) reg) };
+    value
+}
+
+fn iocsr_write_w(reg: usize, value: u32) {
+    unsafe { core::arch::asm!("iocsrwr.w {}, {}", in(reg) value, in(reg) reg) };
+}
+
+/// Enables or disables the given IRQ on the current CPU.
 pub fn set_enable(irq_num: usize, enabled: bool) {
-    if irq_num == TIMER_IRQ_NUM {
-        let old_value = ecfg::read().lie();
-        let new_value = match enabled {
-            true => old_value | LineBasedInterrupt::TIMER,
-            false => old_value & !LineBasedInterrupt::TIMER,
-        };
-        ecfg::set_lie(new_value);
-    }
+    let line = match irq_num {
+        TIMER_IRQ_NUM => LineBasedInterrupt::TIMER,
+        IPI_IRQ_NUM => LineBasedInterrupt::IPI,
+        _ => return,
+    };
+    let old_value = ecfg::read().lie();
+    let new_value = match enabled {
+        true => old_value | line,
+        false => old_value & !line,
+    };
+    ecfg::set_lie(new_value);
 }
 
 /// Registers an IRQ handler for the given IRQ.
@@ -34,6 +57,19 @@ pub fn register_handler(irq_num: usize, handler: crate::irq::IrqHandler) -> bool
 pub fn dispatch_irq(irq_num: usize) {
     if irq_num == TIMER_IRQ_NUM {
         ticlr::clear_timer_interrupt();
+    } else if irq_num == IPI_IRQ_NUM {
+        iocsr_write_w(IOCSR_IPI_CLEAR, iocsr_read_w(IOCSR_IPI_STATUS));
     }
     crate::irq::dispatch_irq_common(irq_num)
 }
+
+/// Sends an inter-processor interrupt of [`IPI_IRQ_NUM`] to the CPU `cpu_id`.
+pub fn send_ipi(cpu_id: usize) {
+    loongArch64::ipi::send_ipi_single(cpu_id, ACTION_WAKEUP);
+}
+
+/// Accepts the inter-processor interrupts on the current CPU.
+pub(super) fn init_percpu() {
+    iocsr_write_w(IOCSR_IPI_ENABLE, u32::MAX);
+    set_enable(IPI_IRQ_NUM, true);
+}
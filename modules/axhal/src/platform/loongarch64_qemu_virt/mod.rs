@@ -10,11 +10,17 @@ pub mod mp;
 pub mod time;
 
 /// Initializes the platform devices for the primary CPU.
-pub fn platform_init() {}
+pub fn platform_init() {
+    #[cfg(feature = "irq")]
+    self::irq::init_percpu();
+}
 
 /// Initializes the platform devices for secondary CPUs.
 #[cfg(feature = "smp")]
-pub fn platform_init_secondary() {}
+pub fn platform_init_secondary() {
+    #[cfg(feature = "irq")]
+    self::irq::init_percpu();
+}
 
 unsafe extern "C" {
     fn rust_main(cpu_id: usize, dtb: usize);
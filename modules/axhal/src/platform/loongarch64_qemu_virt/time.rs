@@ -34,6 +34,9 @@ pub fn nanos_to_ticks(nanos: u64) -> u64 {
 ///
 /// A timer interrupt will be triggered at the specified monotonic time deadline (in nanoseconds).
 ///
+/// A deadline too far for the timer triggers the interrupt early, at the
+/// farthest time it can count to, and a past one at once.
+///
 /// LoongArch64 TCFG CSR: <https://loongson.github.io/LoongArch-Documentation/LoongArch-Vol1-EN.html#timer-configuration>
 #[cfg(feature = "irq")]
 pub fn set_oneshot_timer(deadline_ns: u64) {
@@ -41,7 +44,9 @@ pub fn set_oneshot_timer(deadline_ns: u64) {
 
     let ticks_now = current_ticks();
     let ticks_deadline = nanos_to_ticks(deadline_ns);
-    let init_value = ticks_deadline - ticks_now;
+    let init_value = ticks_deadline
+        .saturating_sub(ticks_now)
+        .clamp(1, u32::MAX as u64);
     tcfg::set_init_val(init_value as _);
     tcfg::set_en(true);
 }
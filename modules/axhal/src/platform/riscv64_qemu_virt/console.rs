@@ -1,6 +1,6 @@
-use memory_addr::VirtAddr;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
 
-use crate::mem::virt_to_phys;
+use crate::mem::kernel_virt_to_phys;
 
 /// The maximum number of bytes that can be read at once.
 const MAX_RW_SIZE: usize = 256;
@@ -10,17 +10,25 @@ pub fn putchar(c: u8) {
     sbi_rt::console_write_byte(c);
 }
 
+/// Returns the physical address of the buffer at `vaddr` and how many of its
+/// `len` bytes can be passed to SBI at once.
+///
+/// The buffer may be outside the linear mapping (e.g., on a kernel task
+/// stack), where it is physically contiguous only within a page.
+fn sbi_buffer(vaddr: VirtAddr, len: usize) -> (usize, usize) {
+    let paddr = kernel_virt_to_phys(vaddr).expect("console buffer is not mapped");
+    let in_page = vaddr.align_up_4k().as_usize() - vaddr.as_usize();
+    let in_page = if in_page == 0 { PAGE_SIZE_4K } else { in_page };
+    (paddr.as_usize(), len.min(MAX_RW_SIZE).min(in_page))
+}
+
 /// Tries to write bytes to the console from input u8 slice.
 /// Returns the number of bytes written.
 fn try_write_bytes(bytes: &[u8]) -> usize {
-    sbi_rt::console_write(sbi_rt::Physical::new(
-        // A maximum of 256 bytes can be written at a time
-        // to prevent SBI from disabling IRQs for too long.
-        bytes.len().min(MAX_RW_SIZE),
-        virt_to_phys(VirtAddr::from_ptr_of(bytes.as_ptr())).as_usize(),
-        0,
-    ))
-    .value
+    // A maximum of 256 bytes can be written at a time
+    // to prevent SBI from disabling IRQs for too long.
+    let (paddr, len) = sbi_buffer(VirtAddr::from_ptr_of(bytes.as_ptr()), bytes.len());
+    sbi_rt::console_write(sbi_rt::Physical::new(len, paddr, 0)).value
 }
 
 /// Writes bytes to the console from input u8 slice.
@@ -53,10 +61,6 @@ pub fn write_bytes(bytes: &[u8]) {
 /// Reads bytes from the console into the given mutable slice.
 /// Returns the number of bytes read.
 pub fn read_bytes(bytes: &mut [u8]) -> usize {
-    sbi_rt::console_read(sbi_rt::Physical::new(
-        bytes.len().min(MAX_RW_SIZE),
-        virt_to_phys(VirtAddr::from_mut_ptr_of(bytes.as_mut_ptr())).as_usize(),
-        0,
-    ))
-    .value
+    let (paddr, len) = sbi_buffer(VirtAddr::from_mut_ptr_of(bytes.as_mut_ptr()), bytes.len());
+    sbi_rt::console_read(sbi_rt::Physical::new(len, paddr, 0)).value
 }
@@ -1,14 +1,11 @@
-//! TODO: PLIC
-
 use crate::irq::IrqHandler;
 use lazyinit::LazyInit;
-use riscv::register::sie;
+use riscv::register::{sie, sip};
 
 /// `Interrupt` bit in `scause`
 pub(super) const INTC_IRQ_BASE: usize = 1 << (usize::BITS - 1);
 
 /// Supervisor software interrupt in `scause`
-#[allow(unused)]
 pub(super) const S_SOFT: usize = INTC_IRQ_BASE + 1;
 
 /// Supervisor timer interrupt in `scause`
@@ -18,6 +15,7 @@ pub(super) const S_TIMER: usize = INTC_IRQ_BASE + 5;
 pub(super) const S_EXT: usize = INTC_IRQ_BASE + 9;
 
 static TIMER_HANDLER: LazyInit<IrqHandler> = LazyInit::new();
+static IPI_HANDLER: LazyInit<IrqHandler> = LazyInit::new();
 
 /// The maximum number of IRQs.
 pub const MAX_IRQ_COUNT: usize = 1024;
@@ -25,9 +23,19 @@ pub const MAX_IRQ_COUNT: usize = 1024;
 /// The timer IRQ number (supervisor timer interrupt in `scause`).
 pub const TIMER_IRQ_NUM: usize = S_TIMER;
 
+/// The IRQ number of the inter-processor interrupts sent by [`send_ipi`]
+/// (supervisor software interrupt in `scause`).
+pub const IPI_IRQ_NUM: usize = S_SOFT;
+
 macro_rules! with_cause {
-    ($cause: expr, @TIMER => $timer_op: expr, @EXT => $ext_op: expr $(,)?) => {
+    (
+        $cause: expr,
+        @SOFT => $soft_op: expr,
+        @TIMER => $timer_op: expr,
+        @EXT => $ext_op: expr $(,)?
+    ) => {
         match $cause {
+            S_SOFT => $soft_op,
             S_TIMER => $timer_op,
             S_EXT => $ext_op,
             _ => panic!("invalid trap cause: {:#x}", $cause),
@@ -35,30 +43,122 @@ macro_rules! with_cause {
     };
 }
 
+/// Minimal driver of the platform-level interrupt controller (PLIC).
+///
+/// Only the supervisor-mode context of each hart is used, which is context
+/// `2 * hart_id + 1` on QEMU virt.
+mod plic {
+    use crate::mem::phys_to_virt;
+    use core::ptr::{read_volatile, write_volatile};
+    use memory_addr::pa;
+
+    const PRIORITY_BASE: usize = 0;
+    const ENABLE_BASE: usize = 0x2000;
+    const ENABLE_STRIDE: usize = 0x80;
+    const CONTEXT_BASE: usize = 0x20_0000;
+    const CONTEXT_STRIDE: usize = 0x1000;
+    const CONTEXT_THRESHOLD: usize = 0;
+    const CONTEXT_CLAIM: usize = 4;
+
+    fn reg(offset: usize) -> *mut u32 {
+        (phys_to_virt(pa!(axconfig::devices::PLIC_PADDR)).as_usize() + offset) as *mut u32
+    }
+
+    const fn s_context(hart_id: usize) -> usize {
+        2 * hart_id + 1
+    }
+
+    /// Enables or disables the interrupt source on all harts.
+    pub fn set_enable(irq: usize, enabled: bool) {
+        unsafe {
+            write_volatile(reg(PRIORITY_BASE + irq * 4), enabled as u32);
+            for hart_id in 0..axconfig::SMP {
+                let ptr = reg(ENABLE_BASE + s_context(hart_id) * ENABLE_STRIDE + irq / 32 * 4);
+                let mask = 1 << (irq % 32);
+                let val = read_volatile(ptr);
+                write_volatile(ptr, if enabled { val | mask } else { val & !mask });
+            }
+        }
+    }
+
+    /// Accepts all interrupts with a non-zero priority on the current hart.
+    pub fn init_percpu() {
+        let ctx = s_context(crate::cpu::this_cpu_id());
+        unsafe {
+            write_volatile(
+                reg(CONTEXT_BASE + ctx * CONTEXT_STRIDE + CONTEXT_THRESHOLD),
+                0,
+            )
+        };
+    }
+
+    /// Claims the highest-priority pending interrupt, if any.
+    pub fn claim() -> Option<usize> {
+        let ctx = s_context(crate::cpu::this_cpu_id());
+        let irq =
+            unsafe { read_volatile(reg(CONTEXT_BASE + ctx * CONTEXT_STRIDE + CONTEXT_CLAIM)) };
+        (irq != 0).then_some(irq as usize)
+    }
+
+    /// Signals the completion of the given claimed interrupt.
+    pub fn complete(irq: usize) {
+        let ctx = s_context(crate::cpu::this_cpu_id());
+        unsafe {
+            write_volatile(
+                reg(CONTEXT_BASE + ctx * CONTEXT_STRIDE + CONTEXT_CLAIM),
+                irq as u32,
+            )
+        };
+    }
+}
+
 /// Enables or disables the given IRQ.
-pub fn set_enable(scause: usize, _enabled: bool) {
-    if scause == S_EXT {
-        // TODO: set enable in PLIC
+///
+/// `irq_num` is either an interrupt cause in `scause`, or a PLIC interrupt
+/// source number for external devices.
+pub fn set_enable(irq_num: usize, enabled: bool) {
+    if irq_num & INTC_IRQ_BASE == 0 {
+        plic::set_enable(irq_num, enabled);
     }
 }
 
 /// Registers an IRQ handler for the given IRQ.
 ///
+/// `irq_num` is either an interrupt cause in `scause`, or a PLIC interrupt
+/// source number for external devices.
+///
 /// It also enables the IRQ if the registration succeeds. It returns `false` if
 /// the registration failed.
-pub fn register_handler(scause: usize, handler: IrqHandler) -> bool {
+pub fn register_handler(irq_num: usize, handler: IrqHandler) -> bool {
+    if irq_num & INTC_IRQ_BASE == 0 {
+        return crate::irq::register_handler_common(irq_num, handler);
+    }
     with_cause!(
-        scause,
+        irq_num,
+        @SOFT => if !IPI_HANDLER.is_inited() {
+            IPI_HANDLER.init_once(handler);
+            true
+        } else {
+            false
+        },
         @TIMER => if !TIMER_HANDLER.is_inited() {
             TIMER_HANDLER.init_once(handler);
             true
         } else {
             false
         },
-        @EXT => crate::irq::register_handler_common(scause & !INTC_IRQ_BASE, handler),
+        @EXT => {
+            warn!("external IRQs must be registered by their PLIC source numbers");
+            false
+        },
     )
 }
 
+/// Sends an inter-processor interrupt of [`IPI_IRQ_NUM`] to the CPU `cpu_id`.
+pub fn send_ipi(cpu_id: usize) {
+    sbi_rt::send_ipi(sbi_rt::HartMask::from_mask_base(1, cpu_id));
+}
+
 /// Dispatches the IRQ.
 ///
 /// This function is called by the common interrupt handler. It looks
@@ -67,15 +167,26 @@ pub fn register_handler(scause: usize, handler: IrqHandler) -> bool {
 pub fn dispatch_irq(scause: usize) {
     with_cause!(
         scause,
+        @SOFT => {
+            trace!("IRQ: IPI");
+            unsafe { sip::clear_ssoft() };
+            IPI_HANDLER();
+        },
         @TIMER => {
             trace!("IRQ: timer");
             TIMER_HANDLER();
         },
-        @EXT => crate::irq::dispatch_irq_common(0), // TODO: get IRQ number from PLIC
+        @EXT => {
+            while let Some(irq) = plic::claim() {
+                crate::irq::dispatch_irq_common(irq);
+                plic::complete(irq);
+            }
+        },
     );
 }
 
 pub(super) fn init_percpu() {
+    plic::init_percpu();
     // enable soft interrupts, timer interrupts, and external interrupts
     unsafe {
         sie::set_ssoft();
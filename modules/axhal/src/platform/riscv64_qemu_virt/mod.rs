@@ -20,6 +20,7 @@ unsafe extern "C" {
 unsafe extern "C" fn rust_entry(cpu_id: usize, dtb: usize) {
     crate::mem::clear_bss();
     crate::cpu::init_primary(cpu_id);
+    unsafe { crate::memmap::init_from_fdt(dtb) };
     #[cfg(feature = "uspace")]
     riscv::register::sstatus::set_sum();
     self::time::init_early();
@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+use core::sync::atomic::AtomicU8;
 use core::{cell::SyncUnsafeCell, mem::MaybeUninit};
 
 use kspin::SpinNoIrq;
@@ -16,6 +17,10 @@ pub(super) mod vectors {
     pub const APIC_TIMER_VECTOR: u8 = 0xf0;
     pub const APIC_SPURIOUS_VECTOR: u8 = 0xf1;
     pub const APIC_ERROR_VECTOR: u8 = 0xf2;
+    pub const APIC_IPI_VECTOR: u8 = 0xf3;
+    /// The first vector of message-signaled interrupts, the ones below are
+    /// left to the IO APIC.
+    pub const MSI_VECTOR_BASE: u8 = 0x80;
 }
 
 /// The maximum number of IRQs.
@@ -24,18 +29,22 @@ pub const MAX_IRQ_COUNT: usize = 256;
 /// The timer IRQ number.
 pub const TIMER_IRQ_NUM: usize = APIC_TIMER_VECTOR as usize;
 
+/// The IRQ number of the inter-processor interrupts sent by [`send_ipi`].
+pub const IPI_IRQ_NUM: usize = APIC_IPI_VECTOR as usize;
+
 const IO_APIC_BASE: PhysAddr = pa!(0xFEC0_0000);
 
 static LOCAL_APIC: SyncUnsafeCell<MaybeUninit<LocalApic>> =
     SyncUnsafeCell::new(MaybeUninit::uninit());
 static mut IS_X2APIC: bool = false;
 static IO_APIC: LazyInit<SpinNoIrq<IoApic>> = LazyInit::new();
+static NEXT_MSI_VECTOR: AtomicU8 = AtomicU8::new(MSI_VECTOR_BASE);
 
 /// Enables or disables the given IRQ.
 #[cfg(feature = "irq")]
 pub fn set_enable(vector: usize, enabled: bool) {
-    // should not affect LAPIC interrupts
-    if vector < APIC_TIMER_VECTOR as _ {
+    // should not affect LAPIC interrupts and message-signaled interrupts
+    if vector < MSI_VECTOR_BASE as _ {
         unsafe {
             if enabled {
                 IO_APIC.lock().enable_irq(vector as u8);
@@ -55,6 +64,38 @@ pub fn register_handler(vector: usize, handler: crate::irq::IrqHandler) -> bool
     crate::irq::register_handler_common(vector, handler)
 }
 
+/// Allocates a vector for a message-signaled interrupt, and registers
+/// `handler` for it.
+///
+/// Returns the vector, with the address and data of the message that raises
+/// it on the current CPU, or `None` if the vectors are used up.
+#[cfg(feature = "irq")]
+pub fn alloc_msi(handler: crate::irq::IrqHandler) -> Option<crate::irq::MsiMessage> {
+    use core::sync::atomic::Ordering;
+
+    let vector = NEXT_MSI_VECTOR
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+            (v < APIC_TIMER_VECTOR).then_some(v + 1)
+        })
+        .ok()?;
+    if !register_handler(vector as usize, handler) {
+        return None;
+    }
+    // Physical destination mode, fixed delivery, edge-triggered.
+    let dest = crate::cpu::this_cpu_id() as u64;
+    Some(crate::irq::MsiMessage {
+        vector: vector as usize,
+        addr: 0xfee0_0000 | (dest << 12),
+        data: vector as u32,
+    })
+}
+
+/// Sends an inter-processor interrupt of [`IPI_IRQ_NUM`] to the CPU `cpu_id`.
+#[cfg(feature = "irq")]
+pub fn send_ipi(cpu_id: usize) {
+    unsafe { local_apic().send_ipi(APIC_IPI_VECTOR, raw_apic_id(cpu_id as u8)) };
+}
+
 /// Dispatches the IRQ.
 ///
 /// This function is called by the common interrupt handler. It looks
@@ -1,5 +1,3 @@
-// TODO: get memory regions from multiboot info.
-
 use crate::mem::{MemRegion, MemRegionFlags};
 
 /// Returns platform-specific memory regions.
@@ -31,13 +31,13 @@ fn current_cpu_id() -> usize {
     }
 }
 
-unsafe extern "C" fn rust_entry(magic: usize, _mbi: usize) {
-    // TODO: handle multiboot info
+unsafe extern "C" fn rust_entry(magic: usize, mbi: usize) {
     if magic == self::boot::MULTIBOOT_BOOTLOADER_MAGIC {
         crate::mem::clear_bss();
         crate::cpu::init_primary(current_cpu_id());
         self::uart16550::init();
         self::time::init_early();
+        unsafe { crate::memmap::init_from_multiboot(mbi) };
         rust_main(current_cpu_id(), 0);
     }
 }
@@ -38,6 +38,10 @@ pub fn epochoffset_nanos() -> u64 {
 /// Set a one-shot timer.
 ///
 /// A timer interrupt will be triggered at the specified monotonic time deadline (in nanoseconds).
+///
+/// A deadline too far for the 32-bit counter of the local APIC triggers the
+/// interrupt early, at the farthest time it can count to, and a past one at
+/// once.
 #[cfg(feature = "irq")]
 pub fn set_oneshot_timer(deadline_ns: u64) {
     let lapic = super::apic::local_apic();
@@ -45,8 +49,7 @@ pub fn set_oneshot_timer(deadline_ns: u64) {
     unsafe {
         if now_ns < deadline_ns {
             let apic_ticks = NANOS_TO_LAPIC_TICKS_RATIO.mul_trunc(deadline_ns - now_ns);
-            assert!(apic_ticks <= u32::MAX as u64);
-            lapic.set_timer_initial(apic_ticks.max(1) as u32);
+            lapic.set_timer_initial(apic_ticks.clamp(1, u32::MAX as u64) as u32);
         } else {
             lapic.set_timer_initial(1);
         }
@@ -1,25 +1,53 @@
+use alloc::{sync::Arc, vec::Vec};
 use core::fmt;
 
 use axerrno::{AxError, AxResult, ax_err};
 use axhal::mem::phys_to_virt;
 use axhal::paging::{MappingFlags, PageSize, PageTable, PagingError};
 use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, VirtAddrRange, is_aligned};
-use memory_set::{MemoryArea, MemorySet};
+use memory_set::{MappingBackend, MemoryArea, MemorySet};
 
-use crate::backend::Backend;
+use crate::backend::{Backend, FileCache, SharedPages};
 use crate::mapping_err_to_ax_err;
 use crate::page_iter_wrapper::{PAGE_SIZE_4K, PageIterWrapper};
+use crate::stack::{STACK_GUARD_GAP, Stacks};
+use crate::stat::{MappingInfo, MappingUsage, RssCounter, RssKind, RssStat, pages_of};
+use crate::swap::{self, SWAP_CLUSTER, SwapAreaId, SwapState};
+use crate::thp::{self, HUGE_PAGE_SIZE, RangeSet, ThpMode};
 
+use crate::backend::alloc_frame;
 #[cfg(feature = "cow")]
-use crate::backend::{alloc_frame, dealloc_frame};
-#[cfg(feature = "cow")]
+use crate::backend::dealloc_frame;
 use crate::frameinfo::frame_table;
 
+/// What [`AddrSpace::swap_out_page`] did with a page.
+enum SwapOut {
+    /// The page was swapped out.
+    Done,
+    /// The page was kept for a second chance, being accessed since the
+    /// last scan.
+    Accessed,
+    /// The page was kept, being shared copy-on-write.
+    Skipped,
+    /// The page was kept, as it could not be written to swap.
+    Failed,
+    /// The page is not mapped, or not swappable, anymore.
+    Gone,
+}
+
 /// The virtual memory address space.
 pub struct AddrSpace {
     va_range: VirtAddrRange,
     areas: MemorySet<Backend>,
     pt: PageTable,
+    /// Ranges advised with `MADV_HUGEPAGE`.
+    huge_advised: RangeSet,
+    /// Ranges advised with `MADV_NOHUGEPAGE`.
+    huge_disabled: RangeSet,
+    swap: SwapState,
+    rss: RssCounter,
+    /// The stacks growing down on faults.
+    stacks: Stacks,
 }
 
 impl AddrSpace {
@@ -60,6 +88,11 @@ impl AddrSpace {
             va_range: VirtAddrRange::from_start_size(base, size),
             areas: MemorySet::new(),
             pt: PageTable::try_new().map_err(|_| AxError::NoMemory)?,
+            huge_advised: RangeSet::new(),
+            huge_disabled: RangeSet::new(),
+            swap: SwapState::new(),
+            rss: RssCounter::new(),
+            stacks: Stacks::new(),
         })
     }
 
@@ -169,6 +202,13 @@ impl AddrSpace {
         }
     }
 
+    /// Checks whether no area overlaps the range `[start, start + size)`.
+    pub fn is_free(&self, start: VirtAddr, size: usize) -> bool {
+        !self
+            .areas
+            .overlaps(VirtAddrRange::from_start_size(start, size))
+    }
+
     /// Add a new linear mapping.
     ///
     /// See [`Backend`] for more details about the mapping backends.
@@ -221,6 +261,236 @@ impl AddrSpace {
         self.areas
             .map(area, &mut self.pt, false)
             .map_err(mapping_err_to_ax_err)?;
+        if populate {
+            self.account_mapped(start, start + size);
+        }
+        Ok(())
+    }
+
+    /// Add a new stack `[top - size, top)`, which grows down on faults below
+    /// it up to the stack limit.
+    ///
+    /// The initial part is populated, like a populated allocation mapping.
+    pub fn map_stack(&mut self, top: VirtAddr, size: usize, flags: MappingFlags) -> AxResult {
+        self.map_alloc(top - size, size, flags, true, PageSize::Size4K)?;
+        self.stacks.insert(top, top - size);
+        Ok(())
+    }
+
+    /// Returns the size stacks may grow to.
+    pub fn stack_limit(&self) -> usize {
+        self.stacks.limit()
+    }
+
+    /// Sets the size stacks may grow to, as `RLIMIT_STACK`. Stacks already
+    /// larger are kept.
+    pub fn set_stack_limit(&mut self, limit: usize) {
+        self.stacks.set_limit(limit);
+    }
+
+    /// Grows the stack above `vaddr` down to cover it.
+    ///
+    /// Returns `false` if `vaddr` is already mapped, or there is no stack
+    /// that may grow down to it within the stack limit and the guard gap.
+    pub fn grow_stack(&mut self, vaddr: VirtAddr) -> bool {
+        if !self.va_range.contains(vaddr) || self.areas.find(vaddr).is_some() {
+            return false;
+        }
+        let new_bottom = vaddr.align_down_4k();
+        let Some(bottom) = self.stacks.bottom_above(new_bottom) else {
+            return false;
+        };
+        let Some(flags) = self.areas.find(bottom).map(|area| area.flags()) else {
+            return false;
+        };
+        // Nothing may be mapped between the new bottom and the old one, nor
+        // within the guard gap below.
+        let gap_start = new_bottom
+            .as_usize()
+            .saturating_sub(STACK_GUARD_GAP)
+            .max(self.base().as_usize());
+        if !self.is_free(gap_start.into(), bottom.as_usize() - gap_start) {
+            return false;
+        }
+        // Extend the area at the bottom of the stack rather than adding one
+        // per fault, unless it is not a plain anonymous one anymore.
+        let top = self
+            .areas
+            .find(bottom)
+            .filter(|area| {
+                matches!(
+                    area.backend(),
+                    Backend::Alloc {
+                        align: PageSize::Size4K,
+                        ..
+                    }
+                )
+            })
+            .map_or(bottom, |area| area.end());
+        if self
+            .replace_anonymous(new_bottom, bottom, top, flags)
+            .is_err()
+        {
+            return false;
+        }
+        self.stacks.grow(bottom, new_bottom);
+        true
+    }
+
+    /// Replaces the private anonymous area `[start, end)`, if not empty, by
+    /// a lazy one with `flags` covering `[new_start, end)`, where
+    /// `[new_start, start)` is free. The pages mapped in the old area stay
+    /// mapped.
+    fn replace_anonymous(
+        &mut self,
+        new_start: VirtAddr,
+        start: VirtAddr,
+        end: VirtAddr,
+        flags: MappingFlags,
+    ) -> AxResult {
+        // Take the pages out of the old area first, so that unmapping it
+        // frees none of them.
+        let mut pages = Vec::new();
+        let mut addr = start;
+        while addr < end {
+            let Ok((frame, pte_flags, page_size)) = self.pt.query(addr) else {
+                addr += PAGE_SIZE_4K;
+                continue;
+            };
+            let (_, _, tlb) = self.pt.unmap(addr).map_err(|_| AxError::BadState)?;
+            tlb.flush();
+            pages.push((addr, frame.align_down(page_size), pte_flags, page_size));
+            addr += usize::from(page_size);
+        }
+        if start < end {
+            self.areas
+                .unmap(start, end - start, &mut self.pt)
+                .map_err(mapping_err_to_ax_err)?;
+        }
+        let area = MemoryArea::new(
+            new_start,
+            end - new_start,
+            flags,
+            Backend::new_alloc(false, PageSize::Size4K),
+        );
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        for (addr, frame, pte_flags, page_size) in pages {
+            self.pt
+                .map(addr, frame, page_size, pte_flags)
+                .map_err(|_| AxError::BadState)?
+                .ignore();
+        }
+        Ok(())
+    }
+
+    /// Forgets the stacks whose bottom is no longer mapped.
+    fn forget_unmapped_stacks(&mut self) {
+        let areas = &self.areas;
+        self.stacks.retain(|bottom| areas.find(bottom).is_some());
+    }
+
+    /// Add a new file mapping.
+    ///
+    /// The area maps `cache` starting from the file offset `offset`, which
+    /// must be 4K aligned. Pages are read from the file on demand. Unless
+    /// `may_write`, the area can't be made writable by [`AddrSpace::protect`].
+    /// See [`Backend::File`] for more details.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    #[allow(clippy::too_many_arguments)]
+    pub fn map_file(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        cache: Arc<FileCache>,
+        offset: usize,
+        shared: bool,
+        may_write: bool,
+    ) -> AxResult {
+        self.validate_region(start, size, PageSize::Size4K)?;
+        if !is_aligned(offset, PAGE_SIZE_4K) {
+            return ax_err!(InvalidInput, "file offset not aligned");
+        }
+        if flags.contains(MappingFlags::WRITE) && !may_write {
+            return ax_err!(PermissionDenied, "file mapping may not be writable");
+        }
+
+        let area = MemoryArea::new(
+            start,
+            size,
+            flags,
+            Backend::new_file(start, offset, cache, shared, may_write),
+        );
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        Ok(())
+    }
+
+    /// Add a new shared memory mapping.
+    ///
+    /// The area maps `pages` starting from the byte offset `offset`. The
+    /// frames are allocated on demand, and stay shared with the child address
+    /// spaces created by [`AddrSpace::try_clone`]. Unless `may_write`, the
+    /// area can't be made writable by [`AddrSpace::protect`]. See
+    /// [`Backend::Shared`] for more details.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    pub fn map_shared(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        pages: Arc<SharedPages>,
+        offset: usize,
+        may_write: bool,
+    ) -> AxResult {
+        let align = pages.align();
+        self.validate_region(start, size, align)?;
+        if !is_aligned(offset, align.into()) {
+            return ax_err!(InvalidInput, "offset not aligned");
+        }
+        if flags.contains(MappingFlags::WRITE) && !may_write {
+            return ax_err!(PermissionDenied, "shared mapping may not be writable");
+        }
+
+        let area = MemoryArea::new(
+            start,
+            size,
+            flags,
+            Backend::new_shared(start, offset, pages, may_write),
+        );
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        Ok(())
+    }
+
+    /// Writes back the dirty pages of shared file mappings within the
+    /// specified virtual address range, and write-protects them until they
+    /// are written again.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    pub fn msync(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        self.validate_region(start, size, PageSize::Size4K)?;
+        let end = start + size;
+        for area in self
+            .areas
+            .iter()
+            .skip_while(move |a| a.end() <= start)
+            .take_while(move |a| a.start() < end)
+        {
+            let sync_start = start.max(area.start());
+            let sync_size = end.min(area.end()) - sync_start;
+            area.backend()
+                .sync_file(sync_start, sync_size, &mut self.pt)?;
+        }
         Ok(())
     }
 
@@ -248,10 +518,22 @@ impl AddrSpace {
     /// - `AxError::NoMemory`: Failed to allocate.
     /// - `AxError::BadAddress`: An invalid mapping state was detected.
     pub fn populate_area(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        access_flags: MappingFlags,
+    ) -> AxResult {
+        self.populate_area_inner(start, size, access_flags, true)
+    }
+
+    /// Like [`AddrSpace::populate_area`], but file and shared mappings are
+    /// only populated if `populate_lazy` is `true`.
+    fn populate_area_inner(
         &mut self,
         mut start: VirtAddr,
         size: usize,
         _access_flags: MappingFlags,
+        populate_lazy: bool,
     ) -> AxResult {
         self.validate_region(start, size, PageSize::Size4K)?;
         let end = start + size;
@@ -299,9 +581,23 @@ impl AddrSpace {
                         // If the page is not mapped, try map it.
                         Err(PagingError::NotMapped) => {
                             if !populate {
-                                if !backend.handle_page_fault(addr, area.flags(), &mut self.pt) {
+                                let faulted =
+                                    match self.swap.swap_in(addr, area.flags(), &mut self.pt) {
+                                        Some(swapped_in) => swapped_in,
+                                        None => backend.handle_page_fault(
+                                            addr,
+                                            _access_flags,
+                                            area.flags(),
+                                            &mut self.pt,
+                                        ),
+                                    };
+                                if !faulted {
                                     return Err(AxError::NoMemory);
                                 }
+                                self.rss.add(RssKind::Anon, pages_of(align));
+                                if align == PageSize::Size4K {
+                                    self.swap.touch(addr);
+                                }
                             } else {
                                 return Err(AxError::BadAddress);
                             }
@@ -309,6 +605,41 @@ impl AddrSpace {
                         Err(_) => return Err(AxError::BadAddress),
                     };
                 }
+            } else if let Backend::File { .. } | Backend::Shared { .. } = backend
+                && populate_lazy
+            {
+                for addr in PageIterWrapper::new(
+                    start.align_down_4k(),
+                    end.align_up_4k().min(area.end()),
+                    PageSize::Size4K,
+                )
+                .unwrap()
+                {
+                    let (need_fault, mapped) = match self.pt.query(addr) {
+                        // A write to a clean shared page needs to mark it dirty.
+                        Ok((_, flags, _)) => (
+                            _access_flags.contains(MappingFlags::WRITE)
+                                && area.flags().contains(MappingFlags::WRITE)
+                                && !flags.contains(MappingFlags::WRITE),
+                            true,
+                        ),
+                        Err(PagingError::NotMapped) => (true, false),
+                        Err(_) => return Err(AxError::BadAddress),
+                    };
+                    if need_fault
+                        && !backend.handle_page_fault(
+                            addr,
+                            _access_flags,
+                            area.flags(),
+                            &mut self.pt,
+                        )
+                    {
+                        return Err(AxError::NoMemory);
+                    }
+                    if !mapped && let Some(kind) = RssKind::of(backend) {
+                        self.rss.add(kind, 1);
+                    }
+                }
             }
             start = area.end();
             assert!(start.is_aligned(PageSize::Size4K));
@@ -336,13 +667,7 @@ impl AddrSpace {
             .skip_while(move |a| a.end() <= start)
             .take_while(move |a| a.start() < end)
         {
-            let area_align = match *area.backend() {
-                Backend::Alloc { populate: _, align } => align,
-                Backend::Linear {
-                    pa_va_offset: _,
-                    align,
-                } => align,
-            };
+            let area_align = area.backend().align();
 
             let unmap_start = start.max(area.start());
             let unmap_size = end.min(area.end()) - unmap_start;
@@ -351,15 +676,330 @@ impl AddrSpace {
             }
         }
 
+        self.split_huge_pages_at(start, end)?;
+        self.account_unmapped(start, end);
         self.areas
             .unmap(start, size, &mut self.pt)
             .map_err(mapping_err_to_ax_err)?;
+        self.huge_advised.remove(start.as_usize(), end.as_usize());
+        self.huge_disabled.remove(start.as_usize(), end.as_usize());
+        self.swap.remove_range(start, end);
+        self.forget_unmapped_stacks();
         Ok(())
     }
 
+    /// Removes the mappings of `pages` at offset 0 from `start` within
+    /// `[start, start + size)`, leaving other mappings in the range alone,
+    /// e.g., those that replaced parts of it.
+    ///
+    /// Returns whether anything was unmapped.
+    pub fn unmap_shared(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        pages: &Arc<SharedPages>,
+    ) -> AxResult<bool> {
+        self.validate_region(start, size, pages.align())?;
+        let end = start + size;
+        let ranges: Vec<_> = self
+            .areas
+            .iter()
+            .skip_while(|a| a.end() <= start)
+            .take_while(|a| a.start() < end)
+            .filter(|a| {
+                matches!(
+                    a.backend(),
+                    Backend::Shared { offset_base, pages: p, .. }
+                        if *offset_base == start.as_usize() && Arc::ptr_eq(p, pages)
+                )
+            })
+            .map(|a| (start.max(a.start()), end.min(a.end())))
+            .collect();
+        for &(range_start, range_end) in &ranges {
+            self.unmap(range_start, range_end - range_start)?;
+        }
+        Ok(!ranges.is_empty())
+    }
+
     /// To remove user area mappings from address space.
     pub fn unmap_user_areas(&mut self) -> AxResult {
         self.areas.clear(&mut self.pt).unwrap();
+        self.swap.clear();
+        self.rss.reset();
+        self.stacks.clear();
+        Ok(())
+    }
+
+    /// Returns the flags and the backend of the area containing the whole
+    /// range `[start, start + size)`.
+    fn single_area(&self, start: VirtAddr, size: usize) -> AxResult<(MappingFlags, &Backend)> {
+        match self.areas.find(start) {
+            Some(area) if area.end() >= start + size => {
+                if let Backend::Linear { .. } = area.backend() {
+                    return ax_err!(InvalidInput, "cannot remap linear mappings");
+                }
+                Ok((area.flags(), area.backend()))
+            }
+            _ => ax_err!(BadAddress, "range not within one mapping"),
+        }
+    }
+
+    /// Shrinks or grows in place the mapping `[start, start + old_size)` to
+    /// `new_size` bytes.
+    ///
+    /// Growing requires the range to be the end of one area, followed by
+    /// enough free space. The new part maps what the area would map there,
+    /// e.g., the following pages of the same file. The object of a shared
+    /// anonymous mapping grows along, see [`SharedPages::new_anonymous`].
+    ///
+    /// Returns [`AxError::NoMemory`] if the area cannot grow in place.
+    pub fn resize_area(&mut self, start: VirtAddr, old_size: usize, new_size: usize) -> AxResult {
+        if new_size <= old_size {
+            return self.unmap(start + new_size, old_size - new_size);
+        }
+
+        let (flags, backend) = self.single_area(start, old_size)?;
+        let grow_start = start + old_size;
+        let grow_size = new_size - old_size;
+        self.validate_region(grow_start, grow_size, backend.align())?;
+        if self.areas.find(grow_start - 1).map(|a| a.end()) != Some(grow_start)
+            || self
+                .areas
+                .overlaps(VirtAddrRange::from_start_size(grow_start, grow_size))
+        {
+            return ax_err!(NoMemory, "no space to grow the mapping");
+        }
+
+        let populate = matches!(backend, Backend::Alloc { populate: true, .. });
+        backend.grow_shared(start + new_size);
+        let area = MemoryArea::new(grow_start, grow_size, flags, backend.clone());
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        if populate {
+            self.account_mapped(grow_start, start + new_size);
+        }
+        Ok(())
+    }
+
+    /// Moves the mapping `[old_start, old_start + old_size)` to `new_start`,
+    /// resizing it to `new_size` bytes.
+    ///
+    /// The range must be within one area, and the destination must be free.
+    /// Mapped pages are moved by their page table entries, so the contents
+    /// are kept without copying.
+    pub fn move_area(
+        &mut self,
+        old_start: VirtAddr,
+        old_size: usize,
+        new_start: VirtAddr,
+        new_size: usize,
+    ) -> AxResult {
+        let (flags, backend) = self.single_area(old_start, old_size)?;
+        let align = backend.align();
+        self.validate_region(old_start, old_size, align)?;
+        self.validate_region(new_start, new_size, align)?;
+        if self
+            .areas
+            .overlaps(VirtAddrRange::from_start_size(new_start, new_size))
+        {
+            return ax_err!(AlreadyExists, "destination already mapped");
+        }
+
+        let backend = backend.relocate(old_start, new_start);
+        let populate = matches!(backend, Backend::Alloc { populate: true, .. });
+        backend.grow_shared(new_start + new_size);
+
+        let moved = old_size.min(new_size);
+        let old_end = old_start + old_size;
+        if (new_start.as_usize().wrapping_sub(old_start.as_usize())) % HUGE_PAGE_SIZE != 0 {
+            // Huge pages cannot land on a misaligned destination.
+            let mut block = old_start.align_down(PageSize::Size2M);
+            while block < old_end {
+                if !thp::split_huge_page(&mut self.pt, block) {
+                    return ax_err!(NoMemory, "failed to split huge page");
+                }
+                block += HUGE_PAGE_SIZE;
+            }
+        } else {
+            self.split_huge_pages_at(old_start, old_end)?;
+            self.split_huge_pages_at(old_start + moved, old_end)?;
+        }
+
+        let mut addr = old_start;
+        while addr < old_start + moved {
+            // Keep the entry flags, e.g., of copy-on-write pages.
+            let Ok((frame, pte_flags, page_size)) = self.pt.query(addr) else {
+                addr += usize::from(align);
+                continue;
+            };
+            let frame = frame.align_down(page_size);
+            let (_, _, tlb) = self.pt.unmap(addr).map_err(|_| AxError::BadState)?;
+            tlb.flush();
+            let new_addr = new_start + (addr - old_start);
+            self.pt
+                .map(new_addr, frame, page_size, pte_flags)
+                .map_err(|_| AxError::BadState)?
+                .ignore();
+            if page_size == PageSize::Size4K {
+                self.swap.touch(new_addr);
+            }
+            addr += usize::from(page_size);
+        }
+        self.swap.remove_range(old_start + moved, old_end);
+        self.swap.move_range(old_start, moved, new_start);
+
+        // Nothing of the moved part is mapped there anymore, this only
+        // removes the old area and frees the pages beyond `new_size`.
+        self.account_unmapped(old_start, old_end);
+        self.areas
+            .unmap(old_start, old_size, &mut self.pt)
+            .map_err(mapping_err_to_ax_err)?;
+        self.forget_unmapped_stacks();
+
+        // The moved entries fill the new area, populating it only maps the
+        // pages beyond them.
+        let area = MemoryArea::new(new_start, new_size, flags, backend);
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        if populate {
+            self.account_mapped(new_start + moved, new_start + new_size);
+        }
+        Ok(())
+    }
+
+    /// Discards the pages in the specified range without unmapping it, as
+    /// `MADV_DONTNEED` does.
+    ///
+    /// Private anonymous pages read back as zeros, while file and shared
+    /// pages are faulted in again from the file or the shared object.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    pub fn discard(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        self.validate_region(start, size, PageSize::Size4K)?;
+
+        let end = start + size;
+        let overlapping = move |a: &&MemoryArea<Backend>| a.end() > start && a.start() < end;
+        for area in self.areas.iter().filter(overlapping) {
+            let align = area.backend().align();
+            let discard_start = start.max(area.start());
+            let discard_size = end.min(area.end()) - discard_start;
+            if !discard_start.is_aligned(align) || !is_aligned(discard_size, align.into()) {
+                return ax_err!(InvalidInput, "address not aligned");
+            }
+            if let Backend::Linear { .. } = area.backend() {
+                return ax_err!(InvalidInput, "cannot discard linear mappings");
+            }
+        }
+        self.split_huge_pages_at(start, end)?;
+        self.swap.remove_range(start, end);
+        self.account_unmapped(start, end);
+
+        for area in self.areas.iter().filter(overlapping) {
+            let discard_start = start.max(area.start());
+            let discard_size = end.min(area.end()) - discard_start;
+            let backend = area.backend();
+            // Populated areas get fresh zeroed frames, lazy ones nothing.
+            if !backend.unmap(discard_start, discard_size, &mut self.pt)
+                || !backend.map(discard_start, discard_size, area.flags(), &mut self.pt)
+            {
+                return Err(AxError::NoMemory);
+            }
+        }
+        self.account_mapped(start, end);
+        Ok(())
+    }
+
+    /// Whether all mappings within `[start, start + size)` are private
+    /// anonymous ones.
+    pub fn is_private_anonymous(&self, start: VirtAddr, size: usize) -> bool {
+        let end = start + size;
+        self.areas
+            .iter()
+            .filter(|a| a.end() > start && a.start() < end)
+            .all(|a| matches!(a.backend(), Backend::Alloc { .. }))
+    }
+
+    /// Advises whether `[start, start + size)` should use transparent huge
+    /// pages, as `MADV_HUGEPAGE` and `MADV_NOHUGEPAGE` do.
+    ///
+    /// Huge pages already mapped in the range are kept either way.
+    pub fn advise_huge(&mut self, start: VirtAddr, size: usize, enable: bool) -> AxResult {
+        self.validate_region(start, size, PageSize::Size4K)?;
+        let (start, end) = (start.as_usize(), start.as_usize() + size);
+        if enable {
+            self.huge_disabled.remove(start, end);
+            self.huge_advised.insert(start, end);
+        } else {
+            self.huge_advised.remove(start, end);
+            self.huge_disabled.insert(start, end);
+        }
+        Ok(())
+    }
+
+    /// Merges the fully populated 2M blocks of anonymous areas into huge
+    /// pages, returning the number of huge pages made.
+    ///
+    /// This is the work of Linux's `khugepaged`, and is meant to be called
+    /// periodically. Only the local TLB is flushed before the old pages are
+    /// freed, so no thread using this address space may run on another CPU.
+    pub fn collapse_huge_pages(&mut self) -> usize {
+        if thp::thp_mode() == ThpMode::Never {
+            return 0;
+        }
+        let mut blocks = Vec::new();
+        for area in self.areas.iter() {
+            let Backend::Alloc {
+                populate: false,
+                align: PageSize::Size4K,
+            } = area.backend()
+            else {
+                continue;
+            };
+            let mut block = area.start().align_up(PageSize::Size2M);
+            while block + HUGE_PAGE_SIZE <= area.end() {
+                if self.huge_page_allowed(block) {
+                    blocks.push(block);
+                }
+                block += HUGE_PAGE_SIZE;
+            }
+        }
+        blocks
+            .into_iter()
+            .filter(|&block| thp::collapse_huge_page(&mut self.pt, block))
+            .count()
+    }
+
+    /// Checks whether the 2M block `block` may be mapped by a huge page.
+    fn huge_page_allowed(&self, block: VirtAddr) -> bool {
+        let (start, end) = (block.as_usize(), block.as_usize() + HUGE_PAGE_SIZE);
+        match thp::thp_mode() {
+            ThpMode::Never => false,
+            _ if self.huge_disabled.intersects(start, end) => false,
+            ThpMode::Always => true,
+            ThpMode::Madvise => self.huge_advised.contains(start, end),
+        }
+    }
+
+    /// Splits the huge pages crossing `start` or `end`, so that operations on
+    /// `[start, end)` only see whole pages.
+    fn split_huge_pages_at(&mut self, start: VirtAddr, end: VirtAddr) -> AxResult {
+        for addr in [start, end] {
+            if addr.is_aligned(PageSize::Size2M) {
+                continue;
+            }
+            if let Some(area) = self.areas.find(addr)
+                && let Backend::Alloc {
+                    align: PageSize::Size4K,
+                    ..
+                } = area.backend()
+                && !thp::split_huge_page(&mut self.pt, addr)
+            {
+                return ax_err!(NoMemory, "failed to split huge page");
+            }
+        }
         Ok(())
     }
 
@@ -448,10 +1088,31 @@ impl AddrSpace {
     /// Updates mapping within the specified virtual address range.
     ///
     /// Returns an error if the address range is out of the address space or not
-    /// aligned.
+    /// aligned, and [`AxError::PermissionDenied`] if it would make writable a
+    /// file or shared mapping that may not be.
     pub fn protect(&mut self, start: VirtAddr, size: usize, flags: MappingFlags) -> AxResult {
+        if flags.contains(MappingFlags::WRITE)
+            && self.areas.iter().any(|area| {
+                area.start() < start + size
+                    && start < area.end()
+                    && matches!(
+                        area.backend(),
+                        Backend::File {
+                            may_write: false,
+                            ..
+                        } | Backend::Shared {
+                            may_write: false,
+                            ..
+                        }
+                    )
+            })
+        {
+            return ax_err!(PermissionDenied, "mapping may not be writable");
+        }
         // Populate the area first, which also checks the address range for us.
-        self.populate_area(start, size, flags)?;
+        // File mappings are left lazy, their pages are faulted in on access.
+        self.populate_area_inner(start, size, flags, false)?;
+        self.split_huge_pages_at(start, start + size)?;
 
         self.areas
             .protect(start, size, |_| Some(flags), &mut self.pt)
@@ -463,6 +1124,9 @@ impl AddrSpace {
     /// Removes all mappings in the address space.
     pub fn clear(&mut self) {
         self.areas.clear(&mut self.pt).unwrap();
+        self.swap.clear();
+        self.rss.reset();
+        self.stacks.clear();
     }
 
     /// Checks whether an access to the specified memory region is valid.
@@ -500,15 +1164,71 @@ impl AddrSpace {
     ///
     /// `access_flags` indicates the access type that caused the page fault.
     ///
+    /// A fault below a stack grows the stack first, see
+    /// [`AddrSpace::grow_stack`].
+    ///
     /// Returns `true` if the page fault is handled successfully (not a real
     /// fault).
     pub fn handle_page_fault(&mut self, vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
+        if self.areas.find(vaddr).is_none() {
+            self.grow_stack(vaddr);
+        }
+        let was_mapped = self.pt.query(vaddr).is_ok();
+        let handled = self.handle_page_fault_inner(vaddr, access_flags) || {
+            // The fault may have failed for lack of memory, swap some pages
+            // out and retry.
+            let valid = self.va_range.contains(vaddr)
+                && self
+                    .areas
+                    .find(vaddr)
+                    .is_some_and(|area| area.flags().contains(access_flags));
+            valid
+                && self.reclaim_pages(SWAP_CLUSTER) > 0
+                && self.handle_page_fault_inner(vaddr, access_flags)
+        };
+        if handled
+            && !was_mapped
+            && let Ok((_, _, page_size)) = self.pt.query(vaddr)
+        {
+            let start = vaddr.align_down(page_size);
+            self.account_mapped(start, start + usize::from(page_size));
+        }
+        handled
+    }
+
+    fn handle_page_fault_inner(&mut self, vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
         if !self.va_range.contains(vaddr) {
             return false;
         }
         if let Some(area) = self.areas.find(vaddr) {
             let orig_flags = area.flags();
             if orig_flags.contains(access_flags) {
+                // File mappings track dirty pages by write faults on their own,
+                // and shared pages must never be copied on write.
+                if let Backend::File { .. } | Backend::Shared { .. } = area.backend() {
+                    return area.backend().handle_page_fault(
+                        vaddr,
+                        access_flags,
+                        orig_flags,
+                        &mut self.pt,
+                    );
+                }
+
+                // Only private 4K pages of lazy anonymous areas are swapped.
+                let swappable = matches!(
+                    area.backend(),
+                    Backend::Alloc {
+                        populate: false,
+                        align: PageSize::Size4K,
+                    }
+                );
+                let page = vaddr.align_down_4k();
+                if swappable
+                    && let Some(swapped_in) = self.swap.swap_in(page, orig_flags, &mut self.pt)
+                {
+                    return swapped_in;
+                }
+
                 // Two cases enter the branch:
                 // - shared pages (If there is a shared page in the vma)
                 // - cow
@@ -519,23 +1239,298 @@ impl AddrSpace {
                     // 1. page fault caused by write
                     // 2. pte exists
                     // 3. Not shared memory
-                    return Self::handle_cow_fault(
-                        vaddr,
-                        paddr,
-                        orig_flags,
-                        page_size,
-                        &mut self.pt,
-                    );
+                    let handled =
+                        Self::handle_cow_fault(vaddr, paddr, orig_flags, page_size, &mut self.pt);
+                    if handled && swappable && page_size == PageSize::Size4K {
+                        self.swap.touch(page);
+                    }
+                    return handled;
+                }
+
+                if swappable {
+                    let block = vaddr.align_down(PageSize::Size2M);
+                    if block >= area.start()
+                        && block + HUGE_PAGE_SIZE <= area.end()
+                        && self.huge_page_allowed(block)
+                        && thp::fault_huge_page(&mut self.pt, block, orig_flags)
+                    {
+                        return true;
+                    }
                 }
 
-                return area
-                    .backend()
-                    .handle_page_fault(vaddr, orig_flags, &mut self.pt);
+                let handled =
+                    area.backend()
+                        .handle_page_fault(vaddr, access_flags, orig_flags, &mut self.pt);
+                if handled && swappable {
+                    self.swap.touch(page);
+                }
+                return handled;
             }
         }
         false
     }
 
+    /// Swaps out up to `nr` of the least recently used anonymous pages,
+    /// returning the number of pages swapped out.
+    ///
+    /// Pages are scanned from the least recently faulted in. Those accessed
+    /// since they were last scanned get a second chance and go back to the
+    /// tail of the LRU, as told by [`test_and_clear_accessed`] where the
+    /// hardware tracks accesses. The LRU is scanned up to twice, so that
+    /// pages are found even if all were accessed.
+    ///
+    /// Pages still shared copy-on-write are skipped, they are swapped out
+    /// once written. Only the local TLB is flushed before the frames are
+    /// freed, so no other thread using this address space may run on another
+    /// CPU.
+    ///
+    /// [`test_and_clear_accessed`]: axhal::paging::test_and_clear_accessed
+    pub fn reclaim_pages(&mut self, nr: usize) -> usize {
+        if !swap::swap_available() {
+            return 0;
+        }
+        let mut reclaimed = 0;
+        let mut skipped = Vec::new();
+        for _ in 0..2 * self.swap.resident_pages() {
+            if reclaimed == nr {
+                break;
+            }
+            let Some(vaddr) = self.swap.pop_oldest() else {
+                break;
+            };
+            match self.swap_out_page(vaddr, true) {
+                SwapOut::Done => reclaimed += 1,
+                SwapOut::Accessed => self.swap.touch(vaddr),
+                SwapOut::Skipped => skipped.push(vaddr),
+                SwapOut::Failed => {
+                    skipped.push(vaddr);
+                    break;
+                }
+                SwapOut::Gone => {}
+            }
+        }
+        skipped.into_iter().for_each(|vaddr| self.swap.touch(vaddr));
+        reclaimed
+    }
+
+    /// Swaps out the anonymous pages in `[start, start + size)` right away,
+    /// as `MADV_PAGEOUT` does, returning the number of pages swapped out.
+    ///
+    /// Pages are swapped out whether they were accessed recently or not. The
+    /// same limits as [`AddrSpace::reclaim_pages`] apply.
+    pub fn pageout(&mut self, start: VirtAddr, size: usize) -> AxResult<usize> {
+        self.validate_region(start, size, PageSize::Size4K)?;
+        if !swap::swap_available() {
+            return Ok(0);
+        }
+        let mut reclaimed = 0;
+        for vaddr in self.swap.resident_in(start, start + size) {
+            match self.swap_out_page(vaddr, false) {
+                SwapOut::Done => reclaimed += 1,
+                SwapOut::Accessed | SwapOut::Skipped | SwapOut::Gone => {}
+                SwapOut::Failed => break,
+            }
+        }
+        Ok(reclaimed)
+    }
+
+    /// Swaps out the page at `vaddr` if it is a private 4K page of a lazy
+    /// anonymous area, unless `second_chance` and it was accessed since the
+    /// last scan.
+    fn swap_out_page(&mut self, vaddr: VirtAddr, second_chance: bool) -> SwapOut {
+        // The page may have been unmapped or remapped since.
+        let swappable = self.areas.find(vaddr).is_some_and(|area| {
+            matches!(
+                area.backend(),
+                Backend::Alloc {
+                    populate: false,
+                    align: PageSize::Size4K,
+                }
+            )
+        });
+        match self.pt.query(vaddr) {
+            Ok((frame, _, PageSize::Size4K)) if swappable => {
+                let accessed = axhal::paging::test_and_clear_accessed(&mut self.pt, vaddr);
+                if second_chance && accessed == Some(true) {
+                    SwapOut::Accessed
+                } else if frame_table().ref_count(frame) > 1 {
+                    SwapOut::Skipped
+                } else if self.swap.swap_out(vaddr, &mut self.pt) {
+                    self.rss.sub(RssKind::Anon, 1);
+                    SwapOut::Done
+                } else {
+                    // The swap areas are full or failing.
+                    SwapOut::Failed
+                }
+            }
+            _ => SwapOut::Gone,
+        }
+    }
+
+    /// Reads back all the pages swapped out to the area `id`, for `swapoff`.
+    pub fn swap_in_area(&mut self, id: SwapAreaId) -> AxResult {
+        for vaddr in self.swap.pages_in(id) {
+            let flags = self
+                .areas
+                .find(vaddr)
+                .map(|area| area.flags())
+                .ok_or(AxError::BadState)?;
+            if self.swap.swap_in(vaddr, flags, &mut self.pt) != Some(true) {
+                return ax_err!(NoMemory, "failed to swap in");
+            }
+            self.rss.add(RssKind::Anon, 1);
+        }
+        Ok(())
+    }
+
+    /// Returns the number of pages swapped out.
+    pub fn swapped_pages(&self) -> usize {
+        self.swap.swapped_pages()
+    }
+
+    /// Counts the resident pages in `[start, end)` by kind.
+    ///
+    /// Pages of linear mappings are not counted, and huge pages only count
+    /// the part inside the range.
+    fn resident_in(&self, start: VirtAddr, end: VirtAddr) -> RssStat {
+        let mut stat = RssStat::default();
+        for area in self.areas.iter() {
+            let Some(kind) = RssKind::of(area.backend()) else {
+                continue;
+            };
+            let (area_start, area_end) = (area.start().max(start), area.end().min(end));
+            let mut vaddr = area_start;
+            while vaddr < area_end {
+                vaddr = match self.pt.query(vaddr) {
+                    Ok((_, _, page_size)) => {
+                        let next =
+                            (vaddr.align_down(page_size) + usize::from(page_size)).min(area_end);
+                        stat.add(kind, (next - vaddr) / PAGE_SIZE_4K);
+                        next
+                    }
+                    Err(_) => vaddr + PAGE_SIZE_4K,
+                };
+            }
+        }
+        stat
+    }
+
+    /// Counts the pages resident in `[start, end)` as newly mapped.
+    fn account_mapped(&mut self, start: VirtAddr, end: VirtAddr) {
+        let stat = self.resident_in(start, end);
+        self.rss.add_all(stat);
+    }
+
+    /// Uncounts the pages resident in `[start, end)`, which are about to be
+    /// unmapped.
+    fn account_unmapped(&mut self, start: VirtAddr, end: VirtAddr) {
+        let stat = self.resident_in(start, end);
+        self.rss.sub_all(stat);
+    }
+
+    /// Returns the resident pages of the address space by kind.
+    pub fn rss(&self) -> RssStat {
+        self.rss.current()
+    }
+
+    /// Returns the peak number of resident pages, as `VmHWM` in
+    /// `/proc/[pid]/status`.
+    pub fn rss_peak(&self) -> usize {
+        self.rss.peak()
+    }
+
+    /// Returns the total size of all mappings in bytes.
+    pub fn virtual_size(&self) -> usize {
+        self.areas.iter().map(|area| area.size()).sum()
+    }
+
+    /// Lists all mappings in address order.
+    pub fn mappings(&self) -> Vec<MappingInfo> {
+        self.areas
+            .iter()
+            .map(|area| {
+                let (shared, offset, path) = match area.backend() {
+                    Backend::Linear { .. } | Backend::Alloc { .. } => (false, 0, None),
+                    Backend::File {
+                        offset_base,
+                        cache,
+                        shared,
+                        ..
+                    } => (
+                        *shared,
+                        area.start() - *offset_base,
+                        Some(cache.path().into()),
+                    ),
+                    Backend::Shared { offset_base, .. } => {
+                        (true, area.start() - *offset_base, None)
+                    }
+                };
+                MappingInfo {
+                    start: area.start(),
+                    end: area.end(),
+                    flags: area.flags(),
+                    shared,
+                    grows_down: self.stacks.contains(area.start(), area.end()),
+                    offset,
+                    path,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the memory usage of the mapping starting at `start`.
+    pub fn mapping_usage(&self, start: VirtAddr) -> Option<MappingUsage> {
+        let area = self
+            .areas
+            .find(start)
+            .filter(|area| area.start() == start)?;
+        let backend = area.backend();
+        let mut usage = MappingUsage {
+            page_size: match backend {
+                Backend::Alloc { align, .. } => (*align).into(),
+                _ => PAGE_SIZE_4K,
+            },
+            ..Default::default()
+        };
+        if RssKind::of(backend).is_none() {
+            return Some(usage);
+        }
+        let mut vaddr = area.start();
+        while vaddr < area.end() {
+            let Ok((paddr, _, page_size)) = self.pt.query(vaddr) else {
+                vaddr += PAGE_SIZE_4K;
+                continue;
+            };
+            let size = usize::from(page_size);
+            let paddr = paddr.align_down(page_size);
+            let refs = frame_table().ref_count(paddr);
+            let mappers = match backend {
+                // Shared file pages are owned by the file cache, and their
+                // mappers are not counted.
+                Backend::File { shared: true, .. } => 1,
+                // Shared memory objects hold a reference of their own.
+                Backend::Shared { .. } => refs.saturating_sub(1).max(1),
+                _ => refs.max(1),
+            };
+            usage.rss += size;
+            usage.pss += size / mappers;
+            if mappers > 1 {
+                usage.shared += size;
+            } else {
+                usage.private += size;
+            }
+            if let Backend::Alloc { .. } = backend {
+                usage.anonymous += size;
+                if page_size != PageSize::Size4K {
+                    usage.anon_huge += size;
+                }
+            }
+            vaddr = vaddr.align_down(page_size) + size;
+        }
+        usage.swap = self.swap.swapped_in(area.start(), area.end()) * PAGE_SIZE_4K;
+        Some(usage)
+    }
+
     /// Attempts to clone the current address space into a new one.
     ///
     /// This method creates a new empty address space with the same base and size,
@@ -559,6 +1554,12 @@ impl AddrSpace {
     ///   handled, and memory is allocated before copying.
     /// - The actual copying is done using [`core::ptr::copy_nonoverlapping`] at the
     ///   physical address level.
+    ///
+    /// ### Shared Mappings
+    /// - Areas backed by [`Backend::Shared`] or shared [`Backend::File`] are never
+    ///   copied. The new address space faults in the same frames on demand, and
+    ///   they stay writable in both.
+    /// - Pages of private [`Backend::File`] areas are always copied.
     pub fn try_clone(&mut self) -> AxResult<Self> {
         let mut new_aspace = Self::new_empty(self.base(), self.size())?;
 
@@ -584,25 +1585,51 @@ impl AddrSpace {
                 Backend::Alloc { align, .. } => *align,
                 // Linear-backed regions are usually allocated by the kernel and are shared
                 Backend::Linear { .. } => continue,
+                // Shared file pages are faulted in again from the file cache.
+                Backend::File { shared: true, .. } => continue,
+                // Shared frames are mapped writable in both address spaces.
+                Backend::Shared { .. } => continue,
+                Backend::File { shared: false, .. } => {
+                    Self::clone_private_file_pages(
+                        area.start(),
+                        area.end(),
+                        &self.pt,
+                        &mut new_aspace.pt,
+                    )?;
+                    continue;
+                }
             };
 
             #[cfg(feature = "cow")]
             let cow_flags = area.flags() - MappingFlags::WRITE;
 
-            for vaddr in PageIterWrapper::new(area.start(), area.end(), align)
-                .expect("Failed to create page iterator")
-            {
+            let mut vaddr = area.start();
+            while vaddr < area.end() {
                 // Copy data from old memory area to new memory area.
-                match self.pt.query(vaddr) {
+                let step = match self.pt.query(vaddr) {
                     Ok((paddr, _, page_size)) => {
+                        // Transparent huge pages are cloned as a whole.
+                        let paddr = paddr.align_down(page_size);
+
                         #[cfg(not(feature = "cow"))]
                         {
                             let new_addr = match new_aspace.pt.query(vaddr) {
                                 Ok((paddr, _, _)) => paddr,
+                                Err(PagingError::NotMapped) if page_size != align => {
+                                    if !thp::fault_huge_page(
+                                        &mut new_aspace.pt,
+                                        vaddr,
+                                        area.flags(),
+                                    ) {
+                                        return Err(AxError::NoMemory);
+                                    }
+                                    new_aspace.pt.query(vaddr).map_err(|_| AxError::BadState)?.0
+                                }
                                 // If the page is not mapped, try map it.
                                 Err(PagingError::NotMapped) => {
                                     if !area.backend().handle_page_fault(
                                         vaddr,
+                                        MappingFlags::empty(),
                                         area.flags(),
                                         &mut new_aspace.pt,
                                     ) {
@@ -641,19 +1668,59 @@ impl AddrSpace {
                                 .map(vaddr, paddr, page_size, cow_flags)
                                 .map(|tlb| tlb.flush())
                                 .expect("map failed");
-
-                            continue;
                         }
+                        page_size
                     }
                     // If the page is not mapped, skip it.
-                    Err(PagingError::NotMapped) => continue,
+                    Err(PagingError::NotMapped) => align,
                     Err(_) => return Err(AxError::BadAddress),
                 };
+                vaddr += usize::from(step);
             }
         }
+        new_aspace.huge_advised = self.huge_advised.clone();
+        new_aspace.huge_disabled = self.huge_disabled.clone();
+        new_aspace.swap = self.swap.try_clone()?;
+        new_aspace.stacks = self.stacks.clone();
+        new_aspace.account_mapped(new_aspace.base(), new_aspace.end());
         Ok(new_aspace)
     }
 
+    /// Copies the private pages of a file mapping in `[start, end)` to a new
+    /// page table.
+    ///
+    /// Private pages may have been modified, so they are copied eagerly rather
+    /// than read from the file again.
+    fn clone_private_file_pages(
+        start: VirtAddr,
+        end: VirtAddr,
+        pt: &PageTable,
+        new_pt: &mut PageTable,
+    ) -> AxResult {
+        for vaddr in PageIterWrapper::new(start, end, PageSize::Size4K)
+            .expect("Failed to create page iterator")
+        {
+            let (paddr, flags, _) = match pt.query(vaddr) {
+                Ok(entry) => entry,
+                Err(PagingError::NotMapped) => continue,
+                Err(_) => return Err(AxError::BadAddress),
+            };
+            let new_frame = alloc_frame(false, PageSize::Size4K).ok_or(AxError::NoMemory)?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    phys_to_virt(paddr).as_ptr(),
+                    phys_to_virt(new_frame).as_mut_ptr(),
+                    PAGE_SIZE_4K,
+                )
+            };
+            new_pt
+                .map(vaddr, new_frame, PageSize::Size4K, flags)
+                .map(|tlb| tlb.ignore())
+                .map_err(|_| AxError::BadState)?;
+        }
+        Ok(())
+    }
+
     /// Handles a Copy-On-Write (COW) page fault.
     ///
     /// # Arguments
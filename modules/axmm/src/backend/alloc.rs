@@ -4,7 +4,6 @@ use axhal::mem::{phys_to_virt, virt_to_phys};
 use axhal::paging::{MappingFlags, PageSize, PageTable};
 use memory_addr::{PhysAddr, VirtAddr};
 
-#[cfg(feature = "cow")]
 use crate::frameinfo::frame_table;
 
 use super::Backend;
@@ -37,7 +36,6 @@ pub(crate) fn alloc_frame(zeroed: bool, align: PageSize) -> Option<PhysAddr> {
     }
     let paddr = virt_to_phys(vaddr);
 
-    #[cfg(feature = "cow")]
     frame_table().inc_ref(paddr);
 
     Some(paddr)
@@ -50,8 +48,9 @@ pub(crate) fn alloc_frame(zeroed: bool, align: PageSize) -> Option<PhysAddr> {
 /// The size of the memory to be freed is determined by the `align` parameter,
 /// which must be a multiple of 4KiB.
 ///
-/// If `cow` feature is enabled, this function decreases the reference count associated with the frame.
-/// When the reference count reaches 1, it actually frees the frame memory.
+/// This function decreases the reference count associated with the frame, which
+/// may be shared by COW or shared mappings. When the last reference is dropped,
+/// it actually frees the frame memory.
 ///
 /// # Parameters
 /// - `frame`: The physical address of the memory to be freed.
@@ -63,7 +62,6 @@ pub(crate) fn alloc_frame(zeroed: bool, align: PageSize) -> Option<PhysAddr> {
 /// - If the deallocation fails, the function will call `panic!`. Details about
 ///   the failure can be obtained from the global memory allocator’s error messages.
 pub(crate) fn dealloc_frame(frame: PhysAddr, align: PageSize) {
-    #[cfg(feature = "cow")]
     if frame_table().dec_ref(frame) > 1 {
         return;
     }
@@ -99,6 +97,10 @@ impl Backend {
             // allocate all possible physical frames for populated mapping.
             if let Some(iter) = PageIterWrapper::new(start, start + size, align) {
                 for addr in iter {
+                    // Keep the pages already there, e.g., moved by `mremap`.
+                    if pt.query(addr).is_ok() {
+                        continue;
+                    }
                     if let Some(frame) = alloc_frame(true, align) {
                         if let Ok(tlb) = pt.map(addr, frame, align, flags) {
                             tlb.ignore(); // TLB flush on map is unnecessary, as there are no outdated mappings.
@@ -124,11 +126,11 @@ impl Backend {
         debug!("unmap_alloc: [{:#x}, {:#x})", start, start + size);
         if let Some(iter) = PageIterWrapper::new(start, start + size, align) {
             for addr in iter {
-                if let Ok((frame, _page_size, tlb)) = pt.unmap(addr) {
+                if let Ok((frame, page_size, tlb)) = pt.unmap(addr) {
                     // Deallocate the physical frame if there is a mapping in the
-                    // page table.
+                    // page table. It may be a transparent huge page.
                     tlb.flush();
-                    dealloc_frame(frame, align);
+                    dealloc_frame(frame, page_size);
                 } else {
                     // Deallocation is needn't if the page is not mapped.
                 }
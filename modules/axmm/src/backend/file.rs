@@ -0,0 +1,511 @@
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use axerrno::{AxError, AxResult};
+use axfs_vfs::VfsNodeRef;
+use axhal::mem::phys_to_virt;
+use axhal::paging::{MappingFlags, PageSize, PageTable, PagingError};
+use kspin::SpinNoIrq;
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr};
+
+use super::{Backend, alloc_frame, dealloc_frame};
+use crate::page_iter_wrapper::{PAGE_SIZE_4K, PageIterWrapper};
+
+/// All live [`FileCache`]s, indexed by the absolute path of their file.
+///
+/// Files are told apart by their path rather than their node, since some
+/// filesystems (e.g., FAT and ext4) create a new node each time a file is
+/// opened.
+static FILE_CACHES: SpinNoIrq<BTreeMap<String, Weak<FileCache>>> = SpinNoIrq::new(BTreeMap::new());
+
+/// Whether `path` is `dir` or lies below it.
+fn is_under(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || dir.ends_with('/'))
+}
+
+struct CachedPage {
+    frame: PhysAddr,
+    /// Set on write faults, and cleared when the page is written back.
+    dirty: bool,
+    /// The number of page table entries mapping the page writable. Writes
+    /// through them do not fault, so the page stays dirty while there are
+    /// any.
+    writers: usize,
+}
+
+/// The pages of a file, shared by all mappings of it.
+///
+/// Pages are read from the file on the first page fault, and written back
+/// to it by [`FileCache::sync`] and when the last mapping goes away.
+///
+/// Shared mappings map clean pages read-only. The first write faults, marks
+/// the page dirty and maps it writable, until the mapping syncs the page and
+/// write-protects it again.
+pub struct FileCache {
+    node: VfsNodeRef,
+    /// The path the file was first mapped with.
+    path: String,
+    pages: SpinNoIrq<BTreeMap<usize, CachedPage>>,
+}
+
+impl FileCache {
+    /// Returns the page cache of the file `node` at the absolute `path`,
+    /// creating it if it does not exist.
+    ///
+    /// All opens of the file share the cache, whatever their node. `path` also
+    /// names the file in `/proc/[pid]/maps`.
+    pub fn get_or_create(node: VfsNodeRef, path: &str) -> Arc<Self> {
+        let mut caches = FILE_CACHES.lock();
+        if let Some(cache) = caches.get(path).and_then(Weak::upgrade) {
+            return cache;
+        }
+        let cache = Arc::new(Self {
+            node,
+            path: path.into(),
+            pages: SpinNoIrq::new(BTreeMap::new()),
+        });
+        caches.retain(|_, cache| cache.strong_count() > 0);
+        caches.insert(path.into(), Arc::downgrade(&cache));
+        cache
+    }
+
+    /// Forgets the caches of the file at `path` and of the files below it,
+    /// after they have been removed or unmounted, so that new files there get
+    /// caches of their own. Existing mappings keep the old caches.
+    pub fn forget(path: &str) {
+        FILE_CACHES.lock().retain(|key, _| !is_under(key, path));
+    }
+
+    /// Moves the caches of the file at `old` and of the files below it to
+    /// `new`, after they have been renamed, forgetting the caches of the files
+    /// they replace.
+    pub fn rename(old: &str, new: &str) {
+        let mut caches = FILE_CACHES.lock();
+        let moved: Vec<_> = caches
+            .iter()
+            .filter(|(key, _)| is_under(key, old))
+            .map(|(key, cache)| (key.clone(), cache.clone()))
+            .collect();
+        caches.retain(|key, _| !is_under(key, old) && !is_under(key, new));
+        for (key, cache) in moved {
+            caches.insert(format!("{}{}", new, &key[old.len()..]), cache);
+        }
+    }
+
+    /// The mapped file.
+    pub fn node(&self) -> &VfsNodeRef {
+        &self.node
+    }
+
+    /// The path of the mapped file.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the frame holding the page `index` of the file, reading it
+    /// from the file if it is not cached. The page is marked dirty if it is
+    /// mapped `writable`.
+    fn get_page(&self, index: usize, writable: bool) -> Option<PhysAddr> {
+        if let Some(page) = self.pages.lock().get_mut(&index) {
+            page.add_writer(writable);
+            return Some(page.frame);
+        }
+
+        // Read without holding the lock, the file may sleep on I/O.
+        let frame = alloc_frame(true, PageSize::Size4K)?;
+        let buf = unsafe {
+            core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K)
+        };
+        // Reading beyond the end of the file leaves the page zeroed.
+        let mut read = 0;
+        while read < PAGE_SIZE_4K {
+            match self
+                .node
+                .read_at((index * PAGE_SIZE_4K + read) as u64, &mut buf[read..])
+            {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) => {
+                    warn!("failed to read page {} of mapped file: {:?}", index, e);
+                    dealloc_frame(frame, PageSize::Size4K);
+                    return None;
+                }
+            }
+        }
+
+        let mut pages = self.pages.lock();
+        if let Some(page) = pages.get_mut(&index) {
+            // Raced with another fault on the same page.
+            dealloc_frame(frame, PageSize::Size4K);
+            page.add_writer(writable);
+            return Some(page.frame);
+        }
+        let mut page = CachedPage {
+            frame,
+            dirty: false,
+            writers: 0,
+        };
+        page.add_writer(writable);
+        pages.insert(index, page);
+        Some(frame)
+    }
+
+    /// Marks the page `index` dirty, as it gets mapped writable.
+    fn add_writer(&self, index: usize) {
+        if let Some(page) = self.pages.lock().get_mut(&index) {
+            page.add_writer(true);
+        }
+    }
+
+    /// Records that the page `index` is no longer mapped writable by one of
+    /// its mappers.
+    fn remove_writer(&self, index: usize) {
+        if let Some(page) = self.pages.lock().get_mut(&index) {
+            page.writers = page.writers.saturating_sub(1);
+        }
+    }
+
+    /// Writes the dirty pages in `[start, end)` (page indices) back to the
+    /// file.
+    ///
+    /// Pages are clean afterwards, unless some mapping still maps them
+    /// writable.
+    pub fn sync(&self, start: usize, end: usize) -> AxResult {
+        // Clean the pages before writing them, so that writes meanwhile
+        // dirty them again.
+        let dirty: Vec<(usize, PhysAddr)> = self
+            .pages
+            .lock()
+            .range_mut(start..end)
+            .filter(|(_, page)| page.dirty)
+            .map(|(&index, page)| {
+                page.dirty = page.writers > 0;
+                (index, page.frame)
+            })
+            .collect();
+        if dirty.is_empty() {
+            return Ok(());
+        }
+
+        let mut written = 0;
+        let result = self.write_pages(&dirty, &mut written);
+        if result.is_err() {
+            let mut pages = self.pages.lock();
+            for (index, _) in &dirty[written..] {
+                if let Some(page) = pages.get_mut(index) {
+                    page.dirty = true;
+                }
+            }
+        }
+        result
+    }
+
+    /// Writes `pages` back to the file, counting the pages `written`.
+    fn write_pages(&self, pages: &[(usize, PhysAddr)], written: &mut usize) -> AxResult {
+        // Never extend the file, the tail of the last page is not file data.
+        let file_size = self.node.get_attr()?.size() as usize;
+        for &(index, frame) in pages {
+            let offset = index * PAGE_SIZE_4K;
+            if offset < file_size {
+                let len = PAGE_SIZE_4K.min(file_size - offset);
+                let buf = unsafe { core::slice::from_raw_parts(phys_to_virt(frame).as_ptr(), len) };
+                self.node.write_at(offset as u64, buf)?;
+            }
+            *written += 1;
+        }
+        Ok(())
+    }
+}
+
+impl CachedPage {
+    fn add_writer(&mut self, writable: bool) {
+        if writable {
+            self.dirty = true;
+            self.writers += 1;
+        }
+    }
+}
+
+impl Drop for FileCache {
+    fn drop(&mut self) {
+        // The entry may have moved with a rename, or been replaced by a new
+        // cache of the same file meanwhile.
+        FILE_CACHES
+            .lock()
+            .retain(|_, cache| cache.strong_count() > 0);
+
+        if let Err(e) = self.sync(0, usize::MAX) {
+            warn!("failed to write back mapped file: {:?}", e);
+        }
+        for (_, page) in core::mem::take(&mut *self.pages.lock()) {
+            dealloc_frame(page.frame, PageSize::Size4K);
+        }
+    }
+}
+
+impl Backend {
+    /// Creates a new file mapping backend.
+    ///
+    /// The area starting at `start` maps the file from `offset`. Pages of
+    /// `shared` mappings are shared with other mappers of the file and
+    /// written back to it, while private mappings get their own copies.
+    /// Unless `may_write`, the mapping may never be made writable.
+    pub fn new_file(
+        start: VirtAddr,
+        offset: usize,
+        cache: Arc<FileCache>,
+        shared: bool,
+        may_write: bool,
+    ) -> Self {
+        Self::File {
+            offset_base: start.as_usize().wrapping_sub(offset),
+            cache,
+            shared,
+            may_write,
+        }
+    }
+
+    /// Returns the index of the file page mapped at `vaddr`.
+    fn file_page_index(offset_base: usize, vaddr: VirtAddr) -> usize {
+        vaddr.as_usize().wrapping_sub(offset_base) / PAGE_SIZE_4K
+    }
+
+    pub(crate) fn map_file(start: VirtAddr, size: usize, flags: MappingFlags) -> bool {
+        debug!("map_file: [{:#x}, {:#x}) {:?}", start, start + size, flags);
+        // create mapping entries on demand later in `handle_page_fault_file`.
+        true
+    }
+
+    pub(crate) fn unmap_file(
+        start: VirtAddr,
+        size: usize,
+        pt: &mut PageTable,
+        offset_base: usize,
+        cache: &FileCache,
+        shared: bool,
+    ) -> bool {
+        debug!("unmap_file: [{:#x}, {:#x})", start, start + size);
+        if let Some(iter) = PageIterWrapper::new(start, start + size, PageSize::Size4K) {
+            for addr in iter {
+                let Ok((_, flags, _)) = pt.query(addr) else {
+                    continue;
+                };
+                if let Ok((frame, _page_size, tlb)) = pt.unmap(addr) {
+                    tlb.flush();
+                    // Shared frames are owned by the file cache.
+                    if !shared {
+                        dealloc_frame(frame, PageSize::Size4K);
+                    } else if flags.contains(MappingFlags::WRITE) {
+                        cache.remove_writer(Self::file_page_index(offset_base, addr));
+                    }
+                }
+            }
+        }
+        if shared {
+            let first = Self::file_page_index(offset_base, start);
+            let last = Self::file_page_index(offset_base, start + size);
+            if let Err(e) = cache.sync(first, last) {
+                warn!("failed to write back mapped file: {:?}", e);
+            }
+        }
+        true
+    }
+
+    /// Write-protects the mapped pages of a shared file mapping in
+    /// `[start, start + size)`, so that the next write marks them dirty
+    /// again.
+    fn write_protect_file(
+        start: VirtAddr,
+        size: usize,
+        pt: &mut PageTable,
+        offset_base: usize,
+        cache: &FileCache,
+    ) -> bool {
+        let Some(iter) = PageIterWrapper::new(start, start + size, PageSize::Size4K) else {
+            return false;
+        };
+        for addr in iter {
+            let Ok((_, flags, _)) = pt.query(addr) else {
+                continue;
+            };
+            if flags.contains(MappingFlags::WRITE) {
+                match pt.protect(addr, flags - MappingFlags::WRITE) {
+                    Ok((_, tlb)) => tlb.flush(),
+                    Err(_) => return false,
+                }
+                cache.remove_writer(Self::file_page_index(offset_base, addr));
+            }
+        }
+        true
+    }
+
+    /// Changes the flags of a file mapping. The pages of shared mappings are
+    /// write-protected, so that the next write marks them dirty.
+    pub(crate) fn protect_file(
+        start: VirtAddr,
+        size: usize,
+        new_flags: MappingFlags,
+        pt: &mut PageTable,
+        offset_base: usize,
+        cache: &FileCache,
+        shared: bool,
+    ) -> bool {
+        let flags = if shared {
+            if !Self::write_protect_file(start, size, pt, offset_base, cache) {
+                return false;
+            }
+            new_flags - MappingFlags::WRITE
+        } else {
+            new_flags
+        };
+        pt.protect_region(start, size, flags, true)
+            .map(|tlb| tlb.ignore())
+            .is_ok()
+    }
+
+    pub(crate) fn handle_page_fault_file(
+        vaddr: VirtAddr,
+        access_flags: MappingFlags,
+        orig_flags: MappingFlags,
+        pt: &mut PageTable,
+        offset_base: usize,
+        cache: &FileCache,
+        shared: bool,
+    ) -> bool {
+        let vaddr = vaddr.align_down_4k();
+        let index = Self::file_page_index(offset_base, vaddr);
+        let write = access_flags.contains(MappingFlags::WRITE);
+
+        match pt.query(vaddr) {
+            // Raced with another write fault on the same page.
+            Ok((_, flags, _)) if shared && write && flags.contains(MappingFlags::WRITE) => true,
+            // A write to a clean shared page.
+            Ok(_) if shared && write => {
+                if pt
+                    .protect(vaddr, orig_flags)
+                    .map(|(_, tlb)| tlb.flush())
+                    .is_err()
+                {
+                    return false;
+                }
+                cache.add_writer(index);
+                true
+            }
+            Ok(_) => false,
+            Err(PagingError::NotMapped) => {
+                let Some(page) = cache.get_page(index, shared && write) else {
+                    return false;
+                };
+                let (frame, flags) = if shared {
+                    let flags = if write {
+                        orig_flags
+                    } else {
+                        orig_flags - MappingFlags::WRITE
+                    };
+                    (page, flags)
+                } else {
+                    let Some(frame) = alloc_frame(false, PageSize::Size4K) else {
+                        return false;
+                    };
+                    unsafe {
+                        core::ptr::copy_nonoverlapping(
+                            phys_to_virt(page).as_ptr(),
+                            phys_to_virt(frame).as_mut_ptr(),
+                            PAGE_SIZE_4K,
+                        )
+                    };
+                    (frame, orig_flags)
+                };
+                pt.map(vaddr, frame, PageSize::Size4K, flags)
+                    .map(|tlb| tlb.flush())
+                    .is_ok()
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Writes back the dirty pages of a shared file mapping in
+    /// `[start, start + size)`, and write-protects them in `pt` so that later
+    /// writes mark them dirty again.
+    ///
+    /// Only the TLB of the current CPU is flushed, so other CPUs running the
+    /// same address space may keep writing the pages through stale entries.
+    pub(crate) fn sync_file(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> AxResult {
+        match self {
+            Self::File {
+                offset_base,
+                cache,
+                shared: true,
+                ..
+            } => {
+                let end = (start + size).align_up_4k();
+                if !Self::write_protect_file(start, end - start, pt, *offset_base, cache) {
+                    return Err(AxError::BadAddress);
+                }
+                cache.sync(
+                    Self::file_page_index(*offset_base, start),
+                    Self::file_page_index(*offset_base, end),
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+
+    use axfs_vfs::{VfsNodeOps, VfsNodeRef};
+
+    use super::FileCache;
+
+    struct TestNode;
+
+    impl VfsNodeOps for TestNode {}
+
+    /// Opens the file anew, as FAT and ext4 do, giving a distinct node.
+    fn open() -> VfsNodeRef {
+        Arc::new(TestNode)
+    }
+
+    #[test]
+    fn test_independent_opens_share_cache() {
+        let (a, b) = (open(), open());
+        assert!(!Arc::ptr_eq(&a, &b));
+        let first = FileCache::get_or_create(a, "/test_opens/file");
+        let second = FileCache::get_or_create(b, "/test_opens/file");
+        assert!(Arc::ptr_eq(&first, &second));
+        let other = FileCache::get_or_create(open(), "/test_opens/file2");
+        assert!(!Arc::ptr_eq(&first, &other));
+    }
+
+    #[test]
+    fn test_rename_and_forget() {
+        let cache = FileCache::get_or_create(open(), "/test_rename/dir/file");
+        let replaced = FileCache::get_or_create(open(), "/test_rename/new/file");
+        FileCache::rename("/test_rename/dir", "/test_rename/new");
+        let renamed = FileCache::get_or_create(open(), "/test_rename/new/file");
+        assert!(Arc::ptr_eq(&cache, &renamed));
+        assert!(!Arc::ptr_eq(&replaced, &renamed));
+        let old = FileCache::get_or_create(open(), "/test_rename/dir/file");
+        assert!(!Arc::ptr_eq(&cache, &old));
+
+        FileCache::forget("/test_rename/new");
+        let fresh = FileCache::get_or_create(open(), "/test_rename/new/file");
+        assert!(!Arc::ptr_eq(&cache, &fresh));
+        // A sibling sharing the prefix is not below the directory.
+        let sibling = FileCache::get_or_create(open(), "/test_rename/newer");
+        FileCache::forget("/test_rename/new");
+        assert!(Arc::ptr_eq(
+            &sibling,
+            &FileCache::get_or_create(open(), "/test_rename/newer")
+        ));
+    }
+}
@@ -1,22 +1,31 @@
 //! Memory mapping backends.
 
+use ::alloc::sync::Arc;
 use axhal::paging::{MappingFlags, PageSize, PageTable};
 use memory_addr::VirtAddr;
 use memory_set::MappingBackend;
 mod alloc;
+mod file;
 mod linear;
+mod shared;
 
 #[allow(unused_imports)]
 pub(crate) use alloc::{alloc_frame, dealloc_frame};
+pub use file::FileCache;
+pub use shared::SharedPages;
 
 /// A unified enum type for different memory mapping backends.
 ///
-/// Currently, two backends are implemented:
+/// Currently, three backends are implemented:
 ///
 /// - **Linear**: used for linear mappings. The target physical frames are
 ///   contiguous and their addresses should be known when creating the mapping.
 /// - **Allocation**: used in general, or for lazy mappings. The target physical
 ///   frames are obtained from the global allocator.
+/// - **File**: used for file mappings. The pages are read from the file on
+///   demand, through a page cache shared by all mappers of the file.
+/// - **Shared**: used for shared anonymous memory. The frames belong to a
+///   [`SharedPages`] object and stay shared across `fork`.
 #[derive(Clone)]
 pub enum Backend {
     /// Linear mapping backend.
@@ -42,6 +51,35 @@ pub enum Backend {
         /// Alignment parameters for the starting address and memory range.
         align: PageSize,
     },
+    /// File mapping backend.
+    ///
+    /// Pages are always 4K. The virtual address `vaddr` maps the file offset
+    /// `vaddr - offset_base`, which stays valid when the area is split.
+    File {
+        /// `start - offset` of the mapping.
+        offset_base: usize,
+        /// The page cache of the mapped file.
+        cache: Arc<FileCache>,
+        /// Whether writes are shared with other mappers and the file.
+        shared: bool,
+        /// Whether the mapping may be made writable. Shared mappings of files
+        /// opened read-only may not.
+        may_write: bool,
+    },
+    /// Shared memory mapping backend.
+    ///
+    /// The virtual address `vaddr` maps the byte `vaddr - offset_base` of the
+    /// shared memory object. Frames are allocated on demand and mapped
+    /// writable in every address space sharing the object.
+    Shared {
+        /// `start - offset` of the mapping.
+        offset_base: usize,
+        /// The shared memory object.
+        pages: Arc<SharedPages>,
+        /// Whether the mapping may be made writable, see
+        /// [`Backend::File::may_write`].
+        may_write: bool,
+    },
 }
 
 impl MappingBackend for Backend {
@@ -50,6 +88,8 @@ impl MappingBackend for Backend {
     type PageTable = PageTable;
     fn map(&self, start: VirtAddr, size: usize, flags: MappingFlags, pt: &mut PageTable) -> bool {
         match *self {
+            Self::File { .. } => Self::map_file(start, size, flags),
+            Self::Shared { .. } => Self::map_shared(start, size, flags),
             Self::Linear {
                 pa_va_offset,
                 align: _,
@@ -67,6 +107,13 @@ impl MappingBackend for Backend {
                 align: _,
             } => Self::unmap_linear(start, size, pt, pa_va_offset),
             Self::Alloc { populate, align } => Self::unmap_alloc(start, size, pt, populate, align),
+            Self::File {
+                offset_base,
+                ref cache,
+                shared,
+                ..
+            } => Self::unmap_file(start, size, pt, offset_base, cache, shared),
+            Self::Shared { ref pages, .. } => Self::unmap_shared(start, size, pt, pages),
         }
     }
 
@@ -77,17 +124,79 @@ impl MappingBackend for Backend {
         new_flags: Self::Flags,
         page_table: &mut Self::PageTable,
     ) -> bool {
-        page_table
-            .protect_region(start, size, new_flags, true)
-            .map(|tlb| tlb.ignore())
-            .is_ok()
+        match *self {
+            Self::File {
+                offset_base,
+                ref cache,
+                shared,
+                ..
+            } => Self::protect_file(
+                start,
+                size,
+                new_flags,
+                page_table,
+                offset_base,
+                cache,
+                shared,
+            ),
+            _ => page_table
+                .protect_region(start, size, new_flags, true)
+                .map(|tlb| tlb.ignore())
+                .is_ok(),
+        }
     }
 }
 
 impl Backend {
+    /// Returns the page size of the mapping.
+    pub fn align(&self) -> PageSize {
+        match *self {
+            Self::Linear { align, .. } | Self::Alloc { align, .. } => align,
+            Self::File { .. } => PageSize::Size4K,
+            Self::Shared { ref pages, .. } => pages.align(),
+        }
+    }
+
+    /// Returns the backend that maps at `to` what this one maps at `from`,
+    /// for an area moved from `from` to `to`.
+    pub(crate) fn relocate(&self, from: VirtAddr, to: VirtAddr) -> Self {
+        let delta = to.as_usize().wrapping_sub(from.as_usize());
+        match self.clone() {
+            Self::Linear {
+                pa_va_offset,
+                align,
+            } => Self::Linear {
+                pa_va_offset: pa_va_offset.wrapping_add(delta),
+                align,
+            },
+            Self::File {
+                offset_base,
+                cache,
+                shared,
+                may_write,
+            } => Self::File {
+                offset_base: offset_base.wrapping_add(delta),
+                cache,
+                shared,
+                may_write,
+            },
+            Self::Shared {
+                offset_base,
+                pages,
+                may_write,
+            } => Self::Shared {
+                offset_base: offset_base.wrapping_add(delta),
+                pages,
+                may_write,
+            },
+            backend @ Self::Alloc { .. } => backend,
+        }
+    }
+
     pub(crate) fn handle_page_fault(
         &self,
         vaddr: VirtAddr,
+        access_flags: MappingFlags,
         orig_flags: MappingFlags,
         page_table: &mut PageTable,
     ) -> bool {
@@ -96,6 +205,25 @@ impl Backend {
             Self::Alloc { populate, align } => {
                 Self::handle_page_fault_alloc(vaddr, orig_flags, page_table, populate, align)
             }
+            Self::File {
+                offset_base,
+                ref cache,
+                shared,
+                ..
+            } => Self::handle_page_fault_file(
+                vaddr,
+                access_flags,
+                orig_flags,
+                page_table,
+                offset_base,
+                cache,
+                shared,
+            ),
+            Self::Shared {
+                offset_base,
+                ref pages,
+                ..
+            } => Self::handle_page_fault_shared(vaddr, orig_flags, page_table, offset_base, pages),
         }
     }
 }
@@ -0,0 +1,285 @@
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axerrno::{AxError, AxResult};
+use axhal::mem::phys_to_virt;
+use axhal::paging::{MappingFlags, PageSize, PageTable, PagingError};
+use kspin::SpinNoIrq;
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr};
+
+use super::{Backend, alloc_frame, dealloc_frame};
+use crate::frameinfo::frame_table;
+use crate::page_iter_wrapper::PageIterWrapper;
+
+/// A shared memory object, i.e., a set of frames shared by all its mappings.
+///
+/// Frames are allocated on the first page fault. The object holds one
+/// reference of each frame in the frame reference table, and every page table
+/// entry mapping the frame holds another one, so a frame lives until both the
+/// object and all its mappings are gone.
+pub struct SharedPages {
+    size: AtomicUsize,
+    /// Whether the object grows with its mappings, see
+    /// [`SharedPages::new_anonymous`].
+    growable: bool,
+    align: PageSize,
+    frames: SpinNoIrq<BTreeMap<usize, PhysAddr>>,
+}
+
+impl SharedPages {
+    /// Creates a new shared memory object of `size` bytes, made of pages of
+    /// `align` size.
+    pub fn new(size: usize, align: PageSize) -> Arc<Self> {
+        Self::new_inner(size, align, false)
+    }
+
+    /// Creates a new shared memory object of `size` bytes, made of pages of
+    /// `align` size, for a shared anonymous mapping.
+    ///
+    /// Unlike other objects, it grows when a mapping of it is grown past its
+    /// end, e.g., by `mremap`.
+    pub fn new_anonymous(size: usize, align: PageSize) -> Arc<Self> {
+        Self::new_inner(size, align, true)
+    }
+
+    fn new_inner(size: usize, align: PageSize, growable: bool) -> Arc<Self> {
+        Arc::new(Self {
+            size: AtomicUsize::new(size.align_up(align)),
+            growable,
+            align,
+            frames: SpinNoIrq::new(BTreeMap::new()),
+        })
+    }
+
+    /// The size of the object in bytes.
+    pub fn size(&self) -> usize {
+        self.size.load(Ordering::Acquire)
+    }
+
+    /// Grows the object to at least `size` bytes, if it is growable.
+    fn grow(&self, size: usize) {
+        if self.growable {
+            self.size
+                .fetch_max(size.align_up(self.align), Ordering::AcqRel);
+        }
+    }
+
+    /// The page size of the object.
+    pub fn align(&self) -> PageSize {
+        self.align
+    }
+
+    /// Sets the size of the object to `size` bytes, as `ftruncate` does.
+    ///
+    /// Pages past the new end are dropped from the object, but stay mapped
+    /// where they already are.
+    pub fn set_size(&self, size: usize) {
+        let page_size = usize::from(self.align);
+        let mut frames = self.frames.lock();
+        self.size
+            .store(size.align_up(self.align), Ordering::Release);
+        for (_, frame) in frames.split_off(&size.div_ceil(page_size)) {
+            dealloc_frame(frame, self.align);
+        }
+        // Bytes past the end read as zeros if the object grows again.
+        if let Some(&frame) = frames.get(&(size / page_size)) {
+            let offset = size % page_size;
+            unsafe {
+                core::ptr::write_bytes(
+                    phys_to_virt(frame).as_mut_ptr().add(offset),
+                    0,
+                    page_size - offset,
+                )
+            };
+        }
+    }
+
+    /// Reads the object from the byte `offset` into `buf`, stopping at the
+    /// end of the object. Returns the number of bytes read.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let end = self.size().min(offset.saturating_add(buf.len()));
+        let frames = self.frames.lock();
+        self.for_each_page(offset, end, |pos, index, page_offset, len| {
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match frames.get(&index) {
+                Some(&frame) => dst.copy_from_slice(unsafe {
+                    core::slice::from_raw_parts(phys_to_virt(frame).as_ptr().add(page_offset), len)
+                }),
+                // Pages never touched are zeros.
+                None => dst.fill(0),
+            }
+            Ok(())
+        })
+        .ok();
+        end.saturating_sub(offset)
+    }
+
+    /// Writes `buf` to the object at the byte `offset`, growing the object if
+    /// the write goes past its end. Returns the number of bytes written.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> AxResult<usize> {
+        let end = offset.checked_add(buf.len()).ok_or(AxError::InvalidInput)?;
+        let mut frames = self.frames.lock();
+        self.for_each_page(offset, end, |pos, index, page_offset, len| {
+            let frame = match frames.get(&index) {
+                Some(&frame) => frame,
+                None => {
+                    let frame = alloc_frame(true, self.align).ok_or(AxError::NoMemory)?;
+                    frames.insert(index, frame);
+                    frame
+                }
+            };
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    buf[pos - offset..].as_ptr(),
+                    phys_to_virt(frame).as_mut_ptr().add(page_offset),
+                    len,
+                )
+            };
+            Ok(())
+        })?;
+        self.size
+            .fetch_max(end.align_up(self.align), Ordering::AcqRel);
+        Ok(buf.len())
+    }
+
+    /// Calls `f(pos, index, page_offset, len)` for each piece of
+    /// `[start, end)` within one page, where the piece starts at the byte
+    /// `pos` of the object, i.e., at `page_offset` in the page `index`.
+    fn for_each_page(
+        &self,
+        start: usize,
+        end: usize,
+        mut f: impl FnMut(usize, usize, usize, usize) -> AxResult,
+    ) -> AxResult {
+        let page_size = usize::from(self.align);
+        let mut pos = start;
+        while pos < end {
+            let page_offset = pos % page_size;
+            let len = (page_size - page_offset).min(end - pos);
+            f(pos, pos / page_size, page_offset, len)?;
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Returns the frame of the page `index`, allocating it if needed. The
+    /// reference count of the returned frame is increased for the caller.
+    fn get_frame(&self, index: usize) -> Option<PhysAddr> {
+        let mut frames = self.frames.lock();
+        let frame = match frames.get(&index) {
+            Some(&frame) => frame,
+            None => {
+                let frame = alloc_frame(true, self.align)?;
+                frames.insert(index, frame);
+                frame
+            }
+        };
+        frame_table().inc_ref(frame);
+        Some(frame)
+    }
+}
+
+impl Drop for SharedPages {
+    fn drop(&mut self) {
+        for (_, frame) in core::mem::take(&mut *self.frames.lock()) {
+            dealloc_frame(frame, self.align);
+        }
+    }
+}
+
+impl Backend {
+    /// Creates a new shared memory mapping backend.
+    ///
+    /// The area starting at `start` maps `pages` from the byte offset
+    /// `offset`. Unless `may_write`, it can't be made writable.
+    pub fn new_shared(
+        start: VirtAddr,
+        offset: usize,
+        pages: Arc<SharedPages>,
+        may_write: bool,
+    ) -> Self {
+        Self::Shared {
+            offset_base: start.as_usize().wrapping_sub(offset),
+            pages,
+            may_write,
+        }
+    }
+
+    /// Grows the object of a shared mapping, if it is growable, so that the
+    /// mapping is backed up to `end`.
+    pub(crate) fn grow_shared(&self, end: VirtAddr) {
+        if let Self::Shared {
+            offset_base, pages, ..
+        } = self
+        {
+            pages.grow(end.as_usize().wrapping_sub(*offset_base));
+        }
+    }
+
+    pub(crate) fn map_shared(start: VirtAddr, size: usize, flags: MappingFlags) -> bool {
+        debug!(
+            "map_shared: [{:#x}, {:#x}) {:?}",
+            start,
+            start + size,
+            flags
+        );
+        // create mapping entries on demand later in `handle_page_fault_shared`.
+        true
+    }
+
+    pub(crate) fn unmap_shared(
+        start: VirtAddr,
+        size: usize,
+        pt: &mut PageTable,
+        pages: &SharedPages,
+    ) -> bool {
+        debug!("unmap_shared: [{:#x}, {:#x})", start, start + size);
+        if let Some(iter) = PageIterWrapper::new(start, start + size, pages.align) {
+            for addr in iter {
+                if let Ok((frame, _page_size, tlb)) = pt.unmap(addr) {
+                    tlb.flush();
+                    // Drop the reference held by this mapping.
+                    dealloc_frame(frame, pages.align);
+                }
+            }
+        }
+        true
+    }
+
+    pub(crate) fn handle_page_fault_shared(
+        vaddr: VirtAddr,
+        orig_flags: MappingFlags,
+        pt: &mut PageTable,
+        offset_base: usize,
+        pages: &SharedPages,
+    ) -> bool {
+        let vaddr = vaddr.align_down(pages.align);
+        match pt.query(vaddr) {
+            // Restore the permission, e.g., after the page was write-protected.
+            Ok(_) => pt
+                .protect(vaddr, orig_flags)
+                .map(|(_, tlb)| tlb.flush())
+                .is_ok(),
+            Err(PagingError::NotMapped) => {
+                let offset = vaddr.as_usize().wrapping_sub(offset_base);
+                if offset >= pages.size() {
+                    return false;
+                }
+                let Some(frame) = pages.get_frame(offset / usize::from(pages.align)) else {
+                    return false;
+                };
+                match pt.map(vaddr, frame, pages.align, orig_flags) {
+                    Ok(tlb) => {
+                        tlb.flush();
+                        true
+                    }
+                    Err(_) => {
+                        dealloc_frame(frame, pages.align);
+                        false
+                    }
+                }
+            }
+            Err(_) => false,
+        }
+    }
+}
@@ -5,43 +5,85 @@
 //! that keeps track of its reference count.
 //! NOTE: If the page is huge page, its [`FrameInfo`] is placed at the
 //! starting physical address.
-use core::{
-    array,
-    sync::atomic::{AtomicUsize, Ordering},
-};
+//!
+//! The table is split into sections, each covering 128 MiB of physical
+//! memory, which are only allocated for the memory actually present, so that
+//! memory regions may have holes between them, and memory can be added after
+//! boot.
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
 
-use alloc::boxed::Box;
-use lazy_static::lazy_static;
+use axerrno::{AxResult, ax_err};
+use kspin::SpinNoIrq;
 use memory_addr::PhysAddr;
+
+use crate::VBox;
 // 4 kb page
 const FRAME_SHIFT: usize = 12;
 
-pub const MAX_FRAME_NUM: usize = axconfig::plat::PHYS_MEMORY_SIZE >> FRAME_SHIFT;
+/// The physical memory covered by a section is `1 << SECTION_SHIFT` bytes.
+const SECTION_SHIFT: usize = 27;
 
-lazy_static! {
-    static ref FRAME_INFO_TABLE: FrameRefTable = FrameRefTable::default();
-}
+/// The physical addresses covered by the table are below `1 << MAX_PHYS_BITS`.
+const MAX_PHYS_BITS: usize = 40;
+
+const FRAMES_PER_SECTION: usize = 1 << (SECTION_SHIFT - FRAME_SHIFT);
+const MAX_SECTIONS: usize = 1 << (MAX_PHYS_BITS - SECTION_SHIFT);
+
+type Section = [FrameInfo; FRAMES_PER_SECTION];
+
+static FRAME_INFO_TABLE: FrameRefTable = FrameRefTable::new();
 
 pub(crate) fn frame_table() -> &'static FrameRefTable {
     &FRAME_INFO_TABLE
 }
 
 pub(crate) struct FrameRefTable {
-    data: Box<[FrameInfo; MAX_FRAME_NUM]>,
+    /// Allocated by `vmalloc` on demand, as they are too large to be
+    /// physically contiguous reliably.
+    sections: [AtomicPtr<Section>; MAX_SECTIONS],
+    /// Serializes adding sections.
+    add_lock: SpinNoIrq<()>,
 }
 
-impl Default for FrameRefTable {
-    fn default() -> Self {
-        FrameRefTable {
-            data: Box::new(array::from_fn(|_| FrameInfo::default())),
+impl FrameRefTable {
+    const fn new() -> Self {
+        Self {
+            sections: [const { AtomicPtr::new(null_mut()) }; MAX_SECTIONS],
+            add_lock: SpinNoIrq::new(()),
+        }
+    }
+
+    /// Adds the sections covering the memory `[paddr, paddr + size)`.
+    ///
+    /// It must be called before any frame there is allocated by a mapping.
+    pub fn add_memory(&self, paddr: PhysAddr, size: usize) -> AxResult {
+        let start = paddr.as_usize() >> SECTION_SHIFT;
+        let end = (paddr.as_usize() + size).div_ceil(1 << SECTION_SHIFT);
+        if end > MAX_SECTIONS {
+            return ax_err!(InvalidInput, "physical memory beyond the frame table");
         }
+        let _guard = self.add_lock.lock();
+        for section in &self.sections[start..end] {
+            if section.load(Ordering::Acquire).is_null() {
+                let data = VBox::<Section>::try_new_zeroed()?;
+                // SAFETY: a zeroed `FrameInfo` has no references.
+                let data = VBox::leak(unsafe { data.assume_init() });
+                section.store(data, Ordering::Release);
+            }
+        }
+        Ok(())
     }
-}
 
-impl FrameRefTable {
     fn info(&self, paddr: PhysAddr) -> &FrameInfo {
-        let index = (paddr.as_usize() - axconfig::plat::PHYS_MEMORY_BASE) >> FRAME_SHIFT;
-        &self.data[index]
+        let frame = paddr.as_usize() >> FRAME_SHIFT;
+        let section = self
+            .sections
+            .get(frame / FRAMES_PER_SECTION)
+            .map(|section| section.load(Ordering::Acquire))
+            .filter(|section| !section.is_null())
+            .unwrap_or_else(|| panic!("no frame info for {:#x}", paddr));
+        unsafe { &(*section)[frame % FRAMES_PER_SECTION] }
     }
 
     /// Increases the reference count of the frame associated with a physical address.
@@ -8,16 +8,29 @@ extern crate alloc;
 
 mod aspace;
 mod backend;
-#[cfg(feature = "cow")]
 mod frameinfo;
+mod stack;
+mod stat;
+mod swap;
+mod thp;
+mod vmalloc;
 
 pub mod page_iter_wrapper;
 pub use self::aspace::AddrSpace;
-pub use self::backend::Backend;
+pub use self::backend::{Backend, FileCache, SharedPages};
+pub use self::stack::{DEFAULT_STACK_LIMIT, STACK_GUARD_GAP};
+pub use self::stat::{MappingInfo, MappingUsage, RssStat};
+pub use self::swap::{SwapAreaId, should_reclaim, swap_pages, swapoff, swapon};
+pub use self::thp::{HUGE_PAGE_SIZE, ThpMode, set_thp_mode, thp_mode};
+pub use self::vmalloc::{
+    KERNEL_STACK_GUARD_SIZE, VBox, alloc_kernel_stack, dealloc_kernel_stack, on_timer_tick, vfree,
+    vmalloc, vmalloc_range,
+};
 
+use axalloc::AllocError;
 use axerrno::{AxError, AxResult};
-use axhal::mem::phys_to_virt;
-use axhal::paging::PageSize;
+use axhal::mem::{MemRegionFlags, phys_to_virt, virt_to_phys};
+use axhal::paging::{MappingFlags, PageSize};
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 use memory_addr::{PhysAddr, va};
@@ -35,20 +48,32 @@ fn mapping_err_to_ax_err(err: MappingError) -> AxError {
 }
 
 /// Creates a new address space for kernel itself.
+///
+/// Each memory region, e.g., `.text`, `.rodata` and `.data`, is mapped with
+/// its own permissions, and no mapping is both writable and executable.
 pub fn new_kernel_aspace() -> AxResult<AddrSpace> {
     let mut aspace = AddrSpace::new_empty(
         va!(axconfig::plat::KERNEL_ASPACE_BASE),
         axconfig::plat::KERNEL_ASPACE_SIZE,
     )?;
     for r in axhal::mem::memory_regions() {
+        let mut flags = MappingFlags::from(r.flags);
+        if flags.contains(MappingFlags::WRITE | MappingFlags::EXECUTE) {
+            warn!(
+                "{} is writable and executable, mapping it non-executable",
+                r.name
+            );
+            flags -= MappingFlags::EXECUTE;
+        }
         aspace.map_linear(
             phys_to_virt(r.paddr),
             r.paddr,
             r.size,
-            r.flags.into(),
+            flags,
             PageSize::Size4K,
         )?;
     }
+    vmalloc::init_vmalloc(&mut aspace)?;
     Ok(aspace)
 }
 
@@ -74,10 +99,66 @@ pub fn init_memory_management() {
     debug!("kernel address space init OK: {:#x?}", kernel_aspace);
     KERNEL_ASPACE.init_once(SpinNoIrq::new(kernel_aspace));
     axhal::paging::set_kernel_page_table_root(kernel_page_table_root());
+    init_physical_memory();
 
     info!("[AXMM] init_memory_management() FINISHED."); // <--- 加入这行
 }
 
+/// Sets up the frame table for the memory of the global allocator, then adds
+/// the free memory not added at boot, which may not have been accessible
+/// before the kernel address space maps it.
+fn init_physical_memory() {
+    let allocator_regions = axalloc::global_allocator().memory_regions();
+    for &(start, size) in &allocator_regions {
+        frameinfo::frame_table()
+            .add_memory(virt_to_phys(va!(start)), size)
+            .expect("failed to set up the frame table");
+    }
+    for r in axhal::mem::memory_regions() {
+        let start = phys_to_virt(r.paddr).as_usize();
+        let added = allocator_regions
+            .iter()
+            .any(|&(s, size)| start < s + size && s < start + r.size);
+        if r.flags.contains(MemRegionFlags::FREE) && !added {
+            add_memory(r.paddr, r.size).expect("failed to add memory region");
+        }
+    }
+}
+
+/// Adds the physical memory `[paddr, paddr + size)`, e.g., memory hotplugged
+/// after boot, for allocation.
+///
+/// The memory is mapped in the linear mapping of the kernel address space if
+/// not yet, gets entries in the frame table, and is then handed to the global
+/// allocator. Address spaces that copied the kernel mappings, see
+/// [`AddrSpace::copy_mappings_from`], must copy them again to see the new
+/// mapping.
+pub fn add_memory(paddr: PhysAddr, size: usize) -> AxResult {
+    info!("add physical memory: [{:#x}, {:#x})", paddr, paddr + size);
+    let vaddr = phys_to_virt(paddr);
+    {
+        let mut aspace = kernel_aspace().lock();
+        if aspace.is_free(vaddr, size) {
+            aspace.map_linear(
+                vaddr,
+                paddr,
+                size,
+                MappingFlags::READ | MappingFlags::WRITE,
+                PageSize::Size4K,
+            )?;
+        }
+    }
+    frameinfo::frame_table().add_memory(paddr, size)?;
+    axalloc::global_add_memory(vaddr.as_usize(), size).map_err(|err| {
+        warn!("failed to add memory to the allocator: {:?}", err);
+        match err {
+            AllocError::MemoryOverlap => AxError::AlreadyExists,
+            AllocError::NoMemory => AxError::NoMemory,
+            _ => AxError::InvalidInput,
+        }
+    })
+}
+
 /// Initializes kernel paging for secondary CPUs.
 pub fn init_memory_management_secondary() {
     axhal::paging::set_kernel_page_table_root(kernel_page_table_root());
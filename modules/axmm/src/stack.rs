@@ -0,0 +1,95 @@
+//! User stacks that grow down on demand.
+//!
+//! A stack is mapped at its initial size by [`AddrSpace::map_stack`]. A fault
+//! below it extends it down to the faulting page, like Linux's
+//! `VM_GROWSDOWN` areas, as long as the stack stays within the stack limit
+//! and keeps [`STACK_GUARD_GAP`] free below it.
+//!
+//! [`AddrSpace::map_stack`]: crate::AddrSpace::map_stack
+
+use alloc::vec::Vec;
+
+use memory_addr::VirtAddr;
+
+use crate::page_iter_wrapper::PAGE_SIZE_4K;
+
+/// The space kept free below a stack, so that overflows fault instead of
+/// running into the mapping below. Same as Linux's `stack_guard_gap`.
+pub const STACK_GUARD_GAP: usize = 256 * PAGE_SIZE_4K;
+
+/// The default limit of the stack size, as `RLIMIT_STACK`.
+pub const DEFAULT_STACK_LIMIT: usize = 8 * 1024 * 1024;
+
+/// A stack that grows down, covering `[bottom, top)`.
+#[derive(Debug, Clone, Copy)]
+struct GrowsDown {
+    top: VirtAddr,
+    bottom: VirtAddr,
+}
+
+/// The stacks of an address space.
+#[derive(Debug, Clone)]
+pub(crate) struct Stacks {
+    stacks: Vec<GrowsDown>,
+    /// The size each stack may grow to.
+    limit: usize,
+}
+
+impl Stacks {
+    pub const fn new() -> Self {
+        Self {
+            stacks: Vec::new(),
+            limit: DEFAULT_STACK_LIMIT,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// Adds a stack covering `[bottom, top)`.
+    pub fn insert(&mut self, top: VirtAddr, bottom: VirtAddr) {
+        self.stacks.push(GrowsDown { top, bottom });
+    }
+
+    /// Returns the bottom of the stack that may grow down to `vaddr`.
+    pub fn bottom_above(&self, vaddr: VirtAddr) -> Option<VirtAddr> {
+        self.stacks
+            .iter()
+            .filter(|stack| {
+                stack.bottom > vaddr
+                    && stack.top.as_usize().saturating_sub(self.limit) <= vaddr.as_usize()
+            })
+            .map(|stack| stack.bottom)
+            .min()
+    }
+
+    /// Moves the bottom of the stack at `bottom` down to `new_bottom`.
+    pub fn grow(&mut self, bottom: VirtAddr, new_bottom: VirtAddr) {
+        if let Some(stack) = self.stacks.iter_mut().find(|stack| stack.bottom == bottom) {
+            stack.bottom = new_bottom;
+        }
+    }
+
+    /// Checks whether `[start, end)` is part of a stack.
+    pub fn contains(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        self.stacks
+            .iter()
+            .any(|stack| stack.bottom <= start && end <= stack.top)
+    }
+
+    /// Keeps only the stacks whose bottom satisfies `f`, e.g., is still
+    /// mapped.
+    pub fn retain(&mut self, mut f: impl FnMut(VirtAddr) -> bool) {
+        self.stacks.retain(|stack| f(stack.bottom));
+    }
+
+    /// Forgets all stacks, keeping the limit.
+    pub fn clear(&mut self) {
+        self.stacks.clear();
+    }
+}
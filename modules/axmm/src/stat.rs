@@ -0,0 +1,168 @@
+//! Memory usage statistics of address spaces.
+
+use alloc::string::String;
+
+use axhal::paging::{MappingFlags, PageSize};
+use memory_addr::VirtAddr;
+
+use crate::backend::Backend;
+use crate::page_iter_wrapper::PAGE_SIZE_4K;
+
+/// The resident pages of an address space by kind, as `RssAnon`, `RssFile`
+/// and `RssShmem` in `/proc/[pid]/status`. Counts are in 4K pages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RssStat {
+    /// Pages of private anonymous mappings.
+    pub anon: usize,
+    /// Pages of file mappings.
+    pub file: usize,
+    /// Pages of shared memory mappings.
+    pub shmem: usize,
+}
+
+impl RssStat {
+    /// The total number of resident pages.
+    pub fn total(&self) -> usize {
+        self.anon + self.file + self.shmem
+    }
+
+    pub(crate) fn add(&mut self, kind: RssKind, pages: usize) {
+        match kind {
+            RssKind::Anon => self.anon += pages,
+            RssKind::File => self.file += pages,
+            RssKind::Shmem => self.shmem += pages,
+        }
+    }
+}
+
+/// The number of 4K pages in a page of `page_size`.
+pub(crate) fn pages_of(page_size: PageSize) -> usize {
+    usize::from(page_size) / PAGE_SIZE_4K
+}
+
+/// The kind of the pages mapped by a backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RssKind {
+    Anon,
+    File,
+    Shmem,
+}
+
+impl RssKind {
+    /// Returns the kind of the pages of `backend`, or `None` for linear
+    /// mappings, whose pages are not owned by the address space.
+    pub fn of(backend: &Backend) -> Option<Self> {
+        match backend {
+            Backend::Linear { .. } => None,
+            Backend::Alloc { .. } => Some(Self::Anon),
+            Backend::File { .. } => Some(Self::File),
+            Backend::Shared { .. } => Some(Self::Shmem),
+        }
+    }
+}
+
+/// The resident set counters of an address space, updated as pages are
+/// mapped and unmapped.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct RssCounter {
+    current: RssStat,
+    /// The peak of `current.total()`.
+    peak: usize,
+}
+
+impl RssCounter {
+    pub const fn new() -> Self {
+        Self {
+            current: RssStat {
+                anon: 0,
+                file: 0,
+                shmem: 0,
+            },
+            peak: 0,
+        }
+    }
+
+    pub fn current(&self) -> RssStat {
+        self.current
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+
+    /// Counts `pages` newly mapped 4K pages.
+    pub fn add(&mut self, kind: RssKind, pages: usize) {
+        self.current.add(kind, pages);
+        self.peak = self.peak.max(self.current.total());
+    }
+
+    /// Counts newly mapped pages of all kinds.
+    pub fn add_all(&mut self, stat: RssStat) {
+        self.add(RssKind::Anon, stat.anon);
+        self.add(RssKind::File, stat.file);
+        self.add(RssKind::Shmem, stat.shmem);
+    }
+
+    /// Uncounts `pages` unmapped 4K pages.
+    pub fn sub(&mut self, kind: RssKind, pages: usize) {
+        let counter = match kind {
+            RssKind::Anon => &mut self.current.anon,
+            RssKind::File => &mut self.current.file,
+            RssKind::Shmem => &mut self.current.shmem,
+        };
+        *counter = counter.saturating_sub(pages);
+    }
+
+    /// Uncounts unmapped pages of all kinds.
+    pub fn sub_all(&mut self, stat: RssStat) {
+        self.sub(RssKind::Anon, stat.anon);
+        self.sub(RssKind::File, stat.file);
+        self.sub(RssKind::Shmem, stat.shmem);
+    }
+
+    /// Uncounts all pages, e.g., when all mappings are removed.
+    pub fn reset(&mut self) {
+        self.current = RssStat::default();
+    }
+}
+
+/// A mapping of an address space, as listed in `/proc/[pid]/maps`.
+#[derive(Debug, Clone)]
+pub struct MappingInfo {
+    /// The start address of the mapping.
+    pub start: VirtAddr,
+    /// The end address of the mapping.
+    pub end: VirtAddr,
+    /// The protection of the mapping.
+    pub flags: MappingFlags,
+    /// Whether writes are shared with other mappers.
+    pub shared: bool,
+    /// Whether the mapping is part of a stack growing down.
+    pub grows_down: bool,
+    /// The offset of `start` in the mapped file or shared memory object.
+    pub offset: usize,
+    /// The path of the mapped file.
+    pub path: Option<String>,
+}
+
+/// The memory usage of a mapping, as reported in `/proc/[pid]/smaps`. Sizes
+/// are in bytes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MappingUsage {
+    /// The page size of the mapping.
+    pub page_size: usize,
+    /// Resident memory.
+    pub rss: usize,
+    /// Resident memory, divided among the sharers of each page.
+    pub pss: usize,
+    /// Resident memory also mapped by others.
+    pub shared: usize,
+    /// Resident memory mapped by this mapping only.
+    pub private: usize,
+    /// Resident memory of anonymous mappings.
+    pub anonymous: usize,
+    /// Resident memory in transparent huge pages.
+    pub anon_huge: usize,
+    /// Memory swapped out.
+    pub swap: usize,
+}
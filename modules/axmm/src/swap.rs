@@ -0,0 +1,516 @@
+//! Page reclaim and swap.
+//!
+//! Private 4K pages of lazy anonymous areas are kept in a per address space
+//! LRU, ordered by the time they were faulted in, or found accessed by a
+//! scan. When memory runs low, the oldest ones not accessed since the last
+//! scan are written to a swap area, i.e., a block device or a file prepared
+//! with `mkswap`, and read back on the next page fault.
+
+use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use axerrno::{AxError, AxResult, ax_err};
+use axfs_vfs::VfsNodeRef;
+use axhal::mem::phys_to_virt;
+use axhal::paging::{MappingFlags, PageSize, PageTable};
+use kspin::SpinNoIrq;
+use memory_addr::{PhysAddr, VirtAddr};
+
+use crate::backend::{alloc_frame, dealloc_frame};
+use crate::page_iter_wrapper::PAGE_SIZE_4K;
+
+/// The number of pages reclaimed at once.
+pub(crate) const SWAP_CLUSTER: usize = 32;
+/// Below this number of free pages, [`should_reclaim`] asks for reclaim.
+const LOW_WATERMARK: usize = 256;
+
+/// The offset of the signature in the header page of a swap area.
+const SWAP_MAGIC_OFFSET: usize = PAGE_SIZE_4K - 10;
+const SWAP_MAGIC: &[u8] = b"SWAPSPACE2";
+/// The offset of `last_page` in the header page of a swap area.
+const SWAP_LAST_PAGE_OFFSET: usize = 1024 + 4;
+
+/// Identifies an active swap area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapAreaId(u32);
+
+/// A page in a swap area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SwapEntry {
+    area: SwapAreaId,
+    slot: u32,
+}
+
+struct SwapArea {
+    id: SwapAreaId,
+    path: String,
+    node: VfsNodeRef,
+    /// Reference counts of the slots. Slot 0 is the header and never used.
+    slots: Vec<u32>,
+    free: usize,
+    /// Where to look for a free slot first.
+    next: usize,
+    /// Set by `swapoff`, no more pages are swapped out to the area.
+    disabled: bool,
+}
+
+static SWAP_AREAS: SpinNoIrq<Vec<SwapArea>> = SpinNoIrq::new(Vec::new());
+static NEXT_AREA_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Starts swapping to `node`, a file or a block device prepared with
+/// `mkswap`. `path` names the area for [`swapoff`].
+pub fn swapon(path: &str, node: VfsNodeRef) -> AxResult {
+    if SWAP_AREAS.lock().iter().any(|area| area.path == path) {
+        return ax_err!(ResourceBusy, "swap area already active");
+    }
+
+    let mut header = vec![0u8; PAGE_SIZE_4K];
+    if node.read_at(0, &mut header)? != PAGE_SIZE_4K || &header[SWAP_MAGIC_OFFSET..] != SWAP_MAGIC {
+        return ax_err!(InvalidInput, "no swap signature");
+    }
+    let last_page = u32::from_le_bytes(
+        header[SWAP_LAST_PAGE_OFFSET..SWAP_LAST_PAGE_OFFSET + 4]
+            .try_into()
+            .unwrap(),
+    ) as usize;
+    let pages = (node.get_attr()?.size() as usize / PAGE_SIZE_4K).min(last_page + 1);
+    if pages < 2 {
+        return ax_err!(InvalidInput, "swap area too small");
+    }
+
+    let mut slots = vec![0; pages];
+    slots[0] = u32::MAX;
+    let id = SwapAreaId(NEXT_AREA_ID.fetch_add(1, Ordering::Relaxed));
+    info!("swapon: {} with {} pages", path, pages - 1);
+    SWAP_AREAS.lock().push(SwapArea {
+        id,
+        path: path.into(),
+        node,
+        slots,
+        free: pages - 1,
+        next: 1,
+        disabled: false,
+    });
+    Ok(())
+}
+
+/// Stops swapping to the area named `path`.
+///
+/// `swap_in_all` must read back the pages of the area into every address
+/// space, with [`AddrSpace::swap_in_area`]. If it fails, the area is kept.
+///
+/// [`AddrSpace::swap_in_area`]: crate::AddrSpace::swap_in_area
+pub fn swapoff(path: &str, swap_in_all: impl FnOnce(SwapAreaId) -> AxResult) -> AxResult {
+    let id = {
+        let mut areas = SWAP_AREAS.lock();
+        let area = areas
+            .iter_mut()
+            .find(|area| area.path == path && !area.disabled)
+            .ok_or(AxError::InvalidInput)?;
+        area.disabled = true;
+        area.id
+    };
+    let res = swap_in_all(id);
+    let mut areas = SWAP_AREAS.lock();
+    let index = areas.iter().position(|area| area.id == id).unwrap();
+    if res.is_ok() {
+        areas.remove(index);
+        info!("swapoff: {}", path);
+    } else {
+        areas[index].disabled = false;
+    }
+    res
+}
+
+/// Returns the total and free numbers of pages of all swap areas.
+pub fn swap_pages() -> (usize, usize) {
+    SWAP_AREAS
+        .lock()
+        .iter()
+        .fold((0, 0), |(total, free), area| {
+            (total + area.slots.len() - 1, free + area.free)
+        })
+}
+
+/// Checks whether free memory is low and pages can be swapped out.
+pub fn should_reclaim() -> bool {
+    axalloc::global_allocator().available_pages() < LOW_WATERMARK && swap_available()
+}
+
+/// Checks whether any page can be swapped out.
+pub(crate) fn swap_available() -> bool {
+    SWAP_AREAS
+        .lock()
+        .iter()
+        .any(|area| !area.disabled && area.free > 0)
+}
+
+/// Writes the page `frame` to a free slot of a swap area.
+fn write_slot(frame: PhysAddr) -> Option<SwapEntry> {
+    let (entry, node) = {
+        let mut areas = SWAP_AREAS.lock();
+        let area = areas
+            .iter_mut()
+            .find(|area| !area.disabled && area.free > 0)?;
+        let len = area.slots.len();
+        let slot = (area.next..len)
+            .chain(1..area.next)
+            .find(|&slot| area.slots[slot] == 0)?;
+        area.slots[slot] = 1;
+        area.free -= 1;
+        area.next = if slot + 1 < len { slot + 1 } else { 1 };
+        let entry = SwapEntry {
+            area: area.id,
+            slot: slot as u32,
+        };
+        (entry, area.node.clone())
+    };
+
+    // Write without holding the lock, the device may sleep on I/O.
+    let buf = unsafe { core::slice::from_raw_parts(phys_to_virt(frame).as_ptr(), PAGE_SIZE_4K) };
+    let offset = entry.slot as usize * PAGE_SIZE_4K;
+    let mut written = 0;
+    while written < PAGE_SIZE_4K {
+        match node.write_at((offset + written) as u64, &buf[written..]) {
+            Ok(0) | Err(_) => {
+                warn!("swap: failed to write slot {}", entry.slot);
+                free(entry);
+                return None;
+            }
+            Ok(n) => written += n,
+        }
+    }
+    Some(entry)
+}
+
+/// Reads the page `entry` into `frame`.
+fn read_slot(entry: SwapEntry, frame: PhysAddr) -> bool {
+    let Some(node) = with_area(entry.area, |area| area.node.clone()) else {
+        return false;
+    };
+    let buf =
+        unsafe { core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K) };
+    let offset = entry.slot as usize * PAGE_SIZE_4K;
+    let mut read = 0;
+    while read < PAGE_SIZE_4K {
+        match node.read_at((offset + read) as u64, &mut buf[read..]) {
+            Ok(0) | Err(_) => {
+                warn!("swap: failed to read slot {}", entry.slot);
+                return false;
+            }
+            Ok(n) => read += n,
+        }
+    }
+    true
+}
+
+/// Adds a reference to the page `entry`, e.g., for the child on fork.
+///
+/// Fails if the reference count of the slot would overflow.
+fn dup(entry: SwapEntry) -> bool {
+    with_area(entry.area, |area| {
+        let count = &mut area.slots[entry.slot as usize];
+        count.checked_add(1).map(|new| *count = new).is_some()
+    })
+    .unwrap_or(false)
+}
+
+/// Drops a reference to the page `entry`, freeing its slot with the last one.
+fn free(entry: SwapEntry) {
+    with_area(entry.area, |area| {
+        let count = &mut area.slots[entry.slot as usize];
+        *count -= 1;
+        if *count == 0 {
+            area.free += 1;
+        }
+    });
+}
+
+fn with_area<R>(id: SwapAreaId, f: impl FnOnce(&mut SwapArea) -> R) -> Option<R> {
+    SWAP_AREAS
+        .lock()
+        .iter_mut()
+        .find(|area| area.id == id)
+        .map(f)
+}
+
+/// The swapped out pages of an address space, keyed by their address.
+///
+/// Each entry holds a reference to its slot, dropped when the entry is
+/// removed.
+#[derive(Default)]
+struct SwappedPages(BTreeMap<usize, SwapEntry>);
+
+impl SwappedPages {
+    const fn new() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    /// Drops the entries in `[start, end)`.
+    fn remove_range(&mut self, start: usize, end: usize) {
+        let mut removed = self.0.split_off(&start);
+        let mut rest = removed.split_off(&end);
+        self.0.append(&mut rest);
+        removed.into_values().for_each(free);
+    }
+
+    /// Moves the entries in `[from, from + size)` to `to`.
+    fn move_range(&mut self, from: usize, size: usize, to: usize) {
+        let mut moved = self.0.split_off(&from);
+        let mut rest = moved.split_off(&(from + size));
+        self.0.append(&mut rest);
+        self.0.extend(
+            moved
+                .into_iter()
+                .map(|(vaddr, entry)| (vaddr - from + to, entry)),
+        );
+    }
+
+    /// Returns the pages swapped to the area `id`.
+    fn pages_in(&self, id: SwapAreaId) -> Vec<usize> {
+        self.0
+            .iter()
+            .filter(|(_, entry)| entry.area == id)
+            .map(|(&vaddr, _)| vaddr)
+            .collect()
+    }
+
+    fn clear(&mut self) {
+        core::mem::take(&mut self.0).into_values().for_each(free);
+    }
+}
+
+impl SwappedPages {
+    /// Clones the entries, adding a reference to each slot.
+    fn try_clone(&self) -> AxResult<Self> {
+        let mut cloned = Self::new();
+        for (&vaddr, &entry) in &self.0 {
+            if !dup(entry) {
+                return ax_err!(NoMemory, "too many references to a swap slot");
+            }
+            cloned.0.insert(vaddr, entry);
+        }
+        Ok(cloned)
+    }
+}
+
+impl Drop for SwappedPages {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// The resident pages of an address space which may be swapped out, oldest
+/// first.
+#[derive(Debug, Default, Clone)]
+struct PageLru {
+    /// Page address to stamp.
+    pages: BTreeMap<usize, u64>,
+    /// Stamp to page address.
+    order: BTreeMap<u64, usize>,
+    next_stamp: u64,
+}
+
+impl PageLru {
+    const fn new() -> Self {
+        Self {
+            pages: BTreeMap::new(),
+            order: BTreeMap::new(),
+            next_stamp: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Marks the page at `vaddr` as the most recently used.
+    fn touch(&mut self, vaddr: usize) {
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+        if let Some(old) = self.pages.insert(vaddr, stamp) {
+            self.order.remove(&old);
+        }
+        self.order.insert(stamp, vaddr);
+    }
+
+    /// Removes and returns the least recently used page.
+    fn pop_oldest(&mut self) -> Option<usize> {
+        let (_, vaddr) = self.order.pop_first()?;
+        self.pages.remove(&vaddr);
+        Some(vaddr)
+    }
+
+    /// Returns the pages in `[start, end)`.
+    fn pages_in(&self, start: usize, end: usize) -> Vec<usize> {
+        self.pages
+            .range(start..end)
+            .map(|(&vaddr, _)| vaddr)
+            .collect()
+    }
+
+    /// Forgets the pages in `[start, end)`.
+    fn remove_range(&mut self, start: usize, end: usize) {
+        let mut removed = self.pages.split_off(&start);
+        let mut rest = removed.split_off(&end);
+        self.pages.append(&mut rest);
+        for stamp in removed.into_values() {
+            self.order.remove(&stamp);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.pages.clear();
+        self.order.clear();
+    }
+}
+
+/// The swap state of an address space: its resident pages which may be
+/// swapped out, and its swapped out pages.
+#[derive(Default)]
+pub(crate) struct SwapState {
+    lru: PageLru,
+    swapped: SwappedPages,
+}
+
+impl SwapState {
+    pub const fn new() -> Self {
+        Self {
+            lru: PageLru::new(),
+            swapped: SwappedPages::new(),
+        }
+    }
+
+    /// Clones the state for a forked address space, failing if a swapped out
+    /// page has too many references.
+    pub fn try_clone(&self) -> AxResult<Self> {
+        Ok(Self {
+            lru: self.lru.clone(),
+            swapped: self.swapped.try_clone()?,
+        })
+    }
+
+    /// The number of resident pages which may be swapped out.
+    pub fn resident_pages(&self) -> usize {
+        self.lru.len()
+    }
+
+    /// The number of swapped out pages.
+    pub fn swapped_pages(&self) -> usize {
+        self.swapped.0.len()
+    }
+
+    /// The number of swapped out pages in `[start, end)`.
+    pub fn swapped_in(&self, start: VirtAddr, end: VirtAddr) -> usize {
+        self.swapped
+            .0
+            .range(start.as_usize()..end.as_usize())
+            .count()
+    }
+
+    /// Records that the page at `vaddr` was faulted in, or found accessed.
+    pub fn touch(&mut self, vaddr: VirtAddr) {
+        self.lru.touch(vaddr.as_usize());
+    }
+
+    /// Removes and returns the least recently used page.
+    pub fn pop_oldest(&mut self) -> Option<VirtAddr> {
+        self.lru.pop_oldest().map(VirtAddr::from)
+    }
+
+    /// Returns the resident pages in `[start, end)` which may be swapped
+    /// out.
+    pub fn resident_in(&self, start: VirtAddr, end: VirtAddr) -> Vec<VirtAddr> {
+        self.lru
+            .pages_in(start.as_usize(), end.as_usize())
+            .into_iter()
+            .map(VirtAddr::from)
+            .collect()
+    }
+
+    /// Reads back the page at `vaddr` if it was swapped out, mapping it with
+    /// `flags`. Returns `None` if the page was not swapped out.
+    pub fn swap_in(
+        &mut self,
+        vaddr: VirtAddr,
+        flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> Option<bool> {
+        let entry = self.swapped.0.remove(&vaddr.as_usize())?;
+        let frame = alloc_frame(false, PageSize::Size4K);
+        let mapped = frame.is_some_and(|frame| {
+            read_slot(entry, frame)
+                && pt
+                    .map(vaddr, frame, PageSize::Size4K, flags)
+                    .map(|tlb| tlb.flush())
+                    .is_ok()
+        });
+        if mapped {
+            free(entry);
+            self.touch(vaddr);
+        } else {
+            if let Some(frame) = frame {
+                dealloc_frame(frame, PageSize::Size4K);
+            }
+            self.swapped.0.insert(vaddr.as_usize(), entry);
+        }
+        Some(mapped)
+    }
+
+    /// Writes the 4K page at `vaddr` to a swap area and unmaps it.
+    pub fn swap_out(&mut self, vaddr: VirtAddr, pt: &mut PageTable) -> bool {
+        let Ok((frame, flags, PageSize::Size4K)) = pt.query(vaddr) else {
+            return false;
+        };
+        // Unmap first, so that no write is lost while writing the page.
+        let (_, _, tlb) = pt.unmap(vaddr).unwrap();
+        tlb.flush();
+        match write_slot(frame) {
+            Some(entry) => {
+                dealloc_frame(frame, PageSize::Size4K);
+                self.lru
+                    .remove_range(vaddr.as_usize(), vaddr.as_usize() + PAGE_SIZE_4K);
+                self.swapped.0.insert(vaddr.as_usize(), entry);
+                true
+            }
+            None => {
+                pt.map(vaddr, frame, PageSize::Size4K, flags)
+                    .unwrap()
+                    .flush();
+                false
+            }
+        }
+    }
+
+    /// Returns the pages swapped out to the area `id`.
+    pub fn pages_in(&self, id: SwapAreaId) -> Vec<VirtAddr> {
+        self.swapped
+            .pages_in(id)
+            .into_iter()
+            .map(VirtAddr::from)
+            .collect()
+    }
+
+    /// Forgets the pages in `[start, end)`, dropping the swapped out ones.
+    pub fn remove_range(&mut self, start: VirtAddr, end: VirtAddr) {
+        self.lru.remove_range(start.as_usize(), end.as_usize());
+        self.swapped.remove_range(start.as_usize(), end.as_usize());
+    }
+
+    /// Moves the swapped out pages in `[from, from + size)` to `to`.
+    ///
+    /// Resident pages are not moved, they are recorded again by [`touch`].
+    ///
+    /// [`touch`]: Self::touch
+    pub fn move_range(&mut self, from: VirtAddr, size: usize, to: VirtAddr) {
+        self.lru
+            .remove_range(from.as_usize(), from.as_usize() + size);
+        self.swapped
+            .move_range(from.as_usize(), size, to.as_usize());
+    }
+
+    pub fn clear(&mut self) {
+        self.lru.clear();
+        self.swapped.clear();
+    }
+}
@@ -0,0 +1,245 @@
+//! Transparent huge pages of anonymous mappings.
+//!
+//! Lazy 4K allocation areas fault in a whole 2M page when the aligned 2M
+//! block around the fault lies within the area and nothing is mapped in it
+//! yet. Huge pages are split back into 4K pages when an operation covers
+//! them partially, and [`AddrSpace::collapse_huge_pages`] merges fully
+//! populated blocks, like Linux's `khugepaged`.
+//!
+//! [`AddrSpace::collapse_huge_pages`]: crate::AddrSpace::collapse_huge_pages
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use axhal::mem::phys_to_virt;
+use axhal::paging::{MappingFlags, PageSize, PageTable};
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr};
+
+use crate::backend::{alloc_frame, dealloc_frame};
+use crate::frameinfo::frame_table;
+use crate::page_iter_wrapper::PAGE_SIZE_4K;
+
+/// The size of a transparent huge page.
+pub const HUGE_PAGE_SIZE: usize = 0x20_0000;
+const PAGES_PER_HUGE_PAGE: usize = HUGE_PAGE_SIZE / PAGE_SIZE_4K;
+
+/// When to use transparent huge pages, as in
+/// `/sys/kernel/mm/transparent_hugepage/enabled`.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThpMode {
+    /// In all anonymous mappings, except `MADV_NOHUGEPAGE` ranges.
+    Always,
+    /// Only in `MADV_HUGEPAGE` ranges.
+    Madvise,
+    /// Never.
+    Never,
+}
+
+static THP_MODE: AtomicU8 = AtomicU8::new(ThpMode::Madvise as u8);
+
+/// Returns the current transparent huge page mode.
+pub fn thp_mode() -> ThpMode {
+    match THP_MODE.load(Ordering::Relaxed) {
+        0 => ThpMode::Always,
+        1 => ThpMode::Madvise,
+        _ => ThpMode::Never,
+    }
+}
+
+/// Sets the transparent huge page mode. Huge pages already mapped are kept.
+pub fn set_thp_mode(mode: ThpMode) {
+    THP_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// A set of disjoint address ranges, e.g., the ranges advised with
+/// `MADV_HUGEPAGE`.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct RangeSet(BTreeMap<usize, usize>);
+
+impl RangeSet {
+    pub const fn new() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    /// Adds `[start, end)`, merging it with adjacent ranges.
+    pub fn insert(&mut self, mut start: usize, mut end: usize) {
+        self.remove(start, end);
+        if let Some((&s, &e)) = self.0.range(..start).next_back()
+            && e == start
+        {
+            self.0.remove(&s);
+            start = s;
+        }
+        if let Some(e) = self.0.remove(&end) {
+            end = e;
+        }
+        self.0.insert(start, end);
+    }
+
+    /// Removes `[start, end)`, trimming the ranges overlapping it.
+    pub fn remove(&mut self, start: usize, end: usize) {
+        let overlapping: Vec<(usize, usize)> = self
+            .0
+            .range(..end)
+            .rev()
+            .take_while(|&(_, &e)| e > start)
+            .map(|(&s, &e)| (s, e))
+            .collect();
+        for (s, e) in overlapping {
+            self.0.remove(&s);
+            if s < start {
+                self.0.insert(s, start);
+            }
+            if e > end {
+                self.0.insert(end, e);
+            }
+        }
+    }
+
+    /// Checks whether `[start, end)` is fully in the set.
+    pub fn contains(&self, start: usize, end: usize) -> bool {
+        self.0
+            .range(..=start)
+            .next_back()
+            .is_some_and(|(_, &e)| e >= end)
+    }
+
+    /// Checks whether any address of `[start, end)` is in the set.
+    pub fn intersects(&self, start: usize, end: usize) -> bool {
+        self.0
+            .range(..end)
+            .next_back()
+            .is_some_and(|(_, &e)| e > start)
+    }
+}
+
+/// Maps a new zeroed huge page at the 2M block `block`.
+///
+/// Fails if any page is mapped in the block, or was mapped since the last
+/// page table of the block was freed.
+pub(crate) fn fault_huge_page(pt: &mut PageTable, block: VirtAddr, flags: MappingFlags) -> bool {
+    let Some(frame) = alloc_frame(true, PageSize::Size2M) else {
+        return false;
+    };
+    match pt.map(block, frame, PageSize::Size2M, flags) {
+        Ok(tlb) => {
+            tlb.flush();
+            true
+        }
+        Err(_) => {
+            dealloc_frame(frame, PageSize::Size2M);
+            false
+        }
+    }
+}
+
+/// Splits the huge page mapping `vaddr`, if any, into 4K pages with the same
+/// contents and flags.
+///
+/// The contents are copied to new 4K frames, as the huge frame was allocated
+/// and is reference counted as a whole, and only ever freed as a whole.
+pub(crate) fn split_huge_page(pt: &mut PageTable, vaddr: VirtAddr) -> bool {
+    let block = vaddr.align_down(PageSize::Size2M);
+    let Ok((frame, flags, PageSize::Size2M)) = pt.query(block) else {
+        return true;
+    };
+
+    let mut frames = Vec::with_capacity(PAGES_PER_HUGE_PAGE);
+    for i in 0..PAGES_PER_HUGE_PAGE {
+        let sub = frame + i * PAGE_SIZE_4K;
+        let Some(copy) = alloc_frame(false, PageSize::Size4K) else {
+            frames
+                .into_iter()
+                .for_each(|f| dealloc_frame(f, PageSize::Size4K));
+            return false;
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                phys_to_virt(sub).as_ptr(),
+                phys_to_virt(copy).as_mut_ptr(),
+                PAGE_SIZE_4K,
+            )
+        };
+        frames.push(copy);
+    }
+
+    let (_, _, tlb) = pt.unmap(block).unwrap();
+    tlb.flush();
+    if !map_4k_pages(pt, block, &frames, flags) {
+        // Only creating the last-level table can fail, put the huge page back.
+        pt.map(block, frame, PageSize::Size2M, flags)
+            .unwrap()
+            .flush();
+        frames
+            .into_iter()
+            .for_each(|f| dealloc_frame(f, PageSize::Size4K));
+        return false;
+    }
+
+    dealloc_frame(frame, PageSize::Size2M);
+    true
+}
+
+/// Merges the 4K pages of the 2M block `block` into a huge page.
+///
+/// All the pages must be mapped with the same flags and used by this mapping
+/// only, so that the merged page has the same contents.
+pub(crate) fn collapse_huge_page(pt: &mut PageTable, block: VirtAddr) -> bool {
+    let mut flags = None;
+    for i in 0..PAGES_PER_HUGE_PAGE {
+        match pt.query(block + i * PAGE_SIZE_4K) {
+            Ok((frame, f, PageSize::Size4K)) if frame_table().ref_count(frame) == 1 => {
+                if flags.is_some_and(|flags| flags != f) {
+                    return false;
+                }
+                flags = Some(f);
+            }
+            _ => return false,
+        }
+    }
+    let flags = flags.unwrap();
+    let Some(huge) = alloc_frame(false, PageSize::Size2M) else {
+        return false;
+    };
+
+    // Unmap first, so that no write is lost while copying.
+    let mut frames = Vec::with_capacity(PAGES_PER_HUGE_PAGE);
+    for i in 0..PAGES_PER_HUGE_PAGE {
+        let (frame, _, tlb) = pt.unmap(block + i * PAGE_SIZE_4K).unwrap();
+        tlb.flush();
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                phys_to_virt(frame).as_ptr(),
+                phys_to_virt(huge + i * PAGE_SIZE_4K).as_mut_ptr(),
+                PAGE_SIZE_4K,
+            )
+        };
+        frames.push(frame);
+    }
+
+    if !axhal::paging::free_empty_last_level_table(pt, block)
+        || pt.map(block, huge, PageSize::Size2M, flags).is_err()
+    {
+        map_4k_pages(pt, block, &frames, flags);
+        dealloc_frame(huge, PageSize::Size2M);
+        return false;
+    }
+    frames
+        .into_iter()
+        .for_each(|f| dealloc_frame(f, PageSize::Size4K));
+    true
+}
+
+fn map_4k_pages(
+    pt: &mut PageTable,
+    start: VirtAddr,
+    frames: &[PhysAddr],
+    flags: MappingFlags,
+) -> bool {
+    frames.iter().enumerate().all(|(i, &frame)| {
+        pt.map(start + i * PAGE_SIZE_4K, frame, PageSize::Size4K, flags)
+            .map(|tlb| tlb.flush())
+            .is_ok()
+    })
+}
@@ -0,0 +1,326 @@
+//! The kernel virtual range for mappings outside the linear mapping.
+//!
+//! Large kernel buffers are allocated here by [`vmalloc`], from frames
+//! scattered in physical memory, so they do not fail when no physically
+//! contiguous block is large enough. [`VBox`] holds a single object this way.
+//!
+//! With the `stack-guard` feature of `axtask`, kernel task stacks are
+//! allocated here too, each with an unmapped guard page below it, so that a
+//! stack overflow faults instead of silently corrupting the memory below.
+//!
+//! There is no TLB shootdown, so a freed range is unmapped at once but its
+//! frames and addresses are reused only after every CPU has flushed its TLB,
+//! which each does on its next timer tick, see [`on_timer_tick`].
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::fmt;
+use core::mem::{ManuallyDrop, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axalloc::global_allocator;
+use axerrno::{AxResult, ax_err};
+use axhal::mem::{VMALLOC_SIZE, VMALLOC_START, phys_to_virt, virt_to_phys};
+use axhal::paging::{MappingFlags, PageSize};
+use kspin::SpinNoIrq;
+use memory_addr::{PhysAddr, VirtAddr, VirtAddrRange, align_up_4k, va};
+
+use crate::page_iter_wrapper::PAGE_SIZE_4K;
+use crate::{AddrSpace, kernel_aspace};
+
+/// The unmapped space below each kernel stack.
+pub const KERNEL_STACK_GUARD_SIZE: usize = PAGE_SIZE_4K;
+
+/// The live [`vmalloc`] allocations, by start and size.
+static VMALLOC_AREAS: SpinNoIrq<BTreeMap<VirtAddr, usize>> = SpinNoIrq::new(BTreeMap::new());
+
+/// A range freed by [`vfree`], unmapped but not yet reusable.
+struct PendingFree {
+    /// The range, including the guard page.
+    range: VirtAddrRange,
+    frames: Vec<PhysAddr>,
+    /// The value of [`TLB_EPOCH`] that every CPU must have flushed at.
+    epoch: usize,
+}
+
+/// Ranges freed by [`vfree`] that some CPU may still cache translations of.
+static PENDING_FREE: SpinNoIrq<Vec<PendingFree>> = SpinNoIrq::new(Vec::new());
+
+/// Bumped on every [`vfree`].
+static TLB_EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+/// The value of [`TLB_EPOCH`] at the last TLB flush of each CPU, or
+/// `usize::MAX` if it has not ticked yet, so it cached nothing freed.
+static FLUSHED_EPOCH: [AtomicUsize; axconfig::SMP] =
+    [const { AtomicUsize::new(usize::MAX) }; axconfig::SMP];
+
+/// Returns the kernel virtual range reserved for kernel stacks and
+/// [`vmalloc`].
+pub fn vmalloc_range() -> VirtAddrRange {
+    VirtAddrRange::from_start_size(va!(VMALLOC_START), VMALLOC_SIZE)
+}
+
+/// Creates the page tables of the range in the kernel address space.
+///
+/// User page tables copy the top-level entries of the kernel one when
+/// created, so they must exist before any user address space, or later
+/// kernel stacks would be missing there.
+///
+/// A linear mapping is used, as frames cannot be reference counted before
+/// the frame table, itself allocated by [`vmalloc`], exists.
+pub(crate) fn init_vmalloc(aspace: &mut AddrSpace) -> AxResult {
+    let start = vmalloc_range().start;
+    aspace.map_linear(
+        start,
+        PhysAddr::from(axconfig::plat::PHYS_MEMORY_BASE),
+        PAGE_SIZE_4K,
+        MappingFlags::READ,
+        PageSize::Size4K,
+    )?;
+    aspace.unmap(start, PAGE_SIZE_4K)
+}
+
+/// Flushes the TLB of the current CPU if any range was freed since it last
+/// did, so that the range can be reused once every CPU has.
+///
+/// It is called on every timer interrupt on each CPU, which comes at least
+/// once a second even while the CPU idles without ticks.
+pub fn on_timer_tick() {
+    flush_stale_tlb();
+}
+
+/// Flushes the TLB of the current CPU if any range was freed since it last
+/// did. Preemption must be disabled.
+fn flush_stale_tlb() {
+    let epoch = TLB_EPOCH.load(Ordering::Acquire);
+    let flushed = &FLUSHED_EPOCH[axhal::cpu::this_cpu_id()];
+    if flushed.load(Ordering::Relaxed) != epoch {
+        axhal::arch::flush_tlb(None);
+        flushed.store(epoch, Ordering::Release);
+    }
+}
+
+/// Frees the frames of the pending ranges that no CPU may cache translations
+/// of anymore, which makes their addresses reusable.
+fn reclaim_pending() {
+    let ready: Vec<_> = {
+        let mut pending = PENDING_FREE.lock();
+        // The current CPU need not wait for its own tick.
+        flush_stale_tlb();
+        let flushed = FLUSHED_EPOCH
+            .iter()
+            .map(|epoch| epoch.load(Ordering::Acquire))
+            .min()
+            .unwrap_or(usize::MAX);
+        let (ready, rest) = core::mem::take(&mut *pending)
+            .into_iter()
+            .partition(|range| range.epoch <= flushed);
+        *pending = rest;
+        ready
+    };
+    for range in ready {
+        free_frames(&range.frames);
+    }
+}
+
+/// Finds a free range of `size` bytes, at or above `hint` if possible, that
+/// is not pending to be freed.
+fn find_free_area(aspace: &AddrSpace, hint: VirtAddr, size: usize) -> Option<VirtAddr> {
+    let range = vmalloc_range();
+    let pending = PENDING_FREE.lock();
+    for mut hint in [hint, range.start] {
+        while let Some(start) = aspace.find_free_area(hint, size, range, PageSize::Size4K) {
+            let area = VirtAddrRange::from_start_size(start, size);
+            match pending.iter().find(|p| p.range.overlaps(area)) {
+                Some(p) => hint = p.range.end,
+                None => return Some(start),
+            }
+        }
+    }
+    None
+}
+
+/// Maps a kernel stack of `size` bytes with a guard page below it, and
+/// returns its bottom.
+pub fn alloc_kernel_stack(size: usize) -> AxResult<VirtAddr> {
+    vmalloc(size)
+}
+
+/// Frees the kernel stack at `bottom` of `size` bytes, allocated by
+/// [`alloc_kernel_stack`].
+pub fn dealloc_kernel_stack(bottom: VirtAddr, _size: usize) {
+    vfree(bottom)
+}
+
+/// Allocates `size` bytes of zeroed, virtually contiguous kernel memory, and
+/// returns its start.
+///
+/// The memory is made of 4K frames that need not be physically contiguous,
+/// and is preceded by an unmapped guard page. It must be freed by [`vfree`].
+pub fn vmalloc(size: usize) -> AxResult<VirtAddr> {
+    if size == 0 {
+        return ax_err!(InvalidInput, "vmalloc of zero bytes");
+    }
+    reclaim_pending();
+    let size = align_up_4k(size);
+    let mut frames = Vec::with_capacity(size / PAGE_SIZE_4K);
+    for _ in 0..size / PAGE_SIZE_4K {
+        match global_allocator().alloc_pages(1, PAGE_SIZE_4K) {
+            Ok(vaddr) => {
+                unsafe { core::ptr::write_bytes(vaddr as *mut u8, 0, PAGE_SIZE_4K) };
+                frames.push(virt_to_phys(va!(vaddr)));
+            }
+            Err(_) => {
+                free_frames(&frames);
+                return ax_err!(NoMemory, "vmalloc out of frames");
+            }
+        }
+    }
+
+    let mut aspace = kernel_aspace().lock();
+    let hint = VMALLOC_AREAS
+        .lock()
+        .last_key_value()
+        .map_or(vmalloc_range().start, |(&start, &size)| start + size);
+    let Some(guard) = find_free_area(&aspace, hint, size + PAGE_SIZE_4K) else {
+        free_frames(&frames);
+        return ax_err!(NoMemory, "vmalloc range exhausted");
+    };
+    let start = guard + PAGE_SIZE_4K;
+
+    // Map each run of physically contiguous frames as one linear area.
+    let mut mapped = 0;
+    for run in frames.chunk_by(|a, b| *a + PAGE_SIZE_4K == *b) {
+        let run_size = run.len() * PAGE_SIZE_4K;
+        let flags = MappingFlags::READ | MappingFlags::WRITE;
+        if let Err(err) =
+            aspace.map_linear(start + mapped, run[0], run_size, flags, PageSize::Size4K)
+        {
+            if mapped > 0 {
+                aspace.unmap(start, mapped).ok();
+            }
+            free_frames(&frames);
+            return Err(err);
+        }
+        mapped += run_size;
+    }
+    VMALLOC_AREAS.lock().insert(start, size);
+    Ok(start)
+}
+
+/// Frees the memory at `start` allocated by [`vmalloc`].
+///
+/// It is unmapped at once, but its frames are freed only after every CPU
+/// has flushed its TLB.
+pub fn vfree(start: VirtAddr) {
+    {
+        let mut aspace = kernel_aspace().lock();
+        let Some(size) = VMALLOC_AREAS.lock().remove(&start) else {
+            warn!("vfree: {:#x} is not allocated by vmalloc", start);
+            return;
+        };
+        let frames = (0..size)
+            .step_by(PAGE_SIZE_4K)
+            .filter_map(|offset| aspace.page_table().query(start + offset).ok())
+            .map(|(frame, ..)| frame)
+            .collect::<Vec<_>>();
+        aspace.unmap(start, size).unwrap();
+        let epoch = TLB_EPOCH.fetch_add(1, Ordering::AcqRel) + 1;
+        PENDING_FREE.lock().push(PendingFree {
+            range: VirtAddrRange::from_start_size(start - PAGE_SIZE_4K, size + PAGE_SIZE_4K),
+            frames,
+            epoch,
+        });
+    }
+    reclaim_pending();
+}
+
+fn free_frames(frames: &[PhysAddr]) {
+    for &frame in frames {
+        global_allocator().dealloc_pages(phys_to_virt(frame).as_usize(), 1);
+    }
+}
+
+/// A pointer type for large kernel objects, like [`Box`], but allocated by
+/// [`vmalloc`] instead of from physically contiguous memory.
+///
+/// [`Box`]: alloc::boxed::Box
+pub struct VBox<T> {
+    ptr: NonNull<T>,
+}
+
+unsafe impl<T: Send> Send for VBox<T> {}
+unsafe impl<T: Sync> Sync for VBox<T> {}
+
+impl<T> VBox<T> {
+    /// Allocates memory and moves `value` into it.
+    ///
+    /// `value` is still built on the stack first; use
+    /// [`VBox::try_new_zeroed`] for objects too large for that.
+    pub fn try_new(value: T) -> AxResult<Self> {
+        let mut uninit = Self::try_new_zeroed()?;
+        uninit.write(value);
+        Ok(unsafe { uninit.assume_init() })
+    }
+
+    /// Allocates zeroed memory for a `T`, without initializing it.
+    pub fn try_new_zeroed() -> AxResult<VBox<MaybeUninit<T>>> {
+        assert!(core::mem::align_of::<T>() <= PAGE_SIZE_4K);
+        let start = vmalloc(core::mem::size_of::<T>().max(1))?;
+        Ok(VBox {
+            ptr: NonNull::new(start.as_mut_ptr()).unwrap().cast(),
+        })
+    }
+}
+
+impl<T> VBox<T> {
+    /// Consumes the box and returns a reference to the value, which is never
+    /// freed.
+    pub fn leak<'a>(this: Self) -> &'a mut T {
+        let this = ManuallyDrop::new(this);
+        unsafe { &mut *this.ptr.as_ptr() }
+    }
+}
+
+impl<T> VBox<MaybeUninit<T>> {
+    /// Converts to `VBox<T>`.
+    ///
+    /// # Safety
+    ///
+    /// The value must be initialized, e.g., all zeros must be a valid `T` if
+    /// it comes from [`VBox::try_new_zeroed`] untouched.
+    pub unsafe fn assume_init(self) -> VBox<T> {
+        let this = ManuallyDrop::new(self);
+        VBox {
+            ptr: this.ptr.cast(),
+        }
+    }
+}
+
+impl<T> Deref for VBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for VBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for VBox<T> {
+    fn drop(&mut self) {
+        unsafe { core::ptr::drop_in_place(self.ptr.as_ptr()) };
+        vfree(VirtAddr::from_mut_ptr_of(self.ptr.as_ptr()));
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for VBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
@@ -7,6 +7,7 @@
 //!
 //! - `alloc`: Enable global memory allocator.
 //! - `paging`: Enable page table manipulation support.
+//! - `stack-guard`: Map task stacks with an unmapped guard page below each.
 //! - `irq`: Enable interrupt handling support.
 //! - `multitask`: Enable multi-threading support.
 //! - `smp`: Enable SMP (symmetric multiprocessing) support.
@@ -83,6 +84,21 @@ impl axlog::LogIf for LogIfImpl {
     }
 }
 
+#[cfg(feature = "stack-guard")]
+struct KernelStackIfImpl;
+
+#[cfg(feature = "stack-guard")]
+#[crate_interface::impl_interface]
+impl axtask::KernelStackIf for KernelStackIfImpl {
+    fn alloc_kernel_stack(size: usize) -> Option<axhal::mem::VirtAddr> {
+        axmm::alloc_kernel_stack(size).ok()
+    }
+
+    fn dealloc_kernel_stack(bottom: axhal::mem::VirtAddr, size: usize) {
+        axmm::dealloc_kernel_stack(bottom, size)
+    }
+}
+
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 static INITED_CPUS: AtomicUsize = AtomicUsize::new(0);
@@ -159,7 +175,11 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
         let all_devices = axdriver::init_drivers();
 
         #[cfg(feature = "fs")]
-        axfs::init_filesystems(all_devices.block);
+        {
+            axfs::init_filesystems(all_devices.block);
+            #[cfg(feature = "multitask")]
+            spawn_block_flusher();
+        }
 
         #[cfg(feature = "net")]
         axnet::init_network(all_devices.net);
@@ -194,6 +214,9 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
 
     unsafe { main() };
 
+    #[cfg(feature = "fs")]
+    axfs::api::sync().ok();
+
     #[cfg(feature = "multitask")]
     axtask::exit(0);
     #[cfg(not(feature = "multitask"))]
@@ -203,6 +226,20 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     }
 }
 
+/// Periodically writes back dirty blocks of the block cache.
+#[cfg(all(feature = "fs", feature = "multitask"))]
+fn spawn_block_flusher() {
+    const FLUSH_INTERVAL: core::time::Duration = core::time::Duration::from_secs(5);
+    axtask::spawn_raw(
+        || loop {
+            axtask::sleep(FLUSH_INTERVAL);
+            axfs::api::sync().ok();
+        },
+        "bdflush".into(),
+        axconfig::TASK_STACK_SIZE,
+    );
+}
+
 #[cfg(feature = "alloc")]
 fn init_allocator() {
     use axhal::mem::{MemRegionFlags, memory_regions, phys_to_virt};
@@ -210,22 +247,27 @@ fn init_allocator() {
     info!("Initialize global memory allocator...");
     info!("  use {} allocator.", axalloc::global_allocator().name());
 
-    let mut max_region_size = 0;
-    let mut max_region_paddr = 0.into();
-    for r in memory_regions() {
-        if r.flags.contains(MemRegionFlags::FREE) && r.size > max_region_size {
-            max_region_size = r.size;
-            max_region_paddr = r.paddr;
-        }
-    }
-    for r in memory_regions() {
-        if r.flags.contains(MemRegionFlags::FREE) && r.paddr == max_region_paddr {
-            axalloc::global_init(phys_to_virt(r.paddr).as_usize(), r.size);
-            break;
-        }
-    }
-    for r in memory_regions() {
-        if r.flags.contains(MemRegionFlags::FREE) && r.paddr != max_region_paddr {
+    // Start with the free region right after the kernel image, which the boot
+    // page table surely maps, or else the largest one.
+    let kernel_end = memory_regions()
+        .filter(|r| {
+            r.flags.contains(MemRegionFlags::RESERVED) && !r.flags.contains(MemRegionFlags::DEVICE)
+        })
+        .map(|r| r.paddr + r.size)
+        .max();
+    let free_regions = || memory_regions().filter(|r| r.flags.contains(MemRegionFlags::FREE));
+    let init_region = free_regions()
+        .filter(|r| kernel_end.is_some_and(|end| r.paddr >= end))
+        .min_by_key(|r| r.paddr)
+        .or_else(|| free_regions().max_by_key(|r| r.size))
+        .expect("no free memory region");
+    axalloc::global_init(phys_to_virt(init_region.paddr).as_usize(), init_region.size);
+
+    // With paging, the other regions may be out of the boot page table, so
+    // they are added once the kernel address space maps them.
+    #[cfg(not(feature = "paging"))]
+    for r in free_regions() {
+        if r.paddr != init_region.paddr {
             axalloc::global_add_memory(phys_to_virt(r.paddr).as_usize(), r.size)
                 .expect("add heap memory region failed");
         }
@@ -237,13 +279,14 @@ fn init_interrupt() {
     use axhal::time::TIMER_IRQ_NUM;
 
     // Setup timer interrupt handler
-    const PERIODIC_INTERVAL_NANOS: u64 =
-        axhal::time::NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
+    #[cfg(not(feature = "multitask"))]
+    fn update_timer() -> bool {
+        const PERIODIC_INTERVAL_NANOS: u64 =
+            axhal::time::NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
 
-    #[percpu::def_percpu]
-    static NEXT_DEADLINE: u64 = 0;
+        #[percpu::def_percpu]
+        static NEXT_DEADLINE: u64 = 0;
 
-    fn update_timer() {
         let now_ns = axhal::time::monotonic_time_nanos();
         // Safety: we have disabled preemption in IRQ handler.
         let mut deadline = unsafe { NEXT_DEADLINE.read_current_raw() };
@@ -252,14 +295,28 @@ fn init_interrupt() {
         }
         unsafe { NEXT_DEADLINE.write_current_raw(deadline + PERIODIC_INTERVAL_NANOS) };
         axhal::time::set_oneshot_timer(deadline);
+        true
     }
 
+    // With multitasking, the task manager programs the timer for its timed
+    // events and periodic ticks.
+    #[cfg(feature = "multitask")]
+    use axtask::on_timer_tick as update_timer;
+
     axhal::irq::register_handler(TIMER_IRQ_NUM, || {
-        update_timer();
-        #[cfg(feature = "multitask")]
-        axtask::on_timer_tick();
+        let _tick_due = update_timer();
+        #[cfg(feature = "paging")]
+        axmm::on_timer_tick();
+        #[cfg(feature = "alloc-sanitize")]
+        if _tick_due {
+            axalloc::on_timer_tick();
+        }
     });
 
+    // Other CPUs wake up an idle one with IPIs when they give it tasks.
+    #[cfg(all(feature = "smp", feature = "multitask"))]
+    axhal::irq::register_handler(axhal::irq::IPI_IRQ_NUM, axtask::on_ipi);
+
     // Enable IRQs before starting app
     axhal::arch::enable_irqs();
 }
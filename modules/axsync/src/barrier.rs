@@ -0,0 +1,65 @@
+//! A barrier to synchronize a number of tasks.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+
+use crate::spin::SpinNoIrq;
+
+/// A barrier, similar to
+/// [`std::sync::Barrier`](https://doc.rust-lang.org/std/sync/struct.Barrier.html).
+///
+/// It blocks the tasks calling [`wait`](Barrier::wait) in a wait queue, until
+/// `n` of them have called it, then lets them all continue. It can be used
+/// again afterwards.
+pub struct Barrier {
+    wq: WaitQueue,
+    n: usize,
+    /// The number of tasks waiting in the current generation.
+    count: SpinNoIrq<usize>,
+    /// Bumped each time all tasks have arrived.
+    generation: AtomicUsize,
+}
+
+/// The result of [`Barrier::wait`].
+#[derive(Debug)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Returns `true` for exactly one of the tasks released together, the
+    /// last one to arrive.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    /// Creates a barrier for `n` tasks.
+    pub const fn new(n: usize) -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            n,
+            count: SpinNoIrq::new(0),
+            generation: AtomicUsize::new(0),
+        }
+    }
+
+    /// Blocks the current task until all `n` tasks have called this method.
+    pub fn wait(&self) -> BarrierWaitResult {
+        let mut count = self.count.lock();
+        let generation = self.generation.load(Ordering::Acquire);
+        *count += 1;
+        if *count < self.n {
+            drop(count);
+            self.wq
+                .wait_until(|| self.generation.load(Ordering::Acquire) != generation);
+            BarrierWaitResult(false)
+        } else {
+            *count = 0;
+            self.generation.fetch_add(1, Ordering::Release);
+            drop(count);
+            self.wq.notify_all(true);
+            BarrierWaitResult(true)
+        }
+    }
+}
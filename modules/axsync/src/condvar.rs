@@ -0,0 +1,77 @@
+//! A condition variable.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+use lock_api::{MutexGuard, RawMutex};
+
+/// A condition variable, similar to
+/// [`std::sync::Condvar`](https://doc.rust-lang.org/std/sync/struct.Condvar.html).
+///
+/// It works with [`Mutex`](crate::Mutex) and [`PiMutex`](crate::PiMutex).
+/// Waiting tasks are put into a wait queue, and may wake up spuriously, so
+/// the condition should be checked in a loop, or with [`Condvar::wait_while`].
+pub struct Condvar {
+    wq: WaitQueue,
+    /// Bumped on each notification, so that a notification sent between
+    /// unlocking the mutex and sleeping is not lost.
+    seq: AtomicUsize,
+}
+
+impl Condvar {
+    /// Creates a new condition variable.
+    pub const fn new() -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            seq: AtomicUsize::new(0),
+        }
+    }
+
+    /// Blocks the current task until this condition variable receives a
+    /// notification, with the mutex of `guard` unlocked meanwhile.
+    pub fn wait<'a, R: RawMutex, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, R, T>,
+    ) -> MutexGuard<'a, R, T> {
+        let seq = self.seq.load(Ordering::Acquire);
+        MutexGuard::unlocked(&mut guard, || {
+            self.wq
+                .wait_until(|| self.seq.load(Ordering::Acquire) != seq)
+        });
+        guard
+    }
+
+    /// Blocks the current task while `condition` returns `true`, with the
+    /// mutex of `guard` unlocked while waiting.
+    pub fn wait_while<'a, R: RawMutex, T: ?Sized, F>(
+        &self,
+        mut guard: MutexGuard<'a, R, T>,
+        mut condition: F,
+    ) -> MutexGuard<'a, R, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Wakes up one task blocked on this condition variable.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.wq.notify_one(true);
+    }
+
+    /// Wakes up all tasks blocked on this condition variable.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.wq.notify_all(true);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -3,25 +3,67 @@
 //! Currently supported primitives:
 //!
 //! - [`Mutex`]: A mutual exclusion primitive.
+//! - [`PiMutex`]: A mutex with priority inheritance.
+//! - [`RwLock`]: A reader-writer lock.
+//! - [`Condvar`]: A condition variable.
+//! - [`Semaphore`]: A counting semaphore.
+//! - [`Barrier`]: A barrier to synchronize a number of tasks.
 //! - mod [`spin`]: spinlocks imported from the [`kspin`] crate.
 //!
 //! # Cargo Features
 //!
 //! - `multitask`: For use in the multi-threaded environments. If the feature is
-//!   not enabled, [`Mutex`] will be an alias of [`spin::SpinNoIrq`]. This
+//!   not enabled, [`Mutex`] will be an alias of [`spin::SpinNoIrq`], and the
+//!   other primitives, which block the current task, are not available. This
 //!   feature is enabled by default.
+//! - `lockdep`: Check the order in which [`Mutex`], [`PiMutex`], the spin
+//!   locks of mod [`spin`] and the locks of [`axtask::WaitQueue`] are taken,
+//!   by lock class, and report the orders that may deadlock, and spin locks
+//!   held when a task blocks. The spin locks of mod [`spin`] then wrap those
+//!   of [`kspin`], while other locks of [`kspin`] used directly are not
+//!   checked.
 
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
 
+#[cfg(feature = "lockdep")]
+#[macro_use]
+extern crate log;
+
+#[cfg(not(feature = "lockdep"))]
 pub use kspin as spin;
+#[cfg(feature = "lockdep")]
+pub mod spin;
+
+#[cfg(feature = "lockdep")]
+mod lockdep;
 
+#[cfg(feature = "multitask")]
+mod barrier;
+#[cfg(feature = "multitask")]
+mod condvar;
 #[cfg(feature = "multitask")]
 mod mutex;
+#[cfg(feature = "multitask")]
+mod pi_mutex;
+#[cfg(feature = "multitask")]
+mod rwlock;
+#[cfg(feature = "multitask")]
+mod semaphore;
+
+#[cfg(test)]
+mod tests;
 
 #[cfg(feature = "multitask")]
 #[doc(cfg(feature = "multitask"))]
-pub use self::mutex::{Mutex, MutexGuard, RawMutex};
+pub use self::{
+    barrier::{Barrier, BarrierWaitResult},
+    condvar::Condvar,
+    mutex::{Mutex, MutexGuard, RawMutex},
+    pi_mutex::{PiMutex, PiMutexGuard, RawPiMutex},
+    rwlock::{RawRwLock, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    semaphore::Semaphore,
+};
 
 #[cfg(not(feature = "multitask"))]
 #[doc(cfg(not(feature = "multitask")))]
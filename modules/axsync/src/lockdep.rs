@@ -0,0 +1,460 @@
+//! A lock dependency checker.
+//!
+//! It records, for each task, the locks it holds, and the order in which
+//! locks are taken while others are held, as a graph with an edge from each
+//! held lock to each lock acquired after it. An acquisition that closes a
+//! cycle in the graph is reported as a possible deadlock, even if it did not
+//! deadlock this time. Blocking while holding a spin lock is reported too.
+//!
+//! The nodes of the graph are lock classes rather than locks, as in Linux:
+//! all the locks created at the same place in the source, e.g., the locks of
+//! the address spaces of all processes, are one class, so that the orders
+//! taken on some instances are checked against those taken on others. Taking
+//! two locks of the same class at once is allowed, and adds no edge. A
+//! mutex and the spin lock of its wait queue are different classes. A lock
+//! created where the place is unknown, e.g., by a C static initializer, is a
+//! class of its own.
+//! Reports give the classes and the names of the tasks involved, and the
+//! backtraces of the acquisitions, and turn the checker off, as later
+//! reports are often caused by the first.
+//!
+//! The backtraces are only meaningful if the kernel is built with
+//! `-C force-frame-pointers=yes`.
+
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use axalloc::backtrace;
+use axtask::current_may_uninit;
+use kspin::SpinNoIrq;
+
+/// The number of locks a task may hold at once.
+const MAX_HELD: usize = 16;
+/// The number of tasks that may hold locks at once.
+const MAX_TASKS: usize = 256;
+/// The number of dependencies between lock classes recorded at most.
+const MAX_DEPS: usize = 2048;
+/// The number of frames kept in the backtrace of each acquisition.
+const MAX_FRAMES: usize = 6;
+/// The number of bytes kept of the task names.
+const MAX_NAME_LEN: usize = 16;
+
+/// Where a lock is created, which stands for its class.
+pub(crate) type LockClass = &'static Location<'static>;
+
+/// The kind of a lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LockKind {
+    /// A spin lock, which must not be held when blocking.
+    Spin,
+    /// A lock that blocks the task while it waits.
+    Sleep,
+}
+
+/// A node of the graph.
+#[derive(Clone, Copy)]
+struct Class {
+    /// Where the locks of the class are created, if known.
+    loc: Option<LockClass>,
+    key: usize,
+}
+
+impl Class {
+    const EMPTY: Self = Self { loc: None, key: 0 };
+
+    /// Returns the class of the lock at `addr` of `kind` created at `loc`.
+    ///
+    /// Places are compared by value, as the same [`Location`] may have
+    /// several copies in the kernel image.
+    fn new(loc: Option<LockClass>, addr: usize, kind: LockKind) -> Self {
+        let Some(loc) = loc else {
+            return Self { loc, key: addr };
+        };
+        // FNV-1a.
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        let bytes = loc.file().bytes().chain(loc.line().to_le_bytes());
+        for byte in bytes.chain(loc.column().to_le_bytes()).chain([kind as u8]) {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+        Self {
+            loc: Some(loc),
+            key: hash as usize,
+        }
+    }
+}
+
+impl core::fmt::Display for Class {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.loc {
+            Some(loc) => write!(f, "{}", loc),
+            None => write!(f, "of lock {:#x}", self.key),
+        }
+    }
+}
+
+/// The backtrace of an acquisition.
+#[derive(Clone, Copy)]
+struct Site([usize; MAX_FRAMES]);
+
+impl Site {
+    const EMPTY: Self = Self([0; MAX_FRAMES]);
+
+    #[inline(always)]
+    fn capture() -> Self {
+        let mut site = Self::EMPTY;
+        backtrace::capture(&mut site.0);
+        site
+    }
+
+    fn print(&self) {
+        for &ra in self.0.iter().take_while(|&&ra| ra != 0) {
+            error!("      at {:#x}", ra);
+        }
+    }
+}
+
+/// The name of a task, truncated.
+#[derive(Clone, Copy)]
+struct TaskName {
+    id: u64,
+    name: [u8; MAX_NAME_LEN],
+    len: usize,
+}
+
+impl TaskName {
+    const EMPTY: Self = Self {
+        id: 0,
+        name: [0; MAX_NAME_LEN],
+        len: 0,
+    };
+
+    fn new(id: u64, name: &str) -> Self {
+        let mut task = Self { id, ..Self::EMPTY };
+        task.len = name.len().min(MAX_NAME_LEN);
+        task.name[..task.len].copy_from_slice(&name.as_bytes()[..task.len]);
+        task
+    }
+}
+
+impl core::fmt::Display for TaskName {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let name = core::str::from_utf8(&self.name[..self.len]).unwrap_or("?");
+        write!(f, "Task({}, {:?})", self.id, name)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct HeldLock {
+    addr: usize,
+    class: Class,
+    kind: LockKind,
+    site: Site,
+}
+
+/// The locks held by a task, in the order they were acquired.
+#[derive(Clone, Copy)]
+struct TaskLocks {
+    /// Zero if the slot is free.
+    task_id: u64,
+    held: [HeldLock; MAX_HELD],
+    len: usize,
+}
+
+/// A lock of class `to` was acquired while one of class `from` was held.
+#[derive(Clone, Copy)]
+struct Dep {
+    from: usize,
+    to: usize,
+    from_class: Class,
+    to_class: Class,
+    from_site: Site,
+    to_site: Site,
+    task: TaskName,
+}
+
+const EMPTY_DEP: Dep = Dep {
+    from: 0,
+    to: 0,
+    from_class: Class::EMPTY,
+    to_class: Class::EMPTY,
+    from_site: Site::EMPTY,
+    to_site: Site::EMPTY,
+    task: TaskName::EMPTY,
+};
+
+struct Lockdep {
+    tasks: [TaskLocks; MAX_TASKS],
+    deps: [Dep; MAX_DEPS],
+    num_deps: usize,
+    /// Scratch space to search the graph: the index of the edge reaching
+    /// each node in the queue, and the position of its parent.
+    queue: [(usize, usize); MAX_DEPS],
+}
+
+static LOCKDEP: SpinNoIrq<Lockdep> = SpinNoIrq::new(Lockdep {
+    tasks: [TaskLocks {
+        task_id: 0,
+        held: [HeldLock {
+            addr: 0,
+            class: Class::EMPTY,
+            kind: LockKind::Spin,
+            site: Site::EMPTY,
+        }; MAX_HELD],
+        len: 0,
+    }; MAX_TASKS],
+    deps: [EMPTY_DEP; MAX_DEPS],
+    num_deps: 0,
+    queue: [(0, 0); MAX_DEPS],
+});
+
+/// Whether the checker is on. It turns off after the first report, or when
+/// it runs out of space.
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+impl Lockdep {
+    fn task_locks(&mut self, task_id: u64) -> Option<&mut TaskLocks> {
+        let idx = match self.tasks.iter().position(|t| t.task_id == task_id) {
+            Some(idx) => idx,
+            None => {
+                let Some(idx) = self.tasks.iter().position(|t| t.task_id == 0) else {
+                    warn!("lockdep: too many tasks holding locks, turning off");
+                    disable();
+                    return None;
+                };
+                self.tasks[idx].task_id = task_id;
+                self.tasks[idx].len = 0;
+                idx
+            }
+        };
+        Some(&mut self.tasks[idx])
+    }
+
+    fn has_dep(&self, from: usize, to: usize) -> bool {
+        self.deps[..self.num_deps]
+            .iter()
+            .any(|dep| dep.from == from && dep.to == to)
+    }
+
+    /// Searches the graph for a path from `start` to `target`, and returns
+    /// the position in the queue of its last edge.
+    fn find_path(&mut self, start: usize, target: usize) -> Option<usize> {
+        let (mut head, mut tail) = (0, 0);
+        let mut node = start;
+        let mut parent = usize::MAX;
+        loop {
+            for (idx, dep) in self.deps[..self.num_deps].iter().enumerate() {
+                if dep.from != node {
+                    continue;
+                }
+                // Each edge is queued at most once.
+                if self.queue[..tail].iter().any(|&(queued, _)| queued == idx) {
+                    continue;
+                }
+                self.queue[tail] = (idx, parent);
+                tail += 1;
+                if dep.to == target {
+                    return Some(tail - 1);
+                }
+            }
+            if head == tail {
+                return None;
+            }
+            node = self.deps[self.queue[head].0].to;
+            parent = head;
+            head += 1;
+        }
+    }
+
+    fn add_dep(&mut self, dep: Dep) {
+        if self.num_deps == MAX_DEPS {
+            warn!("lockdep: too many lock dependencies, turning off");
+            disable();
+            return;
+        }
+        self.deps[self.num_deps] = dep;
+        self.num_deps += 1;
+    }
+
+    fn print_path(&self, mut pos: usize) {
+        while pos != usize::MAX {
+            let (idx, parent) = self.queue[pos];
+            let dep = &self.deps[idx];
+            error!(
+                "  {} acquired a lock of class {} while holding one of class {}, acquired",
+                dep.task, dep.to_class, dep.from_class
+            );
+            dep.to_site.print();
+            error!("    and held since");
+            dep.from_site.print();
+            pos = parent;
+        }
+    }
+}
+
+fn current_task() -> Option<TaskName> {
+    let curr = current_may_uninit()?;
+    Some(TaskName::new(curr.id().as_u64(), curr.name()))
+}
+
+/// Records that the current task acquires the lock at `addr` created at
+/// `class`.
+///
+/// It is called before a lock is acquired, so that orders that may deadlock
+/// are reported before they do. For a lock that is tried, it is called only
+/// if the lock was taken, with `try_lock` set, as trying cannot deadlock.
+#[inline(always)]
+pub(crate) fn acquire(class: Option<LockClass>, addr: usize, kind: LockKind, try_lock: bool) {
+    if !enabled() {
+        return;
+    }
+    let class = Class::new(class, addr, kind);
+    let Some(task) = current_task() else {
+        return;
+    };
+    let site = Site::capture();
+    let mut lockdep = LOCKDEP.lock();
+    let Some(locks) = lockdep.task_locks(task.id) else {
+        return;
+    };
+    let held_locks = *locks;
+    if held_locks.len == MAX_HELD {
+        warn!("lockdep: {} holds too many locks, turning off", task);
+        disable();
+        return;
+    }
+    let held = &held_locks.held[..held_locks.len];
+    if !try_lock {
+        if let Some(prev) = held.iter().find(|held| held.addr == addr) {
+            error!(
+                "lockdep: recursive locking of lock {:#x} of class {} by {}",
+                addr, class, task
+            );
+            site.print();
+            error!("    already held since");
+            prev.site.print();
+            disable();
+            return;
+        }
+        let key = class.key;
+        for prev in held {
+            let prev_key = prev.class.key;
+            if prev_key == key || lockdep.has_dep(prev_key, key) {
+                continue;
+            }
+            if let Some(pos) = lockdep.find_path(key, prev_key) {
+                error!(
+                    "lockdep: possible deadlock: {} acquires lock {:#x} of class {}",
+                    task, addr, class
+                );
+                site.print();
+                error!(
+                    "    while holding lock {:#x} of class {}, acquired",
+                    prev.addr, prev.class
+                );
+                prev.site.print();
+                error!("  but the classes were taken in the reverse order before:");
+                lockdep.print_path(pos);
+                disable();
+                return;
+            }
+            lockdep.add_dep(Dep {
+                from: prev_key,
+                to: key,
+                from_class: prev.class,
+                to_class: class,
+                from_site: prev.site,
+                to_site: site,
+                task,
+            });
+        }
+    }
+    let Some(locks) = lockdep.task_locks(task.id) else {
+        return;
+    };
+    locks.held[locks.len] = HeldLock {
+        addr,
+        class,
+        kind,
+        site,
+    };
+    locks.len += 1;
+}
+
+/// Records that the current task released the lock at `addr`.
+pub(crate) fn release(addr: usize) {
+    let Some(curr) = current_may_uninit() else {
+        return;
+    };
+    let task_id = curr.id().as_u64();
+    let mut lockdep = LOCKDEP.lock();
+    let Some(locks) = lockdep.tasks.iter_mut().find(|t| t.task_id == task_id) else {
+        return;
+    };
+    // Locks may be released in any order.
+    if let Some(idx) = locks.held[..locks.len]
+        .iter()
+        .rposition(|held| held.addr == addr)
+    {
+        locks.held.copy_within(idx + 1..locks.len, idx);
+        locks.len -= 1;
+        if locks.len == 0 {
+            locks.task_id = 0;
+        }
+    }
+}
+
+/// Reports the spin locks held by the current task, as it is about to block.
+pub(crate) fn check_blocking() {
+    if !enabled() {
+        return;
+    }
+    let Some(task) = current_task() else {
+        return;
+    };
+    let lockdep = LOCKDEP.lock();
+    let Some(locks) = lockdep.tasks.iter().find(|t| t.task_id == task.id) else {
+        return;
+    };
+    let mut spins = locks.held[..locks.len]
+        .iter()
+        .filter(|held| held.kind == LockKind::Spin)
+        .peekable();
+    if spins.peek().is_none() {
+        return;
+    }
+    error!("lockdep: {} blocks while holding spin locks:", task);
+    Site::capture().print();
+    for held in spins {
+        error!(
+            "    lock {:#x} of class {}, acquired",
+            held.addr, held.class
+        );
+        held.site.print();
+    }
+    disable();
+}
+
+struct LockdepIfImpl;
+
+#[crate_interface::impl_interface]
+impl axtask::LockdepIf for LockdepIfImpl {
+    fn check_blocking() {
+        check_blocking();
+    }
+
+    fn acquire_spin(class: Option<LockClass>, addr: usize, try_lock: bool) {
+        acquire(class, addr, LockKind::Spin, try_lock);
+    }
+
+    fn release(addr: usize) {
+        release(addr);
+    }
+}
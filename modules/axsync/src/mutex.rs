@@ -1,9 +1,14 @@
 //! A naïve sleeping mutex.
 
+use core::fmt;
+use core::ops::{Deref, DerefMut};
 use core::sync::atomic::{AtomicU64, Ordering};
 
 use axtask::{WaitQueue, current};
 
+#[cfg(feature = "lockdep")]
+use crate::lockdep::{self, LockKind};
+
 /// A [`lock_api::RawMutex`] implementation.
 ///
 /// When the mutex is locked, the current task will block and be put into the
@@ -16,7 +21,11 @@ pub struct RawMutex {
 
 impl RawMutex {
     /// Creates a [`RawMutex`].
+    ///
+    /// With the `lockdep` feature, the caller stands for its lock class, kept
+    /// by its wait queue.
     #[inline(always)]
+    #[track_caller]
     pub const fn new() -> Self {
         Self {
             wq: WaitQueue::new(),
@@ -25,12 +34,21 @@ impl RawMutex {
     }
 }
 
+#[cfg(feature = "lockdep")]
+impl RawMutex {
+    fn addr(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
 unsafe impl lock_api::RawMutex for RawMutex {
     const INIT: Self = RawMutex::new();
 
     type GuardMarker = lock_api::GuardSend;
 
     fn lock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.wq.lock_class(), self.addr(), LockKind::Sleep, false);
         let current_id = current().id().as_u64();
         loop {
             // Can fail to lock even if the spinlock is not locked. May be more efficient than `try_lock`
@@ -60,12 +78,20 @@ unsafe impl lock_api::RawMutex for RawMutex {
         let current_id = current().id().as_u64();
         // The reason for using a strong compare_exchange is explained here:
         // https://github.com/Amanieu/parking_lot/pull/207#issuecomment-575869107
-        self.owner_id
+        let locked = self
+            .owner_id
             .compare_exchange(0, current_id, Ordering::Acquire, Ordering::Relaxed)
-            .is_ok()
+            .is_ok();
+        #[cfg(feature = "lockdep")]
+        if locked {
+            lockdep::acquire(self.wq.lock_class(), self.addr(), LockKind::Sleep, true);
+        }
+        locked
     }
 
     unsafe fn unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.addr());
         let owner_id = self.owner_id.swap(0, Ordering::Release);
         assert_eq!(
             owner_id,
@@ -81,18 +107,73 @@ unsafe impl lock_api::RawMutex for RawMutex {
     }
 }
 
-/// An alias of [`lock_api::Mutex`].
-pub type Mutex<T> = lock_api::Mutex<RawMutex, T>;
+/// A mutex, which dereferences to the [`lock_api::Mutex`] it
+/// wraps.
+///
+/// It is not an alias only so that [`Mutex::new`] knows its caller, which
+/// stands for the lock class with the `lockdep` feature.
+pub struct Mutex<T: ?Sized>(lock_api::Mutex<RawMutex, T>);
+
+impl<T> Mutex<T> {
+    /// Creates a new mutex in an unlocked state ready for use.
+    #[inline(always)]
+    #[track_caller]
+    pub const fn new(val: T) -> Self {
+        Self(lock_api::Mutex::from_raw(RawMutex::new(), val))
+    }
+
+    /// Consumes this mutex, returning the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+}
+
+impl<T: ?Sized> Deref for Mutex<T> {
+    type Target = lock_api::Mutex<RawMutex, T>;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T: ?Sized> DerefMut for Mutex<T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    #[inline(always)]
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    #[track_caller]
+    fn from(val: T) -> Self {
+        Self::new(val)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 /// An alias of [`lock_api::MutexGuard`].
 pub type MutexGuard<'a, T> = lock_api::MutexGuard<'a, RawMutex, T>;
 
 #[cfg(test)]
 mod tests {
     use crate::Mutex;
+    use crate::tests::{INIT, SERIAL};
     use axtask as thread;
-    use std::sync::Once;
-
-    static INIT: Once = Once::new();
 
     fn may_interrupt() {
         // simulate interrupts
@@ -103,6 +184,7 @@ mod tests {
 
     #[test]
     fn lots_and_lots() {
+        let _lock = SERIAL.lock();
         INIT.call_once(thread::init_scheduler);
 
         const NUM_TASKS: u32 = 10;
@@ -0,0 +1,180 @@
+//! A sleeping mutex with priority inheritance.
+
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axtask::{AxTaskRef, WaitQueue, current};
+
+#[cfg(feature = "lockdep")]
+use crate::lockdep::{self, LockKind};
+use crate::spin::SpinNoIrq;
+
+/// A [`lock_api::RawMutex`] implementation with priority inheritance.
+///
+/// When a task blocks on the mutex, the owner inherits its priority if it is
+/// higher, so that tasks of a priority in between cannot keep the owner, and
+/// hence the blocked task, from running. With the scheduling classes, the
+/// owner also inherits the class and real-time priority of a real-time or
+/// deadline task (see [`axtask::inherit_from`]). The owner drops what it
+/// inherited once it releases all such mutexes it holds.
+///
+/// When the mutex is unlocked, all tasks waiting on it are woken up, so that
+/// the scheduler lets the one of the highest priority take it.
+pub struct RawPiMutex {
+    wq: WaitQueue,
+    owner_id: AtomicU64,
+    owner: SpinNoIrq<Option<AxTaskRef>>,
+}
+
+impl RawPiMutex {
+    /// Creates a [`RawPiMutex`].
+    ///
+    /// With the `lockdep` feature, the caller stands for its lock class, kept
+    /// by its wait queue.
+    #[inline(always)]
+    #[track_caller]
+    pub const fn new() -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            owner_id: AtomicU64::new(0),
+            owner: SpinNoIrq::new(None),
+        }
+    }
+}
+
+#[cfg(feature = "lockdep")]
+impl RawPiMutex {
+    fn addr(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+unsafe impl lock_api::RawMutex for RawPiMutex {
+    const INIT: Self = RawPiMutex::new();
+
+    type GuardMarker = lock_api::GuardSend;
+
+    fn lock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.wq.lock_class(), self.addr(), LockKind::Sleep, false);
+        let curr = current();
+        loop {
+            let mut owner = self.owner.lock();
+            match owner.as_ref() {
+                None => {
+                    self.owner_id.store(curr.id().as_u64(), Ordering::Relaxed);
+                    *owner = Some(curr.as_task_ref().clone());
+                    axtask::pi_lock_acquired();
+                    return;
+                }
+                Some(owner) => {
+                    assert_ne!(
+                        owner.id(),
+                        curr.id(),
+                        "{} tried to acquire mutex it already owns.",
+                        curr.id_name()
+                    );
+                    axtask::inherit_from(owner, &curr);
+                }
+            }
+            drop(owner);
+            // Wait until the lock looks unlocked before retrying
+            self.wq.wait_until(|| !self.is_locked());
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        let curr = current();
+        let mut owner = self.owner.lock();
+        if owner.is_some() {
+            return false;
+        }
+        self.owner_id.store(curr.id().as_u64(), Ordering::Relaxed);
+        *owner = Some(curr.as_task_ref().clone());
+        axtask::pi_lock_acquired();
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(self.wq.lock_class(), self.addr(), LockKind::Sleep, true);
+        true
+    }
+
+    unsafe fn unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.addr());
+        let owner = self.owner.lock().take();
+        self.owner_id.store(0, Ordering::Release);
+        assert!(
+            owner.is_some_and(|owner| owner.id() == current().id()),
+            "{} tried to release mutex it doesn't own",
+            current().id_name()
+        );
+        axtask::pi_lock_released();
+        self.wq.notify_all(true);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.owner_id.load(Ordering::Relaxed) != 0
+    }
+}
+
+/// A mutex with priority inheritance, which dereferences to the [`lock_api::Mutex`] it
+/// wraps.
+///
+/// It is not an alias only so that [`PiMutex::new`] knows its caller, which
+/// stands for the lock class with the `lockdep` feature.
+pub struct PiMutex<T: ?Sized>(lock_api::Mutex<RawPiMutex, T>);
+
+impl<T> PiMutex<T> {
+    /// Creates a new mutex in an unlocked state ready for use.
+    #[inline(always)]
+    #[track_caller]
+    pub const fn new(val: T) -> Self {
+        Self(lock_api::Mutex::from_raw(RawPiMutex::new(), val))
+    }
+
+    /// Consumes this mutex, returning the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+}
+
+impl<T: ?Sized> Deref for PiMutex<T> {
+    type Target = lock_api::Mutex<RawPiMutex, T>;
+
+    #[inline(always)]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T: ?Sized> DerefMut for PiMutex<T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T: Default> Default for PiMutex<T> {
+    #[inline(always)]
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for PiMutex<T> {
+    #[track_caller]
+    fn from(val: T) -> Self {
+        Self::new(val)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for PiMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// An alias of [`lock_api::MutexGuard`] of a [`PiMutex`].
+pub type PiMutexGuard<'a, T> = lock_api::MutexGuard<'a, RawPiMutex, T>;
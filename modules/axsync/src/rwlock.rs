@@ -0,0 +1,115 @@
+//! A sleeping reader-writer lock.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+
+/// Set in the state when a writer holds the lock. The other bits count the
+/// readers.
+const WRITER: usize = 1 << (usize::BITS - 1);
+
+/// A [`lock_api::RawRwLock`] implementation.
+///
+/// When the lock cannot be taken, the current task will block and be put
+/// into the wait queue. Readers also wait while writers are waiting, so that
+/// writers are not starved. When the lock is released, all tasks waiting on
+/// the queue will be woken up.
+pub struct RawRwLock {
+    wq: WaitQueue,
+    state: AtomicUsize,
+    writers_waiting: AtomicUsize,
+}
+
+impl RawRwLock {
+    /// Creates a [`RawRwLock`].
+    #[inline(always)]
+    pub const fn new() -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+        }
+    }
+
+    fn can_read(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITER == 0
+            && self.writers_waiting.load(Ordering::Relaxed) == 0
+    }
+}
+
+unsafe impl lock_api::RawRwLock for RawRwLock {
+    const INIT: Self = RawRwLock::new();
+
+    type GuardMarker = lock_api::GuardSend;
+
+    fn lock_shared(&self) {
+        while !self.try_lock_shared() {
+            self.wq.wait_until(|| self.can_read());
+        }
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if state & WRITER != 0 || self.writers_waiting.load(Ordering::Relaxed) != 0 {
+                return false;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                state + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(s) => state = s,
+            }
+        }
+    }
+
+    unsafe fn unlock_shared(&self) {
+        if self.state.fetch_sub(1, Ordering::Release) == 1 {
+            self.wq.notify_all(true);
+        }
+    }
+
+    fn lock_exclusive(&self) {
+        if self.try_lock_exclusive() {
+            return;
+        }
+        self.writers_waiting.fetch_add(1, Ordering::Relaxed);
+        while !self.try_lock_exclusive() {
+            self.wq
+                .wait_until(|| self.state.load(Ordering::Relaxed) == 0);
+        }
+        if self.writers_waiting.fetch_sub(1, Ordering::Relaxed) == 1 {
+            // Let the readers blocked by this writer check again.
+            self.wq.notify_all(false);
+        }
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        self.state.store(0, Ordering::Release);
+        self.wq.notify_all(true);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) != 0
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        self.state.load(Ordering::Relaxed) & WRITER != 0
+    }
+}
+
+/// An alias of [`lock_api::RwLock`].
+pub type RwLock<T> = lock_api::RwLock<RawRwLock, T>;
+/// An alias of [`lock_api::RwLockReadGuard`].
+pub type RwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, RawRwLock, T>;
+/// An alias of [`lock_api::RwLockWriteGuard`].
+pub type RwLockWriteGuard<'a, T> = lock_api::RwLockWriteGuard<'a, RawRwLock, T>;
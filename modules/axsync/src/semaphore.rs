@@ -0,0 +1,53 @@
+//! A counting semaphore.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+
+/// A counting semaphore.
+///
+/// It holds a number of permits. [`acquire`](Semaphore::acquire) takes one,
+/// blocking the current task in a wait queue while there is none, and
+/// [`release`](Semaphore::release) gives one back.
+pub struct Semaphore {
+    wq: WaitQueue,
+    count: AtomicUsize,
+}
+
+impl Semaphore {
+    /// Creates a new semaphore with `count` permits.
+    pub const fn new(count: usize) -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            count: AtomicUsize::new(count),
+        }
+    }
+
+    /// Takes a permit, blocking until one is available.
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            self.wq
+                .wait_until(|| self.count.load(Ordering::Relaxed) > 0);
+        }
+    }
+
+    /// Takes a permit if one is available, and returns whether it did.
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Gives back a permit, waking up a task waiting for it.
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.wq.notify_one(true);
+    }
+
+    /// Returns the number of available permits.
+    pub fn available_permits(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,141 @@
+//! Spin locks of the [`kspin`] crate, checked by the lock dependency checker.
+//!
+//! They have the same interface as those of [`kspin`], which they wrap. The
+//! place where a lock is created stands for its class in the checker.
+
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+
+use kernel_guard::{BaseGuard, NoOp, NoPreempt, NoPreemptIrqSave};
+
+use crate::lockdep::{self, LockClass, LockKind};
+
+/// A spin lock, which also disables what the guard `G` does while held.
+pub struct BaseSpinLock<G: BaseGuard, T: ?Sized> {
+    class: LockClass,
+    lock: kspin::BaseSpinLock<G, T>,
+}
+
+/// A guard that provides mutable data access, and releases the lock when
+/// dropped.
+pub struct BaseSpinLockGuard<'a, G: BaseGuard, T: ?Sized + 'a> {
+    guard: kspin::BaseSpinLockGuard<'a, G, T>,
+    addr: usize,
+}
+
+/// A spin lock that disables kernel preemption while held.
+pub type SpinNoPreempt<T> = BaseSpinLock<NoPreempt, T>;
+/// A guard of [`SpinNoPreempt`].
+pub type SpinNoPreemptGuard<'a, T> = BaseSpinLockGuard<'a, NoPreempt, T>;
+
+/// A spin lock that disables kernel preemption and local IRQs while held.
+pub type SpinNoIrq<T> = BaseSpinLock<NoPreemptIrqSave, T>;
+/// A guard of [`SpinNoIrq`].
+pub type SpinNoIrqGuard<'a, T> = BaseSpinLockGuard<'a, NoPreemptIrqSave, T>;
+
+/// A raw spin lock that does nothing while held.
+pub type SpinRaw<T> = BaseSpinLock<NoOp, T>;
+/// A guard of [`SpinRaw`].
+pub type SpinRawGuard<'a, T> = BaseSpinLockGuard<'a, NoOp, T>;
+
+impl<G: BaseGuard, T> BaseSpinLock<G, T> {
+    /// Creates a new [`BaseSpinLock`] wrapping the supplied data.
+    #[inline(always)]
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self {
+            class: Location::caller(),
+            lock: kspin::BaseSpinLock::new(data),
+        }
+    }
+
+    /// Consumes this [`BaseSpinLock`] and unwraps the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        self.lock.into_inner()
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> BaseSpinLock<G, T> {
+    fn addr(&self) -> usize {
+        self as *const Self as *const u8 as usize
+    }
+
+    /// Locks the [`BaseSpinLock`] and returns a guard that permits access to
+    /// the inner data.
+    #[inline(always)]
+    pub fn lock(&self) -> BaseSpinLockGuard<'_, G, T> {
+        lockdep::acquire(Some(self.class), self.addr(), LockKind::Spin, false);
+        BaseSpinLockGuard {
+            guard: self.lock.lock(),
+            addr: self.addr(),
+        }
+    }
+
+    /// Tries to lock this [`BaseSpinLock`], returning a guard if it succeeds.
+    #[inline(always)]
+    pub fn try_lock(&self) -> Option<BaseSpinLockGuard<'_, G, T>> {
+        let guard = self.lock.try_lock()?;
+        lockdep::acquire(Some(self.class), self.addr(), LockKind::Spin, true);
+        Some(BaseSpinLockGuard {
+            guard,
+            addr: self.addr(),
+        })
+    }
+
+    /// Returns `true` if the lock is currently held.
+    #[inline(always)]
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_locked()
+    }
+
+    /// Returns a mutable reference to the underlying data, which needs no
+    /// locking as the borrow is unique.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.lock.get_mut()
+    }
+}
+
+impl<G: BaseGuard, T: Default> Default for BaseSpinLock<G, T> {
+    #[inline(always)]
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized + fmt::Debug> fmt::Debug for BaseSpinLock<G, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.lock.fmt(f)
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> Deref for BaseSpinLockGuard<'_, G, T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> DerefMut for BaseSpinLockGuard<'_, G, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized + fmt::Debug> fmt::Debug for BaseSpinLockGuard<'_, G, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> Drop for BaseSpinLockGuard<'_, G, T> {
+    fn drop(&mut self) {
+        lockdep::release(self.addr);
+    }
+}
@@ -0,0 +1,195 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Once;
+
+use axtask::current;
+use lock_api::RawMutex as _;
+
+use crate::{Barrier, Condvar, PiMutex, RawMutex, RwLock, Semaphore};
+
+pub(crate) static INIT: Once = Once::new();
+/// The tests share the run queue, so they must not run at the same time.
+pub(crate) static SERIAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Yields until `done` returns `true`, and fails if it does not in time.
+fn yield_until(done: impl Fn() -> bool) {
+    for _ in 0..1000 {
+        if done() {
+            return;
+        }
+        axtask::yield_now();
+    }
+    panic!("tasks did not finish");
+}
+
+#[test]
+fn test_rwlock_writer_preference() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static LOCK: RwLock<u32> = RwLock::new(0);
+    static FINISHED_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+    let guard = LOCK.read();
+    axtask::spawn(|| {
+        *LOCK.write() += 1;
+        FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
+    });
+    axtask::yield_now(); // the writer blocks on the read lock
+
+    // New readers wait behind the writer.
+    assert!(LOCK.try_read().is_none());
+    axtask::spawn(|| {
+        assert_eq!(*LOCK.read(), 1);
+        FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
+    });
+    axtask::yield_now();
+    drop(guard);
+
+    yield_until(|| FINISHED_TASKS.load(Ordering::Relaxed) == 2);
+    assert_eq!(*LOCK.read(), 1);
+}
+
+#[test]
+fn test_condvar_notify_before_sleep() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    /// A mutex that yields right after unlocking, so that the notifier runs
+    /// after the waiter unlocks the mutex, and before it sleeps.
+    struct RawYieldMutex(RawMutex);
+
+    unsafe impl lock_api::RawMutex for RawYieldMutex {
+        const INIT: Self = Self(RawMutex::new());
+
+        type GuardMarker = lock_api::GuardSend;
+
+        fn lock(&self) {
+            self.0.lock();
+        }
+
+        fn try_lock(&self) -> bool {
+            self.0.try_lock()
+        }
+
+        unsafe fn unlock(&self) {
+            unsafe { self.0.unlock() };
+            axtask::yield_now();
+        }
+    }
+
+    static READY: lock_api::Mutex<RawYieldMutex, bool> = lock_api::Mutex::new(false);
+    static CV: Condvar = Condvar::new();
+    static WOKEN: AtomicBool = AtomicBool::new(false);
+
+    axtask::spawn(|| {
+        let ready = CV.wait_while(READY.lock(), |ready| !*ready);
+        assert!(*ready);
+        WOKEN.store(true, Ordering::Relaxed);
+    });
+    axtask::spawn(|| {
+        let mut ready = READY.lock();
+        *ready = true;
+        CV.notify_one();
+    });
+
+    yield_until(|| WOKEN.load(Ordering::Relaxed));
+}
+
+#[test]
+fn test_semaphore_counting() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_TASKS: usize = 5;
+    const NUM_PERMITS: usize = 2;
+    static SEM: Semaphore = Semaphore::new(NUM_PERMITS);
+    static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+    static MAX_ACTIVE: AtomicUsize = AtomicUsize::new(0);
+    static FINISHED_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+    assert!(SEM.try_acquire());
+    assert!(SEM.try_acquire());
+    assert!(!SEM.try_acquire());
+    assert_eq!(SEM.available_permits(), 0);
+    SEM.release();
+    SEM.release();
+    assert_eq!(SEM.available_permits(), NUM_PERMITS);
+
+    for _ in 0..NUM_TASKS {
+        axtask::spawn(|| {
+            SEM.acquire();
+            let active = ACTIVE.fetch_add(1, Ordering::Relaxed) + 1;
+            MAX_ACTIVE.fetch_max(active, Ordering::Relaxed);
+            for _ in 0..3 {
+                axtask::yield_now();
+            }
+            ACTIVE.fetch_sub(1, Ordering::Relaxed);
+            SEM.release();
+            FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    yield_until(|| FINISHED_TASKS.load(Ordering::Relaxed) == NUM_TASKS);
+    assert_eq!(MAX_ACTIVE.load(Ordering::Relaxed), NUM_PERMITS);
+    assert_eq!(SEM.available_permits(), NUM_PERMITS);
+}
+
+#[test]
+fn test_barrier_leader() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_TASKS: usize = 5;
+    const NUM_ROUNDS: usize = 3;
+    static BARRIER: Barrier = Barrier::new(NUM_TASKS);
+    static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+    static LEADERS: AtomicUsize = AtomicUsize::new(0);
+    static FINISHED_TASKS: AtomicUsize = AtomicUsize::new(0);
+
+    for _ in 0..NUM_TASKS {
+        axtask::spawn(|| {
+            for round in 1..=NUM_ROUNDS {
+                ARRIVED.fetch_add(1, Ordering::Relaxed);
+                if BARRIER.wait().is_leader() {
+                    LEADERS.fetch_add(1, Ordering::Relaxed);
+                }
+                // Nobody passes the barrier before all tasks arrive.
+                assert!(ARRIVED.load(Ordering::Relaxed) >= round * NUM_TASKS);
+            }
+            FINISHED_TASKS.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    yield_until(|| FINISHED_TASKS.load(Ordering::Relaxed) == NUM_TASKS);
+    // One leader each time the barrier opens.
+    assert_eq!(LEADERS.load(Ordering::Relaxed), NUM_ROUNDS);
+}
+
+#[test]
+fn test_pi_mutex_priority_restore() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static LOCK: PiMutex<()> = PiMutex::new(());
+    static FINISHED: AtomicBool = AtomicBool::new(false);
+
+    let base = current().priority();
+    let high = base - 10;
+
+    let guard = LOCK.lock();
+    axtask::spawn(move || {
+        // The FIFO scheduler of the tests has no priorities to set, so the
+        // waiter raises its own with an inherited one.
+        axtask::inherit_priority(current().as_task_ref(), high);
+        drop(LOCK.lock());
+        FINISHED.store(true, Ordering::Relaxed);
+    });
+    axtask::yield_now(); // the waiter blocks, lending its priority
+
+    assert_eq!(current().priority(), high);
+    assert_eq!(current().base_priority(), base);
+    drop(guard);
+    assert_eq!(current().priority(), base);
+
+    yield_until(|| FINISHED.load(Ordering::Relaxed));
+}
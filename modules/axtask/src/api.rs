@@ -7,8 +7,18 @@ use alloc::{
 
 use kernel_guard::NoPreemptIrqSave;
 
+pub use crate::run_queue::{LOAD_SCALE, RunQueueStats};
 pub(crate) use crate::run_queue::{current_run_queue, select_run_queue};
+use crate::task::NO_INHERITED_PRIO;
 
+#[cfg(feature = "lockdep")]
+pub use crate::run_queue::LockdepIf;
+#[cfg(feature = "sched_classes")]
+pub use crate::sched::{MAX_RT_PRIO, SchedAttr, SchedAttrError, SchedPolicy};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::stats::TaskStats;
+#[cfg(feature = "stack-guard")]
+pub use crate::task::KernelStackIf;
 #[doc(cfg(feature = "multitask"))]
 pub use crate::task::{CurrentTask, TaskId, TaskInner};
 #[doc(cfg(feature = "multitask"))]
@@ -28,7 +38,10 @@ pub use crate::task::TaskState;
 pub type AxCpuMask = cpumask::CpuMask<{ axconfig::SMP }>;
 
 cfg_if::cfg_if! {
-    if #[cfg(feature = "sched_rr")] {
+    if #[cfg(feature = "sched_classes")] {
+        pub(crate) type AxTask = scheduler::CFSTask<TaskInner>;
+        pub(crate) type Scheduler = crate::sched::ClassScheduler;
+    } else if #[cfg(feature = "sched_rr")] {
         const MAX_TIME_SLICE: usize = 5;
         pub(crate) type AxTask = scheduler::RRTask<TaskInner, MAX_TIME_SLICE>;
         pub(crate) type Scheduler = scheduler::RRScheduler<TaskInner, MAX_TIME_SLICE>;
@@ -94,17 +107,40 @@ pub fn init_scheduler_secondary() {
     crate::timers::init();
 }
 
-/// Handles periodic timer ticks for the task manager.
+/// Handles timer interrupts for the task manager.
+///
+/// For example, checks timed events, advances scheduler states on periodic
+/// ticks, and programs the timer for the next event or tick. The ticks stop
+/// while the CPU is idle.
 ///
-/// For example, advance scheduler states, checks timed events, etc.
+/// Returns whether a periodic tick was due.
 #[cfg(feature = "irq")]
 #[doc(cfg(feature = "irq"))]
-pub fn on_timer_tick() {
+pub fn on_timer_tick() -> bool {
     use kernel_guard::NoOp;
+    let now = axhal::time::monotonic_time_nanos();
+    let tick_due = crate::timers::tick_due(now);
     crate::timers::check_events();
     // Since irq and preemption are both disabled here,
     // we can get current run queue with the default `kernel_guard::NoOp`.
-    current_run_queue::<NoOp>().scheduler_timer_tick();
+    let mut rq = current_run_queue::<NoOp>();
+    if tick_due {
+        rq.scheduler_timer_tick();
+    }
+    crate::timers::reprogram(now, rq.needs_tick());
+    tick_due
+}
+
+/// Handles the inter-processor interrupts that other CPUs send to wake up
+/// this one, when they give it tasks while it idles.
+///
+/// The interrupt itself ends the wait for interrupts. The ticks start again
+/// in case it came just before the wait, so that the CPU does not sleep with
+/// tasks ready.
+#[cfg(all(feature = "smp", feature = "irq"))]
+#[doc(cfg(all(feature = "smp", feature = "irq")))]
+pub fn on_ipi() {
+    crate::timers::restart_tick();
 }
 
 /// Adds the given task to the run queue, returns the task reference.
@@ -150,6 +186,100 @@ pub fn set_priority(prio: isize) -> bool {
     current_run_queue::<NoPreemptIrqSave>().set_current_priority(prio)
 }
 
+/// Set the priority of `task`, which may be running on another CPU, as
+/// [`set_priority`] does for the current task.
+///
+/// Returns `true` if the priority is set successfully.
+pub fn set_task_priority(task: &AxTaskRef, prio: isize) -> bool {
+    current_run_queue::<NoPreemptIrqSave>().set_base_priority(task, prio)
+}
+
+/// Lends the priority `prio` to `task`, if it is higher than the task's own,
+/// e.g., when the current task blocks on a priority inheritance lock held by
+/// `task`. Lower values are higher priorities.
+///
+/// The task keeps the priority until it releases all its priority
+/// inheritance locks. The priority is not passed on to the owners of the
+/// locks `task` itself waits for.
+pub fn inherit_priority(task: &AxTaskRef, prio: isize) {
+    let old = task.raise_inherited_priority(prio);
+    if prio < old && prio < task.base_priority() {
+        current_run_queue::<NoPreemptIrqSave>().set_task_priority(task, prio);
+    }
+}
+
+/// Lends the priority of `waiter`, which blocks on a priority inheritance
+/// lock held by `task`, to `task`, as [`inherit_priority`] does.
+///
+/// With the scheduling classes, `task` also inherits the scheduling class and
+/// real-time priority or deadline of `waiter`, if they outrank its own, and
+/// runs by them even if it is ready in the queue of a lower class.
+pub fn inherit_from(task: &AxTaskRef, waiter: &TaskInner) {
+    inherit_priority(task, waiter.priority());
+    #[cfg(feature = "sched_classes")]
+    {
+        let _guard = NoPreemptIrqSave::new();
+        let attr = waiter.sched_entity().lock().effective();
+        if task.sched_entity().lock().inherit(attr) {
+            crate::run_queue::requeue_task(task);
+        }
+    }
+}
+
+/// Records that the current task acquired a priority inheritance lock.
+pub fn pi_lock_acquired() {
+    current().inc_pi_locks();
+}
+
+/// Records that the current task released a priority inheritance lock.
+///
+/// When it holds no more of them, it drops the priority it inherited.
+pub fn pi_lock_released() {
+    let curr = current();
+    if curr.dec_pi_locks() != 0 {
+        return;
+    }
+    if curr.clear_inherited_priority() != NO_INHERITED_PRIO {
+        current_run_queue::<NoPreemptIrqSave>()
+            .set_task_priority(curr.as_task_ref(), curr.base_priority());
+    }
+    // The task runs in the class it inherited until it is put back in its
+    // run queue, at the next tick at the latest.
+    #[cfg(feature = "sched_classes")]
+    if curr.sched_entity().lock().clear_inherited() {
+        #[cfg(feature = "preempt")]
+        curr.set_preempt_pending(true);
+    }
+}
+
+/// Sets the scheduling attributes of `task`.
+///
+/// They take effect the next time the task is put in a run queue, so the
+/// current task yields if it is `task`.
+///
+/// Fails if the attributes are not valid, or if the bandwidths of the
+/// deadline tasks would add up to more than that of a CPU. The bandwidth of
+/// a deadline task is released when it exits.
+#[cfg(feature = "sched_classes")]
+pub fn set_sched_attr(task: &AxTaskRef, attr: SchedAttr) -> Result<(), SchedAttrError> {
+    if !attr.is_valid() {
+        return Err(SchedAttrError::Invalid);
+    }
+    if !task.set_sched_attr(attr) {
+        return Err(SchedAttrError::Busy);
+    }
+    if current().id() == task.id() {
+        yield_now();
+    }
+    Ok(())
+}
+
+/// Returns the load statistics of the run queue of the CPU `cpu_id`, or
+/// [`None`] if the CPU is not started.
+pub fn run_queue_stats(cpu_id: usize) -> Option<RunQueueStats> {
+    crate::run_queue::run_queue_stats(cpu_id)
+}
+
 /// Set the affinity for the current task.
 /// [`AxCpuMask`] is used to specify the CPU affinity.
 /// Returns `true` if the affinity is set successfully.
@@ -11,8 +11,16 @@
 //!   Otherwise, only a few APIs with naive implementation is available.
 //! - `irq`: Interrupts are enabled. If this feature is enabled, timer-based
 //!   APIs can be used, such as [`sleep`], [`sleep_until`], and
-//!   [`WaitQueue::wait_timeout`].
+//!   [`WaitQueue::wait_timeout`]. The timer is programmed for the next
+//!   deadline, and does not tick on an idle CPU. With `smp`, the other CPUs
+//!   wake it up with an IPI when they give it tasks.
 //! - `preempt`: Enable preemptive scheduling.
+//! - `stack-guard`: Map task stacks with an unmapped guard page below each,
+//!   through the [`KernelStackIf`] implemented by the memory management
+//!   module, so that stack overflows fault. Only x86_64 reports them on a
+//!   separate stack, elsewhere the fault handler itself overflows.
+//! - `lockdep`: Call the [`LockdepIf`] implemented by the lock dependency
+//!   checker before a task blocks, to report the locks it holds.
 //! - `sched_fifo`: Use the [FIFO cooperative scheduler][1]. It also enables the
 //!   `multitask` feature if it is enabled. This feature is enabled by default,
 //!   and it can be overriden by other scheduler features.
@@ -20,10 +28,15 @@
 //!   the `multitask` and `preempt` features if it is enabled.
 //! - `sched_cfs`: Use the [Completely Fair Scheduler][3]. It also enables the
 //!   the `multitask` and `preempt` features if it is enabled.
+//! - `sched_classes`: Use the [deadline, real-time and fair scheduling
+//!   classes][4], with a policy set for each task by [`set_sched_attr`]. It
+//!   overrides the other scheduler features, and also enables the `multitask`
+//!   and `preempt` features.
 //!
 //! [1]: scheduler::FifoScheduler
 //! [2]: scheduler::RRScheduler
 //! [3]: scheduler::CFScheduler
+//! [4]: SchedPolicy
 
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
@@ -44,10 +57,13 @@ cfg_if::cfg_if! {
         mod task;
         mod task_ext;
         mod api;
+        mod stats;
         mod wait_queue;
 
         #[cfg(feature = "irq")]
         mod timers;
+        #[cfg(feature = "sched_classes")]
+        mod sched;
 
         #[doc(cfg(feature = "multitask"))]
         pub use self::api::*;
@@ -1,6 +1,7 @@
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 #[cfg(feature = "smp")]
 use alloc::sync::Weak;
@@ -39,6 +40,30 @@ percpu_static! {
     PREV_TASK: Weak<crate::AxTask> = Weak::new(),
 }
 
+/// The interface to check the locks held by the current task before it
+/// blocks, implemented by the lock dependency checker.
+#[cfg(feature = "lockdep")]
+#[crate_interface::def_interface]
+pub trait LockdepIf {
+    /// Called before the current task blocks or sleeps.
+    fn check_blocking();
+
+    /// Called before the spin lock at `addr` of a [`WaitQueue`] created at
+    /// `class` is acquired, or after it was taken if `try_lock` is set.
+    ///
+    /// [`WaitQueue`]: crate::WaitQueue
+    fn acquire_spin(
+        class: Option<&'static core::panic::Location<'static>>,
+        addr: usize,
+        try_lock: bool,
+    );
+
+    /// Called when the lock at `addr` of a [`WaitQueue`] is released.
+    ///
+    /// [`WaitQueue`]: crate::WaitQueue
+    fn release(addr: usize);
+}
+
 /// An array of references to run queues, one for each CPU, indexed by cpu_id.
 ///
 /// This static variable holds references to the run queues for each CPU in the system.
@@ -53,6 +78,83 @@ static mut RUN_QUEUES: [MaybeUninit<&'static mut AxRunQueue>; axconfig::SMP] =
 #[allow(clippy::declare_interior_mutable_const)] // It's ok because it's used only for initialization `RUN_QUEUES`.
 const ARRAY_REPEAT_VALUE: MaybeUninit<&'static mut AxRunQueue> = MaybeUninit::uninit();
 
+/// Whether the run queue of each CPU in [`RUN_QUEUES`] is initialized.
+static RUN_QUEUES_ONLINE: [AtomicBool; axconfig::SMP] =
+    [const { AtomicBool::new(false) }; axconfig::SMP];
+
+/// The scale of the fixed-point [`RunQueueStats::load_avg`].
+pub const LOAD_SCALE: usize = 1024;
+
+/// The number of timer ticks between two periodic load balancing on a CPU.
+#[cfg(feature = "smp")]
+const BALANCE_INTERVAL_TICKS: usize = 10;
+/// A task that ran within this time, in nanoseconds, is not migrated unless
+/// balancing keeps failing, since its data is likely still in the caches of
+/// its CPU.
+#[cfg(feature = "smp")]
+pub(crate) const MIGRATION_COST_NS: u64 = 500_000;
+/// The number of failed load balancing before cache-hot tasks are migrated
+/// too.
+#[cfg(feature = "smp")]
+const CACHE_NICE_TRIES: usize = 2;
+/// The number of ready tasks looked at to find one to migrate.
+#[cfg(feature = "smp")]
+const MIGRATION_SCAN_MAX: usize = 8;
+
+/// The load statistics of a run queue.
+#[derive(Debug, Clone, Copy)]
+pub struct RunQueueStats {
+    /// The number of ready tasks, plus one if a task other than the idle task
+    /// is running.
+    pub nr_running: usize,
+    /// The average of `nr_running` over the recent timer ticks, decaying by
+    /// 1/8 on each, in units of 1/[`LOAD_SCALE`].
+    pub load_avg: usize,
+    /// The number of tasks pulled from other run queues by load balancing.
+    pub nr_migrations: usize,
+}
+
+/// Taking a ready task out of a scheduler, to run it on another CPU.
+#[cfg(feature = "smp")]
+pub(crate) trait MigrateTask {
+    /// Takes a ready task accepted by `can_migrate`, looking at no more than
+    /// `max` tasks.
+    fn take_migratable(
+        &mut self,
+        max: usize,
+        can_migrate: impl FnMut(&AxTaskRef) -> bool,
+    ) -> Option<AxTaskRef>;
+}
+
+#[cfg(all(feature = "smp", not(feature = "sched_classes")))]
+impl MigrateTask for Scheduler {
+    fn take_migratable(
+        &mut self,
+        max: usize,
+        mut can_migrate: impl FnMut(&AxTaskRef) -> bool,
+    ) -> Option<AxTaskRef> {
+        // The schedulers only give out their next task, so the tasks passed
+        // over are put back, which may change the order of those of equal
+        // priorities.
+        let mut passed = alloc::vec::Vec::new();
+        let mut taken = None;
+        for _ in 0..max {
+            let Some(task) = self.pick_next_task() else {
+                break;
+            };
+            if can_migrate(&task) {
+                taken = Some(task);
+                break;
+            }
+            passed.push(task);
+        }
+        for task in passed {
+            self.put_prev_task(task, false);
+        }
+        taken
+    }
+}
+
 /// Returns a reference to the current run queue in [`CurrentRunQueueRef`].
 ///
 /// ## Safety
@@ -79,7 +181,8 @@ pub(crate) fn current_run_queue<G: BaseGuard>() -> CurrentRunQueueRef<'static, G
 /// Selects the run queue index based on a CPU set bitmap and load balancing.
 ///
 /// This function filters the available run queues based on the provided `cpumask` and
-/// selects the run queue index for the next task. The selection is based on a round-robin algorithm.
+/// selects the run queue index for the next task. The least loaded run queue is selected,
+/// and the ties are broken by a round-robin algorithm.
 ///
 /// ## Arguments
 ///
@@ -98,20 +201,84 @@ pub(crate) fn current_run_queue<G: BaseGuard>() -> CurrentRunQueueRef<'static, G
 #[allow(clippy::modulo_one)]
 #[inline]
 fn select_run_queue_index(cpumask: AxCpuMask) -> usize {
-    use core::sync::atomic::{AtomicUsize, Ordering};
     static RUN_QUEUE_INDEX: AtomicUsize = AtomicUsize::new(0);
 
     assert!(!cpumask.is_empty(), "No available CPU for task execution");
 
-    // Round-robin selection of the run queue index.
-    loop {
-        let index = RUN_QUEUE_INDEX.fetch_add(1, Ordering::SeqCst) % axconfig::SMP;
-        if cpumask.get(index) {
-            return index;
+    // Start from a round-robin index, so that the ties are spread.
+    let start = RUN_QUEUE_INDEX.fetch_add(1, Ordering::SeqCst) % axconfig::SMP;
+    least_loaded_index(
+        axconfig::SMP,
+        start,
+        |index| cpumask.get(index),
+        |index| try_get_run_queue(index).map_or(usize::MAX, |rq| rq.nr_running()),
+    )
+    .unwrap()
+}
+
+/// Returns the index of the run queue with the fewest running tasks, as
+/// told by `nr_running`, among the `nr_cpus` ones that are `allowed`.
+///
+/// The ties go to the first one from `start` on.
+#[cfg(any(feature = "smp", test))]
+pub(crate) fn least_loaded_index(
+    nr_cpus: usize,
+    start: usize,
+    allowed: impl Fn(usize) -> bool,
+    nr_running: impl Fn(usize) -> usize,
+) -> Option<usize> {
+    (0..nr_cpus)
+        .map(|i| (start + i) % nr_cpus)
+        .filter(|&index| allowed(index))
+        .min_by_key(|&index| nr_running(index))
+}
+
+/// Whether the ready `task` may be pulled to the CPU `cpu_id` by load
+/// balancing at `now`: it must be allowed there, done with its scheduling
+/// process, and not cache-hot unless `allow_hot`.
+#[cfg(feature = "smp")]
+pub(crate) fn can_migrate(task: &AxTaskRef, cpu_id: usize, now: u64, allow_hot: bool) -> bool {
+    task.cpumask().get(cpu_id)
+        && !task.on_cpu()
+        && (allow_hot || !task.is_cache_hot(now, MIGRATION_COST_NS))
+}
+
+/// Returns the run queue of the CPU `index`, if it is initialized.
+#[inline]
+fn try_get_run_queue(index: usize) -> Option<&'static AxRunQueue> {
+    if index < axconfig::SMP && RUN_QUEUES_ONLINE[index].load(Ordering::Acquire) {
+        Some(unsafe { &**RUN_QUEUES[index].assume_init_ref() })
+    } else {
+        None
+    }
+}
+
+/// Returns the load statistics of the run queue of the CPU `cpu_id`, or
+/// [`None`] if it is not initialized.
+pub(crate) fn run_queue_stats(cpu_id: usize) -> Option<RunQueueStats> {
+    try_get_run_queue(cpu_id).map(AxRunQueue::stats)
+}
+
+/// Puts the ready `task` back in its run queue, wherever it is, so that the
+/// scheduling attributes it inherited take effect. IRQs must be disabled.
+#[cfg(feature = "sched_classes")]
+pub(crate) fn requeue_task(task: &AxTaskRef) {
+    for rq in (0..axconfig::SMP).filter_map(try_get_run_queue) {
+        if rq.scheduler.lock().requeue(task) {
+            break;
         }
     }
 }
 
+/// Finds the run queue with the most tasks other than that of `cpu_id`.
+#[cfg(feature = "smp")]
+fn find_busiest_run_queue(cpu_id: usize) -> Option<&'static AxRunQueue> {
+    (0..axconfig::SMP)
+        .filter(|&index| index != cpu_id)
+        .filter_map(try_get_run_queue)
+        .max_by_key(|rq| (rq.nr_running(), rq.load_avg.load(Ordering::Relaxed)))
+}
+
 /// Retrieves a `'static` reference to the run queue corresponding to the given index.
 ///
 /// This function asserts that the provided index is within the range of available CPUs
@@ -148,10 +315,8 @@ fn get_run_queue(index: usize) -> &'static mut AxRunQueue {
 ///
 /// * [`AxRunQueueRef`] - a static reference to the selected [`AxRunQueue`] (current or remote).
 ///
-/// ## TODO
-///
-/// 1. Implement better load balancing across CPUs for more efficient task distribution.
-/// 2. Use a more generic load balancing algorithm that can be customized or replaced.
+/// Tasks are also moved between run queues later, by the load balancing in
+/// the scheduler timer tick and when a CPU is about to idle.
 ///
 #[inline]
 pub(crate) fn select_run_queue<G: BaseGuard>(task: &AxTaskRef) -> AxRunQueueRef<'static, G> {
@@ -186,6 +351,20 @@ pub(crate) struct AxRunQueue {
     /// Since irq and preempt are preserved by the kernel guard hold by `AxRunQueueRef`,
     /// we just use a simple raw spin lock here.
     scheduler: SpinRaw<Scheduler>,
+    /// The number of ready tasks in the scheduler.
+    nr_ready: AtomicUsize,
+    /// Whether a task other than the idle task is running.
+    busy: AtomicBool,
+    /// See [`RunQueueStats::load_avg`].
+    load_avg: AtomicUsize,
+    /// See [`RunQueueStats::nr_migrations`].
+    nr_migrations: AtomicUsize,
+    /// The number of timer ticks on this CPU.
+    #[cfg(feature = "smp")]
+    ticks: usize,
+    /// The number of load balancing in a row that found no task to migrate.
+    #[cfg(feature = "smp")]
+    balance_failed: usize,
 }
 
 /// A reference to the run queue with specific guard.
@@ -237,7 +416,12 @@ impl<G: BaseGuard> AxRunQueueRef<'_, G> {
             self.inner.cpu_id
         );
         assert!(task.is_ready());
+        task.sched_stats()
+            .set_ready(axhal::time::monotonic_time_nanos());
         self.inner.scheduler.lock().add_task(task);
+        self.inner.nr_ready.fetch_add(1, Ordering::Relaxed);
+        #[cfg(all(feature = "smp", feature = "irq"))]
+        self.inner.kick();
     }
 
     /// Unblock one task by inserting it into the run queue.
@@ -259,11 +443,13 @@ impl<G: BaseGuard> AxRunQueueRef<'_, G> {
             let cpu_id = self.inner.cpu_id;
             debug!("task unblock: {} on run_queue {}", task_id_name, cpu_id);
             // Note: when the task is unblocked on another CPU's run queue,
-            // we just ingiore the `resched` flag.
+            // we just ingiore the `resched` flag, but wake up that CPU if it idles.
             if resched && cpu_id == this_cpu_id() {
                 #[cfg(feature = "preempt")]
                 crate::current().set_preempt_pending(true);
             }
+            #[cfg(all(feature = "smp", feature = "irq"))]
+            self.inner.kick();
         }
     }
 }
@@ -273,10 +459,33 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
     #[cfg(feature = "irq")]
     pub fn scheduler_timer_tick(&mut self) {
         let curr = &self.current_task;
+        self.inner.update_load_avg();
         if !curr.is_idle() && self.inner.scheduler.lock().task_tick(curr.as_task_ref()) {
             #[cfg(feature = "preempt")]
             curr.set_preempt_pending(true);
         }
+
+        // Balance periodically, at different ticks on different CPUs.
+        #[cfg(feature = "smp")]
+        {
+            self.inner.ticks += 1;
+            if self.inner.ticks % BALANCE_INTERVAL_TICKS
+                == self.inner.cpu_id % BALANCE_INTERVAL_TICKS
+                && self.inner.balance(self.inner.nr_running())
+                && curr.is_idle()
+            {
+                #[cfg(feature = "preempt")]
+                curr.set_preempt_pending(true);
+            }
+        }
+    }
+
+    /// Whether this CPU needs the periodic ticks: while it runs a task or has
+    /// tasks ready. Other CPUs wake it up with an IPI when they give it tasks
+    /// while it idles.
+    #[cfg(feature = "irq")]
+    pub fn needs_tick(&self) -> bool {
+        !self.current_task.is_idle() || self.inner.nr_ready.load(Ordering::Relaxed) > 0
     }
 
     /// Yield the current task and reschedule.
@@ -366,6 +575,9 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
             axhal::misc::terminate();
         } else {
             curr.set_state(TaskState::Exited);
+            // Release the bandwidth of a deadline task.
+            #[cfg(feature = "sched_classes")]
+            curr.set_sched_attr(crate::SchedAttr::normal());
 
             // Notify the joiner task.
             curr.notify_exit(exit_code);
@@ -410,6 +622,9 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
         wq_guard.push_back(curr.clone());
         // Drop the lock of wait queue explictly.
         drop(wq_guard);
+        // Only then, as the lock of the wait queue is a spin lock itself.
+        #[cfg(feature = "lockdep")]
+        crate_interface::call_interface!(LockdepIf::check_blocking());
 
         // Current task's state has been changed to `Blocked` and added to the wait queue.
         // Note that the state may have been set as `Ready` in `unblock_task()`,
@@ -426,6 +641,9 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
         assert!(curr.is_running());
         assert!(!curr.is_idle());
 
+        #[cfg(feature = "lockdep")]
+        crate_interface::call_interface!(LockdepIf::check_blocking());
+
         let now = axhal::time::wall_time();
         if now < deadline {
             crate::timers::set_alarm_wakeup(deadline, curr.clone());
@@ -435,10 +653,32 @@ impl<G: BaseGuard> CurrentRunQueueRef<'_, G> {
     }
 
     pub fn set_current_priority(&mut self, prio: isize) -> bool {
-        self.inner
-            .scheduler
-            .lock()
-            .set_priority(self.current_task.as_task_ref(), prio)
+        let curr = self.current_task.as_task_ref().clone();
+        self.set_base_priority(&curr, prio)
+    }
+
+    /// Sets the priority of `task`, which may be on another run queue, as
+    /// set by [`set_priority`](crate::set_priority).
+    pub fn set_base_priority(&mut self, task: &AxTaskRef, prio: isize) -> bool {
+        let mut scheduler = self.inner.scheduler.lock();
+        if !scheduler.set_priority(task, prio) {
+            return false;
+        }
+        task.set_base_priority(prio);
+        // Keep a higher inherited priority in effect.
+        if task.priority() < prio {
+            scheduler.set_priority(task, task.priority());
+        }
+        true
+    }
+
+    /// Sets the effective priority of `task`, which may be on another run
+    /// queue.
+    ///
+    /// The schedulers keep the priority in the task itself, so the scheduler
+    /// of any run queue can update it.
+    pub fn set_task_priority(&mut self, task: &AxTaskRef, prio: isize) -> bool {
+        self.inner.scheduler.lock().set_priority(task, prio)
     }
 }
 
@@ -455,9 +695,93 @@ impl AxRunQueue {
         Self {
             cpu_id,
             scheduler: SpinRaw::new(scheduler),
+            nr_ready: AtomicUsize::new(1),
+            busy: AtomicBool::new(false),
+            load_avg: AtomicUsize::new(0),
+            nr_migrations: AtomicUsize::new(0),
+            #[cfg(feature = "smp")]
+            ticks: 0,
+            #[cfg(feature = "smp")]
+            balance_failed: 0,
         }
     }
 
+    /// Returns the number of ready tasks, plus one if a task other than the
+    /// idle task is running.
+    fn nr_running(&self) -> usize {
+        self.nr_ready.load(Ordering::Relaxed) + self.busy.load(Ordering::Relaxed) as usize
+    }
+
+    /// Wakes up the CPU of this run queue with an IPI if it is another one and
+    /// idles, as it may wait for interrupts without ticks.
+    #[cfg(all(feature = "smp", feature = "irq"))]
+    fn kick(&self) {
+        if self.cpu_id != this_cpu_id() && !self.busy.load(Ordering::Relaxed) {
+            axhal::irq::send_ipi(self.cpu_id);
+        }
+    }
+
+    fn stats(&self) -> RunQueueStats {
+        RunQueueStats {
+            nr_running: self.nr_running(),
+            load_avg: self.load_avg.load(Ordering::Relaxed),
+            nr_migrations: self.nr_migrations.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Samples the number of tasks into the load average, on a timer tick.
+    #[cfg(feature = "irq")]
+    fn update_load_avg(&self) {
+        let load = self.load_avg.load(Ordering::Relaxed);
+        let load = (load * 7 + self.nr_running() * LOAD_SCALE) / 8;
+        self.load_avg.store(load, Ordering::Relaxed);
+    }
+
+    /// Pulls a ready task from the busiest other run queue, if it has at least
+    /// two tasks more than the `local` ones of this run queue.
+    ///
+    /// Only tasks allowed on this CPU and done with their scheduling process
+    /// are pulled. Tasks that ran very recently are passed over, unless the
+    /// balancing failed a few times in a row.
+    ///
+    /// Returns `true` if a task is pulled.
+    #[cfg(feature = "smp")]
+    fn balance(&mut self, local: usize) -> bool {
+        let Some(busiest) = find_busiest_run_queue(self.cpu_id) else {
+            return false;
+        };
+        if busiest.nr_ready.load(Ordering::Relaxed) == 0 || busiest.nr_running() < local + 2 {
+            return false;
+        }
+
+        let cpu_id = self.cpu_id;
+        let now = axhal::time::monotonic_time_nanos();
+        let allow_hot = self.balance_failed >= CACHE_NICE_TRIES;
+        let task = busiest
+            .scheduler
+            .lock()
+            .take_migratable(MIGRATION_SCAN_MAX, |task| {
+                can_migrate(task, cpu_id, now, allow_hot)
+            });
+        let Some(task) = task else {
+            self.balance_failed += 1;
+            return false;
+        };
+        busiest.nr_ready.fetch_sub(1, Ordering::Relaxed);
+        self.balance_failed = 0;
+
+        debug!(
+            "task migrate: {} from run_queue {} to {}",
+            task.id_name(),
+            busiest.cpu_id,
+            cpu_id
+        );
+        self.scheduler.lock().add_task(task);
+        self.nr_ready.fetch_add(1, Ordering::Relaxed);
+        self.nr_migrations.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
     /// Puts target task into current run queue with `Ready` state
     /// if its state matches `current_state` (except idle task).
     ///
@@ -492,9 +816,12 @@ impl AxRunQueue {
                     // Wait for the task to finish its scheduling process.
                     core::hint::spin_loop();
                 }
+                task.sched_stats()
+                    .set_ready(axhal::time::monotonic_time_nanos());
             }
             // TODO: priority
             self.scheduler.lock().put_prev_task(task, preempt);
+            self.nr_ready.fetch_add(1, Ordering::Relaxed);
             true
         } else {
             false
@@ -504,14 +831,20 @@ impl AxRunQueue {
     /// Core reschedule subroutine.
     /// Pick the next task to run and switch to it.
     fn resched(&mut self) {
-        let next = self
-            .scheduler
-            .lock()
-            .pick_next_task()
-            .unwrap_or_else(|| unsafe {
-                // Safety: IRQs must be disabled at this time.
-                IDLE_TASK.current_ref_raw().get_unchecked().clone()
-            });
+        #[allow(unused_mut)]
+        let mut next = self.scheduler.lock().pick_next_task();
+        // Pull a task from another CPU rather than idle.
+        #[cfg(feature = "smp")]
+        if next.is_none() && self.balance(0) {
+            next = self.scheduler.lock().pick_next_task();
+        }
+        if next.is_some() {
+            self.nr_ready.fetch_sub(1, Ordering::Relaxed);
+        }
+        let next = next.unwrap_or_else(|| unsafe {
+            // Safety: IRQs must be disabled at this time.
+            IDLE_TASK.current_ref_raw().get_unchecked().clone()
+        });
         assert!(
             next.is_ready(),
             "next {} is not ready: {:?}",
@@ -536,10 +869,23 @@ impl AxRunQueue {
         #[cfg(feature = "preempt")]
         next_task.set_preempt_pending(false);
         next_task.set_state(TaskState::Running);
+        self.busy.store(!next_task.is_idle(), Ordering::Relaxed);
         if prev_task.ptr_eq(&next_task) {
             return;
         }
 
+        #[cfg(feature = "irq")]
+        if prev_task.is_idle() {
+            crate::timers::restart_tick();
+        }
+        let now = axhal::time::monotonic_time_nanos();
+        prev_task
+            .sched_stats()
+            .switch_out(now, prev_task.is_ready());
+        next_task.sched_stats().switch_in(self.cpu_id, now);
+        #[cfg(feature = "smp")]
+        prev_task.set_last_ran(now);
+
         // Claim the task as running, we do this before switching to it
         // such that any running task will have this set.
         #[cfg(feature = "smp")]
@@ -603,11 +949,14 @@ fn gc_entry() {
 /// then puts the task to the scheduler of target run queue.
 #[cfg(feature = "smp")]
 pub(crate) fn migrate_entry(migrated_task: AxTaskRef) {
-    select_run_queue::<kernel_guard::NoPreemptIrqSave>(&migrated_task)
-        .inner
+    let rq = select_run_queue::<kernel_guard::NoPreemptIrqSave>(&migrated_task);
+    rq.inner
         .scheduler
         .lock()
-        .put_prev_task(migrated_task, false)
+        .put_prev_task(migrated_task, false);
+    rq.inner.nr_ready.fetch_add(1, Ordering::Relaxed);
+    #[cfg(feature = "irq")]
+    rq.inner.kick();
 }
 
 /// Clear the `on_cpu` field of previous task running on this CPU.
@@ -640,10 +989,13 @@ pub(crate) fn init() {
 
     RUN_QUEUE.with_current(|rq| {
         rq.init_once(AxRunQueue::new(cpu_id));
+        // The `main` task is running.
+        rq.busy.store(true, Ordering::Relaxed);
     });
     unsafe {
         RUN_QUEUES[cpu_id].write(RUN_QUEUE.current_ref_mut_raw());
     }
+    RUN_QUEUES_ONLINE[cpu_id].store(true, Ordering::Release);
 }
 
 pub(crate) fn init_secondary() {
@@ -663,4 +1015,5 @@ pub(crate) fn init_secondary() {
     unsafe {
         RUN_QUEUES[cpu_id].write(RUN_QUEUE.current_ref_mut_raw());
     }
+    RUN_QUEUES_ONLINE[cpu_id].store(true, Ordering::Release);
 }
@@ -0,0 +1,659 @@
+//! Deadline, real-time and fair scheduling classes.
+//!
+//! Each task has a scheduling policy, which puts it in one of three classes.
+//! A ready task of a higher class always runs before those of lower ones:
+//!
+//! 1. Deadline: the task with the earliest absolute deadline runs first
+//!    (EDF). Each task may run for `runtime` in each `period`, before its
+//!    relative `deadline`, and is throttled until its next period once the
+//!    runtime is used up, so that it cannot starve the lower classes. Waking
+//!    tasks follow the rule of the constant bandwidth server (CBS): a new
+//!    deadline is given if the old one cannot be met with the bandwidth.
+//!    The bandwidths (`runtime / period`) of all the deadline tasks may add
+//!    up to that of one CPU at most, as they are not placed on the CPUs by
+//!    their bandwidths.
+//! 2. Real-time: the task of the highest real-time priority runs first, and
+//!    those of the same priority in FIFO order. FIFO tasks run until they
+//!    block or yield; round-robin tasks also give way to the others of the
+//!    same priority after a time slice.
+//! 3. Fair: the other tasks, run by the [`CFScheduler`].
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use axhal::time::monotonic_time_nanos;
+use scheduler::{BaseScheduler, CFScheduler};
+
+use crate::{AxTaskRef, TaskInner, WeakAxTaskRef};
+
+/// The highest real-time priority.
+pub const MAX_RT_PRIO: u32 = 99;
+
+/// The number of timer ticks in a time slice of a round-robin task.
+pub(crate) const RR_TIME_SLICE: usize = 10;
+
+/// The fixed-point shift of bandwidths.
+const BW_SHIFT: u32 = 20;
+/// The bandwidth of a CPU, the most the deadline tasks may take altogether.
+const MAX_DL_BW: u64 = 1 << BW_SHIFT;
+
+/// The total bandwidth of the deadline tasks.
+static DL_TOTAL_BW: AtomicU64 = AtomicU64::new(0);
+
+/// The ID of the next [`ClassScheduler`].
+static NEXT_SCHEDULER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// The scheduling policy of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// Scheduled by the fair class, with the priority set by
+    /// [`set_priority`](crate::set_priority).
+    Normal,
+    /// Real-time, run until it blocks or yields.
+    Fifo,
+    /// Real-time, run for a time slice at a time.
+    RoundRobin,
+    /// Scheduled by the deadline class.
+    Deadline,
+}
+
+/// The reasons [`set_sched_attr`](crate::set_sched_attr) fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedAttrError {
+    /// The attributes are not valid, see [`SchedAttr::is_valid`].
+    Invalid,
+    /// The bandwidth of the deadline tasks would exceed that of a CPU.
+    Busy,
+}
+
+/// The scheduling attributes of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedAttr {
+    /// The scheduling policy.
+    pub policy: SchedPolicy,
+    /// The real-time priority, from 1 to [`MAX_RT_PRIO`] for the real-time
+    /// policies, where higher values are higher priorities, or 0 for the
+    /// others.
+    pub rt_priority: u32,
+    /// The time the task may run in each period, in nanoseconds, for the
+    /// deadline policy.
+    pub runtime: u64,
+    /// The time from the start of each period by which the task must have
+    /// run, in nanoseconds, for the deadline policy.
+    pub deadline: u64,
+    /// The period, in nanoseconds, for the deadline policy.
+    pub period: u64,
+}
+
+impl SchedAttr {
+    /// The attributes of the fair class, those of a new task.
+    pub const fn normal() -> Self {
+        Self {
+            policy: SchedPolicy::Normal,
+            rt_priority: 0,
+            runtime: 0,
+            deadline: 0,
+            period: 0,
+        }
+    }
+
+    /// Returns `true` if the attributes are consistent with the policy.
+    ///
+    /// The deadline policy requires `0 < runtime <= deadline <= period`.
+    pub fn is_valid(&self) -> bool {
+        match self.policy {
+            SchedPolicy::Normal => self.rt_priority == 0,
+            SchedPolicy::Fifo | SchedPolicy::RoundRobin => {
+                (1..=MAX_RT_PRIO).contains(&self.rt_priority)
+            }
+            SchedPolicy::Deadline => {
+                self.rt_priority == 0
+                    && 0 < self.runtime
+                    && self.runtime <= self.deadline
+                    && self.deadline <= self.period
+            }
+        }
+    }
+
+    /// The share of a CPU taken by a deadline task, in units of
+    /// `1 / MAX_DL_BW`, or 0 for the other policies.
+    fn bandwidth(&self) -> u64 {
+        match self.policy {
+            SchedPolicy::Deadline => {
+                ((self.runtime as u128) << BW_SHIFT).div_ceil(self.period as u128) as u64
+            }
+            _ => 0,
+        }
+    }
+
+    /// Whether a task runs before the others with the attributes `other`:
+    /// those of a higher class, of a higher real-time priority, or of a
+    /// shorter relative deadline.
+    fn outranks(&self, other: &Self) -> bool {
+        match (Class::of(self.policy), Class::of(other.policy)) {
+            (Class::Deadline, Class::Deadline) => self.deadline < other.deadline,
+            (Class::RealTime, Class::RealTime) => self.rt_priority > other.rt_priority,
+            (class, other) => class < other,
+        }
+    }
+}
+
+/// The scheduling classes, from the highest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Class {
+    Deadline,
+    RealTime,
+    Fair,
+}
+
+impl Class {
+    fn of(policy: SchedPolicy) -> Self {
+        match policy {
+            SchedPolicy::Deadline => Self::Deadline,
+            SchedPolicy::Fifo | SchedPolicy::RoundRobin => Self::RealTime,
+            SchedPolicy::Normal => Self::Fair,
+        }
+    }
+}
+
+/// The queue of a [`ClassScheduler`] a task is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Queue {
+    Deadline,
+    Throttled,
+    RealTime(u32),
+    Fair,
+}
+
+/// The scheduling state of a task, kept by the task itself.
+pub(crate) struct SchedEntity {
+    attr: SchedAttr,
+    /// The attributes lent by a task blocked on a priority inheritance lock
+    /// this task holds, if they outrank its own.
+    inherited: Option<SchedAttr>,
+    /// The class the task was last queued in, which it runs in until it is
+    /// queued again with new attributes.
+    class: Option<Class>,
+    queued: Option<Queue>,
+    /// The ID of the scheduler the task is queued in.
+    queued_in: usize,
+    /// When the task last started running or was charged.
+    exec_start: u64,
+    /// The absolute deadline of a deadline task.
+    abs_deadline: u64,
+    /// The runtime left in the current period of a deadline task.
+    remaining: i64,
+    /// When a throttled deadline task may run again.
+    replenish_at: u64,
+    /// The ticks left in the time slice of a round-robin task.
+    slice: usize,
+}
+
+impl SchedEntity {
+    pub(crate) const fn new() -> Self {
+        Self {
+            attr: SchedAttr::normal(),
+            inherited: None,
+            class: None,
+            queued: None,
+            queued_in: 0,
+            exec_start: 0,
+            abs_deadline: 0,
+            remaining: 0,
+            replenish_at: 0,
+            slice: RR_TIME_SLICE,
+        }
+    }
+
+    pub(crate) fn attr(&self) -> SchedAttr {
+        self.attr
+    }
+
+    /// The attributes the task is scheduled by: the inherited ones if they
+    /// outrank its own, which may have been changed since.
+    pub(crate) fn effective(&self) -> SchedAttr {
+        match self.inherited {
+            Some(lent) if lent.outranks(&self.attr) => lent,
+            _ => self.attr,
+        }
+    }
+
+    /// Lends the attributes `attr` of a waiter to the task, if they outrank
+    /// those it is scheduled by. They take effect the next time the task is
+    /// put in a run queue.
+    ///
+    /// Returns `true` if they are lent.
+    pub(crate) fn inherit(&mut self, attr: SchedAttr) -> bool {
+        let lent = attr.outranks(&self.effective());
+        if lent {
+            self.inherited = Some(attr);
+            // A throttled task may run again at once.
+            self.replenish_at = 0;
+        }
+        lent
+    }
+
+    /// Drops the inherited attributes, and returns `true` if there were any.
+    pub(crate) fn clear_inherited(&mut self) -> bool {
+        self.inherited.take().is_some()
+    }
+
+    /// Sets the attributes, if the total bandwidth of the deadline tasks
+    /// stays within that of a CPU.
+    pub(crate) fn set_attr(&mut self, attr: SchedAttr) -> bool {
+        let (old, new) = (self.attr.bandwidth(), attr.bandwidth());
+        let admitted = DL_TOTAL_BW
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |total| {
+                let total = total - old + new;
+                (new <= old || total <= MAX_DL_BW).then_some(total)
+            })
+            .is_ok();
+        if admitted {
+            self.attr = attr;
+        }
+        admitted
+    }
+
+    /// Charges the time run since the last charge, and moves a deadline
+    /// task that used up its runtime to its next period.
+    fn charge(&mut self, now: u64) {
+        let delta = now.saturating_sub(self.exec_start);
+        self.exec_start = now;
+        let attr = self.effective();
+        // The attributes may have changed since the task was queued.
+        if self.class != Some(Class::Deadline) || attr.policy != SchedPolicy::Deadline {
+            return;
+        }
+        self.remaining -= delta as i64;
+        if self.remaining <= 0 {
+            while self.remaining <= 0 {
+                self.abs_deadline += attr.period;
+                self.remaining += attr.runtime as i64;
+            }
+            // A task running on lent attributes is not throttled, so that it
+            // releases the lock soon.
+            if attr == self.attr {
+                self.replenish_at = self.abs_deadline - attr.deadline;
+            }
+        }
+    }
+
+    /// Gives a deadline task a new deadline and a full runtime, if it cannot
+    /// meet the old deadline without exceeding its bandwidth.
+    fn update_deadline(&mut self, now: u64) {
+        let SchedAttr {
+            runtime,
+            deadline,
+            period,
+            ..
+        } = self.effective();
+        let overflow = self.abs_deadline <= now
+            || self.remaining as u128 * period as u128
+                > (self.abs_deadline - now) as u128 * runtime as u128;
+        if overflow {
+            self.abs_deadline = now + deadline;
+            self.remaining = runtime as i64;
+            self.replenish_at = 0;
+        }
+    }
+
+    fn is_throttled(&self, now: u64) -> bool {
+        self.replenish_at > now
+    }
+}
+
+/// A scheduler with the deadline, real-time and fair classes.
+pub(crate) struct ClassScheduler {
+    /// The ready deadline tasks, by absolute deadline and ID.
+    deadline: BTreeMap<(u64, u64), AxTaskRef>,
+    /// The deadline tasks waiting for their next period.
+    throttled: Vec<AxTaskRef>,
+    /// The ready real-time tasks of each priority.
+    rt: [VecDeque<AxTaskRef>; MAX_RT_PRIO as usize + 1],
+    /// The priorities that have ready real-time tasks.
+    rt_bitmap: u128,
+    fair: CFScheduler<TaskInner>,
+    /// The task last picked, which is charged when the next one is.
+    running: WeakAxTaskRef,
+    /// The ID of the scheduler, which tells the tasks queued in it.
+    id: usize,
+}
+
+impl ClassScheduler {
+    /// Creates a new empty [`ClassScheduler`].
+    pub(crate) fn new() -> Self {
+        Self {
+            deadline: BTreeMap::new(),
+            throttled: Vec::new(),
+            rt: [const { VecDeque::new() }; MAX_RT_PRIO as usize + 1],
+            rt_bitmap: 0,
+            fair: CFScheduler::new(),
+            running: WeakAxTaskRef::new(),
+            id: NEXT_SCHEDULER_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the name of the scheduler.
+    pub(crate) fn scheduler_name() -> &'static str {
+        "Deadline/Real-time/Fair"
+    }
+
+    fn highest_rt_prio(&self) -> Option<u32> {
+        (self.rt_bitmap != 0).then(|| u128::BITS - 1 - self.rt_bitmap.leading_zeros())
+    }
+
+    /// Moves the throttled deadline tasks that reached their next period to
+    /// the ready queue.
+    fn unthrottle(&mut self, now: u64) {
+        let mut i = 0;
+        while i < self.throttled.len() {
+            let mut se = self.throttled[i].sched_entity().lock();
+            if se.is_throttled(now) {
+                i += 1;
+                continue;
+            }
+            se.queued = Some(Queue::Deadline);
+            let key = (se.abs_deadline, self.throttled[i].id().as_u64());
+            drop(se);
+            let task = self.throttled.swap_remove(i);
+            self.deadline.insert(key, task);
+        }
+    }
+
+    /// Puts `task` in the queue of its class. `running` tells whether it is
+    /// the task that was running, rather than one that woke up.
+    fn enqueue(&mut self, task: AxTaskRef, running: bool, preempt: bool, now: u64) {
+        let mut se = task.sched_entity().lock();
+        let attr = se.effective();
+        let class = Class::of(attr.policy);
+        let changed = se.class != Some(class);
+        se.class = Some(class);
+        let queue = match class {
+            Class::Deadline => {
+                if changed || !running {
+                    se.update_deadline(now);
+                }
+                if se.is_throttled(now) {
+                    Queue::Throttled
+                } else {
+                    Queue::Deadline
+                }
+            }
+            Class::RealTime => {
+                let prio = attr.rt_priority;
+                if changed {
+                    se.slice = RR_TIME_SLICE;
+                }
+                Queue::RealTime(prio)
+            }
+            Class::Fair => Queue::Fair,
+        };
+        se.queued = Some(queue);
+        se.queued_in = self.id;
+        let abs_deadline = se.abs_deadline;
+        // A preempted real-time task keeps its place at the front, unless it
+        // used up its time slice.
+        let front = running && preempt && se.slice > 0;
+        if se.slice == 0 {
+            se.slice = RR_TIME_SLICE;
+        }
+        drop(se);
+
+        match queue {
+            Queue::Deadline => {
+                self.deadline
+                    .insert((abs_deadline, task.id().as_u64()), task);
+            }
+            Queue::Throttled => self.throttled.push(task),
+            Queue::RealTime(prio) => {
+                let rq = &mut self.rt[prio as usize];
+                if front {
+                    rq.push_front(task);
+                } else {
+                    rq.push_back(task);
+                }
+                self.rt_bitmap |= 1 << prio;
+            }
+            Queue::Fair if changed => self.fair.add_task(task),
+            Queue::Fair => self.fair.put_prev_task(task, preempt),
+        }
+    }
+
+    fn is_running(&self, task: &AxTaskRef) -> bool {
+        core::ptr::eq(self.running.as_ptr(), Arc::as_ptr(task))
+    }
+
+    /// Adds a new or woken `task` at `now`, as
+    /// [`add_task`](BaseScheduler::add_task) does.
+    pub(crate) fn add_at(&mut self, task: AxTaskRef, now: u64) {
+        self.enqueue(task, false, false, now);
+    }
+
+    /// Picks the next task to run at `now`, as
+    /// [`pick_next_task`](BaseScheduler::pick_next_task) does.
+    pub(crate) fn pick_next_at(&mut self, now: u64) -> Option<AxTaskRef> {
+        if let Some(prev) = self.running.upgrade() {
+            prev.sched_entity().lock().charge(now);
+        }
+        self.unthrottle(now);
+
+        let next = if let Some((_, task)) = self.deadline.pop_first() {
+            Some(task)
+        } else if let Some(prio) = self.highest_rt_prio() {
+            let rq = &mut self.rt[prio as usize];
+            let task = rq.pop_front();
+            if rq.is_empty() {
+                self.rt_bitmap &= !(1 << prio);
+            }
+            task
+        } else {
+            self.fair.pick_next_task()
+        };
+
+        self.running = next
+            .as_ref()
+            .map_or_else(WeakAxTaskRef::new, Arc::downgrade);
+        if let Some(next) = &next {
+            let mut se = next.sched_entity().lock();
+            se.queued = None;
+            se.exec_start = now;
+        }
+        next
+    }
+
+    /// Puts `prev` back at `now`, as
+    /// [`put_prev_task`](BaseScheduler::put_prev_task) does.
+    pub(crate) fn put_prev_at(&mut self, prev: AxTaskRef, preempt: bool, now: u64) {
+        let running = self.is_running(&prev);
+        if running {
+            self.running = WeakAxTaskRef::new();
+            prev.sched_entity().lock().charge(now);
+        }
+        self.enqueue(prev, running, preempt, now);
+    }
+
+    /// Puts the ready `task` back in the queue of its class, if it is queued
+    /// in this scheduler, so that the attributes it inherited take effect.
+    ///
+    /// Returns `false` if it is not queued here.
+    pub(crate) fn requeue(&mut self, task: &AxTaskRef) -> bool {
+        let se = task.sched_entity().lock();
+        let queued_here = se.queued.is_some() && se.queued_in == self.id;
+        drop(se);
+        if !queued_here {
+            return false;
+        }
+        if let Some(task) = self.remove_task(task) {
+            self.add_at(task, monotonic_time_nanos());
+        }
+        true
+    }
+
+    /// Accounts a timer tick at `now`, as
+    /// [`task_tick`](BaseScheduler::task_tick) does.
+    pub(crate) fn tick_at(&mut self, current: &AxTaskRef, now: u64) -> bool {
+        self.unthrottle(now);
+        let mut se = current.sched_entity().lock();
+        se.charge(now);
+        // Put a task whose class changed in the queue of the new one.
+        if se
+            .class
+            .is_some_and(|class| class != Class::of(se.effective().policy))
+        {
+            return true;
+        }
+        match se.class {
+            Some(Class::Deadline) => {
+                se.is_throttled(now)
+                    || self
+                        .deadline
+                        .first_key_value()
+                        .is_some_and(|(&(deadline, _), _)| deadline < se.abs_deadline)
+            }
+            Some(Class::RealTime) => {
+                let attr = se.effective();
+                let prio = attr.rt_priority;
+                if !self.deadline.is_empty() || self.highest_rt_prio() > Some(prio) {
+                    return true;
+                }
+                if attr.policy != SchedPolicy::RoundRobin {
+                    return false;
+                }
+                se.slice = se.slice.saturating_sub(1);
+                if se.slice > 0 {
+                    false
+                } else if self.rt[prio as usize].is_empty() {
+                    // Nothing else to run, start another slice.
+                    se.slice = RR_TIME_SLICE;
+                    false
+                } else {
+                    true
+                }
+            }
+            _ => {
+                drop(se);
+                // Always advance the fair clock of the task.
+                let expired = self.fair.task_tick(current);
+                expired || !self.deadline.is_empty() || self.rt_bitmap != 0
+            }
+        }
+    }
+}
+
+impl BaseScheduler for ClassScheduler {
+    type SchedItem = AxTaskRef;
+
+    fn init(&mut self) {
+        self.fair.init();
+    }
+
+    fn add_task(&mut self, task: Self::SchedItem) {
+        self.add_at(task, monotonic_time_nanos());
+    }
+
+    fn remove_task(&mut self, task: &Self::SchedItem) -> Option<Self::SchedItem> {
+        let mut se = task.sched_entity().lock();
+        let queue = se.queued.take()?;
+        let key = (se.abs_deadline, task.id().as_u64());
+        drop(se);
+        match queue {
+            Queue::Deadline => self.deadline.remove(&key),
+            Queue::Throttled => {
+                let pos = self.throttled.iter().position(|t| Arc::ptr_eq(t, task))?;
+                Some(self.throttled.swap_remove(pos))
+            }
+            Queue::RealTime(prio) => {
+                let rq = &mut self.rt[prio as usize];
+                let task = rq.remove(rq.iter().position(|t| Arc::ptr_eq(t, task))?);
+                if rq.is_empty() {
+                    self.rt_bitmap &= !(1 << prio);
+                }
+                task
+            }
+            Queue::Fair => self.fair.remove_task(task),
+        }
+    }
+
+    fn pick_next_task(&mut self) -> Option<Self::SchedItem> {
+        self.pick_next_at(monotonic_time_nanos())
+    }
+
+    fn put_prev_task(&mut self, prev: Self::SchedItem, preempt: bool) {
+        self.put_prev_at(prev, preempt, monotonic_time_nanos());
+    }
+
+    fn task_tick(&mut self, current: &Self::SchedItem) -> bool {
+        self.tick_at(current, monotonic_time_nanos())
+    }
+
+    fn set_priority(&mut self, task: &Self::SchedItem, prio: isize) -> bool {
+        self.fair.set_priority(task, prio)
+    }
+}
+
+/// Clears the state of a task taken out of its run queue for another, so
+/// that it is placed there like a new task.
+#[cfg(feature = "smp")]
+fn detach(task: AxTaskRef) -> AxTaskRef {
+    let mut se = task.sched_entity().lock();
+    se.queued = None;
+    se.class = None;
+    drop(se);
+    task
+}
+
+#[cfg(feature = "smp")]
+impl crate::run_queue::MigrateTask for ClassScheduler {
+    fn take_migratable(
+        &mut self,
+        max: usize,
+        mut can_migrate: impl FnMut(&AxTaskRef) -> bool,
+    ) -> Option<AxTaskRef> {
+        // Look at the tasks of each class in turn, starting from those that
+        // would run last here.
+        let key = self
+            .deadline
+            .iter()
+            .rev()
+            .take(max)
+            .find_map(|(&key, task)| can_migrate(task).then_some(key));
+        if let Some(key) = key {
+            return self.deadline.remove(&key).map(detach);
+        }
+
+        let mut bitmap = self.rt_bitmap;
+        while bitmap != 0 {
+            let prio = u128::BITS - 1 - bitmap.leading_zeros();
+            bitmap &= !(1 << prio);
+            let rq = &mut self.rt[prio as usize];
+            if let Some(pos) = rq.iter().rev().take(max).position(&mut can_migrate) {
+                let task = rq.remove(rq.len() - 1 - pos);
+                if rq.is_empty() {
+                    self.rt_bitmap &= !(1 << prio);
+                }
+                return task.map(detach);
+            }
+        }
+
+        // The fair scheduler only gives out its next task, so the tasks passed
+        // over are put back.
+        let mut passed = Vec::new();
+        let mut taken = None;
+        for _ in 0..max {
+            let Some(task) = self.fair.pick_next_task() else {
+                break;
+            };
+            if can_migrate(&task) {
+                taken = Some(task);
+                break;
+            }
+            passed.push(task);
+        }
+        for task in passed {
+            self.fair.put_prev_task(task, false);
+        }
+        taken.map(detach)
+    }
+}
@@ -0,0 +1,110 @@
+//! CPU time accounting and scheduling statistics of tasks.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::task::TaskState;
+
+/// The CPU time and scheduling statistics of a task, as returned by
+/// [`TaskInner::stats`](crate::TaskInner::stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TaskStats {
+    /// The time spent running on CPUs, in nanoseconds.
+    pub run_time: u64,
+    /// The time spent ready in run queues, waiting to run, in nanoseconds.
+    pub wait_time: u64,
+    /// The number of times the task was switched to.
+    pub nr_switches: u64,
+    /// The number of times the task switched out as it blocked or exited.
+    pub nr_voluntary_switches: u64,
+    /// The number of times the task switched out while still ready, as it was
+    /// preempted, yielded or migrated.
+    pub nr_involuntary_switches: u64,
+    /// The number of times the task ran on another CPU than the last time.
+    pub nr_migrations: u64,
+    /// The CPU the task last ran on.
+    pub last_cpu: usize,
+}
+
+/// The counters behind [`TaskStats`].
+///
+/// They are updated by the CPU that runs or queues the task, with IRQs
+/// disabled, and may be read from any CPU.
+pub(crate) struct SchedStats {
+    run_time: AtomicU64,
+    wait_time: AtomicU64,
+    nr_switches: AtomicU64,
+    nr_voluntary_switches: AtomicU64,
+    nr_involuntary_switches: AtomicU64,
+    nr_migrations: AtomicU64,
+    last_cpu: AtomicUsize,
+    /// When the task started running or became ready, in nanoseconds.
+    timestamp: AtomicU64,
+}
+
+impl SchedStats {
+    pub const fn new() -> Self {
+        Self {
+            run_time: AtomicU64::new(0),
+            wait_time: AtomicU64::new(0),
+            nr_switches: AtomicU64::new(0),
+            nr_voluntary_switches: AtomicU64::new(0),
+            nr_involuntary_switches: AtomicU64::new(0),
+            nr_migrations: AtomicU64::new(0),
+            last_cpu: AtomicUsize::new(0),
+            timestamp: AtomicU64::new(0),
+        }
+    }
+
+    fn elapsed(&self, now: u64) -> u64 {
+        now.saturating_sub(self.timestamp.load(Ordering::Relaxed))
+    }
+
+    /// Records that the task became ready at `now`.
+    pub fn set_ready(&self, now: u64) {
+        self.timestamp.store(now, Ordering::Relaxed);
+    }
+
+    /// Records that the task starts running on `cpu_id` at `now`.
+    pub fn switch_in(&self, cpu_id: usize, now: u64) {
+        self.wait_time
+            .fetch_add(self.elapsed(now), Ordering::Relaxed);
+        let last_cpu = self.last_cpu.swap(cpu_id, Ordering::Relaxed);
+        if self.nr_switches.fetch_add(1, Ordering::Relaxed) > 0 && last_cpu != cpu_id {
+            self.nr_migrations.fetch_add(1, Ordering::Relaxed);
+        }
+        self.timestamp.store(now, Ordering::Relaxed);
+    }
+
+    /// Records that the task stops running at `now`, and whether it is still
+    /// `ready` to run.
+    pub fn switch_out(&self, now: u64, ready: bool) {
+        self.run_time
+            .fetch_add(self.elapsed(now), Ordering::Relaxed);
+        if ready {
+            self.nr_involuntary_switches.fetch_add(1, Ordering::Relaxed);
+            self.timestamp.store(now, Ordering::Relaxed);
+        } else {
+            self.nr_voluntary_switches.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the statistics at `now`, including the time since the task
+    /// started running or became ready, as told by its `state`.
+    pub fn snapshot(&self, now: u64, state: TaskState) -> TaskStats {
+        let mut stats = TaskStats {
+            run_time: self.run_time.load(Ordering::Relaxed),
+            wait_time: self.wait_time.load(Ordering::Relaxed),
+            nr_switches: self.nr_switches.load(Ordering::Relaxed),
+            nr_voluntary_switches: self.nr_voluntary_switches.load(Ordering::Relaxed),
+            nr_involuntary_switches: self.nr_involuntary_switches.load(Ordering::Relaxed),
+            nr_migrations: self.nr_migrations.load(Ordering::Relaxed),
+            last_cpu: self.last_cpu.load(Ordering::Relaxed),
+        };
+        match state {
+            TaskState::Running => stats.run_time += self.elapsed(now),
+            TaskState::Ready => stats.wait_time += self.elapsed(now),
+            _ => {}
+        }
+        stats
+    }
+}
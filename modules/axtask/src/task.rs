@@ -1,11 +1,10 @@
 use alloc::{boxed::Box, string::String, sync::Arc};
 use core::ops::Deref;
-use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU8, AtomicU64, Ordering};
+use core::sync::atomic::{
+    AtomicBool, AtomicI32, AtomicIsize, AtomicU8, AtomicU64, AtomicUsize, Ordering,
+};
 use core::{alloc::Layout, cell::UnsafeCell, fmt, ptr::NonNull};
 
-#[cfg(feature = "preempt")]
-use core::sync::atomic::AtomicUsize;
-
 use kspin::SpinNoIrq;
 use memory_addr::{VirtAddr, align_up_4k};
 
@@ -13,6 +12,9 @@ use axhal::arch::TaskContext;
 #[cfg(feature = "tls")]
 use axhal::tls::TlsArea;
 
+#[cfg(feature = "sched_classes")]
+use crate::sched::{SchedAttr, SchedEntity};
+use crate::stats::{SchedStats, TaskStats};
 use crate::task_ext::AxTaskExt;
 use crate::{AxCpuMask, AxTask, AxTaskRef, WaitQueue};
 
@@ -35,6 +37,9 @@ pub enum TaskState {
     Exited = 4,
 }
 
+/// The value of the inherited priority of a task that inherits none.
+pub(crate) const NO_INHERITED_PRIO: isize = isize::MAX;
+
 /// The inner task structure.
 pub struct TaskInner {
     id: TaskId,
@@ -51,9 +56,26 @@ pub struct TaskInner {
     /// Mark whether the task is in the wait queue.
     in_wait_queue: AtomicBool,
 
+    /// The priority set by [`set_priority`](crate::set_priority).
+    base_prio: AtomicIsize,
+    /// The priority inherited from tasks blocked on the locks this task
+    /// holds, or [`NO_INHERITED_PRIO`].
+    inherited_prio: AtomicIsize,
+    /// The number of priority inheritance locks held.
+    pi_locks: AtomicUsize,
+
+    /// The scheduling attributes and the state of the scheduling classes.
+    #[cfg(feature = "sched_classes")]
+    sched: SpinNoIrq<SchedEntity>,
+    /// The CPU time and scheduling statistics.
+    stats: SchedStats,
+
     /// Used to indicate whether the task is running on a CPU.
     #[cfg(feature = "smp")]
     on_cpu: AtomicBool,
+    /// When the task last stopped running on a CPU, in nanoseconds.
+    #[cfg(feature = "smp")]
+    last_ran: AtomicU64,
 
     /// A ticket ID used to identify the timer event.
     /// Set by `set_timer_ticket()` when creating a timer event in `set_alarm_wakeup()`,
@@ -230,6 +252,12 @@ impl TaskInner {
     pub fn exit_code(&self) -> i32 {
         self.exit_code.load(Ordering::Acquire)
     }
+
+    /// Returns the CPU time and scheduling statistics of the task.
+    pub fn stats(&self) -> TaskStats {
+        self.stats
+            .snapshot(axhal::time::monotonic_time_nanos(), self.state())
+    }
 }
 
 // private methods
@@ -245,10 +273,18 @@ impl TaskInner {
             // By default, the task is allowed to run on all CPUs.
             cpumask: SpinNoIrq::new(AxCpuMask::full()),
             in_wait_queue: AtomicBool::new(false),
+            base_prio: AtomicIsize::new(0),
+            inherited_prio: AtomicIsize::new(NO_INHERITED_PRIO),
+            pi_locks: AtomicUsize::new(0),
+            #[cfg(feature = "sched_classes")]
+            sched: SpinNoIrq::new(SchedEntity::new()),
+            stats: SchedStats::new(),
             #[cfg(feature = "irq")]
             timer_ticket_id: AtomicU64::new(0),
             #[cfg(feature = "smp")]
             on_cpu: AtomicBool::new(false),
+            #[cfg(feature = "smp")]
+            last_ran: AtomicU64::new(0),
             #[cfg(feature = "preempt")]
             need_resched: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
@@ -286,6 +322,70 @@ impl TaskInner {
         Arc::new(AxTask::new(self))
     }
 
+    /// Returns the effective priority of the task, the higher of its own and
+    /// the inherited one. Lower values are higher priorities.
+    pub fn priority(&self) -> isize {
+        self.base_priority()
+            .min(self.inherited_prio.load(Ordering::Acquire))
+    }
+
+    /// Returns the priority of the task set by
+    /// [`set_priority`](crate::set_priority), ignoring the inherited one.
+    pub fn base_priority(&self) -> isize {
+        self.base_prio.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_base_priority(&self, prio: isize) {
+        self.base_prio.store(prio, Ordering::Release)
+    }
+
+    /// Raises the inherited priority to `prio`, and returns the old one.
+    pub(crate) fn raise_inherited_priority(&self, prio: isize) -> isize {
+        self.inherited_prio.fetch_min(prio, Ordering::AcqRel)
+    }
+
+    /// Drops the inherited priority, and returns the old one.
+    pub(crate) fn clear_inherited_priority(&self) -> isize {
+        self.inherited_prio
+            .swap(NO_INHERITED_PRIO, Ordering::AcqRel)
+    }
+
+    pub(crate) fn inc_pi_locks(&self) {
+        self.pi_locks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a priority inheritance lock as released, and returns how many
+    /// are still held.
+    pub(crate) fn dec_pi_locks(&self) -> usize {
+        self.pi_locks.fetch_sub(1, Ordering::Relaxed) - 1
+    }
+
+    /// Returns the scheduling attributes of the task.
+    #[cfg(feature = "sched_classes")]
+    pub fn sched_attr(&self) -> SchedAttr {
+        self.sched.lock().attr()
+    }
+
+    /// Sets the scheduling attributes, which take effect the next time the
+    /// task is put in a run queue.
+    ///
+    /// Returns `false` if the bandwidth of the deadline tasks would exceed
+    /// that of a CPU.
+    #[cfg(feature = "sched_classes")]
+    pub(crate) fn set_sched_attr(&self, attr: SchedAttr) -> bool {
+        self.sched.lock().set_attr(attr)
+    }
+
+    #[cfg(feature = "sched_classes")]
+    pub(crate) fn sched_entity(&self) -> &SpinNoIrq<SchedEntity> {
+        &self.sched
+    }
+
+    #[inline]
+    pub(crate) fn sched_stats(&self) -> &SchedStats {
+        &self.stats
+    }
+
     /// Returns the task's current state.
     #[inline]
     pub fn state(&self) -> TaskState {
@@ -439,6 +539,21 @@ impl TaskInner {
     pub(crate) fn set_on_cpu(&self, on_cpu: bool) {
         self.on_cpu.store(on_cpu, Ordering::Release)
     }
+
+    /// Records that the task stopped running on a CPU at `now`.
+    #[cfg(feature = "smp")]
+    #[inline]
+    pub(crate) fn set_last_ran(&self, now: u64) {
+        self.last_ran.store(now, Ordering::Relaxed)
+    }
+
+    /// Returns whether the task ran within `cost` nanoseconds before `now`,
+    /// so that its data is likely still in the caches of its last CPU.
+    #[cfg(feature = "smp")]
+    #[inline]
+    pub(crate) fn is_cache_hot(&self, now: u64, cost: u64) -> bool {
+        now.saturating_sub(self.last_ran.load(Ordering::Relaxed)) < cost
+    }
 }
 
 impl fmt::Debug for TaskInner {
@@ -457,12 +572,26 @@ impl Drop for TaskInner {
     }
 }
 
+/// The interface to map kernel stacks with guard pages, implemented by the
+/// memory management module.
+#[cfg(feature = "stack-guard")]
+#[crate_interface::def_interface]
+pub trait KernelStackIf {
+    /// Maps a kernel stack of `size` bytes with an unmapped guard page below
+    /// it, and returns its bottom.
+    fn alloc_kernel_stack(size: usize) -> Option<VirtAddr>;
+
+    /// Frees the kernel stack at `bottom` of `size` bytes.
+    fn dealloc_kernel_stack(bottom: VirtAddr, size: usize);
+}
+
 struct TaskStack {
     ptr: NonNull<u8>,
     layout: Layout,
 }
 
 impl TaskStack {
+    #[cfg(not(feature = "stack-guard"))]
     pub fn alloc(size: usize) -> Self {
         let layout = Layout::from_size_align(size, 16).unwrap();
         Self {
@@ -471,15 +600,36 @@ impl TaskStack {
         }
     }
 
+    #[cfg(feature = "stack-guard")]
+    pub fn alloc(size: usize) -> Self {
+        let layout = Layout::from_size_align(size, 16).unwrap();
+        let bottom = crate_interface::call_interface!(KernelStackIf::alloc_kernel_stack(size))
+            .expect("failed to map kernel stack");
+        Self {
+            ptr: NonNull::new(bottom.as_mut_ptr()).unwrap(),
+            layout,
+        }
+    }
+
     pub const fn top(&self) -> VirtAddr {
         unsafe { core::mem::transmute(self.ptr.as_ptr().add(self.layout.size())) }
     }
 }
 
 impl Drop for TaskStack {
+    #[cfg(not(feature = "stack-guard"))]
     fn drop(&mut self) {
         unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
     }
+
+    #[cfg(feature = "stack-guard")]
+    fn drop(&mut self) {
+        let bottom = VirtAddr::from_mut_ptr_of(self.ptr.as_ptr());
+        crate_interface::call_interface!(KernelStackIf::dealloc_kernel_stack(
+            bottom,
+            self.layout.size()
+        ));
+    }
 }
 
 use core::mem::ManuallyDrop;
@@ -1,6 +1,17 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Mutex, Once};
 
+use crate::run_queue::least_loaded_index;
+#[cfg(all(feature = "sched_classes", feature = "smp"))]
+use crate::{
+    AxCpuMask,
+    run_queue::{MIGRATION_COST_NS, MigrateTask, can_migrate},
+};
+#[cfg(feature = "sched_classes")]
+use crate::{
+    AxTaskRef, SchedAttr, SchedPolicy, TaskInner,
+    sched::{ClassScheduler, RR_TIME_SLICE},
+};
 use crate::{WaitQueue, api as axtask, current};
 
 static INIT: Once = Once::new();
@@ -128,3 +139,196 @@ fn test_task_join() {
         assert_eq!(tasks[i].join(), Some(i as _));
     }
 }
+
+#[test]
+fn test_least_loaded_run_queue() {
+    let loads = [3, 1, 1, 2];
+    let nr_running = |index: usize| loads[index];
+
+    assert_eq!(least_loaded_index(4, 0, |_| true, nr_running), Some(1));
+    // Ties go to the first run queue from the round-robin start.
+    assert_eq!(least_loaded_index(4, 2, |_| true, nr_running), Some(2));
+    assert_eq!(least_loaded_index(4, 3, |_| true, nr_running), Some(1));
+    // Only the CPUs allowed are chosen from.
+    let allowed = |index: usize| index == 0 || index == 3;
+    assert_eq!(least_loaded_index(4, 0, allowed, nr_running), Some(3));
+    assert_eq!(least_loaded_index(4, 0, |_| false, nr_running), None);
+}
+
+#[cfg(feature = "sched_classes")]
+const MS: u64 = 1_000_000;
+
+#[cfg(feature = "sched_classes")]
+fn new_task(name: &str) -> AxTaskRef {
+    TaskInner::new(|| {}, name.into(), 0x1000).into_arc()
+}
+
+#[cfg(feature = "sched_classes")]
+fn set_policy(task: &AxTaskRef, policy: SchedPolicy, rt_priority: u32) {
+    let attr = SchedAttr {
+        policy,
+        rt_priority,
+        ..SchedAttr::normal()
+    };
+    assert!(task.set_sched_attr(attr));
+}
+
+#[cfg(feature = "sched_classes")]
+fn set_deadline(task: &AxTaskRef, runtime: u64, deadline: u64, period: u64) {
+    let attr = SchedAttr {
+        policy: SchedPolicy::Deadline,
+        runtime,
+        deadline,
+        period,
+        ..SchedAttr::normal()
+    };
+    assert!(task.set_sched_attr(attr));
+}
+
+#[cfg(feature = "sched_classes")]
+fn assert_picked(next: Option<AxTaskRef>, task: &AxTaskRef) {
+    let next = next.expect("no task picked");
+    assert!(
+        alloc::sync::Arc::ptr_eq(&next, task),
+        "picked {} instead of {}",
+        next.id_name(),
+        task.id_name()
+    );
+}
+
+#[cfg(feature = "sched_classes")]
+#[test]
+fn test_sched_classes_edf() {
+    let mut sched = ClassScheduler::new();
+    let rt = new_task("rt");
+    set_policy(&rt, SchedPolicy::Fifo, 50);
+    let dl: [AxTaskRef; 3] = core::array::from_fn(|i| new_task(&format!("dl{}", i)));
+    for (task, deadline) in dl.iter().zip([30 * MS, 10 * MS, 20 * MS]) {
+        set_deadline(task, MS, deadline, 100 * MS);
+    }
+
+    sched.add_at(rt.clone(), 0);
+    for task in &dl {
+        sched.add_at(task.clone(), 0);
+    }
+    // The earliest deadline first, and the deadline class before the others.
+    assert_picked(sched.pick_next_at(0), &dl[1]);
+    assert_picked(sched.pick_next_at(0), &dl[2]);
+    assert_picked(sched.pick_next_at(0), &dl[0]);
+    assert_picked(sched.pick_next_at(0), &rt);
+
+    // Release the bandwidth.
+    for task in &dl {
+        assert!(task.set_sched_attr(SchedAttr::normal()));
+    }
+}
+
+#[cfg(feature = "sched_classes")]
+#[test]
+fn test_sched_classes_throttle() {
+    let mut sched = ClassScheduler::new();
+    let task = new_task("dl");
+    set_deadline(&task, 10 * MS, 100 * MS, 100 * MS);
+
+    sched.add_at(task.clone(), 0);
+    assert_picked(sched.pick_next_at(0), &task);
+    assert!(!sched.tick_at(&task, 5 * MS));
+    // The runtime is used up, the task is throttled until its next period.
+    assert!(sched.tick_at(&task, 10 * MS));
+    sched.put_prev_at(task.clone(), true, 10 * MS);
+    assert!(sched.pick_next_at(50 * MS).is_none());
+
+    // Replenished with a full runtime.
+    assert_picked(sched.pick_next_at(100 * MS), &task);
+    assert!(!sched.tick_at(&task, 109 * MS));
+    assert!(sched.tick_at(&task, 110 * MS));
+
+    assert!(task.set_sched_attr(SchedAttr::normal()));
+}
+
+#[cfg(feature = "sched_classes")]
+#[test]
+fn test_sched_classes_rr_slice() {
+    let mut sched = ClassScheduler::new();
+    let tasks: [AxTaskRef; 2] = core::array::from_fn(|i| new_task(&format!("rr{}", i)));
+    for task in &tasks {
+        set_policy(task, SchedPolicy::RoundRobin, 10);
+        sched.add_at(task.clone(), 0);
+    }
+
+    let mut now = 0;
+    for round in 0..4 {
+        let curr = &tasks[round % 2];
+        assert_picked(sched.pick_next_at(now), curr);
+        // Preempted within the time slice, the task keeps its place.
+        now += MS;
+        assert!(!sched.tick_at(curr, now));
+        sched.put_prev_at(curr.clone(), true, now);
+        assert_picked(sched.pick_next_at(now), curr);
+        // Then gives way to the other task of its priority at the end of it.
+        for _ in 1..RR_TIME_SLICE - 1 {
+            now += MS;
+            assert!(!sched.tick_at(curr, now));
+        }
+        now += MS;
+        assert!(sched.tick_at(curr, now));
+        sched.put_prev_at(curr.clone(), true, now);
+    }
+}
+
+#[cfg(feature = "sched_classes")]
+#[test]
+fn test_sched_classes_inherit() {
+    let mut sched = ClassScheduler::new();
+    let owner = new_task("owner");
+    let rt = new_task("rt");
+    set_policy(&rt, SchedPolicy::Fifo, 10);
+    sched.add_at(owner.clone(), 0);
+    sched.add_at(rt.clone(), 0);
+
+    let lent = SchedAttr {
+        policy: SchedPolicy::Fifo,
+        rt_priority: 50,
+        ..SchedAttr::normal()
+    };
+    assert!(owner.sched_entity().lock().inherit(lent));
+    // Requeued only by the scheduler it is in.
+    assert!(!ClassScheduler::new().requeue(&owner));
+    assert!(sched.requeue(&owner));
+    assert_picked(sched.pick_next_at(0), &owner);
+
+    // Back to the fair class at the next tick once it drops the attributes.
+    assert!(!sched.tick_at(&owner, MS));
+    assert!(owner.sched_entity().lock().clear_inherited());
+    assert!(sched.tick_at(&owner, 2 * MS));
+    sched.put_prev_at(owner.clone(), true, 2 * MS);
+    assert_picked(sched.pick_next_at(2 * MS), &rt);
+    assert_picked(sched.pick_next_at(2 * MS), &owner);
+}
+
+#[cfg(all(feature = "sched_classes", feature = "smp"))]
+#[test]
+fn test_take_migratable() {
+    let mut sched = ClassScheduler::new();
+    let now = 10 * MIGRATION_COST_NS;
+    let pinned = new_task("pinned");
+    pinned.set_cpumask(AxCpuMask::new());
+    let hot = new_task("hot");
+    hot.set_last_ran(now);
+    let cold = new_task("cold");
+    for task in [&pinned, &hot, &cold] {
+        sched.add_at(task.clone(), 0);
+    }
+
+    let mut take =
+        |allow_hot| sched.take_migratable(8, |task| can_migrate(task, 0, now, allow_hot));
+    assert_picked(take(false), &cold);
+    assert!(take(false).is_none());
+    // Cache-hot tasks are taken once balancing keeps failing.
+    assert_picked(take(true), &hot);
+    // Tasks not allowed on the CPU never are.
+    assert!(take(true).is_none());
+
+    // The tasks passed over stay queued.
+    assert_picked(sched.pick_next_at(0), &pinned);
+}
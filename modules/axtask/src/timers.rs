@@ -1,17 +1,39 @@
+//! Timer events of tasks, and the programming of the timer interrupt.
+//!
+//! The timer is programmed in one-shot mode, for the earliest of the next
+//! timer event and the next periodic tick, so that the events fire at their
+//! deadlines rather than on the ticks. The ticks stop while the CPU is idle
+//! (tickless idle), and start again when it runs a task.
+
 use core::sync::atomic::{AtomicU64, Ordering};
 
 use kernel_guard::NoOp;
 use lazyinit::LazyInit;
 use timer_list::{TimeValue, TimerEvent, TimerList};
 
-use axhal::time::wall_time;
+use axhal::time::{
+    NANOS_PER_SEC, epochoffset_nanos, monotonic_time, monotonic_time_nanos, set_oneshot_timer,
+};
 
 use crate::{AxTaskRef, select_run_queue};
 
+/// The interval between two periodic ticks, in nanoseconds.
+const TICK_INTERVAL_NANOS: u64 = NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
+/// The longest time the timer is programmed for, in nanoseconds, within the
+/// range of all the timer hardware.
+const MAX_TIMER_NANOS: u64 = NANOS_PER_SEC;
+/// The value of [`NEXT_TICK`] while the ticks are stopped.
+const TICK_STOPPED: u64 = u64::MAX;
+
 static TIMER_TICKET_ID: AtomicU64 = AtomicU64::new(1);
 
 percpu_static! {
+    /// The timer events, by their deadlines in monotonic time.
     TIMER_LIST: LazyInit<TimerList<TaskWakeupEvent>> = LazyInit::new(),
+    /// The monotonic time of the next periodic tick, in nanoseconds.
+    NEXT_TICK: u64 = 0,
+    /// The monotonic time the timer is programmed for, in nanoseconds.
+    TIMER_DEADLINE: u64 = 0,
 }
 
 struct TaskWakeupEvent {
@@ -35,17 +57,31 @@ impl TimerEvent for TaskWakeupEvent {
     }
 }
 
+// Note: the functions below must be called with IRQs disabled, as they
+// access the per-CPU states of the timer.
+
+fn program_timer(deadline: u64) {
+    unsafe { TIMER_DEADLINE.write_current_raw(deadline) };
+    set_oneshot_timer(deadline);
+}
+
+/// Wakes up `task` at `deadline` in wall time.
 pub fn set_alarm_wakeup(deadline: TimeValue, task: AxTaskRef) {
+    let deadline = deadline.saturating_sub(TimeValue::from_nanos(epochoffset_nanos()));
     TIMER_LIST.with_current(|timer_list| {
         let ticket_id = TIMER_TICKET_ID.fetch_add(1, Ordering::AcqRel);
         task.set_timer_ticket(ticket_id);
         timer_list.set(deadline, TaskWakeupEvent { ticket_id, task });
-    })
+    });
+    let deadline = deadline.as_nanos() as u64;
+    if deadline < unsafe { TIMER_DEADLINE.read_current_raw() } {
+        program_timer(deadline);
+    }
 }
 
 pub fn check_events() {
     loop {
-        let now = wall_time();
+        let now = monotonic_time();
         let event = unsafe {
             // Safety: IRQs are disabled at this time.
             TIMER_LIST.current_ref_mut_raw()
@@ -59,6 +95,52 @@ pub fn check_events() {
     }
 }
 
+/// Returns whether a periodic tick is due at `now`, and if so, moves on to
+/// the next one.
+pub fn tick_due(now: u64) -> bool {
+    let next = unsafe { NEXT_TICK.read_current_raw() };
+    if now < next {
+        return false;
+    }
+    // Skip the ticks missed, if any.
+    let next = if next + TICK_INTERVAL_NANOS > now {
+        next + TICK_INTERVAL_NANOS
+    } else {
+        now + TICK_INTERVAL_NANOS
+    };
+    unsafe { NEXT_TICK.write_current_raw(next) };
+    true
+}
+
+/// Programs the timer for the next timer event, and the next tick if the
+/// ticks are `needed`, or stops them otherwise.
+pub fn reprogram(now: u64, needed: bool) {
+    let mut next_tick = unsafe { NEXT_TICK.read_current_raw() };
+    if !needed {
+        next_tick = TICK_STOPPED;
+    } else if next_tick == TICK_STOPPED {
+        next_tick = now + TICK_INTERVAL_NANOS;
+    }
+    unsafe { NEXT_TICK.write_current_raw(next_tick) };
+
+    let next_event = unsafe { TIMER_LIST.current_ref_raw() }
+        .next_deadline()
+        .map_or(u64::MAX, |deadline| deadline.as_nanos() as u64);
+    program_timer(next_event.min(next_tick).min(now + MAX_TIMER_NANOS));
+}
+
+/// Starts the ticks again if they are stopped, when the CPU leaves idle.
+pub fn restart_tick() {
+    if unsafe { NEXT_TICK.read_current_raw() } != TICK_STOPPED {
+        return;
+    }
+    let next_tick = monotonic_time_nanos() + TICK_INTERVAL_NANOS;
+    unsafe { NEXT_TICK.write_current_raw(next_tick) };
+    if next_tick < unsafe { TIMER_DEADLINE.read_current_raw() } {
+        program_timer(next_tick);
+    }
+}
+
 pub fn init() {
     TIMER_LIST.with_current(|timer_list| {
         timer_list.init_once(TimerList::new());
@@ -1,6 +1,9 @@
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
 
 use kernel_guard::{NoOp, NoPreemptIrqSave};
 use kspin::{SpinNoIrq, SpinNoIrqGuard};
@@ -31,22 +34,61 @@ use crate::{AxTaskRef, CurrentTask, current_run_queue, select_run_queue};
 /// ```
 pub struct WaitQueue {
     queue: SpinNoIrq<VecDeque<AxTaskRef>>,
+    /// Where the queue was created, which stands for the class of its lock
+    /// in the lock dependency checker.
+    /// It is `None` if the queue was not created by [`WaitQueue::new`],
+    /// e.g., by a C static initializer of a mutex.
+    #[cfg(feature = "lockdep")]
+    class: Option<&'static Location<'static>>,
 }
 
-pub(crate) type WaitQueueGuard<'a> = SpinNoIrqGuard<'a, VecDeque<AxTaskRef>>;
+/// A guard of the lock of a [`WaitQueue`].
+pub(crate) struct WaitQueueGuard<'a> {
+    guard: SpinNoIrqGuard<'a, VecDeque<AxTaskRef>>,
+    #[cfg(feature = "lockdep")]
+    addr: usize,
+}
 
 impl WaitQueue {
     /// Creates an empty wait queue.
+    #[track_caller]
     pub const fn new() -> Self {
         Self {
             queue: SpinNoIrq::new(VecDeque::new()),
+            #[cfg(feature = "lockdep")]
+            class: Some(Location::caller()),
         }
     }
 
     /// Creates an empty wait queue with space for at least `capacity` elements.
+    #[track_caller]
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
             queue: SpinNoIrq::new(VecDeque::with_capacity(capacity)),
+            #[cfg(feature = "lockdep")]
+            class: Some(Location::caller()),
+        }
+    }
+
+    /// Returns where the queue was created, which stands for the lock class
+    /// of the lock it belongs to in the lock dependency checker.
+    #[cfg(feature = "lockdep")]
+    pub fn lock_class(&self) -> Option<&'static Location<'static>> {
+        self.class
+    }
+
+    /// Locks the queue, as a spin lock checked by the [`LockdepIf`].
+    ///
+    /// [`LockdepIf`]: crate::LockdepIf
+    fn lock(&self) -> WaitQueueGuard<'_> {
+        #[cfg(feature = "lockdep")]
+        let addr = &self.queue as *const _ as usize;
+        #[cfg(feature = "lockdep")]
+        crate_interface::call_interface!(crate::LockdepIf::acquire_spin(self.class, addr, false));
+        WaitQueueGuard {
+            guard: self.lock(),
+            #[cfg(feature = "lockdep")]
+            addr,
         }
     }
 
@@ -57,7 +99,7 @@ impl WaitQueue {
         // the event from another queue.
         if curr.in_wait_queue() {
             // wake up by timer (timeout).
-            self.queue.lock().retain(|t| !curr.ptr_eq(t));
+            self.lock().retain(|t| !curr.ptr_eq(t));
             curr.set_in_wait_queue(false);
         }
 
@@ -77,7 +119,7 @@ impl WaitQueue {
     /// Blocks the current task and put it into the wait queue, until other task
     /// notifies it.
     pub fn wait(&self) {
-        current_run_queue::<NoPreemptIrqSave>().blocked_resched(self.queue.lock());
+        current_run_queue::<NoPreemptIrqSave>().blocked_resched(self.lock());
         self.cancel_events(crate::current(), false);
     }
 
@@ -93,7 +135,7 @@ impl WaitQueue {
         let curr = crate::current();
         loop {
             let mut rq = current_run_queue::<NoPreemptIrqSave>();
-            let wq = self.queue.lock();
+            let wq = self.lock();
             if condition() {
                 break;
             }
@@ -117,7 +159,7 @@ impl WaitQueue {
         );
         crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
-        rq.blocked_resched(self.queue.lock());
+        rq.blocked_resched(self.lock());
 
         let timeout = curr.in_wait_queue(); // still in the wait queue, must have timed out
 
@@ -151,7 +193,7 @@ impl WaitQueue {
             if axhal::time::wall_time() >= deadline {
                 break;
             }
-            let wq = self.queue.lock();
+            let wq = self.lock();
             if condition() {
                 timeout = false;
                 break;
@@ -170,7 +212,7 @@ impl WaitQueue {
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_one(&self, resched: bool) -> bool {
-        let mut wq = self.queue.lock();
+        let mut wq = self.lock();
         if let Some(task) = wq.pop_front() {
             unblock_one_task(task, resched);
             true
@@ -194,7 +236,7 @@ impl WaitQueue {
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_task(&mut self, resched: bool, task: &AxTaskRef) -> bool {
-        let mut wq = self.queue.lock();
+        let mut wq = self.lock();
         if let Some(index) = wq.iter().position(|t| Arc::ptr_eq(t, task)) {
             unblock_one_task(wq.remove(index).unwrap(), resched);
             true
@@ -208,12 +250,12 @@ impl WaitQueue {
     /// Returns the number of tasks requeued.
     pub fn requeue(&self, mut count: usize, target: &WaitQueue) -> usize {
         let tasks: Vec<_> = {
-            let mut wq = self.queue.lock();
+            let mut wq = self.lock();
             count = count.min(wq.len());
             wq.drain(..count).collect()
         };
         if !tasks.is_empty() {
-            let mut wq = target.queue.lock();
+            let mut wq = target.lock();
             wq.extend(tasks);
         }
         count
@@ -221,12 +263,33 @@ impl WaitQueue {
 
     /// Returns the number of tasks in the wait queue.
     pub fn len(&self) -> usize {
-        self.queue.lock().len()
+        self.lock().len()
     }
 
     /// Returns true if the wait queue is empty.
     pub fn is_empty(&self) -> bool {
-        self.queue.lock().is_empty()
+        self.lock().is_empty()
+    }
+}
+
+impl Deref for WaitQueueGuard<'_> {
+    type Target = VecDeque<AxTaskRef>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl DerefMut for WaitQueueGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+#[cfg(feature = "lockdep")]
+impl Drop for WaitQueueGuard<'_> {
+    fn drop(&mut self) {
+        crate_interface::call_interface!(crate::LockdepIf::release(self.addr));
     }
 }
 
@@ -79,5 +79,6 @@ define unit_test
   # TODO: Refactor to avoid hardcoding 'axfs'
   $(call run_cmd,cargo test,-p axfs $(1) $(verbose) -- --nocapture)
   $(call run_cmd,cargo test,-p axfs $(1) --features "myfs" $(verbose) -- --nocapture)
+  $(call run_cmd,cargo test,-p axtask $(1) --features "sched_classes smp" $(verbose) -- --nocapture)
   $(call run_cmd,cargo test,--workspace --exclude axfs $(1) $(verbose) -- --nocapture)
 endef
\ No newline at end of file
@@ -7,7 +7,7 @@
 #
 # Inputs:
 #   - FEATURES: Space/comma-separated list of features from the command line.
-#   - LOG, BUS, SMP, etc.: High-level config variables.
+#   - LOG, BUS, SMP, BLK_DEV, NET_MODEL, etc.: High-level config variables.
 #
 # Outputs:
 #   - FINAL_FEATURES: A clean, comma-separated string of all enabled features
@@ -20,10 +20,10 @@
 # --- 内核特性清单 ---
 _KERNEL_FEATURE_LIST := \
     smp fp_simd irq alloc alloc-tlsf alloc-slab alloc-buddy page-alloc-64g \
-    page-alloc-4g paging dma tls multitask sched_fifo sched_rr sched_cfs fs \
-    myfs lwext4_rs net dns display rtc bus-mmio bus-pci driver-ramdisk \
-    driver-ixgbe driver-fxmac driver-bcm2835-sdhci log-level-off \
-    log-level-error log-level-warn log-level-info log-level-debug log-level-trace
+    page-alloc-4g paging dma tls multitask sched_fifo sched_rr sched_cfs sched_classes \
+    fs myfs lwext4_rs net dns display rtc bus-mmio bus-pci driver-ramdisk \
+    driver-ixgbe driver-fxmac driver-bcm2835-sdhci driver-nvme driver-e1000 \
+    log-level-off log-level-error log-level-warn log-level-info log-level-debug log-level-trace
 
 # --- 确定内核特性前缀 ---
 ifeq ($(NO_AXSTD), y)
@@ -46,6 +46,16 @@ ifneq ($(SMP), 0)
     _all_features_raw += smp
 endif
 endif
+ifeq ($(BLK), y)
+ifeq ($(BLK_DEV), nvme)
+    _all_features_raw += driver-nvme
+endif
+endif
+ifeq ($(NET), y)
+ifneq ($(filter e1000 e1000e, $(NET_MODEL)),)
+    _all_features_raw += driver-e1000
+endif
+endif
 
 # --- 2. 清理和规范化这个列表 ---
 _all_features_clean := $(strip $(shell echo $(_all_features_raw) | tr ',' ' '))
@@ -50,13 +50,21 @@ qemu_args := -m $(MEM) -smp $(SMP) $(QEMU_ARCH_ARGS.$(ARCH))
 
 # --- 存储设备 ---
 ifeq ($(BLK),y)
-  qemu_args += -device virtio-blk-$(vdev-suffix),drive=disk0
+  ifeq ($(BLK_DEV),nvme)
+    qemu_args += -device nvme,serial=arceos-nvme0,drive=disk0
+  else
+    qemu_args += -device virtio-blk-$(vdev-suffix),drive=disk0
+  endif
   qemu_args += -drive id=disk0,if=none,format=raw,file=$(DISK_IMG)
 endif
 
 # --- 网络设备 ---
 ifeq ($(NET),y)
-  qemu_args += -device virtio-net-$(vdev-suffix),netdev=net0
+  ifneq ($(filter e1000 e1000e,$(NET_MODEL)),)
+    qemu_args += -device $(NET_MODEL),netdev=net0
+  else
+    qemu_args += -device virtio-net-$(vdev-suffix),netdev=net0
+  endif
 
   # --- 网络后端配置 (使用 ifeq，更清晰) ---
   